@@ -0,0 +1,334 @@
+//! Authentication of the requests by api keys.
+//!
+//! The api keys are read from a toml file, each key being given to a client with the datasets
+//! it may see, the datasets used when it does not ask for any and its rate limit:
+//!
+//! ```toml
+//! [[clients]]
+//! name = "my-client"
+//! key = "a-secret-key"
+//! pt_datasets = ["fr-idf"]
+//! poi_datasets = ["my-private-pois"]
+//! default_poi_datasets = ["my-private-pois"]
+//! # max number of requests by second
+//! rate_limit = 20
+//! ```
+//!
+//! The key is given in the `Authorization` header of the requests.
+
+use crate::model::BragiError;
+use crate::watch::watch;
+use actix_service::{Service, Transform};
+use actix_web::{
+    dev::{ServiceRequest, ServiceResponse},
+    http::header::AUTHORIZATION,
+    Error, HttpMessage, HttpRequest,
+};
+use futures::future::{ok, Either, FutureResult};
+use futures::Poll;
+use serde::Deserialize;
+use slog_scope::{info, warn};
+use std::collections::HashMap;
+use std::fs::read_to_string;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant, SystemTime};
+
+/// Routes which can be called without api key
+const PUBLIC_PATHS: &[&str] = &["/", "/status"];
+
+/// A client of bragi, identified by its api key
+#[derive(Deserialize, Debug, Clone)]
+pub struct ApiClient {
+    pub name: String,
+    pub key: String,
+    /// Whether the client can use `_all_data`, and thus see all the datasets
+    #[serde(default)]
+    pub all_data: bool,
+    /// The datasets the client can ask for
+    #[serde(default)]
+    pub pt_datasets: Vec<String>,
+    #[serde(default)]
+    pub poi_datasets: Vec<String>,
+    /// The datasets used when the client does not ask for any
+    #[serde(default)]
+    pub default_pt_datasets: Vec<String>,
+    #[serde(default)]
+    pub default_poi_datasets: Vec<String>,
+    /// Max number of requests by second, not limited if not given
+    pub rate_limit: Option<u32>,
+    /// Whether the client can call the administration routes (like the reload of the settings)
+    #[serde(default)]
+    pub admin: bool,
+}
+
+impl ApiClient {
+    fn can_see(&self, datasets: &[String], allowed: &[String]) -> bool {
+        self.all_data || datasets.iter().all(|d| allowed.contains(d))
+    }
+
+    fn check(&self) -> Result<(), String> {
+        if !self.can_see(&self.default_pt_datasets, &self.pt_datasets)
+            || !self.can_see(&self.default_poi_datasets, &self.poi_datasets)
+        {
+            return Err(format!(
+                "the default datasets of `{}` are not in its datasets",
+                self.name
+            ));
+        }
+        if self.rate_limit == Some(0) {
+            return Err(format!(
+                "the rate limit of `{}` must be positive",
+                self.name
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct ApiKeysConfig {
+    #[serde(default)]
+    clients: Vec<ApiClient>,
+}
+
+/// The requests a client can still make: a bucket of `rate_limit` requests,
+/// refilled at `rate_limit` requests by second
+#[derive(Debug)]
+struct RateLimit {
+    available: f64,
+    updated_at: Instant,
+}
+
+impl RateLimit {
+    fn take(&mut self, rate_limit: u32) -> bool {
+        let rate_limit = f64::from(rate_limit);
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.updated_at);
+        self.available = (self.available + elapsed.as_secs_f64() * rate_limit).min(rate_limit);
+        self.updated_at = now;
+        if self.available >= 1. {
+            self.available -= 1.;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// The clients of bragi by api key, read from a file
+#[derive(Debug)]
+pub struct ApiKeys {
+    path: PathBuf,
+    clients: RwLock<HashMap<String, Arc<ApiClient>>>,
+    rate_limits: Mutex<HashMap<String, RateLimit>>,
+}
+
+fn read_clients(path: &Path) -> Result<HashMap<String, Arc<ApiClient>>, String> {
+    let content =
+        read_to_string(path).map_err(|e| format!("Failed to read `{}`: {}", path.display(), e))?;
+    let config: ApiKeysConfig = toml::from_str(&content)
+        .map_err(|e| format!("failed to parse `{}`: {}", path.display(), e))?;
+    let mut clients = HashMap::new();
+    for client in config.clients {
+        client.check()?;
+        let key = client.key.clone();
+        if clients.insert(key, Arc::new(client)).is_some() {
+            return Err(format!("an api key is duplicated in `{}`", path.display()));
+        }
+    }
+    Ok(clients)
+}
+
+impl ApiKeys {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        Ok(ApiKeys {
+            path: path.to_owned(),
+            clients: RwLock::new(read_clients(path)?),
+            rate_limits: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Read the file of the api keys again, the keys are kept if it is invalid
+    pub fn reload(&self) -> Result<(), String> {
+        let clients = read_clients(&self.path)?;
+        info!(
+            "{} api keys loaded from {}",
+            clients.len(),
+            self.path.display()
+        );
+        *self.clients.write().unwrap() = clients;
+        self.rate_limits.lock().unwrap().clear();
+        Ok(())
+    }
+
+    /// The client of the api key, if it has not exceeded its rate limit
+    pub fn authenticate(&self, key: Option<&str>) -> Result<Arc<ApiClient>, BragiError> {
+        let key = key.ok_or(BragiError::Unauthorized("no api key given"))?;
+        let client = self
+            .clients
+            .read()
+            .unwrap()
+            .get(key)
+            .cloned()
+            .ok_or(BragiError::Unauthorized("invalid api key"))?;
+        if let Some(rate_limit) = client.rate_limit {
+            let mut rate_limits = self.rate_limits.lock().unwrap();
+            let available = rate_limits
+                .entry(client.key.clone())
+                .or_insert_with(|| RateLimit {
+                    available: f64::from(rate_limit),
+                    updated_at: Instant::now(),
+                })
+                .take(rate_limit);
+            if !available {
+                return Err(BragiError::TooManyRequests);
+            }
+        }
+        Ok(client)
+    }
+}
+
+/// Reload the api keys each time their file is modified.
+pub fn watch_api_keys(api_keys: &Arc<ApiKeys>, interval: Duration) {
+    let modified_at = |path: &Path| -> Option<SystemTime> {
+        std::fs::metadata(path).and_then(|m| m.modified()).ok()
+    };
+    let mut known_modification = modified_at(&api_keys.path);
+    watch(api_keys, interval, move |api_keys| {
+        let modification = modified_at(&api_keys.path);
+        if modification != known_modification {
+            known_modification = modification;
+            if let Err(err) = api_keys.reload() {
+                warn!("impossible to reload the api keys: {}", err);
+            }
+        }
+    });
+}
+
+/// Restrict the datasets of a request to those its client can see.
+///
+/// The default datasets of the client are used if the request does not ask for any.
+/// Nothing is checked if the api keys are not enabled.
+pub fn authorize_datasets(
+    req: &HttpRequest,
+    pt_datasets: &mut Vec<String>,
+    poi_datasets: &mut Vec<String>,
+    all_data: bool,
+) -> Result<(), BragiError> {
+    let client = match req.extensions().get::<Arc<ApiClient>>() {
+        Some(client) => client.clone(),
+        None => return Ok(()),
+    };
+    if all_data && !client.all_data {
+        return Err(BragiError::Forbidden(
+            "the api key does not allow _all_data",
+        ));
+    }
+    if pt_datasets.is_empty() {
+        *pt_datasets = client.default_pt_datasets.clone();
+    }
+    if poi_datasets.is_empty() {
+        *poi_datasets = client.default_poi_datasets.clone();
+    }
+    if !client.can_see(pt_datasets, &client.pt_datasets) {
+        return Err(BragiError::Forbidden(
+            "the api key does not allow this pt_dataset",
+        ));
+    }
+    if !client.can_see(poi_datasets, &client.poi_datasets) {
+        return Err(BragiError::Forbidden(
+            "the api key does not allow this poi_dataset",
+        ));
+    }
+    Ok(())
+}
+
+/// Check that the client of a request can call the administration routes.
+///
+/// Nothing is checked if the api keys are not enabled.
+pub fn authorize_admin(req: &HttpRequest) -> Result<(), BragiError> {
+    match req.extensions().get::<Arc<ApiClient>>() {
+        Some(client) if !client.admin => Err(BragiError::Forbidden(
+            "the api key does not allow the administration of bragi",
+        )),
+        _ => Ok(()),
+    }
+}
+
+#[derive(Clone)]
+#[must_use = "must be set up as middleware for actix-web"]
+/// Reject the requests without a valid api key (`401 Unauthorized`) or whose client has exceeded
+/// its rate limit (`429 Too Many Requests`).
+///
+/// The client of the request is stored in its extensions, for `authorize_datasets`.
+/// All the requests are accepted if no api keys are given.
+pub struct ApiKeyAuth {
+    api_keys: Option<Arc<ApiKeys>>,
+}
+
+impl ApiKeyAuth {
+    pub fn new(api_keys: Option<Arc<ApiKeys>>) -> Self {
+        ApiKeyAuth { api_keys }
+    }
+}
+
+impl<S, B> Transform<S> for ApiKeyAuth
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = ApiKeyAuthMiddleware<S>;
+    type Future = FutureResult<Self::Transform, Self::InitError>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(ApiKeyAuthMiddleware {
+            service,
+            api_keys: self.api_keys.clone(),
+        })
+    }
+}
+
+#[doc(hidden)]
+/// Middleware service for ApiKeyAuth
+pub struct ApiKeyAuthMiddleware<S> {
+    service: S,
+    api_keys: Option<Arc<ApiKeys>>,
+}
+
+impl<S, B> Service for ApiKeyAuthMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = Either<FutureResult<Self::Response, Self::Error>, S::Future>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.service.poll_ready()
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let api_keys = match self.api_keys {
+            Some(ref api_keys) if !PUBLIC_PATHS.contains(&req.path()) => api_keys,
+            _ => return Either::B(self.service.call(req)),
+        };
+        let key = req
+            .headers()
+            .get(AUTHORIZATION)
+            .and_then(|key| key.to_str().ok());
+        match api_keys.authenticate(key) {
+            Ok(client) => {
+                req.extensions_mut().insert(client);
+                Either::B(self.service.call(req))
+            }
+            Err(err) => Either::A(ok(req.error_response(err))),
+        }
+    }
+}
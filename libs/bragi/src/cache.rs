@@ -0,0 +1,281 @@
+//! In-process cache of the responses of /autocomplete, /features and /reverse
+
+use crate::model::{BragiError, BragiFuture};
+use crate::prometheus_middleware::{CACHE_HITS, CACHE_MISSES};
+use crate::watch::watch;
+use crate::Context;
+use actix_http::http::header::{CacheControl, CacheDirective, ETAG, IF_NONE_MATCH};
+use actix_web::{HttpRequest, HttpResponse};
+use futures::{future, Future};
+use lru_cache::LruCache;
+use mimir::backend::SearchBackend;
+use serde::Serialize;
+use serde_json::Value;
+use slog_scope::{info, warn};
+use std::collections::hash_map::DefaultHasher;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// The coordinates of the keys are rounded to 4 decimals (about 10 meters)
+const COORD_PRECISION: f64 = 10_000.;
+
+/// Parameters which don't change the content of a response
+const IGNORED_PARAMS: &[&str] = &["timeout", "request_id"];
+
+#[derive(Clone, Debug)]
+pub struct CachedResponse {
+    pub body: String,
+    pub etag: String,
+    created_at: Instant,
+}
+
+impl CachedResponse {
+    pub fn new(body: String) -> Self {
+        CachedResponse {
+            etag: etag(&body),
+            body,
+            created_at: Instant::now(),
+        }
+    }
+}
+
+/// A bounded LRU cache of the serialized responses, each one expiring after `ttl`
+pub struct ResponseCache {
+    entries: Mutex<LruCache<String, CachedResponse>>,
+    ttl: Duration,
+}
+
+impl fmt::Debug for ResponseCache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ResponseCache")
+            .field("ttl", &self.ttl)
+            .finish()
+    }
+}
+
+impl ResponseCache {
+    pub fn new(size: usize, ttl: Duration) -> Self {
+        ResponseCache {
+            entries: Mutex::new(LruCache::new(size)),
+            ttl,
+        }
+    }
+
+    /// The response stored for the key if it has not expired,
+    /// counted as a hit or a miss of the handler
+    pub fn get(&self, handler: &str, key: &str) -> Option<CachedResponse> {
+        let mut entries = self.entries.lock().unwrap();
+        let response = match entries.get_mut(key).cloned() {
+            Some(response) if response.created_at.elapsed() < self.ttl => Some(response),
+            Some(_) => {
+                entries.remove(key);
+                None
+            }
+            None => None,
+        };
+        if response.is_some() {
+            CACHE_HITS.with_label_values(&[handler]).inc();
+        } else {
+            CACHE_MISSES.with_label_values(&[handler]).inc();
+        }
+        response
+    }
+
+    pub fn insert(&self, key: String, response: CachedResponse) {
+        self.entries.lock().unwrap().insert(key, response);
+    }
+
+    pub fn clear(&self) {
+        self.entries.lock().unwrap().clear();
+    }
+}
+
+/// Clear the cache each time the aliases of the munin indexes change (when a new
+/// dataset is published).
+pub fn watch_aliases<B>(cache: &Arc<ResponseCache>, backend: B, interval: Duration)
+where
+    B: SearchBackend + Send + 'static,
+{
+    let mut known_aliases = None;
+    watch(cache, interval, move |cache| {
+        match backend.get_all_aliased_index("munin") {
+            Ok(aliases) => {
+                if known_aliases
+                    .as_ref()
+                    .map_or(false, |known| known != &aliases)
+                {
+                    info!("the published indexes have changed, clearing the response cache");
+                    cache.clear();
+                }
+                known_aliases = Some(aliases);
+            }
+            Err(err) => warn!("impossible to check the published indexes: {}", err),
+        }
+    });
+}
+
+/// The key of the cache for the parameters of a request on `route`:
+/// the parameters which don't change the response are removed,
+/// the lists are sorted, the coordinates rounded and the query string
+/// trimmed and lowercased, with its spaces collapsed.
+pub fn cache_key<P: Serialize>(route: &str, params: &P) -> String {
+    let params = serde_json::to_value(params)
+        .map(normalize)
+        .unwrap_or(Value::Null);
+    format!("{}?{}", route, params)
+}
+
+fn normalize(value: Value) -> Value {
+    match value {
+        Value::Object(params) => Value::Object(
+            params
+                .into_iter()
+                .filter(|(name, _)| !IGNORED_PARAMS.contains(&name.as_str()))
+                .map(|(name, value)| {
+                    let value = match (name.as_str(), value.as_f64()) {
+                        ("lat", Some(coord)) | ("lon", Some(coord)) => {
+                            Value::from((coord * COORD_PRECISION).round() / COORD_PRECISION)
+                        }
+                        ("q", _) if value.is_string() => Value::from(
+                            value
+                                .as_str()
+                                .unwrap_or_default()
+                                .split_whitespace()
+                                .collect::<Vec<_>>()
+                                .join(" ")
+                                .to_lowercase(),
+                        ),
+                        _ => normalize(value),
+                    };
+                    (name, value)
+                })
+                .collect(),
+        ),
+        Value::Array(mut values) => {
+            values.sort_by_key(Value::to_string);
+            values.dedup();
+            Value::Array(values)
+        }
+        value => value,
+    }
+}
+
+fn etag(body: &str) -> String {
+    let mut hasher = DefaultHasher::new();
+    body.hash(&mut hasher);
+    format!("\"{:016x}\"", hasher.finish())
+}
+
+fn matches_etag(req: &HttpRequest, etag: &str) -> bool {
+    req.headers()
+        .get(IF_NONE_MATCH)
+        .and_then(|value| value.to_str().ok())
+        .map_or(false, |tags| {
+            tags.split(',')
+                .map(|tag| tag.trim().trim_start_matches("W/"))
+                .any(|tag| tag == etag || tag == "*")
+        })
+}
+
+fn http_response(
+    req: &HttpRequest,
+    response: CachedResponse,
+    reusable: bool,
+    http_cache_duration: u32,
+) -> HttpResponse {
+    // a partial response should not be reused
+    let cache_directive = if reusable {
+        CacheDirective::MaxAge(http_cache_duration)
+    } else {
+        CacheDirective::NoStore
+    };
+    if matches_etag(req, &response.etag) {
+        HttpResponse::NotModified()
+            .set(CacheControl(vec![cache_directive]))
+            .header(ETAG, response.etag.as_str())
+            .finish()
+    } else {
+        HttpResponse::Ok()
+            .set(CacheControl(vec![cache_directive]))
+            .header(ETAG, response.etag.as_str())
+            .content_type("application/json")
+            .body(response.body)
+    }
+}
+
+/// Build the response of a request, served from the cache if a key is given
+/// and the cache is enabled.
+/// `build` gives the search of the content of the response and whether it can be reused
+/// (which is not the case of partial responses).
+/// The response has an ETag and is a `304 Not Modified` if it matches the
+/// `If-None-Match` header of the request.
+pub fn respond<T, F>(
+    state: &Context,
+    req: &HttpRequest,
+    handler: &str,
+    key: Option<String>,
+    build: F,
+) -> BragiFuture<HttpResponse>
+where
+    T: Serialize + 'static,
+    F: FnOnce() -> Result<BragiFuture<(T, bool)>, BragiError>,
+{
+    let cache = match (state.get_cache(), key) {
+        (Some(cache), Some(key)) => Some((cache.clone(), key)),
+        _ => None,
+    };
+    let http_cache_duration = state.http_cache_duration;
+    let req = req.clone();
+    if let Some(response) = cache
+        .as_ref()
+        .and_then(|(cache, key)| cache.get(handler, key))
+    {
+        return Box::new(future::ok(http_response(
+            &req,
+            response,
+            true,
+            http_cache_duration,
+        )));
+    }
+
+    let content = match build() {
+        Ok(content) => content,
+        Err(err) => return Box::new(future::err(err)),
+    };
+    Box::new(content.and_then(move |(content, reusable)| {
+        let body =
+            serde_json::to_string(&content).map_err(|err| BragiError::Backend(err.into()))?;
+        let response = CachedResponse::new(body);
+        if let (Some((cache, key)), true) = (cache, reusable) {
+            cache.insert(key, response.clone());
+        }
+        Ok(http_response(&req, response, reusable, http_cache_duration))
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_cache_key() {
+        let key = cache_key(
+            "autocomplete",
+            &json!({"q": "  Rue   du Port ", "lat": 48.53701, "type": ["street", "poi"]}),
+        );
+        assert_eq!(
+            key,
+            cache_key(
+                "autocomplete",
+                &json!({"q": "rue du port", "lat": 48.537, "type": ["poi", "street"], "timeout": 10}),
+            )
+        );
+        assert_ne!(
+            key,
+            cache_key("autocomplete", &json!({"q": "rue du pont", "lat": 48.537}))
+        );
+    }
+}
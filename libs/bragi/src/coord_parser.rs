@@ -0,0 +1,412 @@
+//! Detection of the coordinates written in a query.
+//!
+//! The coordinates can be given:
+//! * in decimal degrees, like `48.8566, 2.3522` or `48.8566N 2.3522E`
+//! * in degrees, minutes and seconds, like `48°51'24"N 2°21'08"E` or `N 48°51.4' E 2°21.1'`
+//! * as a full plus code (an Open Location Code), like `8FW4V75V+8Q`
+//! * as a short plus code, like `V75V+8Q`, recovered near the position of the request
+//!
+//! Without hemisphere, the latitude comes first.
+
+use mimir::objects::Coord;
+
+/// The digits of the plus codes
+const PLUS_CODE_ALPHABET: &[u8] = b"23456789CFGHJMPQRVWX";
+
+/// The position of the `+` in a full plus code
+const PLUS_CODE_SEPARATOR_POSITION: usize = 8;
+
+/// The number of digits of a plus code encoded by pairs (latitude then longitude),
+/// the next ones refining a grid of 5 rows and 4 columns
+const PLUS_CODE_PAIRS_LENGTH: usize = 10;
+
+/// A coordinate found in a query
+#[derive(Debug, Clone)]
+pub struct CoordLiteral {
+    pub coord: Coord,
+    /// The label of the coordinate: its latitude and longitude, or its full plus code
+    pub label: String,
+}
+
+/// The coordinate written in `q`, if the whole query is a coordinate.
+/// The short plus codes are only recovered if the position of the request is given.
+pub fn parse_coord(q: &str, near: Option<&Coord>) -> Option<CoordLiteral> {
+    let q = q.trim();
+    if let Some(literal) = parse_plus_code(q, near) {
+        return Some(literal);
+    }
+    let (lat, lon) = parse_lat_lon(q)?;
+    Some(CoordLiteral {
+        coord: Coord::new(lon, lat),
+        label: format!("{}, {}", round(lat), round(lon)),
+    })
+}
+
+/// The coordinates are rounded to 6 decimals (about 10 centimeters) in the labels
+fn round(value: f64) -> f64 {
+    (value * 1e6).round() / 1e6
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Token {
+    Number(f64, bool),
+    Degrees,
+    Minutes,
+    Seconds,
+    Hemisphere(char),
+    Separator,
+}
+
+/// The tokens of a coordinate, none if `q` contains something else.
+/// A number is given with whether it has decimals.
+fn tokenize(q: &str) -> Option<Vec<Token>> {
+    let chars = q.chars().collect::<Vec<_>>();
+    let mut tokens = vec![];
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        match c {
+            c if c.is_whitespace() => {}
+            '0'..='9' | '-' | '+' | '.' => {
+                let start = i;
+                while i + 1 < chars.len() && matches!(chars[i + 1], '0'..='9' | '.') {
+                    i += 1;
+                }
+                let number = chars[start..=i].iter().collect::<String>();
+                tokens.push(Token::Number(number.parse().ok()?, number.contains('.')));
+            }
+            '°' | 'º' => tokens.push(Token::Degrees),
+            '\'' | '′' | '’' => {
+                // two quotes are the mark of the seconds
+                if chars.get(i + 1) == Some(&c) {
+                    i += 1;
+                    tokens.push(Token::Seconds);
+                } else {
+                    tokens.push(Token::Minutes);
+                }
+            }
+            '"' | '″' | '”' => tokens.push(Token::Seconds),
+            ',' | ';' | '/' => tokens.push(Token::Separator),
+            'N' | 'S' | 'E' | 'W' | 'n' | 's' | 'e' | 'w' => {
+                tokens.push(Token::Hemisphere(c.to_ascii_uppercase()))
+            }
+            _ => return None,
+        }
+        i += 1;
+    }
+    Some(tokens)
+}
+
+/// An angle of a coordinate, with its hemisphere if given
+struct Angle {
+    value: f64,
+    hemisphere: Option<char>,
+}
+
+/// Read an angle from the tokens: `[hemisphere] degrees [minutes [seconds]] [hemisphere]`.
+/// Decimal degrees without any mark must have decimals, to not take numbers for coordinates.
+fn parse_angle(tokens: &[Token], i: &mut usize) -> Option<Angle> {
+    let mut hemisphere = match tokens.get(*i) {
+        Some(Token::Hemisphere(h)) => {
+            *i += 1;
+            Some(*h)
+        }
+        _ => None,
+    };
+    let mut value = match tokens.get(*i) {
+        Some(Token::Number(n, _)) => *n,
+        _ => return None,
+    };
+    let has_decimals = matches!(tokens.get(*i), Some(Token::Number(_, true)));
+    *i += 1;
+    let mut has_marks = false;
+    if tokens.get(*i) == Some(&Token::Degrees) {
+        *i += 1;
+        has_marks = true;
+        // the minutes and the seconds are positive, and only the last part can have decimals
+        let mut last_has_decimals = has_decimals;
+        for (mark, divisor) in &[(Token::Minutes, 60.), (Token::Seconds, 3600.)] {
+            match (tokens.get(*i), tokens.get(*i + 1)) {
+                (Some(Token::Number(n, decimals)), Some(m))
+                    if m == mark && !last_has_decimals && (0. ..60.).contains(n) =>
+                {
+                    value += value.signum() * n / divisor;
+                    last_has_decimals = *decimals;
+                    *i += 2;
+                }
+                _ => break,
+            }
+        }
+    }
+    if hemisphere.is_none() {
+        if let Some(Token::Hemisphere(h)) = tokens.get(*i) {
+            hemisphere = Some(*h);
+            *i += 1;
+        }
+    }
+    if !has_marks && hemisphere.is_none() && !has_decimals {
+        return None;
+    }
+    if hemisphere.is_some() && value < 0. {
+        return None;
+    }
+    let value = match hemisphere {
+        Some('S') | Some('W') => -value,
+        _ => value,
+    };
+    Some(Angle { value, hemisphere })
+}
+
+/// The latitude and the longitude written in `q`
+fn parse_lat_lon(q: &str) -> Option<(f64, f64)> {
+    let tokens = tokenize(q)?;
+    let mut i = 0;
+    let first = parse_angle(&tokens, &mut i)?;
+    if tokens.get(i) == Some(&Token::Separator) {
+        i += 1;
+    }
+    let second = parse_angle(&tokens, &mut i)?;
+    if i != tokens.len() {
+        return None;
+    }
+    let is_lat = |h: Option<char>| matches!(h, Some('N') | Some('S'));
+    let is_lon = |h: Option<char>| matches!(h, Some('E') | Some('W'));
+    let (lat, lon) = if is_lon(first.hemisphere) || is_lat(second.hemisphere) {
+        (second, first)
+    } else {
+        (first, second)
+    };
+    if is_lon(lat.hemisphere) || is_lat(lon.hemisphere) {
+        return None;
+    }
+    if (-90. ..=90.).contains(&lat.value) && (-180. ..=180.).contains(&lon.value) {
+        Some((lat.value, lon.value))
+    } else {
+        None
+    }
+}
+
+fn plus_code_digit(c: u8) -> Option<usize> {
+    PLUS_CODE_ALPHABET.iter().position(|&d| d == c)
+}
+
+/// The full or short plus code written in `q`, the short codes being recovered near `near`
+fn parse_plus_code(q: &str, near: Option<&Coord>) -> Option<CoordLiteral> {
+    let code = q.to_ascii_uppercase();
+    let separator = code.find('+')?;
+    let (before, after) = (
+        &code.as_bytes()[..separator],
+        &code.as_bytes()[separator + 1..],
+    );
+    if !(2..=PLUS_CODE_SEPARATOR_POSITION).contains(&separator) || separator % 2 == 1 {
+        return None;
+    }
+    // the padding (`0`) is only allowed at the end of a full code, before the separator
+    let padding = before.iter().position(|&c| c == b'0');
+    if let Some(padding) = padding {
+        if separator != PLUS_CODE_SEPARATOR_POSITION
+            || padding == 0
+            || padding % 2 == 1
+            || before[padding..].iter().any(|&c| c != b'0')
+            || !after.is_empty()
+        {
+            return None;
+        }
+    } else if after.len() < 2 && !(after.is_empty() && separator == PLUS_CODE_SEPARATOR_POSITION) {
+        return None;
+    }
+    let digits = before[..padding.unwrap_or(separator)]
+        .iter()
+        .chain(after)
+        .map(|&c| plus_code_digit(c))
+        .collect::<Option<Vec<_>>>()?;
+
+    let full_code = if separator == PLUS_CODE_SEPARATOR_POSITION {
+        // the first digits are limited to the range of the latitude and the longitude
+        if digits[0] >= 9 || digits[1] >= 18 {
+            return None;
+        }
+        code
+    } else {
+        let near = near?;
+        format!(
+            "{}{}",
+            &encode_plus_code(near.lat(), near.lon())[..PLUS_CODE_SEPARATOR_POSITION - separator],
+            code
+        )
+    };
+    let (lat, lon) = decode_plus_code(&full_code)?;
+    let (lat, lon) = match (separator, near) {
+        (PLUS_CODE_SEPARATOR_POSITION, _) | (_, None) => (lat, lon),
+        (_, Some(near)) => {
+            // the area of the code is the nearest one to the reference
+            let resolution = 20f64.powi(separator as i32 / 2 - 2);
+            let nearest = |value: f64, reference: f64, max: f64| {
+                if reference + resolution / 2. < value && value - resolution >= -max {
+                    value - resolution
+                } else if reference - resolution / 2. > value && value + resolution <= max {
+                    value + resolution
+                } else {
+                    value
+                }
+            };
+            (
+                nearest(lat, near.lat(), 90.),
+                nearest(lon, near.lon(), 180.),
+            )
+        }
+    };
+    let full_code = if separator == PLUS_CODE_SEPARATOR_POSITION {
+        full_code
+    } else {
+        // the code of the area actually used, whose prefix can differ from the reference's
+        let prefix = encode_plus_code(lat, lon);
+        format!(
+            "{}{}",
+            &prefix[..PLUS_CODE_SEPARATOR_POSITION - separator],
+            &full_code[PLUS_CODE_SEPARATOR_POSITION - separator..]
+        )
+    };
+    Some(CoordLiteral {
+        coord: Coord::new(lon, lat),
+        label: full_code,
+    })
+}
+
+/// The first digits (before the separator) of the plus code of a position
+fn encode_plus_code(lat: f64, lon: f64) -> String {
+    let mut lat = (lat + 90.).max(0.).min(180. - 1e-10);
+    let mut lon = (lon + 180.).rem_euclid(360.);
+    let mut code = String::with_capacity(PLUS_CODE_SEPARATOR_POSITION);
+    let mut resolution = 20.;
+    for _ in 0..PLUS_CODE_SEPARATOR_POSITION / 2 {
+        for value in &mut [&mut lat, &mut lon] {
+            let digit = (**value / resolution).floor() as usize;
+            **value -= digit as f64 * resolution;
+            code.push(PLUS_CODE_ALPHABET[digit.min(19)] as char);
+        }
+        resolution /= 20.;
+    }
+    code
+}
+
+/// The center of the area of a full plus code
+fn decode_plus_code(code: &str) -> Option<(f64, f64)> {
+    let digits = code
+        .bytes()
+        .filter(|&c| c != b'+' && c != b'0')
+        .map(plus_code_digit)
+        .collect::<Option<Vec<_>>>()?;
+    let (mut lat, mut lon) = (-90., -180.);
+    let (mut lat_resolution, mut lon_resolution) = (400., 400.);
+    for (i, pair) in digits[..digits.len().min(PLUS_CODE_PAIRS_LENGTH)]
+        .chunks(2)
+        .enumerate()
+    {
+        lat_resolution = 20f64.powi(1 - i as i32);
+        lon_resolution = lat_resolution;
+        lat += pair[0] as f64 * lat_resolution;
+        lon += *pair.get(1)? as f64 * lon_resolution;
+    }
+    for &digit in digits.iter().skip(PLUS_CODE_PAIRS_LENGTH) {
+        lat_resolution /= 5.;
+        lon_resolution /= 4.;
+        lat += (digit / 4) as f64 * lat_resolution;
+        lon += (digit % 4) as f64 * lon_resolution;
+    }
+    let lat = (lat + lat_resolution / 2.).min(90.);
+    let lon = lon + lon_resolution / 2.;
+    let lon = if lon >= 180. { lon - 360. } else { lon };
+    Some((lat, lon))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lat_lon(q: &str, near: Option<&Coord>) -> Option<(f64, f64)> {
+        parse_coord(q, near).map(|l| (round(l.coord.lat()), round(l.coord.lon())))
+    }
+
+    #[test]
+    fn parse_decimal_degrees() {
+        assert_eq!(lat_lon("48.8566, 2.3522", None), Some((48.8566, 2.3522)));
+        assert_eq!(lat_lon(" 48.8566 2.3522 ", None), Some((48.8566, 2.3522)));
+        assert_eq!(
+            lat_lon("-33.8688;151.2093", None),
+            Some((-33.8688, 151.2093))
+        );
+        assert_eq!(lat_lon("2.3522E 48.8566N", None), Some((48.8566, 2.3522)));
+        assert_eq!(
+            lat_lon("48.8566 S, 2.3522 W", None),
+            Some((-48.8566, -2.3522))
+        );
+        assert_eq!(
+            parse_coord("48.8566, 2.3522", None).unwrap().label,
+            "48.8566, 2.3522"
+        );
+    }
+
+    #[test]
+    fn parse_degrees_minutes_seconds() {
+        assert_eq!(
+            lat_lon("48°51'24\"N 2°21'08\"E", None),
+            Some((48.856667, 2.352222))
+        );
+        assert_eq!(
+            lat_lon("N 48° 51′ 24″, E 2° 21′ 8″", None),
+            Some((48.856667, 2.352222))
+        );
+        assert_eq!(
+            lat_lon("48°51.4'N 2°21.1'W", None),
+            Some((48.856667, -2.351667))
+        );
+        assert_eq!(lat_lon("-48°30', 2°", None), Some((-48.5, 2.)));
+    }
+
+    #[test]
+    fn reject_non_coordinates() {
+        assert_eq!(lat_lon("15 75012", None), None);
+        assert_eq!(lat_lon("48, 2", None), None);
+        assert_eq!(lat_lon("12 rue de la paix", None), None);
+        assert_eq!(lat_lon("95.1, 2.3", None), None);
+        assert_eq!(lat_lon("48.1N 2.3N", None), None);
+        assert_eq!(lat_lon("48°61'N 2°E", None), None);
+        assert_eq!(lat_lon("1.2.3 4.5", None), None);
+        assert_eq!(lat_lon("west", None), None);
+    }
+
+    #[test]
+    fn parse_full_plus_codes() {
+        let literal = parse_coord("8FW4V75V+8Q", None).unwrap();
+        assert_eq!(literal.label, "8FW4V75V+8Q");
+        assert_eq!(
+            (round(literal.coord.lat()), round(literal.coord.lon())),
+            (48.858313, 2.294438)
+        );
+        assert_eq!(lat_lon("8fw4v75v+8qr", None), Some((48.858363, 2.294391)));
+        assert_eq!(lat_lon("8FW40000+", None), Some((48.5, 2.5)));
+        assert_eq!(lat_lon("8FW4V75V+8", None), None);
+        assert_eq!(lat_lon("8FW4V70V+", None), None);
+        assert_eq!(lat_lon("XFW4V75V+8Q", None), None);
+    }
+
+    #[test]
+    fn parse_short_plus_codes() {
+        let paris = Coord::new(2.35, 48.85);
+        let literal = parse_coord("V75V+8Q", Some(&paris)).unwrap();
+        assert_eq!(literal.label, "8FW4V75V+8Q");
+        assert_eq!(
+            (round(literal.coord.lat()), round(literal.coord.lon())),
+            (48.858313, 2.294438)
+        );
+        // a short code needs a position
+        assert!(parse_coord("V75V+8Q", None).is_none());
+        // the nearest area can be in the next cell of the reference
+        let near_border = Coord::new(2.35, 48.99);
+        assert_eq!(
+            lat_lon("CXC2+2Q", Some(&near_border)).map(|(lat, _)| lat > 49.),
+            Some(true)
+        );
+    }
+}
@@ -0,0 +1,308 @@
+//! A non-blocking elasticsearch client.
+//!
+//! `Rubber` blocks the thread during the requests to elasticsearch, so the requests of bragi
+//! are answered with this client instead: the actix workers keep serving other requests
+//! while waiting for elasticsearch.
+//!
+//! The searches are built for Elasticsearch 2, and converted like `RestBackend` does
+//! for the recent versions (`--backend rest`).
+
+use actix_web::client::Client;
+use actix_web::http::StatusCode;
+use failure::Fail;
+use futures::{future, Future};
+use mimir::backend::BackendKind;
+use mimir::objects::{Admin, Coord, Place, Postcode};
+use mimir::rest_backend::convert_search_body;
+use mimir::rubber::{
+    build_containing_query, build_places_around_query, get_indexes, get_main_type_index,
+    keep_admins_containing, keep_postcodes_containing, multi_search_body, read_multi_search,
+    read_raw_places, search_body, MultiSearch, ES_MSEARCH_HISTOGRAM, ES_REVERSE_HISTOGRAM,
+    MAX_ADMINS_CONTAINING,
+};
+use rs_es::query::Query;
+use serde_json::Value;
+use slog_scope::debug;
+use std::time::Duration;
+use url::Url;
+
+// the places can be large (with the explanation of their score), so we allow large responses
+const MAX_RESPONSE_SIZE: usize = 50 * 1024 * 1024;
+
+thread_local! {
+    // the client (and its connection pool) is shared by all the requests of an actix worker,
+    // their timeout is set by request
+    static CLIENT: Client = Client::build().disable_timeout().finish();
+}
+
+#[derive(Debug, Fail)]
+pub enum EsClientError {
+    #[fail(display = "elasticsearch is unavailable: {}", _0)]
+    Unavailable(String),
+    #[fail(display = "invalid elasticsearch response: {}", _0)]
+    InvalidResponse(String),
+}
+
+pub type EsFuture<T> = Box<dyn Future<Item = T, Error = EsClientError>>;
+
+#[derive(Clone, Debug)]
+pub struct EsClient {
+    /// The host of elasticsearch, the path of the connection string is not used
+    url: Url,
+    /// This timeout is both a network timeout and a timeout given to ES
+    pub timeout: Option<Duration>,
+    backend: BackendKind,
+}
+
+impl EsClient {
+    // build a client with a connection string (http://host:port/)
+    pub fn new(cnx: &str, backend: BackendKind) -> Result<EsClient, String> {
+        let mut url = Url::parse(cnx).map_err(|e| format!("invalid url `{}`: {}", cnx, e))?;
+        // like with Rubber, only the host is kept
+        url.set_path("");
+        url.set_query(None);
+        Ok(EsClient {
+            url,
+            timeout: None,
+            backend,
+        })
+    }
+
+    /// The url of `path` (like `munin/_search?ignore_unavailable=true`) on the host
+    fn url(&self, path: &str) -> Result<Url, url::ParseError> {
+        self.url.join(&format!("/{}", path.trim_start_matches('/')))
+    }
+
+    pub fn with_timeout(&self, timeout: Option<Duration>) -> EsClient {
+        EsClient {
+            url: self.url.clone(),
+            timeout,
+            backend: self.backend,
+        }
+    }
+
+    fn post(&self, path: &str, body: String) -> EsFuture<Value> {
+        let url = match self.url(path) {
+            Ok(url) => url,
+            Err(e) => return Box::new(future::err(EsClientError::Unavailable(e.to_string()))),
+        };
+        debug!("doing a post on {} with {}", url, body);
+        let request = CLIENT.with(|client| client.post(url.as_str()));
+        let request = match self.timeout {
+            Some(timeout) => request.timeout(timeout),
+            None => request,
+        };
+        Box::new(
+            request
+                .content_type("application/json")
+                .send_body(body)
+                .map_err(|e| EsClientError::Unavailable(e.to_string()))
+                .and_then(|mut response| {
+                    let status = response.status();
+                    response
+                        .body()
+                        .limit(MAX_RESPONSE_SIZE)
+                        .map_err(|e| EsClientError::Unavailable(e.to_string()))
+                        .and_then(move |body| match status {
+                            StatusCode::OK | StatusCode::CREATED => serde_json::from_slice(&body)
+                                .map_err(|e| EsClientError::InvalidResponse(e.to_string())),
+                            _ => Err(EsClientError::InvalidResponse(format!(
+                                "{}: {}",
+                                status,
+                                String::from_utf8_lossy(&body)
+                            ))),
+                        })
+                }),
+        )
+    }
+
+    /// The body of a search of `query`, with the timeout of the client
+    pub fn search_body(&self, query: &Query, from: u64, size: u64) -> Value {
+        let mut body = search_body(query, from, size, self.timeout);
+        if self.backend == BackendKind::Rest {
+            convert_search_body(&mut body);
+        }
+        body
+    }
+
+    /// Send the search `body` to elasticsearch on the given `indexes` and read the places found.
+    ///
+    /// The distance of the places to `coord` is computed if it is given.
+    /// The places are returned with whether elasticsearch timed out (they are then partial).
+    pub fn search(
+        &self,
+        indexes: &[&str],
+        body: &Value,
+        coord: Option<Coord>,
+    ) -> EsFuture<(Vec<Place>, bool)> {
+        if indexes.is_empty() {
+            // without index, elasticsearch would search on all the indexes,
+            // but we want to return an empty response in this case.
+            return Box::new(future::ok((vec![], false)));
+        }
+        let path = format!("{}/_search?ignore_unavailable=true", indexes.join(","));
+        Box::new(self.post(&path, body.to_string()).map(move |response| {
+            let timed_out = response
+                .get("timed_out")
+                .and_then(Value::as_bool)
+                .unwrap_or(false);
+            (read_raw_places(&response, coord.as_ref()), timed_out)
+        }))
+    }
+
+    /// Send all the `searches` in a single `_msearch` request.
+    ///
+    /// The result contains the places found by each search, in the same order as `searches`.
+    /// A search that failed does not fail the others, its error is given instead of its places.
+    pub fn multi_search(
+        &self,
+        searches: &[MultiSearch<'_>],
+    ) -> EsFuture<Vec<Result<Vec<Place>, String>>> {
+        // without index, elasticsearch would search on all the indexes,
+        // so these searches are not sent and find nothing
+        let is_sent = searches
+            .iter()
+            .map(|search| !search.indexes.is_empty())
+            .collect::<Vec<_>>();
+        let sent = searches
+            .iter()
+            .filter(|search| !search.indexes.is_empty())
+            .cloned()
+            .collect::<Vec<_>>();
+        if sent.is_empty() {
+            return Box::new(future::ok(searches.iter().map(|_| Ok(vec![])).collect()));
+        }
+        let bodies = sent
+            .iter()
+            .map(|search| {
+                let body = self.search_body(search.query, search.from, search.size);
+                (search.indexes, body)
+            })
+            .collect::<Vec<_>>();
+        let body = multi_search_body(&bodies);
+        let coords = sent.iter().map(|search| search.coord).collect::<Vec<_>>();
+
+        let timer = ES_MSEARCH_HISTOGRAM.start_timer();
+        Box::new(self.post("_msearch", body).then(move |res| {
+            timer.observe_duration();
+            res.map(|response| {
+                let mut results = read_multi_search(&response, &coords).into_iter();
+                is_sent
+                    .into_iter()
+                    .map(|is_sent| {
+                        if is_sent {
+                            results.next().unwrap_or_else(|| {
+                                Err("no response from elasticsearch".to_string())
+                            })
+                        } else {
+                            Ok(vec![])
+                        }
+                    })
+                    .collect()
+            })
+        }))
+    }
+
+    /// Get the address (house or street) nearest to `coord`, in a 1km radius
+    pub fn get_address(&self, coord: &Coord) -> EsFuture<Vec<Place>> {
+        let indexes = get_indexes(false, &[], &[], &["house", "street"]);
+        let indexes = indexes
+            .iter()
+            .map(|index| index.as_str())
+            .collect::<Vec<&str>>();
+
+        self.get_places_around(coord, &indexes, 1000., 1)
+    }
+
+    /// Get the `limit` places of `indexes` nearest to `coord`, in a `radius` (in meters),
+    /// sorted by distance.
+    pub fn get_places_around(
+        &self,
+        coord: &Coord,
+        indexes: &[&str],
+        radius: f64,
+        limit: u64,
+    ) -> EsFuture<Vec<Place>> {
+        let query = build_places_around_query(coord, radius);
+        let body = self.search_body(&query, 0, limit);
+
+        let timer = ES_REVERSE_HISTOGRAM.start_timer();
+        Box::new(
+            self.search(indexes, &body, Some(*coord))
+                .then(move |res| {
+                    timer.observe_duration();
+                    res
+                })
+                .map(|(mut places, _)| {
+                    // the score decreases with the distance, but some places can have the same score
+                    places.sort_by_key(|p| p.distance());
+                    places
+                }),
+        )
+    }
+
+    /// Get all the admins whose boundary contains `coord`, from the smallest to the largest.
+    pub fn get_admins_containing(&self, coord: &Coord) -> EsFuture<Vec<Place>> {
+        let coord = *coord;
+        Box::new(
+            self.search_containing(&get_main_type_index::<Admin>(), &coord)
+                .map(move |admins| keep_admins_containing(admins, &coord)),
+        )
+    }
+
+    /// Get the postcodes whose boundary contains `coord`, the nearest first.
+    pub fn get_postcodes_containing(&self, coord: &Coord) -> EsFuture<Vec<Place>> {
+        let coord = *coord;
+        Box::new(
+            self.search_containing(&get_main_type_index::<Postcode>(), &coord)
+                .map(move |postcodes| keep_postcodes_containing(postcodes, &coord)),
+        )
+    }
+
+    /// Search the places of `index` whose indexed boundary contains `coord`.
+    fn search_containing(&self, index: &str, coord: &Coord) -> EsFuture<Vec<Place>> {
+        let query = build_containing_query(coord);
+        let mut body = self.search_body(&query, 0, MAX_ADMINS_CONTAINING);
+        // the boundaries are needed to check that they really contain `coord`
+        if let Some(body) = body.as_object_mut() {
+            body.remove("_source");
+        }
+
+        let timer = ES_REVERSE_HISTOGRAM.start_timer();
+        Box::new(
+            self.search(&[index], &body, Some(*coord))
+                .then(move |res| {
+                    timer.observe_duration();
+                    res
+                })
+                .map(|(places, _)| places),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_url() {
+        for cnx in &[
+            "http://localhost:9200",
+            "http://localhost:9200/",
+            "http://localhost:9200/munin",
+        ] {
+            let client = EsClient::new(cnx, BackendKind::Es2).unwrap();
+            assert_eq!(
+                client
+                    .url("munin_addr,munin_street/_search?ignore_unavailable=true")
+                    .unwrap()
+                    .as_str(),
+                "http://localhost:9200/munin_addr,munin_street/_search?ignore_unavailable=true"
+            );
+            assert_eq!(
+                client.url("_msearch").unwrap().as_str(),
+                "http://localhost:9200/_msearch"
+            );
+        }
+    }
+}
@@ -31,27 +31,41 @@
 #[macro_use]
 extern crate prometheus;
 
+use crate::auth_middleware::ApiKeys;
+use crate::cache::ResponseCache;
+use crate::es_client::EsClient;
+use crate::semaphore::Semaphore;
+use mimir::backend::{BackendKind, SearchBackend};
+use mimir::embedded::EmbeddedBackend;
+use mimir::rest_backend::RestBackend;
 use mimir::rubber::Rubber;
-use slog_scope::debug;
 use std::convert::TryFrom;
+use std::path::PathBuf;
+use std::sync::Arc;
 use std::time::Duration;
 use structopt::StructOpt;
 
-use std::fs::read_to_string;
-
+pub mod auth_middleware;
+mod cache;
+mod coord_parser;
+pub mod es_client;
 mod extractors;
 mod model;
 pub mod prometheus_middleware;
 pub(crate) mod query;
+mod query_parser;
 mod query_settings;
 mod routes;
+mod semaphore;
 pub mod server;
+mod watch;
 
 pub use query::make_place as query_make_place;
-pub use query_settings::QuerySettings;
+pub use query_parser::{ParsedQuery, QueryParser};
+pub use query_settings::{QuerySettings, QuerySettingsProfiles};
 
 lazy_static::lazy_static! {
-    static ref BRAGI_NB_THREADS: String = (8 * ::num_cpus::get()).to_string();
+    static ref BRAGI_NB_THREADS: String = ::num_cpus::get().to_string();
 }
 
 #[derive(StructOpt, Debug, Clone, Default)]
@@ -67,6 +81,10 @@ pub struct Args {
         env = "BRAGI_ES"
     )]
     pub connection_string: String,
+    /// Search engine of the connection string: `es2` for Elasticsearch 2, `rest` for
+    /// Elasticsearch 7+ or OpenSearch (whose indexes are imported with `--backend rest`).
+    #[structopt(long = "backend", default_value = "es2", env = "BRAGI_BACKEND")]
+    pub backend: BackendKind,
     /// Number of threads used to serve http requests, override BRAGI_NB_THREADS environment variable.
     #[structopt(
         short = "t",
@@ -81,18 +99,18 @@ pub struct Args {
     pub max_es_timeout: Option<u64>,
 
     /// Custom timeout for the /reverse
-    /// this is bounded by `max_es_timeout` and is used because for the moment we cannot easily change the timeout of a given rubber
+    /// this is bounded by `max_es_timeout` and is used when the request has no timeout
     #[structopt(long = "max-es-reverse-timeout", env = "BRAGI_MAX_ES_REVERSE_TIMEOUT")]
     pub max_es_reverse_timeout: Option<u64>,
     /// Custom timeout for the /autocomplete
-    /// this is bounded by `max_es_timeout` and is used because for the moment we cannot easily change the timeout of a given rubber
+    /// this is bounded by `max_es_timeout` and is used when the request has no timeout
     #[structopt(
         long = "max-es-autocomplete-timeout",
         env = "BRAGI_MAX_ES_AUTOCOMPLETE_TIMEOUT"
     )]
     pub max_es_autocomplete_timeout: Option<u64>,
     /// Custom timeout for the /features
-    /// this is bounded by `max_es_timeout` and is used because for the moment we cannot easily change the timeout of a given rubber
+    /// this is bounded by `max_es_timeout` and is used when the request has no timeout
     #[structopt(
         long = "max-es-features-timeout",
         env = "BRAGI_MAX_ES_FEATURES_TIMEOUT"
@@ -100,7 +118,7 @@ pub struct Args {
     pub max_es_features_timeout: Option<u64>,
 
     /// Cache duration for http response served by bragi
-    /// This only set the Cache-control Header, it doesn't enable cache on bragi side
+    /// This sets the Cache-control Header, the cache on bragi side is enabled by `--cache-size`
     /// The duration is in seconds
     #[structopt(
         long = "http-cache-duration",
@@ -108,19 +126,141 @@ pub struct Args {
         default_value = "3600"
     )]
     pub http_cache_duration: u32,
+    /// Number of responses of /autocomplete, /features and /reverse kept in an in-process cache,
+    /// the cache is disabled if 0.
+    #[structopt(long = "cache-size", env = "BRAGI_CACHE_SIZE", default_value = "0")]
+    pub cache_size: usize,
+    /// Duration in seconds during which a response of the cache is served.
+    #[structopt(long = "cache-ttl", env = "BRAGI_CACHE_TTL", default_value = "300")]
+    pub cache_ttl: u64,
+    /// Interval in seconds between two checks of the published indexes,
+    /// the cache is cleared when they change.
+    #[structopt(
+        long = "cache-check-interval",
+        env = "BRAGI_CACHE_CHECK_INTERVAL",
+        default_value = "10"
+    )]
+    pub cache_check_interval: u64,
+    /// Interval in seconds between two checks of the published admin indexes,
+    /// the names of the admins known by the query parser are read again when they change.
+    #[structopt(
+        long = "admins-check-interval",
+        env = "BRAGI_ADMINS_CHECK_INTERVAL",
+        default_value = "60"
+    )]
+    pub admins_check_interval: u64,
     #[structopt(long = "weight-config-file")]
     pub weight_config_file: Option<String>,
+    /// Rules of the addresses of each country used to parse the queries
+    /// (cf. `config/address-rules.toml`, which is used if not given).
+    #[structopt(long = "address-rules-file", parse(from_os_str))]
+    pub address_rules_file: Option<PathBuf>,
+    /// Other query settings profiles, given as `name=file`,
+    /// selected by the requests with `_settings=name`.
+    #[structopt(long = "settings-profile", parse(try_from_str = parse_settings_profile))]
+    pub settings_profiles: Vec<(String, String)>,
+    /// Profiles among which the requests without `_settings` are randomly assigned (for an A/B
+    /// test), given as `name=weight`. The requests use the default profile if not given.
+    #[structopt(long = "settings-ab-test", parse(try_from_str = parse_settings_ab_test))]
+    pub settings_ab_test: Vec<(String, f64)>,
+    /// Number of chunks of the /batch requests geocoded at the same time (by all the requests
+    /// together), by default the number of cpus.
+    #[structopt(long = "batch-nb-workers", env = "BRAGI_BATCH_NB_WORKERS")]
+    pub batch_nb_workers: Option<usize>,
+    /// Number of rows of a /batch request sent to Elasticsearch in one request.
+    #[structopt(long = "batch-chunk-size", env = "BRAGI_BATCH_CHUNK_SIZE")]
+    pub batch_chunk_size: Option<usize>,
+    /// Directory of an embedded index (built by the importers with `--embedded-index`).
+    /// If given, /autocomplete, /reverse and /features use it instead of Elasticsearch.
+    #[structopt(
+        long = "embedded-index",
+        env = "BRAGI_EMBEDDED_INDEX",
+        parse(from_os_str)
+    )]
+    pub embedded_index: Option<PathBuf>,
+    /// File of the api keys of the clients (a toml file, cf. `auth_middleware`).
+    /// If given, the requests (except / and /status) must have an api key in their
+    /// `Authorization` header, and only see the datasets of their client.
+    #[structopt(
+        long = "api-keys-file",
+        env = "BRAGI_API_KEYS_FILE",
+        parse(from_os_str)
+    )]
+    pub api_keys_file: Option<PathBuf>,
+    /// Interval in seconds between two checks of the api keys file,
+    /// the api keys are reloaded when it is modified.
+    #[structopt(
+        long = "api-keys-check-interval",
+        env = "BRAGI_API_KEYS_CHECK_INTERVAL",
+        default_value = "10"
+    )]
+    pub api_keys_check_interval: u64,
 }
 
 #[derive(Clone, Debug)]
 pub struct Context {
-    reverse_rubber: Rubber,
-    features_rubber: Rubber,
-    autocomplete_rubber: Rubber,
+    es_client: EsClient,
+    reverse_timeout: Option<Duration>,
+    features_timeout: Option<Duration>,
+    autocomplete_timeout: Option<Duration>,
     pub cnx_string: String,
     pub http_cache_duration: u32,
+    pub batch_nb_workers: usize,
+    pub batch_chunk_size: usize,
+    /// bounds the chunks of all the batches geocoded at the same time
+    batch_permits: Arc<Semaphore>,
+    embedded: Option<EmbeddedBackend>,
+    cache: Option<Arc<ResponseCache>>,
+    api_keys: Option<Arc<ApiKeys>>,
     // pub rubber: Rubber,
-    query_settings: QuerySettings,
+    query_settings: Arc<QuerySettingsProfiles>,
+    query_parser: Arc<QueryParser>,
+}
+
+fn reload_query_settings(
+    profiles: &QuerySettingsProfiles,
+    cache: Option<&ResponseCache>,
+) -> Result<Vec<String>, String> {
+    let names = profiles.reload()?;
+    if let Some(cache) = cache {
+        cache.clear();
+    }
+    Ok(names)
+}
+
+fn parse_settings_profile(arg: &str) -> Result<(String, String), String> {
+    let mut parts = arg.splitn(2, '=');
+    match (parts.next(), parts.next()) {
+        (Some(name), Some(file)) if !name.is_empty() => Ok((name.to_owned(), file.to_owned())),
+        _ => Err(format!("`{}` should be `name=file`", arg)),
+    }
+}
+
+fn parse_settings_ab_test(arg: &str) -> Result<(String, f64), String> {
+    let (name, weight) =
+        parse_settings_profile(arg).map_err(|_| format!("`{}` should be `name=weight`", arg))?;
+    let weight = weight
+        .parse()
+        .map_err(|e| format!("invalid weight in `{}`: {}", arg, e))?;
+    Ok((name, weight))
+}
+
+/// Watch the indexes published on `backend`: the cache is cleared and the admins known by
+/// the query parser are read again when they change.
+fn watch_indexes<B>(
+    backend: B,
+    cache: Option<&Arc<ResponseCache>>,
+    query_parser: &Arc<QueryParser>,
+    args: &Args,
+) where
+    B: SearchBackend + Clone + Send + 'static,
+{
+    if let Some(cache) = cache {
+        let interval = Duration::from_secs(args.cache_check_interval);
+        cache::watch_aliases(cache, backend.clone(), interval);
+    }
+    let interval = Duration::from_secs(args.admins_check_interval.max(1));
+    query_parser::watch_admins(query_parser, backend, interval);
 }
 
 impl TryFrom<&Args> for Context {
@@ -140,62 +280,125 @@ impl TryFrom<&Args> for Context {
                 .or(max_es_timeout)
         };
 
-        let content = match args.weight_config_file {
-            Some(ref file_path) => read_to_string(&file_path)
-                .map_err(|e| format!("Failed to read `{}`: {}", file_path, e))?,
-            None => include_str!("../../../config/bragi-settings.toml").to_owned(),
+        let embedded = args
+            .embedded_index
+            .as_ref()
+            .map(EmbeddedBackend::open)
+            .transpose()
+            .map_err(|err| format!("failed to open the embedded index: {}", err))?;
+        let cache = if args.cache_size > 0 {
+            Some(Arc::new(ResponseCache::new(
+                args.cache_size,
+                Duration::from_secs(args.cache_ttl),
+            )))
+        } else {
+            None
         };
-        Ok(Self {
-            reverse_rubber: Rubber::new_with_timeout(
-                &args.connection_string,
-                bounded_timeout(args.max_es_reverse_timeout),
-            ),
-            features_rubber: Rubber::new_with_timeout(
-                &args.connection_string,
-                bounded_timeout(args.max_es_features_timeout),
+        let query_parser = Arc::new(QueryParser::new(args.address_rules_file.as_deref())?);
+        match (&embedded, args.backend) {
+            (Some(embedded), _) => {
+                watch_indexes(embedded.clone(), cache.as_ref(), &query_parser, args)
+            }
+            (None, BackendKind::Es2) => watch_indexes(
+                Rubber::new_with_timeout(&args.connection_string, max_es_timeout),
+                cache.as_ref(),
+                &query_parser,
+                args,
             ),
-            autocomplete_rubber: Rubber::new_with_timeout(
-                &args.connection_string,
-                bounded_timeout(args.max_es_autocomplete_timeout),
+            (None, BackendKind::Rest) => watch_indexes(
+                RestBackend::new_with_timeout(&args.connection_string, max_es_timeout)
+                    .map_err(|err| err.to_string())?,
+                cache.as_ref(),
+                &query_parser,
+                args,
             ),
+        }
+        let api_keys = match args.api_keys_file {
+            Some(ref path) => {
+                let api_keys = Arc::new(ApiKeys::load(path)?);
+                auth_middleware::watch_api_keys(
+                    &api_keys,
+                    Duration::from_secs(args.api_keys_check_interval.max(1)),
+                );
+                Some(api_keys)
+            }
+            None => None,
+        };
+        let batch_nb_workers = args.batch_nb_workers.unwrap_or_else(num_cpus::get).max(1);
+        Ok(Self {
+            es_client: EsClient::new(&args.connection_string, args.backend)?,
+            reverse_timeout: bounded_timeout(args.max_es_reverse_timeout),
+            features_timeout: bounded_timeout(args.max_es_features_timeout),
+            autocomplete_timeout: bounded_timeout(args.max_es_autocomplete_timeout),
             cnx_string: args.connection_string.clone(),
             http_cache_duration: args.http_cache_duration,
-            query_settings: QuerySettings::new(&content).map_err(|err| {
-                format!(
-                    "failed to parse `{}`: {}",
-                    args.weight_config_file
-                        .as_deref()
-                        .unwrap_or("config/bragi-settings.toml"),
-                    err
-                )
-            })?,
+            batch_nb_workers,
+            batch_chunk_size: args.batch_chunk_size.unwrap_or(100).max(1),
+            batch_permits: Arc::new(Semaphore::new(batch_nb_workers)),
+            embedded,
+            cache,
+            api_keys,
+            query_settings: Arc::new(QuerySettingsProfiles::new(
+                args.weight_config_file.as_deref(),
+                &args.settings_profiles,
+                &args.settings_ab_test,
+            )?),
+            query_parser,
         })
     }
 }
 
 impl Context {
-    pub fn get_rubber_for_reverse(&self, timeout: Option<Duration>) -> Rubber {
-        clone_or_create(&self.reverse_rubber, timeout)
+    /// The non-blocking elasticsearch clients use the timeout of the request if given,
+    /// else the timeout of the route.
+    pub fn get_es_client_for_reverse(&self, timeout: Option<Duration>) -> EsClient {
+        self.es_client
+            .with_timeout(timeout.or(self.reverse_timeout))
     }
-    pub fn get_rubber_for_features(&self, timeout: Option<Duration>) -> Rubber {
-        clone_or_create(&self.features_rubber, timeout)
+    pub fn get_es_client_for_features(&self, timeout: Option<Duration>) -> EsClient {
+        self.es_client
+            .with_timeout(timeout.or(self.features_timeout))
     }
-    pub fn get_rubber_for_autocomplete(&self, timeout: Option<Duration>) -> Rubber {
-        clone_or_create(&self.autocomplete_rubber, timeout)
+    pub fn get_es_client_for_autocomplete(&self, timeout: Option<Duration>) -> EsClient {
+        self.es_client
+            .with_timeout(timeout.or(self.autocomplete_timeout))
     }
-    pub fn get_query_settings(&self) -> &QuerySettings {
+    /// The permits of the chunks of the batches, shared by all the requests
+    pub fn get_batch_permits(&self) -> &Arc<Semaphore> {
+        &self.batch_permits
+    }
+    /// The embedded index, if bragi does not use Elasticsearch
+    pub fn get_embedded(&self) -> Option<&EmbeddedBackend> {
+        self.embedded.as_ref()
+    }
+    /// The query settings of the profile `name` (or of the profile assigned to the request
+    /// if not given), with the name of the profile
+    pub fn get_query_settings(
+        &self,
+        name: Option<&str>,
+    ) -> Result<(String, Arc<QuerySettings>), model::BragiError> {
+        self.query_settings
+            .get(name)
+            .ok_or(model::BragiError::InvalidParam("unknown _settings profile"))
+    }
+    pub fn get_query_settings_profiles(&self) -> &Arc<QuerySettingsProfiles> {
         &self.query_settings
     }
-}
-
-fn clone_or_create(rubber: &Rubber, timeout: Option<Duration>) -> Rubber {
-    if rubber.timeout == timeout {
-        // we clone the rs_es_client, reusing the reqwest connection pool
-        rubber.clone()
-    } else {
-        // if the timeout is different, since there as no easy way to change the timeout for the moment
-        // we build a new Rubber (and thus a new connection)
-        debug!("creating a new rubber for timeout {:?}", &timeout);
-        Rubber::new_with_timeout(&rubber.cnx_string, timeout)
+    /// Reload the query settings profiles from their files, the responses of the cache
+    /// being dropped since they have been ranked with the previous settings
+    pub fn reload_query_settings(&self) -> Result<Vec<String>, String> {
+        reload_query_settings(&self.query_settings, self.cache.as_deref())
+    }
+    /// The parser of the addresses of the queries
+    pub fn get_query_parser(&self) -> &Arc<QueryParser> {
+        &self.query_parser
+    }
+    /// The in-process cache of the responses, if enabled
+    pub fn get_cache(&self) -> Option<&Arc<ResponseCache>> {
+        self.cache.as_ref()
+    }
+    /// The api keys of the clients, if the authentication is enabled
+    pub fn get_api_keys(&self) -> Option<&Arc<ApiKeys>> {
+        self.api_keys.as_ref()
     }
 }
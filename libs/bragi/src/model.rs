@@ -28,7 +28,12 @@
 // https://groups.google.com/d/forum/navitia
 // www.navitia.io
 
+use crate::coord_parser::CoordLiteral;
+use crate::es_client::EsClientError;
+use crate::query_parser::ParsedQuery;
+use crate::query_settings::MatchType;
 use failure::Fail;
+use futures::Future;
 use heck::SnakeCase;
 use rs_es::error::EsError;
 use serde::{Deserialize, Serialize};
@@ -45,6 +50,18 @@ pub enum BragiError {
     Es(EsError),
     #[fail(display = "invalid shape: {}", _0)]
     InvalidShape(&'static str),
+    #[fail(display = "backend error: {}", _0)]
+    Backend(failure::Error),
+    #[fail(display = "{}", _0)]
+    EsClient(EsClientError),
+    #[fail(display = "Unauthorized: {}", _0)]
+    Unauthorized(&'static str),
+    #[fail(display = "Forbidden: {}", _0)]
+    Forbidden(&'static str),
+    #[fail(display = "Too many requests, the rate limit of the api key is exceeded")]
+    TooManyRequests,
+    #[fail(display = "invalid query settings: {}", _0)]
+    InvalidSettings(String),
 }
 
 #[derive(Deserialize, Serialize, Debug)]
@@ -70,6 +87,27 @@ impl actix_web::error::ResponseError for BragiError {
                 short: "validation error".to_owned(),
                 long: format!("{}", self),
             }),
+            BragiError::Unauthorized(_) => actix_web::HttpResponse::Unauthorized().json(ApiError {
+                short: "authentication error".to_owned(),
+                long: format!("{}", self),
+            }),
+            BragiError::Forbidden(_) => actix_web::HttpResponse::Forbidden().json(ApiError {
+                short: "authorization error".to_owned(),
+                long: format!("{}", self),
+            }),
+            BragiError::TooManyRequests => actix_web::HttpResponse::TooManyRequests()
+                .header(actix_web::http::header::RETRY_AFTER, "1")
+                .json(ApiError {
+                    short: "rate limit error".to_owned(),
+                    long: format!("{}", self),
+                }),
+            BragiError::InvalidSettings(ref err) => {
+                error!("impossible to reload the query settings: {}", err);
+                actix_web::HttpResponse::InternalServerError().json(ApiError {
+                    short: "settings error".to_owned(),
+                    long: format!("{}", self),
+                })
+            }
             BragiError::Es(ref es_error) => {
                 error!("es error on query: {}", &es_error);
                 match es_error {
@@ -85,20 +123,69 @@ impl actix_web::error::ResponseError for BragiError {
                     }),
                 }
             }
+            BragiError::EsClient(ref es_error) => {
+                error!("es error on query: {}", &es_error);
+                match es_error {
+                    EsClientError::Unavailable(_) => actix_web::HttpResponse::ServiceUnavailable()
+                        .json(ApiError {
+                            short: "query error".to_owned(),
+                            long: "service unavailable".to_owned(),
+                        }),
+                    EsClientError::InvalidResponse(_) => {
+                        actix_web::HttpResponse::InternalServerError().json(ApiError {
+                            short: "query error".to_owned(),
+                            long: "internal server error".to_owned(),
+                        })
+                    }
+                }
+            }
+            BragiError::Backend(ref err) => {
+                error!("backend error on query: {}", err);
+                actix_web::HttpResponse::InternalServerError().json(ApiError {
+                    short: "query error".to_owned(),
+                    long: "internal server error".to_owned(),
+                })
+            }
         }
     }
 }
 
+pub type BragiFuture<T> = Box<dyn Future<Item = T, Error = BragiError>>;
+
 impl From<EsError> for BragiError {
     fn from(e: EsError) -> Self {
         BragiError::Es(e)
     }
 }
 
+impl From<EsClientError> for BragiError {
+    fn from(e: EsClientError) -> Self {
+        BragiError::EsClient(e)
+    }
+}
+
+impl From<failure::Error> for BragiError {
+    fn from(e: failure::Error) -> Self {
+        // the errors of elasticsearch are kept to answer a 503 when it is unavailable
+        match e.downcast::<EsError>() {
+            Ok(es_error) => BragiError::Es(es_error),
+            Err(e) => BragiError::Backend(e),
+        }
+    }
+}
+
 #[derive(Serialize, Debug)]
 pub struct Geocoding {
     version: String,
     query: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    match_stage: Option<MatchType>,
+    /// The search has been stopped by the timeout of the request
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    partial: bool,
+    /// The components of the address recognized in the query, given with `_debug`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    parsed_query: Option<ParsedQuery>,
 }
 
 #[derive(Serialize, Debug)]
@@ -109,6 +196,9 @@ pub struct Feature {
     pub properties: Properties,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub distance: Option<u32>,
+    /// How well the place matches the query, between 0 and 1
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub confidence: Option<f64>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub context: Option<mimir::Context>,
 }
@@ -180,6 +270,9 @@ pub struct GeocodingResponse {
     pub housenumber: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub street: Option<String>,
+    /// The names of the crossing streets of an intersection
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub streets: Vec<String>,
     pub postcode: Option<String>,
     pub city: Option<String>,
     pub citycode: Option<String>,
@@ -220,6 +313,22 @@ pub struct GeocodingResponse {
     pub bbox: Option<geo_types::Rect<f64>>,
     #[serde(skip_serializing_if = "Vec::is_empty", default)]
     pub country_codes: Vec<String>,
+    /// true for an address whose position has been interpolated along its street
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub interpolated: Option<bool>,
+    // Details of the POIs
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub opening_hours: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub phone: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub website: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub wheelchair: Option<mimir::Wheelchair>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub brand: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub operator: Option<String>,
 }
 
 trait ToGeom {
@@ -234,6 +343,9 @@ impl ToGeom for mimir::Place {
             mimir::Place::Addr(ref addr) => addr.coord.to_geom(),
             mimir::Place::Poi(ref poi) => poi.coord.to_geom(),
             mimir::Place::Stop(ref stop) => stop.coord.to_geom(),
+            mimir::Place::Intersection(ref intersection) => intersection.coord.to_geom(),
+            mimir::Place::Postcode(ref postcode) => postcode.coord.to_geom(),
+            mimir::Place::Locality(ref locality) => locality.coord.to_geom(),
         }
     }
 }
@@ -248,6 +360,7 @@ impl FromWithLang<mimir::Place> for Feature {
     fn from_with_lang(other: mimir::Place, lang: Option<&str>) -> Feature {
         let geom = other.to_geom();
         let distance = other.distance();
+        let confidence = other.confidence();
         let context = other.context();
         let geocoding = match other {
             mimir::Place::Admin(admin) => GeocodingResponse::from_with_lang(admin, lang),
@@ -255,17 +368,45 @@ impl FromWithLang<mimir::Place> for Feature {
             mimir::Place::Addr(addr) => GeocodingResponse::from_with_lang(addr, lang),
             mimir::Place::Poi(poi) => GeocodingResponse::from_with_lang(poi, lang),
             mimir::Place::Stop(poi) => GeocodingResponse::from_with_lang(poi, lang),
+            mimir::Place::Intersection(intersection) => {
+                GeocodingResponse::from_with_lang(intersection, lang)
+            }
+            mimir::Place::Postcode(postcode) => GeocodingResponse::from_with_lang(postcode, lang),
+            mimir::Place::Locality(locality) => GeocodingResponse::from_with_lang(locality, lang),
         };
         Feature {
             feature_type: "Feature".to_string(),
             geometry: geom,
             properties: Properties { geocoding },
             distance,
+            confidence,
             context,
         }
     }
 }
 
+impl From<CoordLiteral> for Feature {
+    fn from(literal: CoordLiteral) -> Feature {
+        let coord = literal.coord;
+        Feature {
+            feature_type: "Feature".to_string(),
+            geometry: coord.to_geom(),
+            properties: Properties {
+                geocoding: GeocodingResponse {
+                    id: format!("coord:{};{}", coord.lon(), coord.lat()),
+                    place_type: "coordinate".to_string(),
+                    label: Some(literal.label.clone()),
+                    name: Some(literal.label),
+                    ..Default::default()
+                },
+            },
+            distance: None,
+            confidence: Some(1.),
+            context: None,
+        }
+    }
+}
+
 pub trait FromWithLang<T> {
     fn from_with_lang(_: T, lang: Option<&str>) -> Self;
 }
@@ -371,6 +512,87 @@ impl FromWithLang<mimir::Street> for GeocodingResponse {
     }
 }
 
+impl GeocodingResponse {
+    /// The response of a place located by its admins, its other fields being left to the caller
+    fn with_admins(
+        id: String,
+        place_type: &str,
+        zip_codes: &[String],
+        admins: &[Arc<mimir::Admin>],
+        lang: Option<&str>,
+    ) -> GeocodingResponse {
+        GeocodingResponse {
+            id,
+            place_type: place_type.to_string(),
+            postcode: if zip_codes.is_empty() {
+                None
+            } else {
+                Some(zip_codes.join(";"))
+            },
+            city: get_city_name(admins),
+            citycode: get_citycode(admins),
+            administrative_regions: admins
+                .iter()
+                .map(|a| AssociatedAdmin::from_with_lang(a, lang))
+                .collect(),
+            ..Default::default()
+        }
+    }
+}
+
+impl FromWithLang<mimir::Intersection> for GeocodingResponse {
+    fn from_with_lang(other: mimir::Intersection, lang: Option<&str>) -> GeocodingResponse {
+        GeocodingResponse {
+            name: Some(other.name),
+            label: Some(other.label),
+            streets: other.street_names,
+            country_codes: other.country_codes,
+            ..GeocodingResponse::with_admins(
+                other.id,
+                "intersection",
+                &other.zip_codes,
+                &other.administrative_regions,
+                lang,
+            )
+        }
+    }
+}
+
+impl FromWithLang<mimir::Postcode> for GeocodingResponse {
+    fn from_with_lang(other: mimir::Postcode, lang: Option<&str>) -> GeocodingResponse {
+        GeocodingResponse {
+            postcode: Some(other.name.clone()),
+            name: Some(other.name),
+            label: Some(other.label),
+            country_codes: other.country_codes,
+            ..GeocodingResponse::with_admins(
+                other.id,
+                "postcode",
+                &[],
+                &other.administrative_regions,
+                lang,
+            )
+        }
+    }
+}
+
+impl FromWithLang<mimir::Locality> for GeocodingResponse {
+    fn from_with_lang(other: mimir::Locality, lang: Option<&str>) -> GeocodingResponse {
+        GeocodingResponse {
+            name: Some(other.name),
+            label: Some(other.label),
+            country_codes: other.country_codes,
+            ..GeocodingResponse::with_admins(
+                other.id,
+                "locality",
+                &other.zip_codes,
+                &other.administrative_regions,
+                lang,
+            )
+        }
+    }
+}
+
 impl FromWithLang<mimir::Addr> for GeocodingResponse {
     fn from_with_lang(other: mimir::Addr, lang: Option<&str>) -> GeocodingResponse {
         let type_ = "house".to_string();
@@ -404,6 +626,7 @@ impl FromWithLang<mimir::Addr> for GeocodingResponse {
             city,
             administrative_regions: associated_admins,
             country_codes: other.country_codes,
+            interpolated: if other.interpolated { Some(true) } else { None },
             ..Default::default()
         }
     }
@@ -457,6 +680,13 @@ impl FromWithLang<mimir::Poi> for GeocodingResponse {
                 _ => None,
             },
             country_codes: other.country_codes,
+            opening_hours: other.details.opening_hours,
+            phone: other.details.phone,
+            website: other.details.website,
+            wheelchair: other.details.wheelchair,
+            brand: other.details.brand,
+            operator: other.details.operator,
+            timezone: other.details.timezone,
             ..Default::default()
         }
     }
@@ -520,10 +750,31 @@ impl Autocomplete {
             geocoding: Geocoding {
                 version: "0.1.0".to_string(),
                 query: Some(q),
+                match_stage: None,
+                partial: false,
+                parsed_query: None,
             },
             features,
         }
     }
+
+    /// Report the stage of the search that found the features
+    pub fn with_match_stage(mut self, match_stage: MatchType) -> Autocomplete {
+        self.geocoding.match_stage = Some(match_stage);
+        self
+    }
+
+    /// Report that the search has not been completed, there could be more features
+    pub fn with_partial(mut self, partial: bool) -> Autocomplete {
+        self.geocoding.partial = partial;
+        self
+    }
+
+    /// Report the components of the address recognized in the query
+    pub fn with_parsed_query(mut self, parsed_query: ParsedQuery) -> Autocomplete {
+        self.geocoding.parsed_query = Some(parsed_query);
+        self
+    }
 }
 
 impl FromWithLang<Vec<mimir::Place>> for Autocomplete {
@@ -8,7 +8,7 @@ use actix_web::{
     dev::{Body, BodySize, MessageBody, ResponseBody, ServiceRequest, ServiceResponse},
     http::{header, Method, StatusCode},
     web::Bytes,
-    Error,
+    Error, HttpRequest,
 };
 use futures::future::{ok, FutureResult};
 use futures::{Async, Future, Poll};
@@ -51,14 +51,14 @@ lazy_static::lazy_static! {
     static ref HTTP_COUNTER: prometheus::CounterVec = prometheus::register_counter_vec!(
         "bragi_http_requests_total",
         "Total number of HTTP requests made.",
-        &["handler", "method", "status"]
+        &["handler", "method", "status", "settings"]
     )
     .unwrap();
 
     static ref HTTP_REQ_HISTOGRAM: prometheus::HistogramVec = prometheus::register_histogram_vec!(
         "bragi_http_request_duration_seconds",
         "The HTTP request latencies in seconds.",
-        &["handler", "method"],
+        &["handler", "method", "settings"],
         prometheus::exponential_buckets(0.001, 1.5, 25).unwrap()
     )
     .unwrap();
@@ -68,6 +68,29 @@ lazy_static::lazy_static! {
         "current number of http request being served"
     )
     .unwrap();
+
+    pub(crate) static ref CACHE_HITS: prometheus::CounterVec = prometheus::register_counter_vec!(
+        "bragi_cache_hits_total",
+        "Total number of responses served from the response cache.",
+        &["handler"]
+    )
+    .unwrap();
+
+    pub(crate) static ref CACHE_MISSES: prometheus::CounterVec = prometheus::register_counter_vec!(
+        "bragi_cache_misses_total",
+        "Total number of responses not found in the response cache.",
+        &["handler"]
+    )
+    .unwrap();
+}
+
+/// The query settings profile used by a request, added as a label of its metrics
+pub(crate) struct SettingsProfile(pub String);
+
+/// Label the metrics of the request with the query settings `profile`
+pub(crate) fn set_settings_profile(req: &HttpRequest, profile: &str) {
+    req.extensions_mut()
+        .insert(SettingsProfile(profile.to_owned()));
 }
 
 #[derive(Clone)]
@@ -112,6 +135,7 @@ impl PrometheusMetrics {
         handler: &str,
         method: &Method,
         status: StatusCode,
+        settings: &str,
         clock: SystemTime,
     ) {
         let method = method.to_string();
@@ -121,12 +145,12 @@ impl PrometheusMetrics {
             let duration =
                 (elapsed.as_secs() as f64) + f64::from(elapsed.subsec_nanos()) / 1_000_000_000_f64;
             HTTP_REQ_HISTOGRAM
-                .with_label_values(&[&handler, &method])
+                .with_label_values(&[&handler, &method, settings])
                 .observe(duration);
         }
 
         HTTP_COUNTER
-            .with_label_values(&[&handler, &method, &status])
+            .with_label_values(&[&handler, &method, &status, settings])
             .inc();
 
         HTTP_IN_FLIGHT.dec();
@@ -216,6 +240,11 @@ where
         let method = req.method().clone();
         let path = req.path().to_string();
         let handler = get_ressource_name(&path);
+        let settings = req
+            .extensions()
+            .get::<SettingsProfile>()
+            .map(|profile| profile.0.clone())
+            .unwrap_or_default();
 
         Ok(Async::Ready(res.map_body(move |mut head, mut body| {
             // We short circuit the response status and body to serve the endpoint
@@ -238,6 +267,7 @@ where
                 status: head.status,
                 handler,
                 method,
+                settings,
             })
         })))
     }
@@ -252,13 +282,19 @@ pub struct StreamLog<B> {
     status: StatusCode,
     handler: String,
     method: Method,
+    settings: String,
 }
 
 impl<B> Drop for StreamLog<B> {
     fn drop(&mut self) {
         // update the metrics for this request at the very end of responding
-        self.inner
-            .update_metrics(&self.handler, &self.method, self.status, self.clock);
+        self.inner.update_metrics(
+            &self.handler,
+            &self.method,
+            self.status,
+            &self.settings,
+            self.clock,
+        );
     }
 }
 
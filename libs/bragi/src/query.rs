@@ -27,20 +27,35 @@
 // IRC #navitia on freenode
 // https://groups.google.com/d/forum/navitia
 // www.navitia.io
-use super::model::{self, BragiError};
-use crate::query_settings::{BuildWeight, Proximity, QuerySettings, Types};
+use super::model::{self, BragiError, BragiFuture};
+use crate::es_client::{EsClient, EsFuture};
+use crate::query_parser::{ParsedQuery, QueryParser};
+use crate::query_settings::{
+    BuildWeight, Gaussian, MatchType, MergePolicy, Proximity, QuerySettings, Stage, Types,
+};
+use chrono::{DateTime, Utc};
+use futures::future::{self, Loop};
+use futures::Future;
+use geo::algorithm::haversine_distance::HaversineDistance;
+use geo_types::Rect;
 use geojson::Geometry;
-use mimir::objects::{Addr, Admin, Coord, MimirObject, PlaceDocType, Poi, Stop, Street};
-use mimir::rubber::{get_indexes, read_places, Rubber};
+use mimir::backend::SearchBackend;
+use mimir::embedded::{trigrams, words, EmbeddedBackend};
+use mimir::interpolation::parse_interpolated_addr_id;
+use mimir::objects::{
+    Addr, Admin, Coord, Intersection, Locality, MimirObject, PlaceDocType, Poi, Postcode, Stop,
+    Street, Wheelchair,
+};
+use mimir::rubber::{get_indexes, get_main_type_index, with_distance, MultiSearch};
 use prometheus::{self, exponential_buckets, histogram_opts, register_histogram_vec, HistogramVec};
-use rs_es::error::EsError;
-use rs_es::operations::search::Source;
 use rs_es::query::compound::BoostMode;
 use rs_es::query::functions::{DecayOptions, FilteredFunction, Function, Modifier};
 use rs_es::query::Query;
 use rs_es::units as rs_u;
 use slog_scope::{debug, error, info, warn};
-use std::{fmt, iter};
+use std::iter;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
 
 lazy_static::lazy_static! {
     static ref ES_REQ_HISTOGRAM: HistogramVec = register_histogram_vec!(
@@ -71,6 +86,9 @@ pub fn make_place(doc_type: String, value: Option<Box<serde_json::Value>>) -> Op
             "admin" => convert(*v, mimir::Place::Admin),
             "poi" => convert(*v, mimir::Place::Poi),
             "stop" => convert(*v, mimir::Place::Stop),
+            "intersection" => convert(*v, mimir::Place::Intersection),
+            "postcode" => convert(*v, mimir::Place::Postcode),
+            "locality" => convert(*v, mimir::Place::Locality),
             _ => {
                 warn!("unknown ES return value, _type field = {}", doc_type);
                 None
@@ -79,20 +97,59 @@ pub fn make_place(doc_type: String, value: Option<Box<serde_json::Value>>) -> Op
     })
 }
 
-#[derive(Clone, Copy, Debug, Eq, PartialEq)]
-enum MatchType {
-    Prefix,
-    Fuzzy,
+/// The part of the words of `q` found in `label`, the last word of `q` possibly being
+/// the beginning of a word of the label.
+///
+/// A word not found (misspelt for instance) only counts for its similarity with the closest
+/// word of the label, the part of their trigrams in common.
+fn label_coverage(q: &str, label: &str) -> f64 {
+    let query_words = words(q);
+    if query_words.is_empty() {
+        return 0.;
+    }
+    let label_words = words(label);
+    let label_trigrams = label_words
+        .iter()
+        .map(|w| trigrams(&[w.clone()]))
+        .collect::<Vec<_>>();
+    let last = query_words.len() - 1;
+    let covered: f64 = query_words
+        .iter()
+        .enumerate()
+        .map(|(i, word)| {
+            if label_words
+                .iter()
+                .any(|l| l == word || (i == last && l.starts_with(word.as_str())))
+            {
+                return 1.;
+            }
+            let word_trigrams = trigrams(&[word.clone()]);
+            label_trigrams
+                .iter()
+                .map(|l| {
+                    let common = word_trigrams.intersection(l).count();
+                    2. * common as f64 / (word_trigrams.len() + l.len()) as f64
+                })
+                .fold(0., f64::max)
+        })
+        .sum();
+    covered / query_words.len() as f64
 }
 
-impl fmt::Display for MatchType {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let printable = match *self {
-            MatchType::Prefix => "prefix",
-            MatchType::Fuzzy => "fuzzy",
-        };
-        write!(f, "{}", printable)
-    }
+/// Set the confidence of the places found for `q`, between 0 and 1.
+///
+/// It is the part of `q` covered by the label of the place, lowered (by half at most) for the
+/// places that have a lower score than the best place found.
+fn with_confidences(q: &str, places: Vec<mimir::Place>) -> Vec<mimir::Place> {
+    places
+        .into_iter()
+        .map(|mut place| {
+            let relative_score = place.confidence().unwrap_or(1.);
+            let confidence = label_coverage(q, place.label()) * (0.5 + 0.5 * relative_score);
+            place.set_confidence((confidence * 1000.).round() / 1000.);
+            place
+        })
+        .collect()
 }
 
 // filter to handle PT coverages
@@ -166,41 +223,193 @@ fn build_with_weight(build_weight: &BuildWeight, types: &Types) -> Query {
             weighted(Admin::doc_type(), types.admin),
             weighted(Poi::doc_type(), types.poi),
             weighted(Street::doc_type(), types.street),
+            weighted(Intersection::doc_type(), types.intersection),
+            weighted(Postcode::doc_type(), types.postcode),
+            weighted(Locality::doc_type(), types.locality),
         ])
         .with_boost_mode(BoostMode::Replace)
         .build()
 }
 
+/// Create a `rs_es::Query` that prioritizes the documents according to their type.
+fn build_type_query(query_settings: &QuerySettings) -> Query {
+    fn match_type_with_boost<T: MimirObject>(boost: f64) -> Query {
+        Query::build_term("_type", T::doc_type())
+            .with_boost(boost)
+            .build()
+    }
+    Query::build_bool()
+        .with_should(vec![
+            match_type_with_boost::<Addr>(query_settings.type_query.boosts.address),
+            match_type_with_boost::<Admin>(query_settings.type_query.boosts.admin),
+            match_type_with_boost::<Stop>(query_settings.type_query.boosts.stop),
+            match_type_with_boost::<Poi>(query_settings.type_query.boosts.poi),
+            match_type_with_boost::<Street>(query_settings.type_query.boosts.street),
+            match_type_with_boost::<Intersection>(query_settings.type_query.boosts.intersection),
+            match_type_with_boost::<Postcode>(query_settings.type_query.boosts.postcode),
+            match_type_with_boost::<Locality>(query_settings.type_query.boosts.locality),
+        ])
+        .with_boost(query_settings.type_query.global)
+        .build()
+}
+
+/// A bounding box sent with the query, like the viewport of a map
+#[derive(Debug, Clone, Copy)]
+pub enum BoundingBox {
+    /// Only the places inside the box are returned
+    Filter(Rect<f64>),
+    /// The places inside the box are favored
+    Bias(Rect<f64>),
+}
+
+fn rect_center(rect: &Rect<f64>) -> Coord {
+    let (min, max) = (rect.min(), rect.max());
+    Coord::new((min.x + max.x) / 2., (min.y + max.y) / 2.)
+}
+
+fn rect_contains(rect: &Rect<f64>, coord: &Coord) -> bool {
+    let (min, max) = (rect.min(), rect.max());
+    (min.x..=max.x).contains(&coord.lon()) && (min.y..=max.y).contains(&coord.lat())
+}
+
+fn rect_to_geometry(rect: &Rect<f64>) -> Geometry {
+    let (min, max) = (rect.min(), rect.max());
+    Geometry::new(geojson::Value::Polygon(vec![vec![
+        vec![min.x, min.y],
+        vec![max.x, min.y],
+        vec![max.x, max.y],
+        vec![min.x, max.y],
+        vec![min.x, min.y],
+    ]]))
+}
+
+/// The center of `rect` and the proximity decay around it used as a bias for the places in
+/// `rect`: the places inside the circle around `rect` are favored the same way, and the
+/// score then decays at the scale of `rect`.
+fn build_bbox_bias(rect: &Rect<f64>, proximity: &Proximity) -> (Coord, Proximity) {
+    let center = rect_center(rect);
+    let center_point: geo_types::Point<f64> = center.0.into();
+    let radius = center_point.haversine_distance(&rect.min().into()) / 1000.;
+    let gaussian = Gaussian {
+        scale: radius,
+        offset: radius,
+        ..proximity.gaussian
+    };
+    (
+        center,
+        Proximity {
+            gaussian,
+            ..*proximity
+        },
+    )
+}
+
+/// Restrict the places of the types of `shape_scope` with `filter`,
+/// the places of the other types are not restricted.
+/// So if shape_scope = {A, B}, we should end up with something like
+/// should {
+///   must [               => filter_w_shape
+///     should [
+///       term _type = A
+///       term _type = B
+///     ]
+///     filter
+///  ],
+///  must_not [            => filter_wo_shape
+///    term _type = A
+///    term _type = B
+///  ]
+fn build_scoped_filter(filter: Query, shape_scope: &[PlaceDocType]) -> Query {
+    let filter_w_shape_term = Query::build_bool()
+        .with_should(
+            shape_scope
+                .iter()
+                .map(PlaceDocType::as_str)
+                .map(|x| Query::build_term("_type", x).build())
+                .collect::<Vec<_>>(),
+        )
+        .build();
+    let filter_w_shape = Query::build_bool()
+        .with_must(vec![filter_w_shape_term, filter])
+        .build();
+    let filter_wo_shape = shape_scope
+        .iter()
+        .map(PlaceDocType::as_str)
+        .map(|x| Query::build_term("_type", x).build())
+        .collect::<Vec<_>>();
+    let filter_wo_shape = Query::build_bool().with_must_not(filter_wo_shape).build();
+
+    Query::build_bool()
+        .with_should(vec![filter_wo_shape, filter_w_shape])
+        .build()
+}
+
+/// The places inside one of the admins `within`, as well as these admins themselves.
+///
+/// The admins of a place are found when it is imported, so there is no need to look up their
+/// boundaries here. The addresses have no admins of their own, they are the ones of their street.
+fn build_within_filter(within: &[&str]) -> Query {
+    Query::build_bool()
+        .with_should(vec![
+            Query::build_terms("id").with_values(within).build(),
+            Query::build_terms("administrative_regions.id")
+                .with_values(within)
+                .build(),
+            Query::build_terms("street.administrative_regions.id")
+                .with_values(within)
+                .build(),
+        ])
+        .build()
+}
+
+/// Same as `build_within_filter` for a place already loaded.
+fn is_within(place: &mimir::Place, within: &[&str]) -> bool {
+    within.contains(&place.id())
+        || place
+            .admins()
+            .iter()
+            .any(|a| within.contains(&a.id.as_str()))
+}
+
 #[allow(clippy::too_many_arguments)]
 fn build_query<'a>(
     q: &str,
+    parsed_query: &ParsedQuery,
     match_type: MatchType,
     coord: Option<Coord>,
     shape: Option<Geometry>,
     shape_scope: &[PlaceDocType],
+    bbox: Option<&BoundingBox>,
+    within: &[&str],
     pt_datasets: &[&str],
     all_data: bool,
     langs: &'a [&'a str],
     zone_types: &[&str],
     poi_types: &[&str],
+    poi_filters: &PoiFilters,
     query_settings: &QuerySettings,
 ) -> Query {
+    // An intersection is matched without the separator of its streets
+    let intersection_text = parsed_query.intersection_text();
+    let q = intersection_text.as_deref().unwrap_or(q);
+
     // Priorization by type
-    fn match_type_with_boost<T: MimirObject>(boost: f64) -> Query {
-        Query::build_term("_type", T::doc_type())
-            .with_boost(boost)
-            .build()
-    }
-    let type_query = Query::build_bool()
-        .with_should(vec![
-            match_type_with_boost::<Addr>(query_settings.type_query.boosts.address),
-            match_type_with_boost::<Admin>(query_settings.type_query.boosts.admin),
-            match_type_with_boost::<Stop>(query_settings.type_query.boosts.stop),
-            match_type_with_boost::<Poi>(query_settings.type_query.boosts.poi),
-            match_type_with_boost::<Street>(query_settings.type_query.boosts.street),
-        ])
-        .with_boost(query_settings.type_query.global)
-        .build();
+    let type_query = build_type_query(query_settings);
+
+    // The places near the position of the request are favored, and a bounding box used as
+    // a bias acts like a second position at its center
+    let bbox_bias = match bbox {
+        Some(BoundingBox::Bias(rect)) => Some(build_bbox_bias(
+            rect,
+            &query_settings.importance_query.proximity,
+        )),
+        _ => None,
+    };
+    let proximities = coord
+        .map(|coord| (coord, query_settings.importance_query.proximity))
+        .into_iter()
+        .chain(bbox_bias)
+        .collect::<Vec<_>>();
 
     let format_names_field = |lang| format!("names.{}", lang);
     let format_labels_field = |lang| format!("labels.{}", lang);
@@ -233,9 +442,37 @@ fn build_query<'a>(
             .with_boost(query_settings.string_query.boosts.house_number)
             .build(),
     ];
+    // The components recognized in the query are matched against their own fields
+    if let Some(street) = parsed_query.street() {
+        string_should.push(
+            Query::build_multi_match(vec!["name".to_string(), "street.name".to_string()], street)
+                .with_boost(query_settings.string_query.boosts.street)
+                .build(),
+        );
+    }
+    for street in &parsed_query.intersection {
+        string_should.push(
+            Query::build_match("street_names", street.as_str())
+                .with_boost(query_settings.string_query.boosts.street)
+                .build(),
+        );
+    }
+    if let Some(city) = &parsed_query.city {
+        string_should.push(
+            Query::build_multi_match(
+                vec![
+                    "administrative_regions.name".to_string(),
+                    "street.administrative_regions.name".to_string(),
+                ],
+                city.as_str(),
+            )
+            .with_boost(query_settings.string_query.boosts.city)
+            .build(),
+        );
+    }
     if let MatchType::Fuzzy = match_type {
         let format_labels_ngram_field = |lang| format!("labels.{}.ngram", lang);
-        string_should.push(if coord.is_some() {
+        string_should.push(if !proximities.is_empty() {
             build_multi_match("label.ngram", &format_labels_ngram_field)
                 .with_boost(query_settings.string_query.boosts.label_ngram_with_coord)
                 .build()
@@ -254,7 +491,7 @@ fn build_query<'a>(
 
     // Weights for minimal radius
     let min_weights = match match_type {
-        MatchType::Prefix => settings.min_radius_prefix,
+        MatchType::Exact | MatchType::Prefix => settings.min_radius_prefix,
         MatchType::Fuzzy => settings.min_radius_fuzzy,
     };
 
@@ -262,12 +499,12 @@ fn build_query<'a>(
     let max_weights = settings.max_radius;
 
     // Compute a linear combination of `min_weights` and `max_weights` depending of
-    // the level of zoom.
-    let zoom_ratio = match coord {
+    // the level of zoom (given by the position of the request if there is one).
+    let zoom_ratio = match proximities.first() {
         None => 1.,
-        Some(_) => {
+        Some((_, proximity)) => {
             let (min_radius, max_radius) = settings.radius_range;
-            let curve = query_settings.importance_query.proximity.gaussian;
+            let curve = proximity.gaussian;
             let radius = (curve.offset + curve.scale).min(max_radius).max(min_radius);
             (radius.ln_1p() - min_radius.ln_1p()) / (max_radius.ln_1p() - min_radius.ln_1p())
         }
@@ -286,16 +523,16 @@ fn build_query<'a>(
     // Priorization by importance
     let mut importance_queries = vec![build_with_weight(&weights, &settings.types)];
 
-    if let Some(ref coord) = coord {
+    for (coord, proximity) in &proximities {
         importance_queries.push(build_proximity_with_boost(
             coord,
-            &query_settings.importance_query.proximity,
+            proximity,
             match_type == MatchType::Fuzzy,
         ))
     }
 
     match match_type {
-        MatchType::Prefix => {
+        MatchType::Exact | MatchType::Prefix => {
             let admin_importance_query = Query::build_function_score()
                 .with_query(Query::build_term("_type", Admin::doc_type()).build())
                 .with_functions(vec![
@@ -321,20 +558,30 @@ fn build_query<'a>(
         MatchType::Fuzzy => {}
     };
 
+    // Filter the places having `field` on the value found in the query,
+    // the places without `field` are kept.
+    let missing_or_match = |field: &str, value: &str| {
+        Query::build_bool()
+            .with_should(vec![
+                Query::build_bool()
+                    .with_must_not(Query::build_exists(field).build())
+                    .build(),
+                Query::build_match(field.to_string(), value.to_string()).build(),
+            ])
+            .build()
+    };
+
     let house_number_condition = {
         if q.split_whitespace().count() > 1 {
             // Filter to handle house number.
             // We either want:
-            // * to exactly match the document house_number
+            // * to exactly match the document house_number (the one recognized in the query
+            //   if any, or else one of the words of the query)
             // * or that the document has no house_number
-            Query::build_bool()
-                .with_should(vec![
-                    Query::build_bool()
-                        .with_must_not(Query::build_exists("house_number").build())
-                        .build(),
-                    Query::build_match("house_number", q.to_string()).build(),
-                ])
-                .build()
+            missing_or_match(
+                "house_number",
+                parsed_query.house_number.as_deref().unwrap_or(q),
+            )
         } else {
             // If the query contains a single word, we don't exect any house number in the result.
             Query::build_bool()
@@ -347,6 +594,10 @@ fn build_query<'a>(
     use rs_es::query::MinimumShouldMatch;
 
     let matching_condition = match match_type {
+        // All the words must be found as they are in full_label.
+        MatchType::Exact => Query::build_match("full_label".to_string(), q.to_string())
+            .with_operator("and")
+            .build(),
         // When the match type is Prefix, we want to use every possible information even though
         // these are not present in label, for instance, the zip_code.
         // The field full_label contains all of them and will do the trick.
@@ -377,55 +628,51 @@ fn build_query<'a>(
 
     let mut filters = vec![house_number_condition, matching_condition];
 
+    // The postcode and the country recognized in the query restrict the places
+    if let Some(postcode) = &parsed_query.postcode {
+        filters.push(missing_or_match("zip_codes", postcode));
+    }
+    if let Some(country) = &parsed_query.country {
+        filters.push(missing_or_match("country_codes", country));
+    }
+
     // if searching through all data, no coverage filter
     if !all_data {
         filters.push(build_coverage_condition(pt_datasets));
     }
 
-    // If there is a shape, all the places listed in shape_scope are restricted to the shape.
-    // and the places that are not listed are not restricted.
-    // So if shape_scope = {A, B}, we should end up with something like
-    // should {
-    //   must [               => filwer_w_shape
-    //     should [
-    //       term _type = A
-    //       term _type = B
-    //     ]
-    //     geoshape
-    //  ],
-    //  must_not [            => filter_wo_shape
-    //    term _type = A
-    //    term _type = B
-    //  ]
-    //
-
     if let Some(s) = shape {
-        let filter_w_shape_term = Query::build_bool()
-            .with_should(
-                shape_scope
-                    .iter()
-                    .map(PlaceDocType::as_str)
-                    .map(|x| Query::build_term("_type", x).build())
-                    .collect::<Vec<_>>(),
-            )
-            .build();
-        let filter_w_shape_geo = Query::build_geo_shape("approx_coord")
+        let shape_filter = Query::build_geo_shape("approx_coord")
             .with_geojson(s)
             .build();
-        let filter_w_shape = Query::build_bool()
-            .with_must(vec![filter_w_shape_term, filter_w_shape_geo])
-            .build();
-        let filter_wo_shape = shape_scope
-            .iter()
-            .map(PlaceDocType::as_str)
-            .map(|x| Query::build_term("_type", x).build())
-            .collect::<Vec<_>>();
-        let filter_wo_shape = Query::build_bool().with_must_not(filter_wo_shape).build();
+        filters.push(build_scoped_filter(shape_filter, shape_scope));
+    }
 
-        let geo_filter = Query::build_bool()
-            .with_should(vec![filter_wo_shape, filter_w_shape])
+    // A bounding box used as a filter restricts the places of shape_scope like a shape,
+    // or all the places if there is no shape_scope.
+    if let Some(BoundingBox::Filter(rect)) = bbox {
+        let bbox_filter = Query::build_geo_shape("approx_coord")
+            .with_geojson(rect_to_geometry(rect))
             .build();
-        filters.push(geo_filter);
+        filters.push(if shape_scope.is_empty() {
+            bbox_filter
+        } else {
+            build_scoped_filter(bbox_filter, shape_scope)
+        });
+    }
+
+    // The admins given in `within` restrict the places the same way as a bounding box
+    if !within.is_empty() {
+        let within_filter = build_within_filter(within);
+        filters.push(if shape_scope.is_empty() {
+            within_filter
+        } else {
+            build_scoped_filter(within_filter, shape_scope)
+        });
+    }
+
+    if let Some(poi_filter) = poi_filters.build_filter() {
+        filters.push(poi_filter);
     }
 
     let mut query = Query::build_bool()
@@ -461,111 +708,194 @@ fn build_query<'a>(
     query.build()
 }
 
+/// Observe the duration of the elasticsearch `request` in the latency histogram of `search_type`
+fn timed<T: 'static>(search_type: &str, request: EsFuture<T>) -> EsFuture<T> {
+    let timer = ES_REQ_HISTOGRAM
+        .get_metric_with_label_values(&[search_type])
+        .map(|h| h.start_timer())
+        .map_err(
+            |err| error!("impossible to get ES_REQ_HISTOGRAM metrics"; "err" => err.to_string()),
+        )
+        .ok();
+    Box::new(request.then(move |res| {
+        if let Some(t) = timer {
+            t.observe_duration();
+        }
+        res
+    }))
+}
+
+/// Send the search `query` to elasticsearch on the given `indexes` and read the places found.
+///
+/// `search_type` is used as label of the ES request latency histogram and `trace` (the
+/// request id and the query string) is only used for logging.
+/// The places are returned with whether elasticsearch timed out (they are then partial).
 #[allow(clippy::too_many_arguments)]
-fn query(
-    q: &str,
-    pt_datasets: &[&str],
-    poi_datasets: &[&str],
-    all_data: bool,
-    rubber: &mut Rubber,
-    match_type: MatchType,
+fn send_query(
+    es_client: &EsClient,
+    indexes: &[&str],
+    query: &Query,
+    search_type: &str,
     offset: u64,
     limit: u64,
     coord: Option<Coord>,
-    shape: Option<Geometry>,
-    shape_scope: &[PlaceDocType],
-    types: &[&str],
-    zone_types: &[&str],
-    poi_types: &[&str],
-    langs: &[&str],
     debug: bool,
-    query_settings: &QuerySettings,
-    request_id: Option<&str>,
-) -> Result<Vec<mimir::Place>, EsError> {
-    if let Some(id) = request_id {
-        info!("query::autocomplete - enter - {} - ({})", id, q);
-    }
-
-    let query_type = match_type.to_string();
-    let query = build_query(
-        q,
-        match_type,
-        coord,
-        shape,
-        shape_scope,
-        pt_datasets,
-        all_data,
-        langs,
-        zone_types,
-        poi_types,
-        query_settings,
-    );
-
-    let indexes = get_indexes(all_data, &pt_datasets, &poi_datasets, types);
-    let indexes = indexes
-        .iter()
-        .map(|index| index.as_str())
-        .collect::<Vec<&str>>();
+    trace: Option<(&str, &str)>,
+) -> EsFuture<(Vec<mimir::Place>, bool)> {
     debug!("ES indexes: {:?}", indexes);
 
     if indexes.is_empty() {
-        // if there is no indexes, rs_es search with index "_all"
+        // if there is no indexes, elasticsearch would search on all the indexes
         // but we want to return empty response in this case.
-        return Ok(vec![]);
+        return Box::new(future::ok((vec![], false)));
     }
-    let timer = ES_REQ_HISTOGRAM
-        .get_metric_with_label_values(&[query_type.as_str()])
-        .map(|h| h.start_timer())
-        .map_err(
-            |err| error!("impossible to get ES_REQ_HISTOGRAM metrics"; "err" => err.to_string()),
-        )
-        .ok();
 
-    let timeout = rubber.timeout.map(|t| format!("{:?}", t));
-    let mut search_query = rubber.es_client.search_query();
-
-    let search_query = search_query
-        .with_ignore_unavailable(true)
-        .with_indexes(&indexes)
-        .with_query(&query)
-        .with_from(offset)
-        .with_size(limit)
-        // No need to fetch "boundary" as it's not used in the geocoding response
-        // and is very large in some documents (countries...)
-        .with_source(Source::exclude(&["boundary"]));
-
-    // We don't want to clutter the Query URL, so we only add an explanation if the option is used
-    let search_query = if debug {
-        search_query.with_explain(true)
-    } else {
-        search_query
-    };
-
-    if let Some(timeout) = &timeout {
-        search_query.with_timeout(timeout.as_str());
+    let mut body = es_client.search_body(query, offset, limit);
+    // We don't want to clutter the query, so we only add an explanation if the option is used
+    if debug {
+        body["explain"] = serde_json::Value::from(true);
     }
 
-    if let Some(id) = request_id {
+    let trace = trace.map(|(id, q)| (id.to_string(), q.to_string()));
+    if let Some((id, q)) = &trace {
         info!("query::autocomplete - es - enter - {} - ({})", id, q);
     }
 
-    let result = search_query.send()?;
+    let search = es_client.search(indexes, &body, coord);
+    Box::new(timed(search_type, search).then(move |res| {
+        if let Some((id, q)) = trace {
+            info!("query::autocomplete - es - exit - {} - ({})", id, q);
+        }
+        res
+    }))
+}
 
-    if let Some(id) = request_id {
-        info!("query::autocomplete - es - exit - {} - ({})", id, q);
-    }
+fn is_zone_type(t: &str) -> bool {
+    t == "zone" || t == "city"
+}
 
-    if let Some(t) = timer {
-        t.observe_duration();
+/// The types of the places searched around the point by a reverse,
+/// only the nearest address (house or street) without `types`.
+fn reverse_place_types<'a>(types: &[&'a str]) -> Vec<&'a str> {
+    if types.is_empty() {
+        vec!["house", "street"]
+    } else {
+        types
+            .iter()
+            .filter(|t| !is_zone_type(t) && **t != "postcode")
+            .cloned()
+            .collect()
     }
+}
 
-    let res = read_places(result, coord.as_ref());
+/// Keep the admins asked by the `types` of a reverse: all of them with "zone",
+/// only the cities with "city".
+fn reverse_zones(types: &[&str], admins: Vec<mimir::Place>) -> Vec<mimir::Place> {
+    let only_cities = !types.contains(&"zone");
+    admins
+        .into_iter()
+        .filter(|p| match p {
+            mimir::Place::Admin(admin) => !only_cities || admin.is_city(),
+            _ => false,
+        })
+        .collect()
+}
 
-    if let Some(id) = request_id {
-        info!("query::autocomplete - exit - {} - ({})", id, q);
-    }
+/// Get the places around `coord`.
+///
+/// The houses, streets, pois and stops are searched in a `radius` (in meters) and the `limit`
+/// nearest are returned, sorted by distance. The zones are the admins containing `coord`,
+/// from the smallest to the largest, followed by the postcodes containing it.
+/// Without `types`, only the nearest address (house or street) is searched.
+#[allow(clippy::too_many_arguments)]
+pub fn reverse(
+    coord: &Coord,
+    types: &[&str],
+    pt_datasets: &[&str],
+    poi_datasets: &[&str],
+    radius: f64,
+    limit: u64,
+    es_client: EsClient,
+) -> BragiFuture<Vec<mimir::Place>> {
+    let place_types = reverse_place_types(types);
+    let places: EsFuture<_> = if place_types.is_empty() {
+        Box::new(future::ok(vec![]))
+    } else {
+        let indexes = get_indexes(false, pt_datasets, poi_datasets, &place_types);
+        let indexes = indexes
+            .iter()
+            .map(|index| index.as_str())
+            .collect::<Vec<&str>>();
+        debug!("ES indexes: {:?}", indexes);
+        if indexes.is_empty() {
+            Box::new(future::ok(vec![]))
+        } else {
+            es_client.get_places_around(coord, &indexes, radius, limit)
+        }
+    };
+
+    // the admins are searched at the same time as the places
+    let zones: EsFuture<_> = if types.iter().any(|t| is_zone_type(t)) {
+        let types = types.iter().map(|t| t.to_string()).collect::<Vec<_>>();
+        Box::new(es_client.get_admins_containing(coord).map(move |admins| {
+            let types = types.iter().map(String::as_str).collect::<Vec<_>>();
+            reverse_zones(&types, admins)
+        }))
+    } else {
+        Box::new(future::ok(vec![]))
+    };
+    let postcodes: EsFuture<_> = if types.contains(&"postcode") {
+        es_client.get_postcodes_containing(coord)
+    } else {
+        Box::new(future::ok(vec![]))
+    };
+
+    Box::new(
+        places
+            .join3(zones, postcodes)
+            .map(|(mut places, zones, postcodes)| {
+                places.extend(zones);
+                places.extend(postcodes);
+                places
+            })
+            .map_err(BragiError::from),
+    )
+}
+
+/// `reverse` on the embedded index.
+pub fn embedded_reverse(
+    coord: &Coord,
+    types: &[&str],
+    pt_datasets: &[&str],
+    poi_datasets: &[&str],
+    radius: f64,
+    limit: u64,
+    mut backend: EmbeddedBackend,
+) -> Result<Vec<mimir::Place>, BragiError> {
+    let place_types = reverse_place_types(types);
+    let mut places = if place_types.is_empty() {
+        vec![]
+    } else {
+        let indexes = get_indexes(false, pt_datasets, poi_datasets, &place_types);
+        let indexes = indexes
+            .iter()
+            .map(|index| index.as_str())
+            .collect::<Vec<&str>>();
+        debug!("ES indexes: {:?}", indexes);
+        if indexes.is_empty() {
+            vec![]
+        } else {
+            backend.get_places_around(coord, &indexes, radius, limit)?
+        }
+    };
 
-    res
+    if types.iter().any(|t| is_zone_type(t)) {
+        places.extend(reverse_zones(types, backend.get_admins_containing(coord)?));
+    }
+    if types.contains(&"postcode") {
+        places.extend(backend.get_postcodes_containing(coord)?);
+    }
+    Ok(places)
 }
 
 pub fn features(
@@ -573,8 +903,14 @@ pub fn features(
     poi_datasets: &[&str],
     all_data: bool,
     id: &str,
-    mut rubber: Rubber,
-) -> Result<Vec<mimir::Place>, BragiError> {
+    es_client: EsClient,
+) -> BragiFuture<Vec<mimir::Place>> {
+    if let Some((street_id, house_number)) = parse_interpolated_addr_id(id) {
+        let streets = features(pt_datasets, poi_datasets, all_data, street_id, es_client);
+        return Box::new(
+            streets.and_then(move |streets| interpolate_features(streets, &house_number)),
+        );
+    }
     let val = rs_es::units::JsonVal::String(id.into());
     let mut filters = vec![Query::build_ids(vec![val]).build()];
 
@@ -594,65 +930,375 @@ pub fn features(
     debug!("ES indexes: {:?}", indexes);
 
     if indexes.is_empty() {
-        // if there is no indexes, rs_es search with index "_all"
+        // if there is no indexes, elasticsearch would search on all the indexes
         // but we want to return an error in this case.
-        return Err(BragiError::ObjectNotFound);
+        return Box::new(future::err(BragiError::ObjectNotFound));
     }
 
-    let timer = ES_REQ_HISTOGRAM
-        .get_metric_with_label_values(&["features"])
-        .map(|h| h.start_timer())
-        .map_err(
-            |err| error!("impossible to get ES_REQ_HISTOGRAM metrics"; "err" => err.to_string()),
-        )
-        .ok();
+    let body = es_client.search_body(&query, 0, 10);
+    let search = es_client.search(&indexes, &body, None);
+    Box::new(
+        timed("features", search)
+            .map_err(BragiError::from)
+            .and_then(|(places, _)| {
+                if places.is_empty() {
+                    Err(BragiError::ObjectNotFound)
+                } else {
+                    Ok(places)
+                }
+            }),
+    )
+}
 
-    let timeout = rubber.timeout.map(|t| format!("{:?}", t));
-    let mut search_query = rubber.es_client.search_query();
+/// `features` on the embedded index, on which only the indexes of the datasets are filtered.
+pub fn embedded_features(
+    pt_datasets: &[&str],
+    poi_datasets: &[&str],
+    all_data: bool,
+    id: &str,
+    mut backend: EmbeddedBackend,
+) -> Result<Vec<mimir::Place>, BragiError> {
+    let indexes = get_indexes(all_data, &pt_datasets, &poi_datasets, &[]);
+    let indexes = indexes
+        .iter()
+        .map(|index| index.as_str())
+        .collect::<Vec<&str>>();
 
-    let search_query = search_query
-        .with_ignore_unavailable(true)
-        .with_indexes(&indexes)
-        .with_query(&query);
+    if let Some((street_id, house_number)) = parse_interpolated_addr_id(id) {
+        let streets = backend.get_by_id(&indexes, street_id)?;
+        return interpolate_features(streets, &house_number);
+    }
+    let features = backend.get_by_id(&indexes, id)?;
+    if features.is_empty() {
+        Err(BragiError::ObjectNotFound)
+    } else {
+        Ok(features)
+    }
+}
 
-    if let Some(timeout) = &timeout {
-        search_query.with_timeout(timeout.as_str());
+/// The addresses at `house_number` of `streets`, for the id of an interpolated address
+/// (cf `Street::interpolate_addr`).
+fn interpolate_features(
+    streets: Vec<mimir::Place>,
+    house_number: &str,
+) -> Result<Vec<mimir::Place>, BragiError> {
+    let addresses = streets
+        .into_iter()
+        .filter_map(|place| match place {
+            mimir::Place::Street(street) => street.interpolate_addr(house_number),
+            _ => None,
+        })
+        .map(mimir::Place::Addr)
+        .collect::<Vec<_>>();
+    if addresses.is_empty() {
+        Err(BragiError::ObjectNotFound)
+    } else {
+        Ok(addresses)
     }
+}
 
-    let result = search_query.send()?;
+/// The fields of a structured geocoding request.
+///
+/// Each field is matched against its own field in the indexed documents, and a field that is
+/// present must match for a document to be returned.
+#[derive(Debug, Default)]
+pub struct StructuredFields<'a> {
+    pub house_number: Option<&'a str>,
+    pub street: Option<&'a str>,
+    pub postcode: Option<&'a str>,
+    pub city: Option<&'a str>,
+    pub country: Option<&'a str>,
+}
 
-    if let Some(t) = timer {
-        t.observe_duration()
+impl<'a> StructuredFields<'a> {
+    fn is_empty(&self) -> bool {
+        self.house_number.is_none()
+            && self.street.is_none()
+            && self.postcode.is_none()
+            && self.city.is_none()
+            && self.country.is_none()
     }
 
-    if result.hits.total == 0 {
-        Err(BragiError::ObjectNotFound)
-    } else {
-        read_places(result, None).map_err(model::BragiError::from)
+    // The text of the fields found in the labels of the places, to which they are compared
+    // (the labels do not contain the postcode and the country).
+    fn text(&self) -> String {
+        [self.house_number, self.street, self.city]
+            .iter()
+            .flatten()
+            .cloned()
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    // The most precise field given determines the type of the places we are looking for.
+    fn types(&self) -> &'static [&'static str] {
+        if self.house_number.is_some() {
+            &["house"]
+        } else if self.street.is_some() {
+            &["street"]
+        } else {
+            &["zone"]
+        }
     }
 }
 
-#[allow(clippy::too_many_arguments)]
-pub fn autocomplete(
-    q: &str,
+fn build_structured_query(
+    fields: &StructuredFields,
+    coord: Option<Coord>,
     pt_datasets: &[&str],
-    poi_datasets: &[&str],
     all_data: bool,
-    offset: u64,
-    limit: u64,
-    coord: Option<Coord>,
-    shape: Option<Geometry>,
-    shape_scope: &[PlaceDocType],
-    types: &[&str],
-    zone_types: &[&str],
-    poi_types: &[&str],
-    langs: &[&str],
-    mut rubber: Rubber,
-    debug: bool,
     query_settings: &QuerySettings,
-    request_id: Option<&str>,
-) -> Result<Vec<mimir::Place>, BragiError> {
-    // Perform parameters validation.
+) -> Query {
+    let boosts = &query_settings.string_query.boosts;
+    let match_all_words = |field: &str, value: &str| {
+        Query::build_match(field.to_string(), value.to_string())
+            .with_operator("and")
+            .build()
+    };
+    let match_type_and = |doc_type: &str, query: Query| {
+        Query::build_bool()
+            .with_must(vec![Query::build_term("_type", doc_type).build(), query])
+            .build()
+    };
+
+    // Each field given is a filter, the `string_should` queries are only used
+    // to score the documents that match all the filters.
+    let mut filters = vec![];
+    let mut string_should = vec![];
+
+    match fields.house_number {
+        Some(house_number) => filters.push(match_all_words("house_number", house_number)),
+        // Without house number, we don't want any address in the results.
+        None => filters.push(
+            Query::build_bool()
+                .with_must_not(Query::build_exists("house_number").build())
+                .build(),
+        ),
+    }
+
+    if let Some(street) = fields.street {
+        filters.push(
+            Query::build_bool()
+                .with_should(vec![
+                    match_type_and(Addr::doc_type(), match_all_words("street.name", street)),
+                    match_type_and(Street::doc_type(), match_all_words("name", street)),
+                ])
+                .build(),
+        );
+        string_should.push(
+            Query::build_match("label", street)
+                .with_boost(boosts.label)
+                .build(),
+        );
+    }
+
+    if let Some(postcode) = fields.postcode {
+        filters.push(match_all_words("zip_codes", postcode));
+        string_should.push(
+            Query::build_match("zip_codes", postcode)
+                .with_boost(boosts.zip_codes)
+                .build(),
+        );
+    }
+
+    if let Some(city) = fields.city {
+        // The city is either one of the admins of the place (the admins of an address are
+        // the ones of its street), or the place itself.
+        filters.push(
+            Query::build_bool()
+                .with_should(vec![
+                    match_all_words("administrative_regions.name", city),
+                    match_all_words("street.administrative_regions.name", city),
+                    match_type_and(Admin::doc_type(), match_all_words("name", city)),
+                ])
+                .build(),
+        );
+        string_should.push(
+            Query::build_match("label", city)
+                .with_boost(boosts.label)
+                .build(),
+        );
+    }
+
+    if let Some(country) = fields.country {
+        // country codes are stored lowercased
+        filters.push(Query::build_match("country_codes", country.to_lowercase()).build());
+    }
+
+    // if searching through all data, no coverage filter
+    if !all_data {
+        filters.push(build_coverage_condition(pt_datasets));
+    }
+
+    let settings = &query_settings.importance_query.weights;
+    let mut importance_queries = vec![build_with_weight(&settings.max_radius, &settings.types)];
+    if let Some(ref coord) = coord {
+        importance_queries.push(build_proximity_with_boost(
+            coord,
+            &query_settings.importance_query.proximity,
+            false,
+        ));
+    }
+
+    let mut should = vec![Query::build_bool()
+        .with_should(string_should)
+        .with_boost(query_settings.string_query.global)
+        .build()];
+    should.extend(importance_queries);
+
+    Query::build_bool()
+        .with_must(build_type_query(query_settings))
+        .with_should(should)
+        .with_filter(Query::build_bool().with_must(filters).build())
+        .build()
+}
+
+/// Search for the places matching the fields of a structured geocoding request.
+#[allow(clippy::too_many_arguments)]
+pub fn structured_search(
+    fields: &StructuredFields,
+    pt_datasets: &[&str],
+    poi_datasets: &[&str],
+    all_data: bool,
+    offset: u64,
+    limit: u64,
+    coord: Option<Coord>,
+    es_client: EsClient,
+    query_settings: &QuerySettings,
+) -> BragiFuture<Vec<mimir::Place>> {
+    if fields.is_empty() {
+        return Box::new(future::err(BragiError::InvalidParam(
+            "at least one of 'housenumber', 'street', 'postcode', 'city' or 'country' is required",
+        )));
+    }
+
+    let query = build_structured_query(fields, coord, pt_datasets, all_data, query_settings);
+
+    let indexes = get_indexes(all_data, pt_datasets, poi_datasets, fields.types());
+    let indexes = indexes
+        .iter()
+        .map(|index| index.as_str())
+        .collect::<Vec<&str>>();
+
+    let text = fields.text();
+    Box::new(
+        send_query(
+            &es_client,
+            &indexes,
+            &query,
+            "structured",
+            offset,
+            limit,
+            coord,
+            false,
+            None,
+        )
+        .map_err(model::BragiError::from)
+        .map(move |(places, _)| with_confidences(&text, places)),
+    )
+}
+
+/// The indexes searched by an autocomplete: the intersections are only searched when they are
+/// asked with the types, or when the query is an intersection.
+fn autocomplete_indexes(
+    all_data: bool,
+    pt_datasets: &[&str],
+    poi_datasets: &[&str],
+    types: &[&str],
+    parsed_query: &ParsedQuery,
+) -> Vec<String> {
+    let mut indexes = get_indexes(all_data, pt_datasets, poi_datasets, types);
+    if !all_data && types.is_empty() && !parsed_query.intersection.is_empty() {
+        indexes.push(get_main_type_index::<Intersection>());
+    }
+    indexes
+}
+
+/// `open_now` being checked after the search, the hits are then fetched by pages of this size
+/// until enough places remain after the filter, with at most `MAX_OPEN_NOW_PAGES` pages.
+const OPEN_NOW_PAGE_SIZE: u64 = 100;
+const MAX_OPEN_NOW_PAGES: u64 = 5;
+
+/// Filters on the details of the POIs.
+/// With at least one of them, only POIs are returned.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PoiFilters {
+    pub wheelchair: Option<Wheelchair>,
+    /// Keep the POIs open (or closed) at the time of the request, in the timezone of the POI.
+    /// The POIs with unknown opening hours or timezone are never kept.
+    pub open_now: Option<bool>,
+}
+
+impl PoiFilters {
+    fn is_empty(&self) -> bool {
+        self.wheelchair.is_none() && self.open_now.is_none()
+    }
+
+    /// The part of the filters done by elasticsearch (the opening hours are checked afterwards)
+    fn build_filter(&self) -> Option<Query> {
+        if self.is_empty() {
+            return None;
+        }
+        let mut filters = vec![Query::build_term("_type", Poi::doc_type()).build()];
+        if let Some(wheelchair) = self.wheelchair {
+            filters.push(Query::build_term("details.wheelchair", wheelchair.as_str()).build());
+        }
+        Some(Query::build_bool().with_must(filters).build())
+    }
+
+    fn accepts(&self, place: &mimir::Place, now: &DateTime<Utc>) -> bool {
+        if self.is_empty() {
+            return true;
+        }
+        let poi = match place {
+            mimir::Place::Poi(poi) => poi,
+            _ => return false,
+        };
+        let wheelchair_ok = self
+            .wheelchair
+            .map_or(true, |w| poi.details.wheelchair == Some(w));
+        let open_now_ok = self.open_now.map_or(true, |open_now| {
+            let schedule = poi.details.schedule.as_ref();
+            let timezone = poi
+                .details
+                .timezone
+                .as_ref()
+                .and_then(|tz| tz.parse::<chrono_tz::Tz>().ok());
+            match (schedule, timezone) {
+                (Some(schedule), Some(timezone)) => {
+                    schedule.is_open(&now.with_timezone(&timezone).naive_local()) == open_now
+                }
+                _ => false,
+            }
+        });
+        wheelchair_ok && open_now_ok
+    }
+
+    /// Whether some filters can only be checked after the search, so more hits than `limit`
+    /// have to be fetched
+    fn is_checked_after_search(&self) -> bool {
+        self.open_now.is_some()
+    }
+
+    /// Remove the places not matching the filters.
+    fn retain(&self, places: Vec<mimir::Place>) -> Vec<mimir::Place> {
+        if self.is_empty() {
+            return places;
+        }
+        let now = Utc::now();
+        places
+            .into_iter()
+            .filter(|place| self.accepts(place, &now))
+            .collect()
+    }
+}
+
+fn check_type_filters(
+    types: &[&str],
+    zone_types: &[&str],
+    poi_types: &[&str],
+    poi_filters: &PoiFilters,
+) -> Result<(), BragiError> {
     if !zone_types.is_empty() && !types.iter().any(|s| *s == "zone") {
         return Err(BragiError::InvalidParam(
             "zone_type[] parameter requires to have 'type[]=zone'",
@@ -663,53 +1309,742 @@ pub fn autocomplete(
             "poi_type[] parameter requires to have 'type[]=poi'",
         ));
     }
+    if !poi_filters.is_empty() && !types.is_empty() && !types.iter().any(|s| *s == "poi") {
+        return Err(BragiError::InvalidParam(
+            "wheelchair and open_now parameters require to have 'type[]=poi' or no type",
+        ));
+    }
+    Ok(())
+}
+
+/// Autocomplete a batch of queries, each one with its optional coord.
+///
+/// It runs the same cascade of stages as `autocomplete`, but all the queries still searched at
+/// a stage are sent in a single `_msearch` request.
+/// The timeout of `es_client` is the deadline of the whole batch, the places already found
+/// being returned as partial results when it is over.
+/// The results are in the same order as `queries`, an error on a query does not fail the
+/// whole batch.
+#[allow(clippy::too_many_arguments)]
+pub fn batch_autocomplete(
+    queries: &[(String, Option<Coord>)],
+    pt_datasets: &[&str],
+    poi_datasets: &[&str],
+    all_data: bool,
+    limit: u64,
+    shape_scope: &[PlaceDocType],
+    bbox: Option<&BoundingBox>,
+    within: &[&str],
+    types: &[&str],
+    zone_types: &[&str],
+    poi_types: &[&str],
+    poi_filters: &PoiFilters,
+    langs: &[&str],
+    es_client: EsClient,
+    query_settings: &QuerySettings,
+    query_parser: &QueryParser,
+) -> BragiFuture<Vec<Result<AutocompleteResults, String>>> {
+    if let Err(err) = check_type_filters(types, zone_types, poi_types, poi_filters) {
+        return Box::new(future::err(err));
+    }
+
+    let searches = queries
+        .iter()
+        .map(|(q, coord)| {
+            CascadeSearch::new(
+                q,
+                &query_parser.parse(q),
+                pt_datasets,
+                poi_datasets,
+                all_data,
+                0,
+                limit,
+                *coord,
+                None,
+                shape_scope,
+                bbox,
+                within,
+                types,
+                zone_types,
+                poi_types,
+                poi_filters,
+                langs,
+                false,
+                query_settings,
+                None,
+                es_client.timeout,
+            )
+        })
+        .collect::<Vec<_>>();
+    let searches = Rc::new(searches);
+    let results: Vec<Result<_, String>> = searches
+        .iter()
+        .map(|search| Ok(search.first_results(false)))
+        .collect();
+    let rows = (0..searches.len()).collect::<Vec<_>>();
+
+    // The stages of the cascade are run one after the other, each one for all the rows
+    // without enough places yet.
+    Box::new(future::loop_fn(
+        (results, rows, 0),
+        move |(mut results, rows, i)| -> BragiFuture<Loop<_, _>> {
+            // the searches have been created together, they share their deadline and budget
+            let first = match rows.first() {
+                Some(&row) => &searches[row],
+                None => return Box::new(future::ok(Loop::Break(results))),
+            };
+            let es_client = match first.next_stage(i) {
+                NextStage::Send(left) => es_client.with_timeout(left),
+                NextStage::Stop { partial } => {
+                    for &row in &rows {
+                        if let Ok(res) = &mut results[row] {
+                            res.partial |= partial;
+                        }
+                    }
+                    return Box::new(future::ok(Loop::Break(results)));
+                }
+            };
+            let search_type = format!("batch_{}", first.stages[i].match_type);
+            let stages_places = {
+                let multi_searches = rows
+                    .iter()
+                    .map(|&row| searches[row].multi_search(i))
+                    .collect::<Vec<_>>();
+                timed(&search_type, es_client.multi_search(&multi_searches))
+            };
+            let searches = searches.clone();
+            Box::new(
+                stages_places.then(move |stages_places| -> Result<_, BragiError> {
+                    let stages_places = match stages_places {
+                        Ok(stages_places) => stages_places,
+                        Err(err) if searches[rows[0]].is_over_deadline() => {
+                            warn!("the {} stage timed out: {}", search_type, err);
+                            for &row in &rows {
+                                if let Ok(res) = &mut results[row] {
+                                    res.partial = true;
+                                }
+                            }
+                            return Ok(Loop::Break(results));
+                        }
+                        Err(err) => rows.iter().map(|_| Err(err.to_string())).collect(),
+                    };
+                    let mut next_rows = vec![];
+                    for (row, stage_places) in rows.into_iter().zip(stages_places) {
+                        let stage_places = match stage_places {
+                            // nothing has been found for this row
+                            Err(err) if i == 0 => {
+                                results[row] = Err(err);
+                                continue;
+                            }
+                            stage_places => stage_places,
+                        };
+                        if let Ok(res) = &mut results[row] {
+                            if !searches[row].add_searched_stage(res, i, stage_places) {
+                                next_rows.push(row);
+                            }
+                        }
+                    }
+                    Ok(Loop::Continue((results, next_rows, i + 1)))
+                }),
+            )
+        },
+    ))
+}
 
-    // First we try a pretty exact match on the prefix.
-    // If there are no results then we do a new fuzzy search (matching ngrams)
-    let results = query(
-        &q,
-        &pt_datasets,
-        &poi_datasets,
+/// Whether the places found so far are enough to stop after `stage`.
+fn is_enough(places: &[mimir::Place], stage: &Stage) -> bool {
+    places
+        .iter()
+        .filter(|place| place.confidence().unwrap_or(0.) >= stage.min_confidence)
+        .count()
+        >= stage.min_results
+}
+
+/// Merge the places found by a stage with the places found by the previous stages,
+/// the places already found are not added again.
+fn merge_places(
+    places: Vec<mimir::Place>,
+    new_places: Vec<mimir::Place>,
+    merge: MergePolicy,
+    limit: usize,
+) -> Vec<mimir::Place> {
+    let new_places = new_places
+        .into_iter()
+        .filter(|new| places.iter().all(|place| place.id() != new.id()))
+        .collect::<Vec<_>>();
+    let mut merged = match merge {
+        MergePolicy::Append => places.into_iter().chain(new_places).collect::<Vec<_>>(),
+        MergePolicy::Interleave => {
+            let mut merged = Vec::with_capacity(places.len() + new_places.len());
+            let (mut places, mut new_places) = (places.into_iter(), new_places.into_iter());
+            loop {
+                match (places.next(), new_places.next()) {
+                    (None, None) => break,
+                    (place, new) => {
+                        merged.extend(place);
+                        merged.extend(new);
+                    }
+                }
+            }
+            merged
+        }
+        MergePolicy::Rescore => {
+            let mut merged = places.into_iter().chain(new_places).collect::<Vec<_>>();
+            merged.sort_by(|a, b| {
+                b.confidence()
+                    .partial_cmp(&a.confidence())
+                    .unwrap_or(std::cmp::Ordering::Equal)
+            });
+            merged
+        }
+    };
+    merged.truncate(limit);
+    merged
+}
+
+/// The places found by `autocomplete`
+pub struct AutocompleteResults {
+    pub places: Vec<mimir::Place>,
+    /// The last stage whose places have been used
+    pub match_type: MatchType,
+    /// The deadline of the request stopped the search, more places could have been found
+    pub partial: bool,
+}
+
+/// The search of an autocomplete through the stages of a cascade
+struct CascadeSearch {
+    q: String,
+    /// The house number recognized in `q` by the query parser
+    house_number: Option<String>,
+    stages: Vec<Stage>,
+    /// The query of each stage
+    queries: Vec<Query>,
+    indexes: Vec<String>,
+    offset: u64,
+    limit: u64,
+    coord: Option<Coord>,
+    poi_filters: PoiFilters,
+    debug: bool,
+    request_id: Option<String>,
+    start: Instant,
+    deadline: Option<Instant>,
+    budget: Option<Duration>,
+}
+
+/// Whether the next stage of a `CascadeSearch` is sent
+enum NextStage {
+    /// The stage is sent with the time left before the deadline
+    Send(Option<Duration>),
+    /// The search stops, the places found being partial if the deadline is over
+    Stop { partial: bool },
+}
+
+impl CascadeSearch {
+    /// The search of `q` with the stages of the cascade of `query_settings`,
+    /// whose deadline is in `timeout`.
+    #[allow(clippy::too_many_arguments)]
+    fn new(
+        q: &str,
+        parsed_query: &ParsedQuery,
+        pt_datasets: &[&str],
+        poi_datasets: &[&str],
+        all_data: bool,
+        offset: u64,
+        limit: u64,
+        coord: Option<Coord>,
+        shape: Option<Geometry>,
+        shape_scope: &[PlaceDocType],
+        bbox: Option<&BoundingBox>,
+        within: &[&str],
+        types: &[&str],
+        zone_types: &[&str],
+        poi_types: &[&str],
+        poi_filters: &PoiFilters,
+        langs: &[&str],
+        debug: bool,
+        query_settings: &QuerySettings,
+        request_id: Option<&str>,
+        timeout: Option<Duration>,
+    ) -> CascadeSearch {
+        let start = Instant::now();
+        let cascade = &query_settings.cascade;
+        CascadeSearch {
+            q: q.to_string(),
+            house_number: parsed_query.house_number.clone(),
+            stages: cascade.stages.clone(),
+            queries: cascade
+                .stages
+                .iter()
+                .map(|stage| {
+                    build_query(
+                        q,
+                        parsed_query,
+                        stage.match_type,
+                        coord,
+                        shape.clone(),
+                        shape_scope,
+                        bbox,
+                        within,
+                        pt_datasets,
+                        all_data,
+                        langs,
+                        zone_types,
+                        poi_types,
+                        poi_filters,
+                        query_settings,
+                    )
+                })
+                .collect(),
+            indexes: autocomplete_indexes(all_data, pt_datasets, poi_datasets, types, parsed_query),
+            offset,
+            limit,
+            coord,
+            poi_filters: *poi_filters,
+            debug,
+            request_id: request_id.map(str::to_string),
+            start,
+            deadline: timeout.map(|timeout| start + timeout),
+            budget: cascade.budget.map(Duration::from_millis),
+        }
+    }
+
+    fn is_over_deadline(&self) -> bool {
+        self.deadline
+            .map_or(false, |deadline| Instant::now() >= deadline)
+    }
+
+    fn is_over_budget(&self) -> bool {
+        self.budget
+            .map_or(false, |budget| self.start.elapsed() > budget)
+    }
+
+    fn time_left(&self) -> Option<Duration> {
+        self.deadline
+            .map(|deadline| deadline.saturating_duration_since(Instant::now()))
+    }
+
+    /// Whether the stage `i` is sent: the first stage always is, the next ones only get
+    /// the time left within the budget and the deadline of the search.
+    fn next_stage(&self, i: usize) -> NextStage {
+        if i > 0 && self.is_over_budget() {
+            return NextStage::Stop { partial: false };
+        }
+        match self.time_left() {
+            Some(left) if i > 0 && left == Duration::from_secs(0) => {
+                NextStage::Stop { partial: true }
+            }
+            left => NextStage::Send(left),
+        }
+    }
+
+    fn first_results(&self, partial: bool) -> AutocompleteResults {
+        AutocompleteResults {
+            places: vec![],
+            match_type: self.stages[0].match_type,
+            partial,
+        }
+    }
+
+    /// The query of the stage `i` in an `_msearch` request, whose places are then given
+    /// to `add_searched_stage`
+    fn multi_search(&self, i: usize) -> MultiSearch<'_> {
+        // the filters checked after the search need more hits, from the first one
+        let (from, size) = if self.poi_filters.is_checked_after_search() {
+            (0, (self.offset + self.limit).max(OPEN_NOW_PAGE_SIZE))
+        } else {
+            (self.offset, self.limit)
+        };
+        MultiSearch {
+            indexes: &self.indexes,
+            query: &self.queries[i],
+            from,
+            size,
+            coord: self.coord,
+        }
+    }
+
+    /// Send the query of the stage `i`
+    fn send_stage(&self, es_client: &EsClient, i: usize) -> EsFuture<(Vec<mimir::Place>, bool)> {
+        if self.poi_filters.is_checked_after_search() {
+            return self.send_stage_pages(es_client, i);
+        }
+        let indexes = self.indexes.iter().map(String::as_str).collect::<Vec<_>>();
+        let trace = self.request_id.as_deref().map(|id| (id, self.q.as_str()));
+        if let Some((id, q)) = trace {
+            info!("query::autocomplete - enter - {} - ({})", id, q);
+        }
+
+        let poi_filters = self.poi_filters;
+        let request_id = self.request_id.clone();
+        let q = self.q.clone();
+        Box::new(
+            send_query(
+                es_client,
+                &indexes,
+                &self.queries[i],
+                &self.stages[i].match_type.to_string(),
+                self.offset,
+                self.limit,
+                self.coord,
+                self.debug,
+                trace,
+            )
+            .map(move |(places, timed_out)| {
+                if let Some(id) = request_id {
+                    info!("query::autocomplete - exit - {} - ({})", id, q);
+                }
+                (poi_filters.retain(places), timed_out)
+            }),
+        )
+    }
+
+    /// Send the query of the stage `i` by pages, from the first hit, until `offset + limit`
+    /// places remain after the filters checked after the search (or there are no more hits).
+    fn send_stage_pages(
+        &self,
+        es_client: &EsClient,
+        i: usize,
+    ) -> EsFuture<(Vec<mimir::Place>, bool)> {
+        if self.indexes.is_empty() {
+            return Box::new(future::ok((vec![], false)));
+        }
+        let mut body = es_client.search_body(&self.queries[i], 0, OPEN_NOW_PAGE_SIZE);
+        if self.debug {
+            body["explain"] = serde_json::Value::from(true);
+        }
+        let es_client = es_client.clone();
+        let indexes = self.indexes.clone();
+        let search_type = self.stages[i].match_type.to_string();
+        let poi_filters = self.poi_filters;
+        let coord = self.coord;
+        let offset = self.offset as usize;
+        let limit = self.limit as usize;
+        Box::new(future::loop_fn(
+            (vec![], 0),
+            move |(mut places, page): (Vec<mimir::Place>, u64)| {
+                body["from"] = serde_json::Value::from(page * OPEN_NOW_PAGE_SIZE);
+                let indexes = indexes.iter().map(String::as_str).collect::<Vec<_>>();
+                timed(&search_type, es_client.search(&indexes, &body, coord)).map(
+                    move |(hits, timed_out)| {
+                        let is_last_page = hits.len() < OPEN_NOW_PAGE_SIZE as usize
+                            || page + 1 >= MAX_OPEN_NOW_PAGES;
+                        places.extend(poi_filters.retain(hits));
+                        if timed_out || is_last_page || places.len() >= offset + limit {
+                            let places = places.into_iter().skip(offset).take(limit).collect();
+                            Loop::Break((places, timed_out))
+                        } else {
+                            Loop::Continue((places, page + 1))
+                        }
+                    },
+                )
+            },
+        ))
+    }
+
+    /// Merge the places found by the stage `i` in the results,
+    /// return whether the search should stop after this stage.
+    fn add_stage(
+        &self,
+        results: &mut AutocompleteResults,
+        i: usize,
+        stage_places: Vec<mimir::Place>,
+    ) -> bool {
+        let stage = &self.stages[i];
+        let stage_places = with_interpolated_addresses(
+            self.house_number.as_deref(),
+            stage_places,
+            self.coord.as_ref(),
+        );
+        let places = std::mem::replace(&mut results.places, vec![]);
+        results.places = merge_places(
+            places,
+            with_confidences(&self.q, stage_places),
+            stage.merge,
+            self.limit as usize,
+        );
+        results.match_type = stage.match_type;
+        results.partial || is_enough(&results.places, stage) || i + 1 == self.stages.len()
+    }
+
+    /// Same as `add_stage` with the places found by the query of `multi_search`,
+    /// a stage that failed finding no places (the ones of the previous stages are kept).
+    fn add_searched_stage(
+        &self,
+        results: &mut AutocompleteResults,
+        i: usize,
+        stage_places: Result<Vec<mimir::Place>, String>,
+    ) -> bool {
+        let stage_places = match stage_places {
+            Ok(stage_places) if self.poi_filters.is_checked_after_search() => {
+                // the search started from the first hit, see `multi_search`
+                self.poi_filters
+                    .retain(stage_places)
+                    .into_iter()
+                    .skip(self.offset as usize)
+                    .take(self.limit as usize)
+                    .collect()
+            }
+            Ok(stage_places) => self.poi_filters.retain(stage_places),
+            Err(err) => {
+                warn!(
+                    "the {} stage of the cascade failed: {}",
+                    self.stages[i].match_type, err
+                );
+                vec![]
+            }
+        };
+        self.add_stage(results, i, stage_places)
+    }
+
+    /// Use the places found by all the stages sent at once
+    fn add_parallel_stages(
+        &self,
+        stages_places: Vec<Result<Vec<mimir::Place>, String>>,
+        partial: bool,
+    ) -> AutocompleteResults {
+        let mut results = self.first_results(partial);
+        for (i, stage_places) in stages_places.into_iter().enumerate() {
+            if self.add_searched_stage(&mut results, i, stage_places) {
+                break;
+            }
+        }
+        results
+    }
+}
+
+/// Autocomplete `q` with the stages of the cascade of `query_settings`.
+///
+/// The stages are run in order until one of them has found enough places, the places of each
+/// stage being merged with the places of the previous ones.
+/// The timeout of `es_client` is the deadline of the whole search: the stages share it, and
+/// when it is over the places already found are returned as partial results.
+/// The components of the address recognized in `q` (`parsed_query`) are boosted, and its
+/// postcode and country filter the places.
+#[allow(clippy::too_many_arguments)]
+pub fn autocomplete(
+    q: &str,
+    parsed_query: &ParsedQuery,
+    pt_datasets: &[&str],
+    poi_datasets: &[&str],
+    all_data: bool,
+    offset: u64,
+    limit: u64,
+    coord: Option<Coord>,
+    shape: Option<Geometry>,
+    shape_scope: &[PlaceDocType],
+    bbox: Option<&BoundingBox>,
+    within: &[&str],
+    types: &[&str],
+    zone_types: &[&str],
+    poi_types: &[&str],
+    poi_filters: &PoiFilters,
+    langs: &[&str],
+    es_client: EsClient,
+    debug: bool,
+    query_settings: &QuerySettings,
+    request_id: Option<&str>,
+) -> BragiFuture<AutocompleteResults> {
+    // Perform parameters validation.
+    if let Err(err) = check_type_filters(types, zone_types, poi_types, poi_filters) {
+        return Box::new(future::err(err));
+    }
+
+    let search = CascadeSearch::new(
+        q,
+        parsed_query,
+        pt_datasets,
+        poi_datasets,
         all_data,
-        &mut rubber,
-        MatchType::Prefix,
         offset,
         limit,
         coord,
-        shape.clone(),
-        &shape_scope,
-        &types,
-        &zone_types,
-        &poi_types,
-        &langs,
+        shape,
+        shape_scope,
+        bbox,
+        within,
+        types,
+        zone_types,
+        poi_types,
+        poi_filters,
+        langs,
         debug,
         query_settings,
         request_id,
-    )
-    .map_err(model::BragiError::from)?;
-    if results.is_empty() {
-        query(
-            &q,
-            &pt_datasets,
-            &poi_datasets,
-            all_data,
-            &mut rubber,
-            MatchType::Fuzzy,
-            offset,
-            limit,
-            coord,
-            shape,
-            &shape_scope,
-            &types,
-            &zone_types,
-            &poi_types,
-            &langs,
-            debug,
-            query_settings,
-            request_id,
+        es_client.timeout,
+    );
+
+    if query_settings.cascade.parallel {
+        // With a parallel cascade, all the stages are sent in a single `_msearch` request,
+        // the budget being the timeout of elasticsearch.
+        let es_client = match search.budget {
+            Some(budget) => {
+                es_client.with_timeout(Some(es_client.timeout.map_or(budget, |t| t.min(budget))))
+            }
+            None => es_client,
+        };
+        let stages_places = {
+            let searches = (0..search.stages.len())
+                .map(|i| search.multi_search(i))
+                .collect::<Vec<_>>();
+            timed("cascade", es_client.multi_search(&searches))
+        };
+        Box::new(
+            stages_places.then(move |stages_places| match stages_places {
+                Ok(stages_places) => Ok(search.add_parallel_stages(stages_places, false)),
+                Err(err) if search.is_over_deadline() => {
+                    warn!("the cascade timed out: {}", err);
+                    Ok(search.first_results(true))
+                }
+                Err(err) => Err(BragiError::from(err)),
+            }),
         )
-        .map_err(model::BragiError::from)
     } else {
-        Ok(results)
+        let search = Rc::new(search);
+        let results = search.first_results(false);
+        Box::new(future::loop_fn(
+            (results, 0),
+            move |(mut results, i): (AutocompleteResults, usize)| -> BragiFuture<Loop<_, _>> {
+                let search = search.clone();
+                let es_client = match search.next_stage(i) {
+                    NextStage::Send(left) => es_client.with_timeout(left),
+                    NextStage::Stop { partial } => {
+                        results.partial |= partial;
+                        return Box::new(future::ok(Loop::Break(results)));
+                    }
+                };
+                Box::new(search.send_stage(&es_client, i).then(move |res| {
+                    let stage = &search.stages[i];
+                    let stage_places = match res {
+                        Ok((stage_places, timed_out)) => {
+                            results.partial |= timed_out;
+                            stage_places
+                        }
+                        Err(err) if search.is_over_deadline() => {
+                            warn!("the {} stage timed out: {}", stage.match_type, err);
+                            results.partial = true;
+                            return Ok(Loop::Break(results));
+                        }
+                        Err(err) if i == 0 => return Err(BragiError::from(err)),
+                        Err(err) => {
+                            // the places found by the previous stages are kept
+                            warn!(
+                                "the {} stage of the cascade failed: {}",
+                                stage.match_type, err
+                            );
+                            vec![]
+                        }
+                    };
+                    if search.add_stage(&mut results, i, stage_places) {
+                        Ok(Loop::Break(results))
+                    } else {
+                        Ok(Loop::Continue((results, i + 1)))
+                    }
+                }))
+            },
+        ))
+    }
+}
+
+/// `autocomplete` on the embedded index.
+///
+/// The prefix then fuzzy matching is done by the embedded index, the settings of the
+/// elasticsearch query (`QuerySettings`) are not used.
+/// Only the places inside `bbox` and inside one of the admins `within` are returned.
+#[allow(clippy::too_many_arguments)]
+pub fn embedded_autocomplete(
+    q: &str,
+    parsed_query: &ParsedQuery,
+    pt_datasets: &[&str],
+    poi_datasets: &[&str],
+    all_data: bool,
+    offset: u64,
+    limit: u64,
+    coord: Option<Coord>,
+    types: &[&str],
+    zone_types: &[&str],
+    poi_types: &[&str],
+    poi_filters: &PoiFilters,
+    bbox: Option<&Rect<f64>>,
+    within: &[&str],
+    backend: &EmbeddedBackend,
+) -> Result<Vec<mimir::Place>, BragiError> {
+    check_type_filters(types, zone_types, poi_types, poi_filters)?;
+
+    let indexes = get_indexes(all_data, &pt_datasets, &poi_datasets, &types);
+    let indexes = indexes
+        .iter()
+        .map(|index| index.as_str())
+        .collect::<Vec<&str>>();
+    debug!("embedded indexes: {:?}", indexes);
+
+    // same filters as the elasticsearch query
+    let now = Utc::now();
+    let filter = |place: &mimir::Place| {
+        let zone_type_ok = zone_types.is_empty()
+            || match place {
+                mimir::Place::Admin(admin) => admin
+                    .zone_type
+                    .as_ref()
+                    .map_or(false, |z| zone_types.contains(&z.as_str())),
+                _ => false,
+            };
+        let poi_type_ok = poi_types.is_empty()
+            || match place {
+                mimir::Place::Poi(poi) => poi_types.contains(&poi.poi_type.id.as_str()),
+                _ => false,
+            };
+        let bbox_ok = bbox.map_or(true, |rect| rect_contains(rect, place.coord()));
+        let within_ok = within.is_empty() || is_within(place, within);
+        zone_type_ok && poi_type_ok && bbox_ok && within_ok && poi_filters.accepts(place, &now)
+    };
+
+    backend
+        .autocomplete(&indexes, q, coord.as_ref(), filter, offset, limit)
+        .map_err(model::BragiError::from)
+        .map(|places| {
+            with_interpolated_addresses(
+                parsed_query.house_number.as_deref(),
+                places,
+                coord.as_ref(),
+            )
+        })
+        .map(|places| with_confidences(q, places))
+}
+
+/// If the query has a house number (as recognized by the query parser, with its suffix)
+/// that has not been found, replace the streets found by the address at this house number,
+/// interpolated along the street when it is possible.
+fn with_interpolated_addresses(
+    house_number: Option<&str>,
+    places: Vec<mimir::Place>,
+    coord: Option<&Coord>,
+) -> Vec<mimir::Place> {
+    let house_number = match house_number {
+        Some(house_number) => house_number,
+        None => return places,
+    };
+    let normalize = |n: &str| {
+        n.chars()
+            .filter(|c| !c.is_whitespace())
+            .flat_map(char::to_lowercase)
+            .collect::<String>()
+    };
+    let normalized = normalize(house_number);
+    let is_found = places.iter().any(|place| match place {
+        mimir::Place::Addr(addr) => normalize(&addr.house_number) == normalized,
+        _ => false,
+    });
+    if is_found {
+        return places;
     }
+    places
+        .into_iter()
+        .map(|place| match place {
+            mimir::Place::Street(street) => match street.interpolate_addr(house_number) {
+                Some(addr) => with_distance(mimir::Place::Addr(addr), coord),
+                None => mimir::Place::Street(street),
+            },
+            place => place,
+        })
+        .collect()
 }
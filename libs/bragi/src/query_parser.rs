@@ -0,0 +1,608 @@
+//! Recognition of the components of an address (house number, street, postcode, city and
+//! country) in the free text of a query.
+//!
+//! The words of the query are tagged with the rules of the countries (read from a toml file,
+//! cf. `config/address-rules.toml`) and with the names of the cities and countries and the zip
+//! codes of the admins of the index.
+
+use crate::watch::watch;
+use cosmogony::ZoneType;
+use mimir::backend::SearchBackend;
+use mimir::embedded::words;
+use mimir::rubber::get_main_type_index;
+use mimir::Admin;
+use serde::{Deserialize, Serialize};
+use slog_scope::{info, warn};
+use std::collections::{HashMap, HashSet};
+use std::fs::read_to_string;
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+const BUNDLED_RULES: &str = include_str!("../../../config/address-rules.toml");
+
+/// The max number of words of the names of the cities and the countries
+const MAX_NAME_WORDS: usize = 5;
+
+/// The separators of the streets of an intersection, like in `rue de rivoli & rue du louvre`
+const INTERSECTION_SEPARATORS: [&str; 4] = ["&", "/", "@", " x "];
+
+/// The component of an address a word of the query belongs to
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Component {
+    HouseNumber,
+    StreetType,
+    StreetName,
+    Postcode,
+    City,
+    Country,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ParsedWord {
+    pub word: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub component: Option<Component>,
+}
+
+/// The components of an address found in a query, as normalized words
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct ParsedQuery {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub house_number: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub street_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub street_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub postcode: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub city: Option<String>,
+    /// The code of the country
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub country: Option<String>,
+    /// The streets of an intersection, with their types
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub intersection: Vec<String>,
+    pub words: Vec<ParsedWord>,
+}
+
+impl ParsedQuery {
+    fn new(words: Vec<String>, tags: Vec<Option<Component>>, country: Option<String>) -> Self {
+        let component = |c: Component| -> Option<String> {
+            let found = words
+                .iter()
+                .zip(&tags)
+                .filter(|(_, tag)| **tag == Some(c))
+                .map(|(word, _)| word.as_str())
+                .collect::<Vec<_>>();
+            if found.is_empty() {
+                None
+            } else {
+                Some(found.join(" "))
+            }
+        };
+        ParsedQuery {
+            house_number: component(Component::HouseNumber),
+            street_type: component(Component::StreetType),
+            street_name: component(Component::StreetName),
+            postcode: component(Component::Postcode),
+            city: component(Component::City),
+            country,
+            intersection: vec![],
+            words: words
+                .into_iter()
+                .zip(tags)
+                .map(|(word, component)| ParsedWord { word, component })
+                .collect(),
+        }
+    }
+
+    /// The street, with its type
+    pub fn street(&self) -> Option<String> {
+        match (&self.street_type, &self.street_name) {
+            (Some(street_type), Some(name)) => Some(format!("{} {}", street_type, name)),
+            (None, Some(name)) => Some(name.clone()),
+            _ => None,
+        }
+    }
+
+    /// The words of an intersection matched against the labels of the places: its streets and
+    /// its postcode, city and country, without the separator of the streets (like the `x` of
+    /// `a x b`)
+    pub fn intersection_text(&self) -> Option<String> {
+        if self.intersection.is_empty() {
+            return None;
+        }
+        let is_place = |w: &&ParsedWord| {
+            matches!(
+                w.component,
+                Some(Component::Postcode) | Some(Component::City) | Some(Component::Country)
+            )
+        };
+        let places = self.words.iter().filter(is_place).map(|w| w.word.as_str());
+        Some(
+            self.intersection
+                .iter()
+                .map(String::as_str)
+                .chain(places)
+                .collect::<Vec<_>>()
+                .join(" "),
+        )
+    }
+}
+
+/// The rules of the addresses of a country
+#[derive(Deserialize, Debug, Default)]
+struct CountryRules {
+    #[serde(default)]
+    names: Vec<String>,
+    #[serde(default)]
+    street_types: Vec<String>,
+    #[serde(default)]
+    street_particles: Vec<String>,
+    #[serde(default)]
+    house_number_suffixes: Vec<String>,
+    postcode_digits: Option<usize>,
+}
+
+/// The rules used to parse the queries: the rules of one country, or of all of them
+#[derive(Debug, Default)]
+struct Rules<'a> {
+    street_types: HashSet<&'a str>,
+    street_particles: HashSet<&'a str>,
+    house_number_suffixes: HashSet<&'a str>,
+    postcode_digits: HashSet<usize>,
+}
+
+impl<'a> Rules<'a> {
+    fn new(countries: impl Iterator<Item = &'a CountryRules>) -> Self {
+        let mut rules = Rules::default();
+        for country in countries {
+            rules
+                .street_types
+                .extend(country.street_types.iter().map(String::as_str));
+            rules
+                .street_particles
+                .extend(country.street_particles.iter().map(String::as_str));
+            rules
+                .house_number_suffixes
+                .extend(country.house_number_suffixes.iter().map(String::as_str));
+            rules.postcode_digits.extend(country.postcode_digits);
+        }
+        rules
+    }
+
+    /// A house number, like `12`, `12a` or `12bis`
+    fn is_house_number(&self, word: &str) -> bool {
+        let suffix = word.trim_start_matches(|c: char| c.is_ascii_digit());
+        suffix.len() < word.len()
+            && (suffix.is_empty()
+                || (suffix.len() == 1 && suffix.chars().all(|c| c.is_ascii_alphabetic()))
+                || self.house_number_suffixes.contains(suffix))
+    }
+}
+
+/// The names of the places of the index the words of the queries are compared to
+#[derive(Debug, Default)]
+struct Lexicon {
+    cities: HashSet<String>,
+    /// the code of the country of each name
+    countries: HashMap<String, String>,
+    zip_codes: HashSet<String>,
+}
+
+impl Lexicon {
+    fn new<'a>(admins: impl Iterator<Item = &'a Admin>) -> Self {
+        let mut lexicon = Lexicon::default();
+        for admin in admins {
+            let names = || {
+                std::iter::once(admin.name.as_str())
+                    .chain(admin.names.0.iter().map(|p| p.value.as_str()))
+                    .map(|name| words(name).join(" "))
+                    .filter(|name| !name.is_empty())
+            };
+            if admin.is_city() {
+                lexicon.cities.extend(names());
+                lexicon
+                    .zip_codes
+                    .extend(admin.zip_codes.iter().map(|z| z.to_lowercase()));
+            } else if matches!(admin.zone_type, Some(ZoneType::Country)) {
+                if let Some(code) = admin.country_codes.first() {
+                    let code = code.to_lowercase();
+                    lexicon
+                        .countries
+                        .extend(names().map(|name| (name, code.clone())));
+                }
+            }
+        }
+        lexicon
+    }
+}
+
+/// The parser of the queries, with the rules of the countries and the names of the admins of
+/// the index (updated by `watch_admins`)
+#[derive(Debug)]
+pub struct QueryParser {
+    rules: HashMap<String, CountryRules>,
+    lexicon: RwLock<Arc<Lexicon>>,
+}
+
+impl QueryParser {
+    /// Read the rules of the countries from `rules_file` (or from the bundled rules)
+    pub fn new(rules_file: Option<&Path>) -> Result<Self, String> {
+        let content = match rules_file {
+            Some(file) => read_to_string(file)
+                .map_err(|e| format!("Failed to read `{}`: {}", file.display(), e))?,
+            None => BUNDLED_RULES.to_owned(),
+        };
+        let rules: HashMap<String, CountryRules> = toml::from_str(&content).map_err(|e| {
+            format!(
+                "failed to parse `{}`: {}",
+                rules_file.map_or("config/address-rules.toml".into(), |f| f
+                    .display()
+                    .to_string()),
+                e
+            )
+        })?;
+        // the rules are compared to the normalized words of the queries
+        let rules = rules
+            .into_iter()
+            .map(|(code, mut country)| {
+                let normalize = |values: &mut Vec<String>| {
+                    *values = values.iter().map(|v| words(v).join(" ")).collect();
+                };
+                normalize(&mut country.names);
+                normalize(&mut country.street_types);
+                normalize(&mut country.street_particles);
+                normalize(&mut country.house_number_suffixes);
+                (code.to_lowercase(), country)
+            })
+            .collect();
+        Ok(QueryParser {
+            rules,
+            lexicon: RwLock::new(Arc::new(Lexicon::default())),
+        })
+    }
+
+    /// Use the names and the zip codes of `admins`, in place of the previous ones
+    pub fn set_admins<'a>(&self, admins: impl Iterator<Item = &'a Admin>) {
+        let lexicon = Lexicon::new(admins);
+        info!(
+            "query parser: {} cities, {} country names and {} zip codes known",
+            lexicon.cities.len(),
+            lexicon.countries.len(),
+            lexicon.zip_codes.len()
+        );
+        *self.lexicon.write().unwrap() = Arc::new(lexicon);
+    }
+
+    /// Tag the words of `q` with the components of an address, and find the streets of `q` if
+    /// it is an intersection
+    pub fn parse(&self, q: &str) -> ParsedQuery {
+        let mut parsed_query = self.parse_address(q);
+        parsed_query.intersection = self.parse_intersection(q);
+        parsed_query
+    }
+
+    /// The streets of the two sides of the first separator of an intersection in `q`, each side
+    /// being parsed as an address (its postcode, city and country are not part of the street)
+    fn parse_intersection(&self, q: &str) -> Vec<String> {
+        let lowercase = q.to_lowercase();
+        let split = INTERSECTION_SEPARATORS
+            .iter()
+            .filter_map(|separator| {
+                lowercase
+                    .find(separator)
+                    .map(|i| (&lowercase[..i], &lowercase[i + separator.len()..]))
+            })
+            .next();
+        let (first, second) = match split {
+            Some(split) => split,
+            None => return vec![],
+        };
+        let street = |part: &str| {
+            let parsed_query = self.parse_address(part);
+            let street = parsed_query
+                .words
+                .iter()
+                .filter(|w| {
+                    !matches!(
+                        w.component,
+                        Some(Component::Postcode)
+                            | Some(Component::City)
+                            | Some(Component::Country)
+                    )
+                })
+                .map(|w| w.word.as_str())
+                .collect::<Vec<_>>();
+            if street.is_empty() {
+                None
+            } else {
+                Some(street.join(" "))
+            }
+        };
+        match (street(first), street(second)) {
+            // a street is not only made of numbers, like in `12/14 rue de la paix`
+            (Some(first), Some(second))
+                if [&first, &second]
+                    .iter()
+                    .all(|s| s.chars().any(|c| c.is_alphabetic())) =>
+            {
+                vec![first, second]
+            }
+            _ => vec![],
+        }
+    }
+
+    /// Tag the words of `q` with the components of an address
+    fn parse_address(&self, q: &str) -> ParsedQuery {
+        let lexicon = self.lexicon.read().unwrap().clone();
+        let words = words(q);
+        let mut tags = vec![None; words.len()];
+
+        // The country is searched first, its rules are then used for the other components.
+        // A query of only a country is the search of the country itself.
+        let country_code = |name: &str| {
+            lexicon.countries.get(name).cloned().or_else(|| {
+                self.rules
+                    .iter()
+                    .find(|(_, rules)| rules.names.iter().any(|n| n == name))
+                    .map(|(code, _)| code.clone())
+            })
+        };
+        let mut country = None;
+        if let Some((start, end, code)) = find_name(&words, &tags, country_code) {
+            if end - start < words.len() {
+                tag(&mut tags, start, end, Component::Country);
+                country = Some(code);
+            }
+        }
+        let rules = match country.as_ref().and_then(|code| self.rules.get(code)) {
+            Some(rules) => Rules::new(std::iter::once(rules)),
+            None => Rules::new(self.rules.values()),
+        };
+
+        // The zip codes of the index, or if it has none the numbers with the digits of a postcode
+        for (word, t) in words.iter().zip(tags.iter_mut()) {
+            let is_postcode = if lexicon.zip_codes.is_empty() {
+                word.chars().all(|c| c.is_ascii_digit())
+                    && rules.postcode_digits.contains(&word.len())
+            } else {
+                lexicon.zip_codes.contains(word)
+            };
+            if t.is_none() && is_postcode {
+                *t = Some(Component::Postcode);
+                break;
+            }
+        }
+
+        // A single number is not taken as a house number, the query could be the beginning
+        // of a postcode or of a name
+        let mut house_number = None;
+        if words.len() > 1 {
+            if let Some(i) =
+                (0..words.len()).find(|&i| tags[i].is_none() && rules.is_house_number(&words[i]))
+            {
+                tags[i] = Some(Component::HouseNumber);
+                house_number = Some(i);
+                let is_suffix = |w: &String| rules.house_number_suffixes.contains(w.as_str());
+                if words.get(i + 1).map_or(false, is_suffix) {
+                    tags[i + 1] = Some(Component::HouseNumber);
+                }
+            }
+        }
+
+        // The street name follows the street type. Without type, it is the words before the
+        // house number (for the countries where it follows the street), or else after it.
+        let street_type = (0..words.len())
+            .find(|&i| tags[i].is_none() && rules.street_types.contains(words[i].as_str()));
+        let is_city = |name: &str| lexicon.cities.contains(name);
+        match (street_type, house_number) {
+            (Some(i), _) => {
+                tags[i] = Some(Component::StreetType);
+                tag_street_name(&words, &mut tags, i + 1, &rules, is_city);
+            }
+            (None, Some(i)) => match (0..i).rev().take_while(|&j| tags[j].is_none()).last() {
+                Some(start) => tag(&mut tags, start, i, Component::StreetName),
+                None => {
+                    let start = (i + 1..words.len())
+                        .find(|&j| tags[j] != Some(Component::HouseNumber))
+                        .unwrap_or(words.len());
+                    tag_street_name(&words, &mut tags, start, &rules, is_city);
+                }
+            },
+            (None, None) => {}
+        }
+
+        // The city is one of the names left
+        let city = |name: &str| if is_city(name) { Some(()) } else { None };
+        if let Some((start, end, ())) = find_name(&words, &tags, city) {
+            tag(&mut tags, start, end, Component::City);
+        }
+
+        ParsedQuery::new(words, tags, country)
+    }
+}
+
+fn tag(tags: &mut [Option<Component>], start: usize, end: usize, component: Component) {
+    for t in &mut tags[start..end] {
+        *t = Some(component);
+    }
+}
+
+/// The longest sequence of untagged words (the last one if several have the same length)
+/// recognized by `find`
+fn find_name<T>(
+    words: &[String],
+    tags: &[Option<Component>],
+    find: impl Fn(&str) -> Option<T>,
+) -> Option<(usize, usize, T)> {
+    for len in (1..=MAX_NAME_WORDS.min(words.len())).rev() {
+        for start in (0..=words.len() - len).rev() {
+            let end = start + len;
+            if tags[start..end].iter().all(Option::is_none) {
+                if let Some(found) = find(&words[start..end].join(" ")) {
+                    return Some((start, end, found));
+                }
+            }
+        }
+    }
+    None
+}
+
+/// Tag the street name starting at `start`: the untagged words until a city, the particles
+/// and the first word of the name never being taken as a city
+fn tag_street_name(
+    words: &[String],
+    tags: &mut [Option<Component>],
+    start: usize,
+    rules: &Rules,
+    is_city: impl Fn(&str) -> bool,
+) {
+    let mut has_name = false;
+    let mut end = start;
+    while end < words.len() && tags[end].is_none() {
+        if has_name
+            && (1..=MAX_NAME_WORDS.min(words.len() - end))
+                .any(|len| is_city(&words[end..end + len].join(" ")))
+        {
+            break;
+        }
+        has_name |= !rules.street_particles.contains(words[end].as_str());
+        end += 1;
+    }
+    tag(tags, start, end, Component::StreetName);
+}
+
+/// Update the names known by the parser each time the admins of the index change
+/// (when a new dataset is published).
+pub fn watch_admins<B>(parser: &Arc<QueryParser>, mut backend: B, interval: Duration)
+where
+    B: SearchBackend + Send + 'static,
+{
+    let mut known_aliases = None;
+    watch(parser, interval, move |parser| {
+        match backend.get_all_aliased_index(&get_main_type_index::<Admin>()) {
+            Ok(aliases) if known_aliases.as_ref() != Some(&aliases) => {
+                match backend.get_admin_names() {
+                    Ok(admins) => {
+                        parser.set_admins(admins.iter());
+                        known_aliases = Some(aliases);
+                    }
+                    Err(err) => warn!("impossible to read the admins: {}", err),
+                }
+            }
+            Ok(_) => {}
+            Err(err) => warn!("impossible to check the published admins: {}", err),
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn city(name: &str, zip_codes: &[&str]) -> Admin {
+        Admin {
+            name: name.to_string(),
+            zip_codes: zip_codes.iter().map(|z| z.to_string()).collect(),
+            zone_type: Some(ZoneType::City),
+            ..Default::default()
+        }
+    }
+
+    fn parser() -> QueryParser {
+        let parser = QueryParser::new(None).unwrap();
+        let mut france = Admin {
+            name: "France".to_string(),
+            zone_type: Some(ZoneType::Country),
+            ..Default::default()
+        };
+        france.country_codes = vec!["FR".to_string()];
+        let admins = vec![
+            city("Paris", &["75012", "75013"]),
+            city("Saint-Denis", &["93200"]),
+            city("Lyon", &["69001"]),
+            france,
+        ];
+        parser.set_admins(admins.iter());
+        parser
+    }
+
+    #[test]
+    fn parse_full_address() {
+        let parsed = parser().parse("15 bis rue de Paris, 75012 Paris France");
+        assert_eq!(parsed.house_number.as_deref(), Some("15 bis"));
+        assert_eq!(parsed.street_type.as_deref(), Some("rue"));
+        assert_eq!(parsed.street_name.as_deref(), Some("de paris"));
+        assert_eq!(parsed.street().as_deref(), Some("rue de paris"));
+        assert_eq!(parsed.postcode.as_deref(), Some("75012"));
+        assert_eq!(parsed.city.as_deref(), Some("paris"));
+        assert_eq!(parsed.country.as_deref(), Some("fr"));
+    }
+
+    #[test]
+    fn parse_street_followed_by_city() {
+        let parsed = parser().parse("12A Avenue Hector Malot Saint Denis");
+        assert_eq!(parsed.house_number.as_deref(), Some("12a"));
+        assert_eq!(parsed.street().as_deref(), Some("avenue hector malot"));
+        assert_eq!(parsed.city.as_deref(), Some("saint denis"));
+        assert_eq!(parsed.postcode, None);
+    }
+
+    #[test]
+    fn parse_street_without_type() {
+        let parsed = parser().parse("Hauptstrasse 5 Lyon");
+        assert_eq!(parsed.house_number.as_deref(), Some("5"));
+        assert_eq!(parsed.street_name.as_deref(), Some("hauptstrasse"));
+        assert_eq!(parsed.city.as_deref(), Some("lyon"));
+    }
+
+    #[test]
+    fn parse_single_words() {
+        let parser = parser();
+        assert_eq!(parser.parse("12").house_number, None);
+        assert_eq!(parser.parse("france").country, None);
+        assert_eq!(parser.parse("paris").city.as_deref(), Some("paris"));
+        assert_eq!(parser.parse("75013").postcode.as_deref(), Some("75013"));
+        assert_eq!(parser.parse("75014").postcode, None);
+    }
+
+    #[test]
+    fn parse_intersections() {
+        let parser = parser();
+        let parsed = parser.parse("Rue de Rivoli & rue du Louvre Paris");
+        assert_eq!(parsed.intersection, vec!["rue de rivoli", "rue du louvre"]);
+        assert_eq!(parsed.city.as_deref(), Some("paris"));
+        assert_eq!(
+            parsed.intersection_text().as_deref(),
+            Some("rue de rivoli rue du louvre paris")
+        );
+        assert_eq!(
+            parser.parse("Main Street x 5th Avenue").intersection,
+            vec!["main street", "5th avenue"]
+        );
+        assert_eq!(
+            parser
+                .parse("rue de Lyon / boulevard Diderot 75012")
+                .intersection,
+            vec!["rue de lyon", "boulevard diderot"]
+        );
+        assert!(parser.parse("12/14 rue de la paix").intersection.is_empty());
+        assert!(parser.parse("rue de la paix /").intersection.is_empty());
+        assert!(parser.parse("rue de l'xylophone").intersection.is_empty());
+    }
+
+    #[test]
+    fn parse_without_admins() {
+        let parsed = QueryParser::new(None)
+            .unwrap()
+            .parse("3 rue du moulin 21000 dijon");
+        assert_eq!(parsed.house_number.as_deref(), Some("3"));
+        assert_eq!(parsed.street().as_deref(), Some("rue du moulin"));
+        assert_eq!(parsed.postcode.as_deref(), Some("21000"));
+        assert_eq!(parsed.city, None);
+    }
+}
@@ -1,4 +1,11 @@
-use serde::Deserialize;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use slog_scope::info;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::read_to_string;
+use std::iter;
+use std::sync::{Arc, RwLock};
 
 #[derive(Clone, Debug, Deserialize)]
 pub struct Types {
@@ -7,6 +14,13 @@ pub struct Types {
     pub stop: f64,
     pub poi: f64,
     pub street: f64,
+    /// The intersections are only searched when the query is one (cf `QueryParser`)
+    #[serde(default)]
+    pub intersection: f64,
+    #[serde(default)]
+    pub postcode: f64,
+    #[serde(default)]
+    pub locality: f64,
 }
 
 #[derive(Clone, Debug, Deserialize)]
@@ -24,6 +38,11 @@ pub struct StringQueryBoosts {
     pub house_number: f64,
     pub label_ngram_with_coord: f64,
     pub label_ngram: f64,
+    /// Boosts of the street and the city recognized in the query by the query parser
+    #[serde(default)]
+    pub street: f64,
+    #[serde(default)]
+    pub city: f64,
 }
 
 #[derive(Clone, Debug, Deserialize)]
@@ -69,15 +88,226 @@ pub struct ImportanceQueryBoosts {
     pub weights: Weights,
 }
 
+/// How the query matches the places during a stage of the search
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MatchType {
+    /// All the words of the query are words of the place
+    Exact,
+    /// All the words of the query are the beginning of words of the place
+    Prefix,
+    /// Most of the words of the query are close to words of the place
+    Fuzzy,
+}
+
+impl fmt::Display for MatchType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let printable = match *self {
+            MatchType::Exact => "exact",
+            MatchType::Prefix => "prefix",
+            MatchType::Fuzzy => "fuzzy",
+        };
+        write!(f, "{}", printable)
+    }
+}
+
+/// How the places found by a stage are merged with the places of the previous stages
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MergePolicy {
+    /// The new places come after the previous ones
+    Append,
+    /// The new places alternate with the previous ones
+    Interleave,
+    /// All the places are sorted by confidence
+    Rescore,
+}
+
+impl Default for MergePolicy {
+    fn default() -> Self {
+        MergePolicy::Append
+    }
+}
+
+fn default_min_results() -> usize {
+    1
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Stage {
+    #[serde(rename = "match")]
+    pub match_type: MatchType,
+    /// The next stages are not run when at least `min_results` places have been found
+    /// with a confidence of at least `min_confidence`.
+    #[serde(default = "default_min_results")]
+    pub min_results: usize,
+    #[serde(default)]
+    pub min_confidence: f64,
+    #[serde(default)]
+    pub merge: MergePolicy,
+}
+
+/// The stages of the search, run in order until enough places are found
+#[derive(Clone, Debug, Deserialize)]
+pub struct Cascade {
+    pub stages: Vec<Stage>,
+    /// Send all the stages at once instead of one after the other
+    #[serde(default)]
+    pub parallel: bool,
+    /// Total time budget of the stages in milliseconds: no stage is started after it when
+    /// they are run one after the other, and it is the timeout of elasticsearch otherwise.
+    pub budget: Option<u64>,
+}
+
+impl Default for Cascade {
+    fn default() -> Self {
+        let stage = |match_type| Stage {
+            match_type,
+            min_results: default_min_results(),
+            min_confidence: 0.,
+            merge: MergePolicy::default(),
+        };
+        Cascade {
+            stages: vec![stage(MatchType::Prefix), stage(MatchType::Fuzzy)],
+            parallel: false,
+            budget: None,
+        }
+    }
+}
+
 #[derive(Clone, Debug, Deserialize)]
 pub struct QuerySettings {
     pub type_query: TypeQueryBoosts,
     pub string_query: StringQuery,
     pub importance_query: ImportanceQueryBoosts,
+    #[serde(default)]
+    pub cascade: Cascade,
 }
 
 impl QuerySettings {
     pub fn new(settings: &str) -> Result<QuerySettings, String> {
-        toml::from_str(settings).map_err(|e| e.to_string())
+        let settings: QuerySettings = toml::from_str(settings).map_err(|e| e.to_string())?;
+        if settings.cascade.stages.is_empty() {
+            return Err("the cascade should have at least one stage".to_owned());
+        }
+        Ok(settings)
+    }
+}
+
+/// The name of the profile of `--weight-config-file` (or of the bundled settings),
+/// used when a request does not select a profile
+pub const DEFAULT_PROFILE: &str = "default";
+
+const BUNDLED_SETTINGS: &str = include_str!("../../../config/bragi-settings.toml");
+
+/// The named query settings profiles, each one read from a file (or from the bundled settings)
+/// and reloaded with `reload`
+#[derive(Debug)]
+pub struct QuerySettingsProfiles {
+    files: Vec<(String, Option<String>)>,
+    profiles: RwLock<HashMap<String, Arc<QuerySettings>>>,
+    /// The profiles among which the requests without profile are randomly assigned,
+    /// with their weight
+    ab_test: Vec<(String, f64)>,
+}
+
+fn read_profiles(
+    files: &[(String, Option<String>)],
+) -> Result<HashMap<String, Arc<QuerySettings>>, String> {
+    files
+        .iter()
+        .map(|(name, file)| {
+            let content = match file {
+                Some(file) => {
+                    read_to_string(file).map_err(|e| format!("Failed to read `{}`: {}", file, e))?
+                }
+                None => BUNDLED_SETTINGS.to_owned(),
+            };
+            let settings = QuerySettings::new(&content).map_err(|err| {
+                format!(
+                    "failed to parse `{}`: {}",
+                    file.as_deref().unwrap_or("config/bragi-settings.toml"),
+                    err
+                )
+            })?;
+            Ok((name.clone(), Arc::new(settings)))
+        })
+        .collect()
+}
+
+impl QuerySettingsProfiles {
+    /// Read the profiles: the default one from `default_file` (or the bundled settings),
+    /// and the others from their file.
+    pub fn new(
+        default_file: Option<&str>,
+        files: &[(String, String)],
+        ab_test: &[(String, f64)],
+    ) -> Result<Self, String> {
+        let files = iter::once((DEFAULT_PROFILE.to_owned(), default_file.map(str::to_owned)))
+            .chain(
+                files
+                    .iter()
+                    .map(|(name, file)| (name.clone(), Some(file.clone()))),
+            )
+            .collect::<Vec<_>>();
+        let profiles = read_profiles(&files)?;
+        if profiles.len() != files.len() {
+            return Err("the names of the settings profiles must be unique".to_owned());
+        }
+        for (name, weight) in ab_test {
+            if !profiles.contains_key(name) {
+                return Err(format!(
+                    "unknown settings profile `{}` in the A/B test",
+                    name
+                ));
+            }
+            if *weight <= 0. {
+                return Err(format!(
+                    "the weight of `{}` in the A/B test must be positive",
+                    name
+                ));
+            }
+        }
+        Ok(QuerySettingsProfiles {
+            files,
+            profiles: RwLock::new(profiles),
+            ab_test: ab_test.to_vec(),
+        })
+    }
+
+    /// Read all the profiles again, they are kept if one of them is invalid.
+    /// The names of the profiles are returned.
+    pub fn reload(&self) -> Result<Vec<String>, String> {
+        let profiles = read_profiles(&self.files)?;
+        *self.profiles.write().unwrap() = profiles;
+        info!("{} query settings profiles reloaded", self.files.len());
+        Ok(self.files.iter().map(|(name, _)| name.clone()).collect())
+    }
+
+    /// The profile `name`, or if not given a profile of the A/B test (randomly chosen
+    /// according to their weights), or else the default profile
+    pub fn get(&self, name: Option<&str>) -> Option<(String, Arc<QuerySettings>)> {
+        let name = name.unwrap_or_else(|| self.pick_ab_test_profile());
+        self.profiles
+            .read()
+            .unwrap()
+            .get(name)
+            .map(|settings| (name.to_owned(), settings.clone()))
+    }
+
+    fn pick_ab_test_profile(&self) -> &str {
+        if self.ab_test.is_empty() {
+            return DEFAULT_PROFILE;
+        }
+        let total: f64 = self.ab_test.iter().map(|(_, weight)| weight).sum();
+        let mut draw = rand::thread_rng().gen_range(0., total);
+        for (name, weight) in &self.ab_test {
+            if draw < *weight {
+                return name;
+            }
+            draw -= weight;
+        }
+        // the draw can only be left by a rounding error
+        &self.ab_test[self.ab_test.len() - 1].0
     }
 }
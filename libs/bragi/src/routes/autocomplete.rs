@@ -1,23 +1,31 @@
+use crate::coord_parser::{self, CoordLiteral};
 use crate::extractors::BragiQuery;
-use crate::model::{Autocomplete, BragiError, FromWithLang};
-use crate::routes::params;
-use crate::{model, query, Context};
-use actix_http::http::header::{CacheControl, CacheDirective};
-use actix_web::web::{Data, HttpResponse, Json};
+use crate::model::{Autocomplete, BragiError, BragiFuture, Feature, FromWithLang};
+use crate::routes::{params, reverse};
+use crate::{auth_middleware, cache, model, prometheus_middleware, query, Context, QuerySettings};
+use actix_web::web::{Data, HttpRequest, HttpResponse, Json};
+use futures::{future, Future};
 use geojson::{GeoJson, Geometry};
-use mimir::objects::{Coord, PlaceDocType};
+use mimir::objects::{Coord, PlaceDocType, Wheelchair};
 use serde::{Deserialize, Serialize};
 use slog_scope::trace;
+use std::sync::Arc;
 use std::time::Duration;
 
 #[derive(Copy, Clone, Debug, Deserialize, Serialize)]
-enum Type {
+pub(super) enum Type {
     #[serde(rename = "city")]
     City,
     #[serde(rename = "house")]
     House,
+    #[serde(rename = "intersection")]
+    Intersection,
+    #[serde(rename = "locality")]
+    Locality,
     #[serde(rename = "poi")]
     Poi,
+    #[serde(rename = "postcode")]
+    Postcode,
     #[serde(rename = "public_transport:stop_area")]
     StopArea,
     #[serde(rename = "street")]
@@ -27,11 +35,14 @@ enum Type {
 }
 
 impl Type {
-    fn as_str(&self) -> &'static str {
+    pub(super) fn as_str(&self) -> &'static str {
         match self {
             Type::City => "city",
             Type::House => "house",
+            Type::Intersection => "intersection",
+            Type::Locality => "locality",
             Type::Poi => "poi",
+            Type::Postcode => "postcode",
             Type::StopArea => "public_transport:stop_area",
             Type::Street => "street",
             Type::Zone => "zone",
@@ -87,16 +98,31 @@ pub struct Params {
     zone_types: Vec<cosmogony::ZoneType>,
     #[serde(default, rename = "poi_type")]
     poi_types: Vec<PoiType>,
+    // Filters on the details of the POIs
+    wheelchair: Option<Wheelchair>,
+    open_now: Option<bool>,
     lang: Option<String>,
     // The scope is a list of place types on which we apply the shape filter.
     // Places found in this list are restricted to the shape.
     #[serde(default)]
     shape_scope: Vec<PlaceDocType>,
+    // A bounding box (min_lon,min_lat,max_lon,max_lat), like the viewport of a map, used as a
+    // filter (restricted to the types of shape_scope if given) or as a bias toward its places.
+    bbox: Option<String>,
+    #[serde(default)]
+    bbox_mode: params::BboxMode,
+    // Ids of admins (like admin:osm:relation:120965) in which the places are searched,
+    // restricted to the types of shape_scope if given.
+    #[serde(default)]
+    within: Vec<String>,
     // Forwards a request for explanation to Elastic Search.
     // This parameter is useful to analyze the order in which search results appear.
     // It is prefixed by an underscore to indicate its not a public parameter.
     #[serde(default, rename = "_debug")]
     debug: Option<bool>,
+    // Name of the query settings profile used to rank the places
+    #[serde(rename = "_settings")]
+    settings: Option<String>,
 
     // Embeds a client id into the request to improve tracing
     request_id: Option<String>,
@@ -112,23 +138,71 @@ impl Params {
     fn poi_types_as_str(&self) -> Vec<&str> {
         self.poi_types.iter().map(PoiType::as_str).collect()
     }
+    fn poi_filters(&self) -> query::PoiFilters {
+        query::PoiFilters {
+            wheelchair: self.wheelchair,
+            open_now: self.open_now,
+        }
+    }
     fn coord(&self) -> Result<Option<Coord>, BragiError> {
         Self::build_coord(self.lon, self.lat)
     }
+    fn bbox(&self) -> Result<Option<query::BoundingBox>, BragiError> {
+        params::make_bounding_box(self.bbox.as_deref(), self.bbox_mode)
+    }
+    fn within(&self) -> Result<Vec<&str>, BragiError> {
+        params::make_within(&self.within)
+    }
     fn langs(&self) -> Vec<&str> {
         self.lang.iter().map(|l| l.as_str()).collect()
     }
     fn timeout(&self) -> Option<Duration> {
         self.timeout.map(Duration::from_millis)
     }
-    fn build_coord(lon: Option<f64>, lat: Option<f64>) -> Result<Option<Coord>, BragiError> {
-        match (lon, lat) {
-            (Some(lon), Some(lat)) => Ok(Some(params::make_coord(lon, lat)?)),
-            (None, None) => Ok(None),
-            _ => Err(BragiError::InvalidParam(
-                "you should provide a 'lon' AND a 'lat' parameter if you provide one of them",
-            )),
+    fn authorize(&mut self, req: &HttpRequest) -> Result<(), BragiError> {
+        auth_middleware::authorize_datasets(
+            req,
+            &mut self.pt_dataset,
+            &mut self.poi_dataset,
+            self.all_data,
+        )
+    }
+    /// The query settings of the profile of the request, which is given to the request
+    /// if it has not selected one
+    fn select_settings(
+        &mut self,
+        state: &Context,
+        req: &HttpRequest,
+    ) -> Result<Arc<QuerySettings>, BragiError> {
+        let (profile, query_settings) = state.get_query_settings(self.settings.as_deref())?;
+        prometheus_middleware::set_settings_profile(req, &profile);
+        self.settings = Some(profile);
+        Ok(query_settings)
+    }
+    /// Reject the parameters that the embedded index would otherwise silently ignore
+    fn check_embedded(&self, state: &Context) -> Result<(), BragiError> {
+        if state.get_embedded().is_none() {
+            return Ok(());
+        }
+        if self.lang.is_some() {
+            return Err(BragiError::InvalidParam(
+                "the lang parameter is not supported by the embedded index",
+            ));
         }
+        if self.settings.is_some() {
+            return Err(BragiError::InvalidParam(
+                "the settings profiles are not supported by the embedded index",
+            ));
+        }
+        if !self.shape_scope.is_empty() && (self.bbox.is_some() || !self.within.is_empty()) {
+            return Err(BragiError::InvalidParam(
+                "the shape_scope is not supported by the embedded index",
+            ));
+        }
+        Ok(())
+    }
+    fn build_coord(lon: Option<f64>, lat: Option<f64>) -> Result<Option<Coord>, BragiError> {
+        params::make_optional_coord(lon, lat)
     }
 }
 
@@ -147,13 +221,42 @@ impl JsonParams {
 }
 
 pub fn call_autocomplete(
+    params: &mut Params,
+    state: &Context,
+    req: &HttpRequest,
+    shape: Option<Geometry>,
+) -> BragiFuture<HttpResponse> {
+    // the datasets and the settings profile are set before they are part of the key of the cache
+    let query_settings = match params
+        .authorize(req)
+        .and_then(|_| params.check_embedded(state))
+        .and_then(|_| params.select_settings(state, req))
+    {
+        Ok(query_settings) => query_settings,
+        Err(err) => return Box::new(future::err(err)),
+    };
+    // the shape is not part of the key, so only the GET requests are cached,
+    // and the places open now change with the time of the request
+    let key = match shape {
+        Some(_) => None,
+        None if params.open_now.is_some() => None,
+        None => Some(cache::cache_key("autocomplete", params)),
+    };
+    cache::respond(state, req, "autocomplete", key, || {
+        autocomplete_content(params, state, shape, &query_settings)
+    })
+}
+
+/// The search of the response of an autocomplete and whether it is complete
+fn autocomplete_content(
     params: &Params,
     state: &Context,
     shape: Option<Geometry>,
-) -> Result<HttpResponse, model::BragiError> {
+    query_settings: &QuerySettings,
+) -> Result<BragiFuture<(Autocomplete, bool)>, model::BragiError> {
     let langs = params.langs();
-    let rubber = state.get_rubber_for_autocomplete(params.timeout());
-    let mut query_settings = state.get_query_settings().clone();
+    let es_client = state.get_es_client_for_autocomplete(params.timeout());
+    let mut query_settings = query_settings.clone();
 
     if let Some(scale) = params.proximity_scale {
         query_settings.importance_query.proximity.gaussian.scale = scale;
@@ -171,58 +274,179 @@ pub fn call_autocomplete(
         trace!("routes::autocomplete by {} ({})", id, params.q);
     }
 
-    let res = query::autocomplete(
-        &params.q,
-        &params
-            .pt_dataset
-            .iter()
-            .map(String::as_str)
-            .collect::<Vec<_>>(),
-        &params
-            .poi_dataset
-            .iter()
-            .map(String::as_str)
-            .collect::<Vec<_>>(),
-        params.all_data,
-        params.offset,
-        params.limit,
-        params.coord()?,
-        shape,
-        &params.shape_scope,
-        &params.types_as_str(),
-        &params.zone_types_as_str(),
-        &params.poi_types_as_str(),
-        &langs,
-        rubber,
-        params.debug.unwrap_or(false),
-        &query_settings,
-        params.request_id.as_deref(),
-    );
-    res.map(|r| Autocomplete::from_with_lang(r, langs.into_iter().next()))
-        .map(|v| {
-            HttpResponse::Ok()
-                .set(CacheControl(vec![CacheDirective::MaxAge(
-                    state.http_cache_duration,
-                )]))
-                .json(v)
-        })
+    if let Some(literal) = coord_parser::parse_coord(&params.q, params.coord()?.as_ref()) {
+        return Ok(coordinate_content(params, state, literal));
+    }
+
+    let bbox = params.bbox()?;
+    let within = params.within()?;
+    let parsed_query = state.get_query_parser().parse(&params.q);
+    let res: BragiFuture<_> = match state.get_embedded() {
+        Some(embedded) => {
+            if shape.is_some() {
+                return Err(BragiError::InvalidParam(
+                    "the shape filter is not supported by the embedded index",
+                ));
+            }
+            let bbox = match bbox {
+                Some(query::BoundingBox::Filter(rect)) => Some(rect),
+                Some(query::BoundingBox::Bias(_)) => {
+                    return Err(BragiError::InvalidParam(
+                        "the bbox bias is not supported by the embedded index",
+                    ));
+                }
+                None => None,
+            };
+            Box::new(future::result(
+                query::embedded_autocomplete(
+                    &params.q,
+                    &parsed_query,
+                    &params
+                        .pt_dataset
+                        .iter()
+                        .map(String::as_str)
+                        .collect::<Vec<_>>(),
+                    &params
+                        .poi_dataset
+                        .iter()
+                        .map(String::as_str)
+                        .collect::<Vec<_>>(),
+                    params.all_data,
+                    params.offset,
+                    params.limit,
+                    params.coord()?,
+                    &params.types_as_str(),
+                    &params.zone_types_as_str(),
+                    &params.poi_types_as_str(),
+                    &params.poi_filters(),
+                    bbox.as_ref(),
+                    &within,
+                    embedded,
+                )
+                // the embedded index does not tell which stage found the places
+                .map(|places| (places, None, false)),
+            ))
+        }
+        None => Box::new(
+            query::autocomplete(
+                &params.q,
+                &parsed_query,
+                &params
+                    .pt_dataset
+                    .iter()
+                    .map(String::as_str)
+                    .collect::<Vec<_>>(),
+                &params
+                    .poi_dataset
+                    .iter()
+                    .map(String::as_str)
+                    .collect::<Vec<_>>(),
+                params.all_data,
+                params.offset,
+                params.limit,
+                params.coord()?,
+                shape,
+                &params.shape_scope,
+                bbox.as_ref(),
+                &within,
+                &params.types_as_str(),
+                &params.zone_types_as_str(),
+                &params.poi_types_as_str(),
+                &params.poi_filters(),
+                &langs,
+                es_client,
+                params.debug.unwrap_or(false),
+                &query_settings,
+                params.request_id.as_deref(),
+            )
+            .map(|res| (res.places, Some(res.match_type), res.partial)),
+        ),
+    };
+    let lang = params.lang.clone();
+    // the response depends on the time of the request with open_now, it is not reused
+    let is_timeless = params.open_now.is_none();
+    let parsed_query = if params.debug.unwrap_or(false) {
+        Some(parsed_query)
+    } else {
+        None
+    };
+    Ok(Box::new(res.map(move |(places, match_type, partial)| {
+        let autocomplete =
+            Autocomplete::from_with_lang(places, lang.as_deref()).with_partial(partial);
+        let autocomplete = match match_type {
+            Some(match_type) => autocomplete.with_match_stage(match_type),
+            None => autocomplete,
+        };
+        let autocomplete = match parsed_query {
+            Some(parsed_query) => autocomplete.with_parsed_query(parsed_query),
+            None => autocomplete,
+        };
+        (autocomplete, !partial && is_timeless)
+    })))
+}
+
+/// The response of an autocomplete on a coordinate: the coordinate itself, followed by the
+/// address found at this position
+fn coordinate_content(
+    params: &Params,
+    state: &Context,
+    literal: CoordLiteral,
+) -> BragiFuture<(Autocomplete, bool)> {
+    let types = params.types_as_str();
+    let pt_datasets = params
+        .pt_dataset
+        .iter()
+        .map(String::as_str)
+        .collect::<Vec<_>>();
+    let poi_datasets = params
+        .poi_dataset
+        .iter()
+        .map(String::as_str)
+        .collect::<Vec<_>>();
+    let places: BragiFuture<_> = match state.get_embedded() {
+        Some(embedded) => Box::new(future::result(query::embedded_reverse(
+            &literal.coord,
+            &types,
+            &pt_datasets,
+            &poi_datasets,
+            reverse::default_radius(),
+            1,
+            embedded.clone(),
+        ))),
+        None => query::reverse(
+            &literal.coord,
+            &types,
+            &pt_datasets,
+            &poi_datasets,
+            reverse::default_radius(),
+            1,
+            state.get_es_client_for_reverse(params.timeout()),
+        ),
+    };
+    let lang = params.lang.clone();
+    Box::new(places.map(move |places| {
+        let mut autocomplete = Autocomplete::from_with_lang(places, lang.as_deref());
+        autocomplete.features.insert(0, Feature::from(literal));
+        (autocomplete, true)
+    }))
 }
 
 pub fn autocomplete(
-    params: BragiQuery<Params>,
+    mut params: BragiQuery<Params>,
     state: Data<Context>,
-) -> Result<HttpResponse, model::BragiError> {
-    call_autocomplete(&*params, &*state, None)
+    req: HttpRequest,
+) -> BragiFuture<HttpResponse> {
+    call_autocomplete(&mut *params, &*state, &req, None)
 }
 
 pub fn post_autocomplete(
-    params: BragiQuery<Params>,
+    mut params: BragiQuery<Params>,
     state: Data<Context>,
+    req: HttpRequest,
     json_params: Json<JsonParams>,
-) -> Result<HttpResponse, model::BragiError> {
-    call_autocomplete(
-        &*params,
-        &*state,
-        Some(json_params.into_inner().get_geometry()?),
-    )
+) -> BragiFuture<HttpResponse> {
+    match json_params.into_inner().get_geometry() {
+        Ok(shape) => call_autocomplete(&mut *params, &*state, &req, Some(shape)),
+        Err(err) => Box::new(future::err(err)),
+    }
 }
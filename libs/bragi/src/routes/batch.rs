@@ -0,0 +1,484 @@
+use crate::es_client::EsClient;
+use crate::extractors::BragiQuery;
+use crate::model::{Autocomplete, BragiError, BragiFuture, FromWithLang};
+use crate::routes::autocomplete::Type;
+use crate::routes::params;
+use crate::{
+    auth_middleware, prometheus_middleware, query, semaphore, Context, QueryParser, QuerySettings,
+};
+use actix_web::error::{ErrorInternalServerError, PayloadError};
+use actix_web::http::header::CONTENT_TYPE;
+use actix_web::web::{Bytes, Data, HttpResponse, Payload};
+use actix_web::HttpRequest;
+use futures::{future, stream, try_ready, Async, Future, Poll, Stream};
+use mimir::objects::{Coord, PlaceDocType, Wheelchair};
+use serde::{Deserialize, Serialize};
+use slog_scope::{error, info};
+use std::collections::VecDeque;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Max size of a row of a batch request
+const MAX_BATCH_ROW_SIZE: usize = 64 * 1024;
+
+fn default_limit() -> u64 {
+    1u64
+}
+
+/// Parameters shared by all the rows of a batch
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Params {
+    #[serde(default)]
+    pt_dataset: Vec<String>,
+    #[serde(default)]
+    poi_dataset: Vec<String>,
+    #[serde(rename = "_all_data", default)]
+    all_data: bool,
+    #[serde(default = "default_limit")]
+    limit: u64,
+    /// timeout in milliseconds, for each chunk of rows
+    timeout: Option<u64>,
+    #[serde(default, rename = "type")]
+    types: Vec<Type>,
+    #[serde(default, rename = "zone_type")]
+    zone_types: Vec<cosmogony::ZoneType>,
+    #[serde(default, rename = "poi_type")]
+    poi_types: Vec<String>,
+    // Filters on the details of the POIs
+    wheelchair: Option<Wheelchair>,
+    open_now: Option<bool>,
+    lang: Option<String>,
+    // Same as the parameters of /autocomplete, without the shape
+    #[serde(default)]
+    shape_scope: Vec<PlaceDocType>,
+    bbox: Option<String>,
+    #[serde(default)]
+    bbox_mode: params::BboxMode,
+    #[serde(default)]
+    within: Vec<String>,
+    // Name of the query settings profile used to rank the places
+    #[serde(rename = "_settings")]
+    settings: Option<String>,
+}
+
+impl Params {
+    fn poi_filters(&self) -> query::PoiFilters {
+        query::PoiFilters {
+            wheelchair: self.wheelchair,
+            open_now: self.open_now,
+        }
+    }
+    fn bbox(&self) -> Result<Option<query::BoundingBox>, BragiError> {
+        params::make_bounding_box(self.bbox.as_deref(), self.bbox_mode)
+    }
+    fn within(&self) -> Result<Vec<&str>, BragiError> {
+        params::make_within(&self.within)
+    }
+    fn authorize(&mut self, req: &HttpRequest) -> Result<(), BragiError> {
+        auth_middleware::authorize_datasets(
+            req,
+            &mut self.pt_dataset,
+            &mut self.poi_dataset,
+            self.all_data,
+        )
+    }
+    /// The query settings of the profile of the request, which is given to the request
+    /// if it has not selected one
+    fn select_settings(
+        &mut self,
+        state: &Context,
+        req: &HttpRequest,
+    ) -> Result<Arc<QuerySettings>, BragiError> {
+        let (profile, query_settings) = state.get_query_settings(self.settings.as_deref())?;
+        prometheus_middleware::set_settings_profile(req, &profile);
+        self.settings = Some(profile);
+        Ok(query_settings)
+    }
+}
+
+/// A row of the batch, read from a NDJSON line or a CSV record
+#[derive(Deserialize, Debug)]
+struct Row {
+    q: String,
+    lat: Option<f64>,
+    lon: Option<f64>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(untagged)]
+enum RowResponse {
+    Ok {
+        row: usize,
+        #[serde(flatten)]
+        response: Autocomplete,
+    },
+    Error {
+        row: usize,
+        error: String,
+    },
+}
+
+/// The lines of a body, read as its chunks are received.
+///
+/// A line longer than `MAX_BATCH_ROW_SIZE` is given as an error, the rest of it being skipped.
+struct Lines<S> {
+    body: S,
+    /// The beginning of the current line
+    line: Vec<u8>,
+    /// The current line is too long, it is skipped until its end
+    is_skipped: bool,
+    /// The lines read and not given yet
+    lines: VecDeque<Result<String, String>>,
+    is_over: bool,
+}
+
+impl<S> Lines<S> {
+    fn new(body: S) -> Self {
+        Lines {
+            body,
+            line: vec![],
+            is_skipped: false,
+            lines: VecDeque::new(),
+            is_over: false,
+        }
+    }
+
+    fn push_chunk(&mut self, chunk: &[u8]) {
+        let mut parts = chunk.split(|b| *b == b'\n');
+        // the last part is the beginning of a line ending in the next chunks
+        let mut part = parts.next().unwrap_or(&[]);
+        for next in parts {
+            self.extend_line(part);
+            self.end_line();
+            part = next;
+        }
+        self.extend_line(part);
+    }
+
+    fn extend_line(&mut self, bytes: &[u8]) {
+        if self.is_skipped {
+            return;
+        }
+        if self.line.len() + bytes.len() > MAX_BATCH_ROW_SIZE {
+            self.is_skipped = true;
+            self.line.clear();
+            self.lines.push_back(Err(format!(
+                "the row is longer than {} bytes",
+                MAX_BATCH_ROW_SIZE
+            )));
+        } else {
+            self.line.extend_from_slice(bytes);
+        }
+    }
+
+    fn end_line(&mut self) {
+        if !self.is_skipped {
+            let line = String::from_utf8_lossy(&self.line).into_owned();
+            self.lines.push_back(Ok(line));
+        }
+        self.line.clear();
+        self.is_skipped = false;
+    }
+}
+
+impl<S> Stream for Lines<S>
+where
+    S: Stream,
+    S::Item: AsRef<[u8]>,
+{
+    type Item = Result<String, String>;
+    type Error = S::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, S::Error> {
+        loop {
+            if let Some(line) = self.lines.pop_front() {
+                return Ok(Async::Ready(Some(line)));
+            }
+            if self.is_over {
+                return Ok(Async::Ready(None));
+            }
+            match try_ready!(self.body.poll()) {
+                Some(chunk) => self.push_chunk(chunk.as_ref()),
+                None => {
+                    self.is_over = true;
+                    self.end_line();
+                }
+            }
+        }
+    }
+}
+
+fn read_ndjson_row(line: Result<String, String>) -> Result<Row, String> {
+    serde_json::from_str(&line?).map_err(|e| format!("invalid row: {}", e))
+}
+
+fn read_csv_record(line: &str) -> Result<csv::StringRecord, csv::Error> {
+    let mut record = csv::StringRecord::new();
+    csv::ReaderBuilder::new()
+        .has_headers(false)
+        .from_reader(line.as_bytes())
+        .read_record(&mut record)?;
+    Ok(record)
+}
+
+/// The rows of CSV lines, the first one being the header
+fn read_csv_rows<S>(lines: S) -> impl Stream<Item = Result<Row, String>, Error = S::Error>
+where
+    S: Stream<Item = Result<String, String>>,
+{
+    lines
+        .into_future()
+        .map_err(|(err, _)| err)
+        .map(|(header, lines)| {
+            let header = header
+                .unwrap_or_else(|| Ok(String::new()))
+                .and_then(|header| read_csv_record(&header).map_err(|e| e.to_string()))
+                .map_err(|e| format!("invalid header: {}", e));
+            lines.map(move |line| {
+                let header = header.as_ref().map_err(String::clone)?;
+                read_csv_record(&line?)
+                    .and_then(|record| record.deserialize(Some(header)))
+                    .map_err(|e| format!("invalid row: {}", e))
+            })
+        })
+        .flatten_stream()
+}
+
+fn check_row(row: Result<Row, String>) -> Result<(String, Option<Coord>), String> {
+    let row = row?;
+    let coord = params::make_optional_coord(row.lon, row.lat).map_err(|e| e.to_string())?;
+    Ok((row.q, coord))
+}
+
+/// Geocode all the rows of a NDJSON (one `{"q": .., "lat": .., "lon": ..}` object per line)
+/// or CSV (with a `q,lat,lon` header) body.
+///
+/// The rows are read as the body is received and split in chunks, each chunk being sent to
+/// elasticsearch in one `_msearch` request by stage of the cascade. The number of chunks
+/// geocoded at the same time is bounded for all the batches together.
+/// The results are streamed back as NDJSON, in the order of the rows, with an error for each
+/// row that could not be geocoded.
+pub fn batch(
+    req: HttpRequest,
+    mut params: BragiQuery<Params>,
+    state: Data<Context>,
+    body: Payload,
+) -> BragiFuture<HttpResponse> {
+    let query_settings = match params
+        .authorize(&req)
+        .and_then(|_| params.bbox())
+        .and_then(|_| params.within().map(|_| ()))
+        .and_then(|_| params.select_settings(&*state, &req))
+    {
+        Ok(query_settings) => query_settings,
+        Err(err) => return Box::new(future::err(err)),
+    };
+    let is_csv = req
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|h| h.to_str().ok())
+        .map(|h| h.starts_with("text/csv"))
+        .unwrap_or(false);
+    let lines = Lines::new(body).filter(|line| match line {
+        Ok(line) => !line.trim().is_empty(),
+        Err(_) => true,
+    });
+    let rows: Box<dyn Stream<Item = Result<Row, String>, Error = PayloadError>> = if is_csv {
+        Box::new(read_csv_rows(lines))
+    } else {
+        Box::new(lines.map(read_ndjson_row))
+    };
+
+    let params = Rc::new((*params).clone());
+    let es_client = state.get_es_client_for_autocomplete(params.timeout.map(Duration::from_millis));
+    let query_parser = state.get_query_parser().clone();
+    let permits = state.get_batch_permits().clone();
+    let chunk_size = state.batch_chunk_size;
+    let nb_workers = state.batch_nb_workers;
+
+    Box::new(
+        rows.into_future()
+            .map_err(|(err, _)| {
+                error!("impossible to read the batch"; "err" => err.to_string());
+                BragiError::InvalidParam("the batch could not be read")
+            })
+            .and_then(move |(first, rows)| {
+                let first = first.ok_or(BragiError::InvalidParam(
+                    "the batch does not contain any row",
+                ))?;
+                info!("batch geocoding");
+
+                let mut nb_rows = 0;
+                let chunks = stream::once(Ok(first))
+                    .chain(rows)
+                    .map(move |row| {
+                        nb_rows += 1;
+                        (nb_rows - 1, row)
+                    })
+                    .chunks(chunk_size)
+                    .map_err(actix_web::Error::from)
+                    .map(move |chunk| {
+                        let params = params.clone();
+                        let es_client = es_client.clone();
+                        let query_settings = query_settings.clone();
+                        let query_parser = query_parser.clone();
+                        // the chunk waits for its turn among the chunks of all the batches
+                        semaphore::acquire(&permits)
+                            .map_err(|()| ErrorInternalServerError("batch failed"))
+                            .and_then(move |permit| {
+                                geocode_chunk(
+                                    chunk,
+                                    &params,
+                                    es_client,
+                                    &query_settings,
+                                    &query_parser,
+                                )
+                                .map(move |lines| {
+                                    drop(permit);
+                                    lines
+                                })
+                            })
+                    })
+                    .buffered(nb_workers);
+
+                Ok(HttpResponse::Ok()
+                    .content_type("application/x-ndjson")
+                    .streaming(chunks))
+            }),
+    )
+}
+
+/// Geocode a chunk of rows and format the responses as NDJSON lines
+fn geocode_chunk(
+    chunk: Vec<(usize, Result<Row, String>)>,
+    params: &Params,
+    es_client: EsClient,
+    query_settings: &QuerySettings,
+    query_parser: &QueryParser,
+) -> impl Future<Item = Bytes, Error = actix_web::Error> {
+    let (rows, checked): (Vec<usize>, Vec<_>) = chunk
+        .into_iter()
+        .map(|(row, r)| (row, check_row(r)))
+        .unzip();
+    let queries = checked
+        .iter()
+        .filter_map(|r| r.as_ref().ok().cloned())
+        .collect::<Vec<_>>();
+
+    let langs = params.lang.iter().map(String::as_str).collect::<Vec<_>>();
+    let results = query::batch_autocomplete(
+        &queries,
+        &params
+            .pt_dataset
+            .iter()
+            .map(String::as_str)
+            .collect::<Vec<_>>(),
+        &params
+            .poi_dataset
+            .iter()
+            .map(String::as_str)
+            .collect::<Vec<_>>(),
+        params.all_data,
+        params.limit,
+        &params.shape_scope,
+        // the parameters have been checked by `batch`
+        params.bbox().unwrap_or(None).as_ref(),
+        &params.within().unwrap_or_default(),
+        &params.types.iter().map(Type::as_str).collect::<Vec<_>>(),
+        &params
+            .zone_types
+            .iter()
+            .map(|x| x.as_str())
+            .collect::<Vec<_>>(),
+        &params
+            .poi_types
+            .iter()
+            .map(String::as_str)
+            .collect::<Vec<_>>(),
+        &params.poi_filters(),
+        &langs,
+        es_client,
+        query_settings,
+        query_parser,
+    );
+
+    let lang = params.lang.clone();
+    results.then(move |results| {
+        let mut results = results
+            .unwrap_or_else(|err| {
+                error!("batch geocoding failed"; "err" => err.to_string());
+                queries.iter().map(|_| Err(err.to_string())).collect()
+            })
+            .into_iter();
+
+        let lines = rows
+            .into_iter()
+            .zip(checked)
+            .map(|(row, checked)| {
+                let res = checked.and_then(|_| {
+                    results
+                        .next()
+                        .unwrap_or_else(|| Err("no result for this row".to_owned()))
+                });
+                let response = match res {
+                    Ok(res) => RowResponse::Ok {
+                        row,
+                        response: Autocomplete::from_with_lang(res.places, lang.as_deref())
+                            .with_match_stage(res.match_type)
+                            .with_partial(res.partial),
+                    },
+                    Err(error) => RowResponse::Error { row, error },
+                };
+                let mut line = serde_json::to_string(&response).unwrap_or_else(|e| {
+                    format!(
+                        r#"{{"row":{},"error":"{}"}}"#,
+                        row,
+                        e.to_string().escape_default()
+                    )
+                });
+                line.push('\n');
+                line
+            })
+            .collect::<String>();
+        Ok(Bytes::from(lines))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_lines(chunks: Vec<&str>) -> Vec<Result<String, String>> {
+        Lines::new(stream::iter_ok::<_, ()>(chunks))
+            .collect()
+            .wait()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_lines_across_chunks() {
+        assert_eq!(
+            read_lines(vec![
+                "{\"q\": \"20 rue",
+                " hector\"}\n{\"q\"",
+                ": \"paris\"}"
+            ]),
+            vec![
+                Ok("{\"q\": \"20 rue hector\"}".to_string()),
+                Ok("{\"q\": \"paris\"}".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_too_long_line() {
+        let long = "a".repeat(MAX_BATCH_ROW_SIZE);
+        let lines = read_lines(vec!["first\n", &long, &long, "\nlast\n"]);
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[0], Ok("first".to_string()));
+        assert!(lines[1].is_err());
+        assert_eq!(lines[2], Ok("last".to_string()));
+        assert_eq!(lines[3], Ok("".to_string()));
+    }
+}
@@ -1,7 +1,8 @@
 use crate::extractors::BragiQuery;
-use crate::{model, model::FromWithLang, query, Context};
-use actix_http::http::header::{CacheControl, CacheDirective};
-use actix_web::web::{Data, HttpResponse, Path};
+use crate::model::{BragiFuture, FromWithLang};
+use crate::{auth_middleware, cache, model, query, Context};
+use actix_web::web::{Data, HttpRequest, HttpResponse, Path};
+use futures::{future, Future};
 use serde::{Deserialize, Serialize};
 use std::time::Duration;
 
@@ -17,34 +18,64 @@ pub struct Params {
     timeout: Option<u64>,
 }
 
+impl Params {
+    fn authorize(&mut self, req: &HttpRequest) -> Result<(), model::BragiError> {
+        auth_middleware::authorize_datasets(
+            req,
+            &mut self.pt_dataset,
+            &mut self.poi_dataset,
+            self.all_data,
+        )
+    }
+}
+
 pub fn features(
-    params: BragiQuery<Params>,
+    mut params: BragiQuery<Params>,
     state: Data<Context>,
     id: Path<String>,
-) -> Result<HttpResponse, model::BragiError> {
-    let rubber = state.get_rubber_for_features(params.timeout.map(Duration::from_millis));
-    let features = query::features(
-        &params
-            .pt_dataset
-            .iter()
-            .map(String::as_str)
-            .collect::<Vec<_>>(),
-        &params
-            .poi_dataset
-            .iter()
-            .map(String::as_str)
-            .collect::<Vec<_>>(),
-        params.all_data,
-        &*id,
-        rubber,
-    );
-    features
-        .map(|r| model::Autocomplete::from_with_lang(r, None))
-        .map(|v| {
-            HttpResponse::Ok()
-                .set(CacheControl(vec![CacheDirective::MaxAge(
-                    state.http_cache_duration,
-                )]))
-                .json(v)
-        })
+    req: HttpRequest,
+) -> BragiFuture<HttpResponse> {
+    if let Err(err) = params.authorize(&req) {
+        return Box::new(future::err(err));
+    }
+    let key = cache::cache_key(&format!("features/{}", id), &*params);
+    cache::respond(&*state, &req, "features", Some(key), || {
+        features_content(&*params, &*state, &*id)
+    })
+}
+
+fn features_content(
+    params: &Params,
+    state: &Context,
+    id: &str,
+) -> Result<BragiFuture<(model::Autocomplete, bool)>, model::BragiError> {
+    let pt_datasets = params
+        .pt_dataset
+        .iter()
+        .map(String::as_str)
+        .collect::<Vec<_>>();
+    let poi_datasets = params
+        .poi_dataset
+        .iter()
+        .map(String::as_str)
+        .collect::<Vec<_>>();
+    let features: BragiFuture<_> = match state.get_embedded() {
+        Some(embedded) => Box::new(future::result(query::embedded_features(
+            &pt_datasets,
+            &poi_datasets,
+            params.all_data,
+            id,
+            embedded.clone(),
+        ))),
+        None => query::features(
+            &pt_datasets,
+            &poi_datasets,
+            params.all_data,
+            id,
+            state.get_es_client_for_features(params.timeout.map(Duration::from_millis)),
+        ),
+    };
+    Ok(Box::new(features.map(|r| {
+        (model::Autocomplete::from_with_lang(r, None), true)
+    })))
 }
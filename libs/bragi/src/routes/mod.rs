@@ -1,12 +1,18 @@
 mod autocomplete;
+mod batch;
 mod entry_point;
 mod features;
 mod params;
 mod reverse;
+mod search;
+mod settings;
 mod status;
 
 pub use autocomplete::{autocomplete, post_autocomplete, JsonParams};
+pub use batch::batch;
 pub use entry_point::entry_point;
 pub use features::features;
 pub use reverse::reverse;
+pub use search::search;
+pub use settings::reload_settings;
 pub use status::status;
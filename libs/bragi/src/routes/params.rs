@@ -1,5 +1,22 @@
 use crate::model::BragiError;
+use crate::query::BoundingBox;
+use geo_types::Rect;
 use mimir::objects::Coord;
+use serde::{Deserialize, Serialize};
+
+/// How the `bbox` parameter restricts the search
+#[derive(Copy, Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BboxMode {
+    Filter,
+    Bias,
+}
+
+impl Default for BboxMode {
+    fn default() -> Self {
+        BboxMode::Filter
+    }
+}
 
 pub fn make_coord(lon: f64, lat: f64) -> Result<Coord, BragiError> {
     if !(-90f64..=90f64).contains(&lat) {
@@ -10,3 +27,71 @@ pub fn make_coord(lon: f64, lat: f64) -> Result<Coord, BragiError> {
         Ok(Coord::new(lon, lat))
     }
 }
+
+pub fn make_optional_coord(
+    lon: Option<f64>,
+    lat: Option<f64>,
+) -> Result<Option<Coord>, BragiError> {
+    match (lon, lat) {
+        (Some(lon), Some(lat)) => Ok(Some(make_coord(lon, lat)?)),
+        (None, None) => Ok(None),
+        _ => Err(BragiError::InvalidParam(
+            "you should provide a 'lon' AND a 'lat' parameter if you provide one of them",
+        )),
+    }
+}
+
+/// Parse a bounding box given as `min_lon,min_lat,max_lon,max_lat`
+pub fn make_bbox(bbox: &str) -> Result<Rect<f64>, BragiError> {
+    let values = bbox
+        .split(',')
+        .map(|v| v.trim().parse::<f64>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| BragiError::InvalidParam("bbox values should be numbers"))?;
+    match values.as_slice() {
+        [min_lon, min_lat, max_lon, max_lat] => {
+            let min = make_coord(*min_lon, *min_lat)?;
+            let max = make_coord(*max_lon, *max_lat)?;
+            if min.lon() >= max.lon() || min.lat() >= max.lat() {
+                return Err(BragiError::InvalidParam(
+                    "bbox should be given as min_lon,min_lat,max_lon,max_lat",
+                ));
+            }
+            Ok(Rect::new(min.0, max.0))
+        }
+        _ => Err(BragiError::InvalidParam(
+            "bbox should be given as min_lon,min_lat,max_lon,max_lat",
+        )),
+    }
+}
+
+/// The bounding box of the `bbox` parameter (if given), used as a filter or a bias
+pub fn make_bounding_box(
+    bbox: Option<&str>,
+    mode: BboxMode,
+) -> Result<Option<BoundingBox>, BragiError> {
+    bbox.map(|bbox| {
+        let rect = make_bbox(bbox)?;
+        Ok(match mode {
+            BboxMode::Filter => BoundingBox::Filter(rect),
+            BboxMode::Bias => BoundingBox::Bias(rect),
+        })
+    })
+    .transpose()
+}
+
+/// Check that the `within` parameter only contains ids of admins
+pub fn make_within(within: &[String]) -> Result<Vec<&str>, BragiError> {
+    within
+        .iter()
+        .map(|id| {
+            if id.starts_with("admin:") {
+                Ok(id.as_str())
+            } else {
+                Err(BragiError::InvalidParam(
+                    "within should only contain ids of admins",
+                ))
+            }
+        })
+        .collect()
+}
@@ -1,34 +1,104 @@
 use crate::extractors::BragiQuery;
+use crate::model::{BragiFuture, FromWithLang};
+use crate::routes::autocomplete::Type;
 use crate::routes::params;
-use crate::{model, model::FromWithLang, Context};
-use actix_http::http::header::{CacheControl, CacheDirective};
-use actix_web::web::{Data, HttpResponse};
+use crate::{auth_middleware, cache, model, query, Context};
+use actix_web::web::{Data, HttpRequest, HttpResponse};
+use futures::{future, Future};
 use serde::{Deserialize, Serialize};
 use std::time::Duration;
 
+fn default_limit() -> u64 {
+    1u64
+}
+
+pub(super) fn default_radius() -> f64 {
+    1000f64
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Params {
     lat: f64,
     lon: f64,
     /// timeout in milliseconds
     timeout: Option<u64>,
+    #[serde(default, rename = "type")]
+    types: Vec<Type>,
+    #[serde(default)]
+    pt_dataset: Vec<String>,
+    #[serde(default)]
+    poi_dataset: Vec<String>,
+    /// max number of places returned around the point (the zones containing it are not limited)
+    #[serde(default = "default_limit")]
+    limit: u64,
+    /// search radius in meters
+    #[serde(default = "default_radius")]
+    radius: f64,
+    lang: Option<String>,
+}
+
+impl Params {
+    fn authorize(&mut self, req: &HttpRequest) -> Result<(), model::BragiError> {
+        auth_middleware::authorize_datasets(req, &mut self.pt_dataset, &mut self.poi_dataset, false)
+    }
 }
 
 pub fn reverse(
-    params: BragiQuery<Params>,
+    mut params: BragiQuery<Params>,
     state: Data<Context>,
-) -> Result<HttpResponse, model::BragiError> {
-    let mut rubber = state.get_rubber_for_reverse(params.timeout.map(Duration::from_millis));
+    req: HttpRequest,
+) -> BragiFuture<HttpResponse> {
+    if let Err(err) = params.authorize(&req) {
+        return Box::new(future::err(err));
+    }
+    let key = cache::cache_key("reverse", &*params);
+    cache::respond(&*state, &req, "reverse", Some(key), || {
+        reverse_content(&*params, &*state)
+    })
+}
+
+fn reverse_content(
+    params: &Params,
+    state: &Context,
+) -> Result<BragiFuture<(model::Autocomplete, bool)>, model::BragiError> {
     let coord = params::make_coord(params.lon, params.lat)?;
-    rubber
-        .get_address(&coord)
-        .map_err(model::BragiError::from)
-        .map(|r| model::Autocomplete::from_with_lang(r, None))
-        .map(|v| {
-            HttpResponse::Ok()
-                .set(CacheControl(vec![CacheDirective::MaxAge(
-                    state.http_cache_duration,
-                )]))
-                .json(v)
-        })
+    if params.radius <= 0. {
+        return Err(model::BragiError::InvalidParam("radius must be positive"));
+    }
+    let types = params.types.iter().map(Type::as_str).collect::<Vec<_>>();
+    let pt_datasets = params
+        .pt_dataset
+        .iter()
+        .map(String::as_str)
+        .collect::<Vec<_>>();
+    let poi_datasets = params
+        .poi_dataset
+        .iter()
+        .map(String::as_str)
+        .collect::<Vec<_>>();
+    let places: BragiFuture<_> = match state.get_embedded() {
+        Some(embedded) => Box::new(future::result(query::embedded_reverse(
+            &coord,
+            &types,
+            &pt_datasets,
+            &poi_datasets,
+            params.radius,
+            params.limit,
+            embedded.clone(),
+        ))),
+        None => query::reverse(
+            &coord,
+            &types,
+            &pt_datasets,
+            &poi_datasets,
+            params.radius,
+            params.limit,
+            state.get_es_client_for_reverse(params.timeout.map(Duration::from_millis)),
+        ),
+    };
+    let lang = params.lang.clone();
+    Ok(Box::new(places.map(move |places| {
+        let response = model::Autocomplete::from_with_lang(places, lang.as_deref());
+        (response, true)
+    })))
 }
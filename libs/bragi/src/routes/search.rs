@@ -0,0 +1,125 @@
+use crate::extractors::BragiQuery;
+use crate::model::{Autocomplete, BragiError, BragiFuture, FromWithLang};
+use crate::query::StructuredFields;
+use crate::routes::params;
+use crate::{auth_middleware, prometheus_middleware, query, Context, QuerySettings};
+use actix_http::http::header::{CacheControl, CacheDirective};
+use actix_web::web::{Data, HttpRequest, HttpResponse};
+use futures::{future, Future};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::Duration;
+
+fn default_limit() -> u64 {
+    10u64
+}
+
+/// Parameters of a structured geocoding request: the address is already split
+/// into its components, each of them being optional.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Params {
+    housenumber: Option<String>,
+    street: Option<String>,
+    postcode: Option<String>,
+    city: Option<String>,
+    /// ISO 3166-1 alpha-2 country code
+    country: Option<String>,
+    #[serde(default)]
+    pt_dataset: Vec<String>,
+    #[serde(default)]
+    poi_dataset: Vec<String>,
+    #[serde(rename = "_all_data", default)]
+    all_data: bool,
+    #[serde(default = "default_limit")]
+    limit: u64,
+    #[serde(default)]
+    offset: u64,
+    /// timeout in milliseconds
+    timeout: Option<u64>,
+    // Position of the request
+    lat: Option<f64>,
+    lon: Option<f64>,
+    lang: Option<String>,
+    // Name of the query settings profile used to rank the places
+    #[serde(rename = "_settings")]
+    settings: Option<String>,
+}
+
+impl Params {
+    fn fields(&self) -> StructuredFields<'_> {
+        StructuredFields {
+            house_number: self.housenumber.as_deref(),
+            street: self.street.as_deref(),
+            postcode: self.postcode.as_deref(),
+            city: self.city.as_deref(),
+            country: self.country.as_deref(),
+        }
+    }
+    fn authorize(&mut self, req: &HttpRequest) -> Result<(), BragiError> {
+        auth_middleware::authorize_datasets(
+            req,
+            &mut self.pt_dataset,
+            &mut self.poi_dataset,
+            self.all_data,
+        )
+    }
+    /// The query settings of the profile of the request, which is given to the request
+    /// if it has not selected one
+    fn select_settings(
+        &mut self,
+        state: &Context,
+        req: &HttpRequest,
+    ) -> Result<Arc<QuerySettings>, BragiError> {
+        let (profile, query_settings) = state.get_query_settings(self.settings.as_deref())?;
+        prometheus_middleware::set_settings_profile(req, &profile);
+        self.settings = Some(profile);
+        Ok(query_settings)
+    }
+}
+
+pub fn search(
+    mut params: BragiQuery<Params>,
+    state: Data<Context>,
+    req: HttpRequest,
+) -> BragiFuture<HttpResponse> {
+    let query_settings = match params
+        .authorize(&req)
+        .and_then(|_| params.select_settings(&*state, &req))
+    {
+        Ok(query_settings) => query_settings,
+        Err(err) => return Box::new(future::err(err)),
+    };
+    let es_client = state.get_es_client_for_autocomplete(params.timeout.map(Duration::from_millis));
+    let coord = match params::make_optional_coord(params.lon, params.lat) {
+        Ok(coord) => coord,
+        Err(err) => return Box::new(future::err(err)),
+    };
+    let res = query::structured_search(
+        &params.fields(),
+        &params
+            .pt_dataset
+            .iter()
+            .map(String::as_str)
+            .collect::<Vec<_>>(),
+        &params
+            .poi_dataset
+            .iter()
+            .map(String::as_str)
+            .collect::<Vec<_>>(),
+        params.all_data,
+        params.offset,
+        params.limit,
+        coord,
+        es_client,
+        &query_settings,
+    );
+    let lang = params.lang.clone();
+    let http_cache_duration = state.http_cache_duration;
+    Box::new(res.map(move |r| {
+        HttpResponse::Ok()
+            .set(CacheControl(vec![CacheDirective::MaxAge(
+                http_cache_duration,
+            )]))
+            .json(Autocomplete::from_with_lang(r, lang.as_deref()))
+    }))
+}
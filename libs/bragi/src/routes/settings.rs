@@ -0,0 +1,21 @@
+use crate::model::BragiError;
+use crate::{auth_middleware, Context};
+use actix_web::web::{Data, HttpRequest, Json};
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Profiles {
+    pub profiles: Vec<String>,
+}
+
+/// Reload the query settings profiles from their files
+pub fn reload_settings(
+    state: Data<Context>,
+    req: HttpRequest,
+) -> Result<Json<Profiles>, BragiError> {
+    auth_middleware::authorize_admin(&req)?;
+    let profiles = state
+        .reload_query_settings()
+        .map_err(BragiError::InvalidSettings)?;
+    Ok(Json(Profiles { profiles }))
+}
@@ -0,0 +1,118 @@
+//! A semaphore for the futures, bounding the number of tasks done at the same time
+//! (like the chunks of all the /batch requests geocoded together).
+
+use futures::task::{self, Task};
+use futures::{Async, Future, Poll};
+use std::sync::{Arc, Mutex};
+
+#[derive(Debug)]
+struct State {
+    permits: usize,
+    // the tasks waiting for a permit, they are all woken up when one is released
+    waiting: Vec<Task>,
+}
+
+#[derive(Debug)]
+pub struct Semaphore {
+    state: Mutex<State>,
+}
+
+impl Semaphore {
+    pub fn new(permits: usize) -> Semaphore {
+        Semaphore {
+            state: Mutex::new(State {
+                permits,
+                waiting: vec![],
+            }),
+        }
+    }
+}
+
+/// Wait for a permit of `semaphore`, which is given back when the `Permit` is dropped.
+pub fn acquire(semaphore: &Arc<Semaphore>) -> Acquire {
+    Acquire {
+        semaphore: semaphore.clone(),
+    }
+}
+
+pub struct Acquire {
+    semaphore: Arc<Semaphore>,
+}
+
+impl Future for Acquire {
+    type Item = Permit;
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<Permit, ()> {
+        let mut state = self.semaphore.state.lock().unwrap();
+        if state.permits == 0 {
+            state.waiting.push(task::current());
+            return Ok(Async::NotReady);
+        }
+        state.permits -= 1;
+        Ok(Async::Ready(Permit {
+            semaphore: self.semaphore.clone(),
+        }))
+    }
+}
+
+pub struct Permit {
+    semaphore: Arc<Semaphore>,
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        let mut state = self.semaphore.state.lock().unwrap();
+        state.permits += 1;
+        // the tasks that do not get the permit wait again
+        for task in state.waiting.drain(..) {
+            task.notify();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::{future, stream, Stream};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn test_semaphore_bounds_the_tasks() {
+        let semaphore = Arc::new(Semaphore::new(2));
+        let running = Arc::new(AtomicUsize::new(0));
+        let max_running = Arc::new(Mutex::new(0));
+
+        let tasks = (0..10).map(|i| {
+            let running = running.clone();
+            let max_running = max_running.clone();
+            acquire(&semaphore).and_then(move |permit| {
+                let nb = running.fetch_add(1, Ordering::SeqCst) + 1;
+                {
+                    let mut max = max_running.lock().unwrap();
+                    *max = (*max).max(nb);
+                }
+                // let the other tasks try to get a permit before this one is over
+                let mut yielded = false;
+                future::poll_fn(move || {
+                    if yielded {
+                        running.fetch_sub(1, Ordering::SeqCst);
+                        Ok(Async::Ready(i))
+                    } else {
+                        yielded = true;
+                        task::current().notify();
+                        Ok(Async::NotReady)
+                    }
+                })
+                .map(move |i| {
+                    drop(permit);
+                    i
+                })
+            })
+        });
+        let done = stream::futures_unordered(tasks).collect().wait().unwrap();
+
+        assert_eq!(done.len(), 10);
+        assert_eq!(*max_running.lock().unwrap(), 2);
+    }
+}
@@ -1,11 +1,18 @@
+use crate::auth_middleware::ApiKeyAuth;
+use crate::cache::ResponseCache;
 use crate::extractors::ActixError;
 use crate::routes::{
-    autocomplete, entry_point, features, post_autocomplete, reverse, status, JsonParams,
+    autocomplete, batch, entry_point, features, post_autocomplete, reload_settings, reverse,
+    search, status, JsonParams,
 };
-use crate::{Args, Context};
+use crate::{Args, Context, QuerySettingsProfiles};
 use actix_web::FromRequest;
 use actix_web::{middleware, web, App, HttpRequest, HttpServer};
+use signal_hook::iterator::Signals;
+use slog_scope::warn;
 use std::convert::TryInto;
+use std::sync::Arc;
+use std::thread;
 use structopt::StructOpt;
 
 pub fn default_404(req: HttpRequest) -> Result<web::Json<()>, ActixError> {
@@ -21,8 +28,8 @@ pub fn configure_server(cfg: &mut web::ServiceConfig) {
     .service(
         web::resource("/autocomplete")
             .name("autocomplete")
-            .route(web::get().to(autocomplete))
-            .route(web::post().to(post_autocomplete))
+            .route(web::get().to_async(autocomplete))
+            .route(web::post().to_async(post_autocomplete))
             .data(web::Json::<JsonParams>::configure(|cfg| {
                 cfg.error_handler(|err, _req| ActixError::InvalidJson(format!("{}", err)).into())
             })),
@@ -35,23 +42,62 @@ pub fn configure_server(cfg: &mut web::ServiceConfig) {
     .service(
         web::resource("/features/{id}")
             .name("features")
-            .route(web::get().to(features)),
+            .route(web::get().to_async(features)),
     )
     .service(
         web::resource("/reverse")
             .name("reverse")
-            .route(web::get().to(reverse)),
+            .route(web::get().to_async(reverse)),
+    )
+    .service(
+        web::resource("/search")
+            .name("search")
+            .route(web::get().to_async(search)),
+    )
+    .service(
+        web::resource("/settings/reload")
+            .name("reload_settings")
+            .route(web::post().to(reload_settings)),
+    )
+    .service(
+        web::resource("/batch")
+            .name("batch")
+            .route(web::post().to_async(batch)),
     );
 }
 
+/// Reload the query settings profiles each time bragi receives a SIGHUP,
+/// clearing the cache of the responses
+fn reload_settings_on_sighup(
+    profiles: Arc<QuerySettingsProfiles>,
+    cache: Option<Arc<ResponseCache>>,
+) -> Result<(), String> {
+    let signals = Signals::new(&[signal_hook::SIGHUP])
+        .map_err(|e| format!("failed to listen to SIGHUP: {}", e))?;
+    thread::spawn(move || {
+        for _ in signals.forever() {
+            if let Err(err) = crate::reload_query_settings(&profiles, cache.as_deref()) {
+                warn!("impossible to reload the query settings: {}", err);
+            }
+        }
+    });
+    Ok(())
+}
+
 pub fn runserver() -> Result<(), String> {
     let args = Args::from_args();
     let ctx: Context = (&args).try_into()?;
+    reload_settings_on_sighup(
+        ctx.get_query_settings_profiles().clone(),
+        ctx.get_cache().cloned(),
+    )?;
     let prometheus = crate::prometheus_middleware::PrometheusMetrics::new("bragi", "/metrics");
     HttpServer::new(move || {
         App::new()
             .data(ctx.clone())
             // NOTE: if some middlewares are added, don't forget to add them in the tests too (in BragiHandler::new)
+            // the api keys are checked after the CORS preflight requests are answered
+            .wrap(ApiKeyAuth::new(ctx.get_api_keys().cloned()))
             .wrap(actix_cors::Cors::new().allowed_methods(vec!["GET"]))
             .wrap(prometheus.clone())
             .wrap(middleware::Logger::default())
@@ -0,0 +1,22 @@
+//! Background checks of the resources bragi reloads while running
+
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// Run `check` on `target` in a background thread, then again every `interval`.
+/// The thread stops when `target` is dropped.
+pub fn watch<T, F>(target: &Arc<T>, interval: Duration, mut check: F)
+where
+    T: Send + Sync + 'static,
+    F: FnMut(&T) + Send + 'static,
+{
+    let target = Arc::downgrade(target);
+    thread::spawn(move || loop {
+        match target.upgrade() {
+            Some(target) => check(&target),
+            None => return,
+        }
+        thread::sleep(interval);
+    });
+}
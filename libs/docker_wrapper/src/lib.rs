@@ -27,6 +27,8 @@
 // IRC #navitia on freenode
 // https://groups.google.com/d/forum/navitia
 // www.navitia.io
+use mimir::backend::SearchBackend;
+use mimir::rest_backend::RestBackend;
 use mimir::rubber::Rubber;
 use slog_scope::{info, warn};
 use std::error::Error;
@@ -37,6 +39,7 @@ use std::time::Duration;
 /// Allowing to setup a docker, tear it down and to provide its address and port
 pub struct DockerWrapper {
     ip: String,
+    name: &'static str,
 }
 
 impl DockerWrapper {
@@ -44,10 +47,11 @@ impl DockerWrapper {
         format!("http://{}:9200", self.ip)
     }
 
-    fn setup(&mut self) -> Result<(), Box<dyn Error>> {
-        info!("Launching ES docker");
+    fn setup(&mut self, image_args: &[&str]) -> Result<(), Box<dyn Error>> {
+        info!("Launching ES docker {}", self.name);
         let status = Command::new("docker")
-            .args(&["run", "-d", "--name=mimirsbrunn_tests", "elasticsearch:2"])
+            .args(&["run", "-d", &format!("--name={}", self.name)])
+            .args(image_args)
             .status()?;
         if !status.success() {
             return Err(format!("`docker run` failed {}", &status).into());
@@ -58,7 +62,7 @@ impl DockerWrapper {
             .args(&[
                 "inspect",
                 "--format={{.NetworkSettings.IPAddress}}",
-                "mimirsbrunn_tests",
+                self.name,
             ])
             .output()?;
 
@@ -86,15 +90,31 @@ impl DockerWrapper {
     }
 
     pub fn new() -> Result<DockerWrapper, Box<dyn Error>> {
-        let mut wrapper = DockerWrapper { ip: "".to_string() };
-        wrapper.setup()?;
+        let mut wrapper = DockerWrapper {
+            ip: "".to_string(),
+            name: "mimirsbrunn_tests",
+        };
+        wrapper.setup(&["elasticsearch:2"])?;
         let rubber = Rubber::new_with_timeout(&wrapper.host(), Duration::from_secs(10)); // use a long timeout
         rubber.initialize_templates().unwrap();
         Ok(wrapper)
     }
+
+    /// Launch an Elasticsearch 7 docker, to be used with `--backend rest`
+    pub fn new_rest() -> Result<DockerWrapper, Box<dyn Error>> {
+        let mut wrapper = DockerWrapper {
+            ip: "".to_string(),
+            name: "mimirsbrunn_rest_tests",
+        };
+        wrapper.setup(&["-e", "discovery.type=single-node", "elasticsearch:7.10.1"])?;
+        let backend = RestBackend::new_with_timeout(&wrapper.host(), Duration::from_secs(10))
+            .map_err(|e| e.to_string())?;
+        backend.initialize_templates().unwrap();
+        Ok(wrapper)
+    }
 }
 
-fn docker_command(args: &[&'static str]) {
+fn docker_command(args: &[&str]) {
     info!("Running docker {:?}", args);
     let status = Command::new("docker").args(args).status();
     match status {
@@ -113,11 +133,12 @@ impl Drop for DockerWrapper {
             warn!(
                 "the docker won't be stoped at the end, you can debug it.
             Note: ES has been mapped to the port 9242 in you localhost
-            manually stop and rm the container mimirsbrunn_tests after debug"
+            manually stop and rm the container {} after debug",
+                self.name
             );
             return;
         }
-        docker_command(&["stop", "mimirsbrunn_tests"]);
-        docker_command(&["rm", "mimirsbrunn_tests"]);
+        docker_command(&["stop", self.name]);
+        docker_command(&["rm", self.name]);
     }
 }
@@ -0,0 +1,360 @@
+// Copyright © 2016, Canal TP and/or its affiliates. All rights reserved.
+//
+// This file is part of Navitia,
+//     the software to build cool stuff with public transport.
+//
+// Hope you'll enjoy and contribute to this project,
+//     powered by Canal TP (www.canaltp.fr).
+// Help us simplify mobility and open public transport:
+//     a non ending quest to the responsive locomotion way of traveling!
+//
+// LICENCE: This program is free software; you can redistribute it
+// and/or modify it under the terms of the GNU Affero General Public
+// License as published by the Free Software Foundation, either
+// version 3 of the License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+// Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public
+// License along with this program. If not, see
+// <http://www.gnu.org/licenses/>.
+//
+// Stay tuned using
+// twitter @navitia
+// IRC #navitia on freenode
+// https://groups.google.com/d/forum/navitia
+// www.navitia.io
+
+use super::objects::{Admin, Coord, MimirObject, Place};
+use super::rubber::{
+    build_proximity_with_boost, get_date_index_name, get_indexes, get_main_type_and_dataset_index,
+    get_main_type_index, IndexSettings, IndexVisibility, PatchOperation, TypedIndex,
+};
+use failure::{bail, format_err, Error, ResultExt};
+use rs_es::query::Query;
+use rs_es::units as rs_u;
+use serde::de::DeserializeOwned;
+use slog_scope::{debug, info};
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+/// The search engine behind a connection string, selected with the `--backend` parameter
+/// of the importers and of bragi
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BackendKind {
+    /// Elasticsearch 2, with `Rubber`
+    Es2,
+    /// The recent Elasticsearch (7+) and OpenSearch versions, with `RestBackend`
+    Rest,
+}
+
+impl Default for BackendKind {
+    fn default() -> Self {
+        BackendKind::Es2
+    }
+}
+
+impl FromStr for BackendKind {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "es2" => Ok(BackendKind::Es2),
+            "rest" => Ok(BackendKind::Rest),
+            _ => bail!("unknown backend '{}', expected 'es2' or 'rest'", s),
+        }
+    }
+}
+
+/// The operations mimir needs from a search engine, to import the data and to query it.
+///
+/// The indexes and aliases follow the same naming scheme whatever the backend
+/// (cf `get_indexes`), and the queries are built with the `rs_es` query builders,
+/// each backend being in charge of translating them for its search engine.
+///
+/// `Rubber` implements it for Elasticsearch 2 (with a mapping type by document type),
+/// `RestBackend` for the recent Elasticsearch and OpenSearch versions (without mapping types).
+pub trait SearchBackend {
+    /// Create the index templates for all the document types
+    fn initialize_templates(&self) -> Result<(), Error>;
+
+    fn create_index(&self, name: &str, index_settings: &IndexSettings) -> Result<(), Error>;
+
+    /// Add all the elements of `iter` in the index, return the number of elements indexed
+    fn bulk_index<T, I>(&mut self, index: &TypedIndex<T>, iter: I) -> Result<usize, Error>
+    where
+        T: MimirObject + std::marker::Send + 'static,
+        I: Iterator<Item = T>;
+
+    /// Make the indexed documents visible to the searches
+    fn refresh_index(&mut self, index: &str) -> Result<(), Error>;
+
+    /// Get all the indexes whose name starts with `{base_index}_`, with their aliases
+    fn get_all_aliased_index(
+        &self,
+        base_index: &str,
+    ) -> Result<BTreeMap<String, Vec<String>>, Error>;
+
+    /// add a list of new indexes to the alias
+    /// remove a list of indexes from the alias
+    fn alias(&self, alias: &str, add: &[String], remove: &[String]) -> Result<(), Error>;
+
+    fn delete_index(&mut self, index: &str) -> Result<(), Error>;
+
+    /// Delete the documents with the ids `ids` from `index`, return the number of deleted documents.
+    /// The ids that are not in the index are ignored.
+    fn delete_docs<T: MimirObject>(
+        &mut self,
+        index: &TypedIndex<T>,
+        ids: &[String],
+    ) -> Result<usize, Error>;
+
+    /// Apply the `operations` to the documents of `index`,
+    /// return the number of documents changed.
+    fn patch_index<T: MimirObject>(
+        &mut self,
+        index: &TypedIndex<T>,
+        operations: &[PatchOperation<T>],
+    ) -> Result<usize, Error>;
+
+    /// Apply the `operations` to the documents of the index currently published for
+    /// the doc_type `T` and `dataset`, and make them visible to the searches.
+    fn patch<T: MimirObject>(
+        &mut self,
+        dataset: &str,
+        operations: &[PatchOperation<T>],
+    ) -> Result<usize, Error> {
+        let index = self.published_index::<T>(dataset)?.ok_or_else(|| {
+            format_err!(
+                "no {} index published for the dataset {}",
+                T::doc_type(),
+                dataset
+            )
+        })?;
+        let nb_patched = self.patch_index(&index, operations)?;
+        self.refresh_index(&index.name)?;
+        Ok(nb_patched)
+    }
+
+    /// Get all the documents of type `T` of `index`
+    fn get_all_objects<T>(&mut self, index: &str) -> Result<Vec<T>, Error>
+    where
+        T: MimirObject + DeserializeOwned + std::fmt::Debug;
+
+    /// Search the places matching `query` in `indexes`.
+    /// The distance of the places to `coord` is computed if it is given.
+    fn search(
+        &mut self,
+        indexes: &[&str],
+        query: &Query,
+        from: u64,
+        size: u64,
+        coord: Option<&Coord>,
+    ) -> Result<Vec<Place>, Error>;
+
+    /// Get all the admins whose boundary contains `coord`, from the smallest to the largest.
+    fn get_admins_containing(&mut self, coord: &Coord) -> Result<Vec<Place>, Error>;
+
+    /// Get the postcodes whose boundary contains `coord`, the nearest first.
+    fn get_postcodes_containing(&mut self, coord: &Coord) -> Result<Vec<Place>, Error>;
+
+    /// Get all the admins of the public admin indexes
+    fn get_all_admins(&mut self) -> Result<Vec<Admin>, Error>;
+
+    /// Get all the admins of the public admin indexes, with at least the fields
+    /// of `ADMIN_NAME_FIELDS` (the backends reading from a server only fetch those)
+    fn get_admin_names(&mut self) -> Result<Vec<Admin>, Error> {
+        self.get_all_admins()
+    }
+
+    /// Get the address (house or street) nearest to `coord`, in a 1km radius
+    fn get_address(&mut self, coord: &Coord) -> Result<Vec<Place>, Error> {
+        let indexes = get_indexes(false, &[], &[], &["house", "street"]);
+        let indexes = indexes
+            .iter()
+            .map(|index| index.as_str())
+            .collect::<Vec<&str>>();
+
+        self.get_places_around(coord, &indexes, 1000., 1)
+    }
+
+    /// Get the places with the id `id` in `indexes`
+    fn get_by_id(&mut self, indexes: &[&str], id: &str) -> Result<Vec<Place>, Error> {
+        let val = rs_u::JsonVal::String(id.into());
+        let query = Query::build_bool()
+            .with_filter(Query::build_ids(vec![val]).build())
+            .build();
+        self.search(indexes, &query, 0, 10, None)
+    }
+
+    /// Get the `limit` places of `indexes` nearest to `coord`, in a `radius` (in meters),
+    /// sorted by distance.
+    fn get_places_around(
+        &mut self,
+        coord: &Coord,
+        indexes: &[&str],
+        radius: f64,
+        limit: u64,
+    ) -> Result<Vec<Place>, Error> {
+        let distance = rs_u::Distance::new(radius, rs_u::DistanceUnit::Meter);
+        let geo_distance =
+            Query::build_geo_distance("coord", (coord.lat(), coord.lon()), distance).build();
+        let query = Query::build_bool()
+            .with_should(build_proximity_with_boost(coord, 1.))
+            .with_must(geo_distance)
+            .build();
+        let mut places = self.search(indexes, &query, 0, limit, Some(coord))?;
+        // the score decreases with the distance, but some places can have the same score
+        places.sort_by_key(|p| p.distance());
+        Ok(places)
+    }
+
+    /// Add the elements of `iter` in `index`, replacing the documents with the same ids.
+    /// Return the number of elements indexed.
+    fn upsert_docs<T, I>(&mut self, index: &TypedIndex<T>, iter: I) -> Result<usize, Error>
+    where
+        T: MimirObject + std::marker::Send + 'static,
+        I: Iterator<Item = T>,
+    {
+        // indexing a document replaces the document with the same id
+        self.bulk_index(index, iter)
+    }
+
+    /// Get the index currently published for the doc_type `T` and `dataset`, if any
+    fn published_index<T: MimirObject>(
+        &self,
+        dataset: &str,
+    ) -> Result<Option<TypedIndex<T>>, Error> {
+        let dataset_index = get_main_type_and_dataset_index::<T>(dataset);
+        Ok(self
+            .get_all_aliased_index(&dataset_index)?
+            .into_iter()
+            .find(|(_, aliases)| aliases.contains(&dataset_index))
+            .map(|(index, _)| TypedIndex::new(index)))
+    }
+
+    fn make_index<T: MimirObject>(
+        &self,
+        dataset: &str,
+        index_settings: &IndexSettings,
+    ) -> Result<TypedIndex<T>, Error> {
+        let index_name = get_date_index_name(&get_main_type_and_dataset_index::<T>(dataset));
+        info!("creating index {}", index_name);
+        self.create_index(&index_name, index_settings)?;
+        Ok(TypedIndex::new(index_name))
+    }
+
+    /// publish the index as the new index for this doc_type and this dataset
+    /// move the index alias of the doc_type and the dataset to point to this indexes
+    /// and remove the old index
+    /// visibility: Indicate if the index is public or private
+    fn publish_index<T: MimirObject>(
+        &mut self,
+        dataset: &str,
+        index: TypedIndex<T>,
+        visibility: IndexVisibility,
+    ) -> Result<(), Error> {
+        debug!("publishing index");
+
+        // Refresh index before publishing
+        self.refresh_index(&index.name)?;
+
+        // get the last indexes for this doc_type/dataset
+        // Note: to be resilient to ghost ES indexes, we take all indexes for this doc_type/dataset
+        // but the new index
+        let dataset_index = get_main_type_and_dataset_index::<T>(dataset);
+        let last_indexes = self
+            .get_all_aliased_index(&dataset_index)?
+            .into_iter()
+            .map(|(k, _)| k)
+            .filter(|i| i.as_str() != index.name)
+            .collect::<Vec<_>>();
+
+        self.alias(&dataset_index, &[index.name], &last_indexes)
+            .with_context(|err| {
+                format!(
+                    "Error occurred when making alias {}: {}",
+                    dataset_index, err
+                )
+            })?;
+
+        let type_index = get_main_type_index::<T>();
+        if let IndexVisibility::Public = visibility {
+            self.alias(&type_index, &[dataset_index], &last_indexes)
+                .with_context(|err| {
+                    format!("Error occurred when making alias {}: {}", type_index, err)
+                })?;
+
+            if T::is_geo_data() {
+                self.alias("munin_geo_data", &[type_index], &[])
+                    .context("Error occurred when making alias: munin_geo_data")?;
+                self.alias("munin", &["munin_geo_data".to_string()], &[])
+                    .context("Error occurred when making alias: munin")?;
+            } else {
+                self.alias("munin", &[type_index], &[])
+                    .context("Error occurred when making alias: munin")?;
+            }
+        }
+
+        for i in last_indexes {
+            self.delete_index(&i)
+                .with_context(|err| format!("Error occurred when deleting index {}: {}", i, err))?;
+        }
+        Ok(())
+    }
+
+    /// add all the element of 'iter' into the search engine
+    ///
+    /// To have zero downtime:
+    /// first all the elements are added in a temporary index and when all has been indexed
+    /// the index is published and the old index is removed
+    fn index<T, I>(
+        &mut self,
+        dataset: &str,
+        visibility: IndexVisibility,
+        index_settings: &IndexSettings,
+        iter: I,
+    ) -> Result<usize, Error>
+    where
+        T: MimirObject + std::marker::Send + 'static,
+        I: Iterator<Item = T>,
+    {
+        let index = self
+            .make_index(dataset, index_settings)
+            .with_context(|err| format!("Error occurred when making index {}: {}", dataset, err))?;
+        let nb_elements = self.bulk_index(&index, iter)?;
+        self.publish_index(dataset, index, visibility)?;
+        Ok(nb_elements)
+    }
+
+    /// Shortcut to `index` for a public index
+    fn public_index<T, I>(
+        &mut self,
+        dataset: &str,
+        index_settings: &IndexSettings,
+        iter: I,
+    ) -> Result<usize, Error>
+    where
+        T: MimirObject + std::marker::Send + 'static,
+        I: Iterator<Item = T>,
+    {
+        self.index(dataset, IndexVisibility::Public, index_settings, iter)
+    }
+
+    /// Shortcut to `index` for a private index
+    fn private_index<T, I>(
+        &mut self,
+        dataset: &str,
+        index_settings: &IndexSettings,
+        iter: I,
+    ) -> Result<usize, Error>
+    where
+        T: MimirObject + std::marker::Send + 'static,
+        I: Iterator<Item = T>,
+    {
+        self.index(dataset, IndexVisibility::Private, index_settings, iter)
+    }
+}
@@ -0,0 +1,979 @@
+// Copyright © 2016, Canal TP and/or its affiliates. All rights reserved.
+//
+// This file is part of Navitia,
+//     the software to build cool stuff with public transport.
+//
+// Hope you'll enjoy and contribute to this project,
+//     powered by Canal TP (www.canaltp.fr).
+// Help us simplify mobility and open public transport:
+//     a non ending quest to the responsive locomotion way of traveling!
+//
+// LICENCE: This program is free software; you can redistribute it
+// and/or modify it under the terms of the GNU Affero General Public
+// License as published by the Free Software Foundation, either
+// version 3 of the License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+// Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public
+// License along with this program. If not, see
+// <http://www.gnu.org/licenses/>.
+//
+// Stay tuned using
+// twitter @navitia
+// IRC #navitia on freenode
+// https://groups.google.com/d/forum/navitia
+// www.navitia.io
+
+//! `SearchBackend` storing the places in a local directory, without any external service.
+//!
+//! Each index is a file of json lines (one document by line) in the directory, and the aliases
+//! are stored in `aliases.json`, following the same naming scheme as with Elasticsearch.
+//! When an index is searched, it is loaded in memory with a full-text index on the words
+//! (for the prefix matching) and the trigrams (for the fuzzy matching) of the labels and names,
+//! and an R-tree of the coordinates.
+
+use super::backend::SearchBackend;
+use super::objects::{Admin, Coord, MimirObject, Place, Postcode};
+use super::rubber::{
+    get_main_type_index, keep_admins_containing, keep_postcodes_containing, make_place,
+    with_distance, with_relative_scores, IndexSettings, PatchOperation, TypedIndex,
+};
+use failure::{bail, format_err, Error, ResultExt};
+use geo_types::Rect;
+use rstar::primitives::PointWithData;
+use rstar::RTree;
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+use slog_scope::{debug, info, warn};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::SystemTime;
+
+const ALIASES_FILE: &str = "aliases.json";
+const INDEX_EXTENSION: &str = "jsonl";
+const DOC_TYPE_FIELD: &str = "doc_type";
+// distance used for the proximity score, like the decay in `build_proximity_with_boost`
+const PROXIMITY_SCALE: f64 = 50_000.;
+// a fuzzy match must share at least this ratio of the trigrams of the query
+const MIN_TRIGRAM_RATIO: f64 = 0.5;
+
+type Aliases = BTreeMap<String, BTreeSet<String>>;
+
+/// An index loaded in memory
+struct IndexData {
+    places: Vec<Place>,
+    ids: HashMap<String, usize>,
+    // number of words and of trigrams of the label of each place
+    nb_words: Vec<usize>,
+    nb_trigrams: Vec<usize>,
+    words: BTreeMap<String, Vec<usize>>,
+    trigrams: HashMap<String, Vec<usize>>,
+    coords: RTree<PointWithData<usize, [f64; 2]>>,
+}
+
+#[derive(Default)]
+struct State {
+    aliases: Aliases,
+    aliases_modified: Option<SystemTime>,
+    indexes: HashMap<String, Arc<IndexData>>,
+}
+
+impl State {
+    /// Forget the loaded indexes that are not aliased anymore (e.g. replaced by a new
+    /// import), they would otherwise be kept in memory as long as the backend lives
+    fn evict_unaliased(&mut self) {
+        let aliases = &self.aliases;
+        self.indexes
+            .retain(|index, _| aliases.values().any(|indexes| indexes.contains(index)));
+    }
+}
+
+#[derive(Clone)]
+pub struct EmbeddedBackend {
+    dir: PathBuf,
+    state: Arc<RwLock<State>>,
+}
+
+impl std::fmt::Debug for EmbeddedBackend {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EmbeddedBackend")
+            .field("dir", &self.dir)
+            .finish()
+    }
+}
+
+/// Lowercase the text and remove the most common diacritics
+fn normalize(text: &str) -> String {
+    let mut normalized = String::with_capacity(text.len());
+    for c in text.chars().flat_map(char::to_lowercase) {
+        match c {
+            'à' | 'á' | 'â' | 'ã' | 'ä' | 'å' => normalized.push('a'),
+            'ç' => normalized.push('c'),
+            'è' | 'é' | 'ê' | 'ë' => normalized.push('e'),
+            'ì' | 'í' | 'î' | 'ï' => normalized.push('i'),
+            'ñ' => normalized.push('n'),
+            'ò' | 'ó' | 'ô' | 'õ' | 'ö' | 'ø' => normalized.push('o'),
+            'ù' | 'ú' | 'û' | 'ü' => normalized.push('u'),
+            'ý' | 'ÿ' => normalized.push('y'),
+            'æ' => normalized.push_str("ae"),
+            'œ' => normalized.push_str("oe"),
+            'ß' => normalized.push_str("ss"),
+            c => normalized.push(c),
+        }
+    }
+    normalized
+}
+
+/// The normalized words of `text`, as they are indexed
+pub fn words(text: &str) -> Vec<String> {
+    normalize(text)
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .map(str::to_owned)
+        .collect()
+}
+
+/// The trigrams of `words`, each word being surrounded by spaces
+pub fn trigrams(words: &[String]) -> BTreeSet<String> {
+    words
+        .iter()
+        .flat_map(|w| {
+            let chars = std::iter::once(' ')
+                .chain(w.chars())
+                .chain(std::iter::once(' '))
+                .collect::<Vec<_>>();
+            chars
+                .windows(3)
+                .map(|t| t.iter().collect::<String>())
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+/// Whether `coord` is in the bounding box of a boundary, checked before the boundary itself
+/// to avoid copying all the boundaries. A place without bbox is always kept.
+fn bbox_contains(bbox: Option<Rect<f64>>, coord: &Coord) -> bool {
+    bbox.map_or(true, |b| {
+        b.min().x <= coord.lon()
+            && coord.lon() <= b.max().x
+            && b.min().y <= coord.lat()
+            && coord.lat() <= b.max().y
+    })
+}
+
+/// The texts of a place on which the full text search is done
+fn searchable_texts(place: &Place) -> Vec<&str> {
+    let mut texts = vec![place.label()];
+    let i18n = match place {
+        Place::Admin(admin) => vec![&admin.names, &admin.labels],
+        Place::Poi(poi) => vec![&poi.names, &poi.labels],
+        _ => vec![],
+    };
+    texts.extend(
+        i18n.into_iter()
+            .flat_map(|p| p.0.iter().map(|p| p.value.as_str())),
+    );
+    texts
+}
+
+impl IndexData {
+    fn new(places: Vec<Place>) -> IndexData {
+        let mut ids = HashMap::new();
+        let mut nb_words = Vec::with_capacity(places.len());
+        let mut nb_trigrams = Vec::with_capacity(places.len());
+        let mut words_index: BTreeMap<String, Vec<usize>> = BTreeMap::new();
+        let mut trigrams_index: HashMap<String, Vec<usize>> = HashMap::new();
+        let mut points = Vec::with_capacity(places.len());
+
+        for (idx, place) in places.iter().enumerate() {
+            ids.insert(place.id().to_owned(), idx);
+            let place_words = searchable_texts(place)
+                .into_iter()
+                .flat_map(words)
+                .collect::<BTreeSet<_>>()
+                .into_iter()
+                .collect::<Vec<_>>();
+            let label_words = words(place.label());
+            nb_words.push(label_words.len());
+            nb_trigrams.push(trigrams(&label_words).len());
+            for trigram in trigrams(&place_words) {
+                trigrams_index.entry(trigram).or_default().push(idx);
+            }
+            for word in place_words {
+                words_index.entry(word).or_default().push(idx);
+            }
+            let coord = place.coord();
+            points.push(PointWithData::new(idx, [coord.lon(), coord.lat()]));
+        }
+
+        IndexData {
+            places,
+            ids,
+            nb_words,
+            nb_trigrams,
+            words: words_index,
+            trigrams: trigrams_index,
+            coords: RTree::bulk_load(points),
+        }
+    }
+
+    /// the places with all the words of the query as prefix of one of their words,
+    /// with the score of the text matching
+    fn prefix_matches(&self, query_words: &[String]) -> HashMap<usize, f64> {
+        let mut matches: Option<HashMap<usize, f64>> = None;
+        for query_word in query_words {
+            let mut word_matches = HashMap::new();
+            for (word, places) in self
+                .words
+                .range(query_word.clone()..)
+                .take_while(|(w, _)| w.starts_with(query_word.as_str()))
+            {
+                let score = if word == query_word { 1. } else { 0.5 };
+                for place in places {
+                    let s = word_matches.entry(*place).or_insert(0.);
+                    if score > *s {
+                        *s = score;
+                    }
+                }
+            }
+            matches = Some(match matches {
+                None => word_matches,
+                Some(matches) => matches
+                    .into_iter()
+                    .filter_map(|(p, s)| word_matches.get(&p).map(|w| (p, s + w)))
+                    .collect(),
+            });
+        }
+        let nb_query_words = query_words.len() as f64;
+        matches
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(p, s)| {
+                // the places with less words that are not in the query are better
+                let nb_words = (self.nb_words[p] as f64).max(nb_query_words);
+                (p, s / nb_query_words * (nb_query_words / nb_words).sqrt())
+            })
+            .collect()
+    }
+
+    /// the places sharing enough trigrams with the query, with the score of the text matching
+    fn fuzzy_matches(&self, query_words: &[String]) -> HashMap<usize, f64> {
+        let query_trigrams = trigrams(query_words);
+        let mut shared: HashMap<usize, usize> = HashMap::new();
+        for trigram in &query_trigrams {
+            for place in self.trigrams.get(trigram).into_iter().flatten() {
+                *shared.entry(*place).or_insert(0) += 1;
+            }
+        }
+        let nb_query_trigrams = query_trigrams.len() as f64;
+        shared
+            .into_iter()
+            .filter(|(_, nb_shared)| *nb_shared as f64 / nb_query_trigrams >= MIN_TRIGRAM_RATIO)
+            .map(|(p, nb_shared)| {
+                // jaccard index of the trigrams, a fuzzy match is worse than a prefix match
+                let nb_shared = nb_shared as f64;
+                let nb_trigrams = (self.nb_trigrams[p] as f64).max(nb_shared);
+                let jaccard = nb_shared / (nb_query_trigrams + nb_trigrams - nb_shared);
+                (p, jaccard * 0.5)
+            })
+            .collect()
+    }
+}
+
+impl EmbeddedBackend {
+    /// Open the index stored in `dir`, creating the directory if needed
+    pub fn open<P: AsRef<Path>>(dir: P) -> Result<EmbeddedBackend, Error> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)
+            .with_context(|_| format!("impossible to create the directory {:?}", dir))?;
+        info!("using the embedded index in {:?}", dir);
+        Ok(EmbeddedBackend {
+            dir,
+            state: Arc::new(RwLock::new(State::default())),
+        })
+    }
+
+    fn index_path(&self, index: &str) -> PathBuf {
+        self.dir.join(format!("{}.{}", index, INDEX_EXTENSION))
+    }
+
+    fn all_indexes(&self) -> Result<Vec<String>, Error> {
+        let mut indexes = vec![];
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|e| e.to_str()) == Some(INDEX_EXTENSION) {
+                if let Some(stem) = path.file_stem().and_then(|s| s.to_str()) {
+                    indexes.push(stem.to_owned());
+                }
+            }
+        }
+        Ok(indexes)
+    }
+
+    /// Read `aliases.json` if it has been modified by an import
+    fn reload_aliases(&self) -> Result<(), Error> {
+        let path = self.dir.join(ALIASES_FILE);
+        let modified = fs::metadata(&path).and_then(|m| m.modified()).ok();
+        let mut state = self.state.write().unwrap();
+        if modified.is_some() && modified == state.aliases_modified {
+            return Ok(());
+        }
+        state.aliases = match modified {
+            Some(_) => serde_json::from_reader(BufReader::new(File::open(&path)?))
+                .with_context(|_| format!("invalid aliases file {:?}", path))?,
+            None => Aliases::new(),
+        };
+        state.aliases_modified = modified;
+        state.evict_unaliased();
+        Ok(())
+    }
+
+    fn save_aliases(&self, aliases: &Aliases) -> Result<(), Error> {
+        let path = self.dir.join(ALIASES_FILE);
+        // write in a temporary file and rename it, so a reader never sees a partial file
+        let tmp_path = self.dir.join(format!("{}.tmp", ALIASES_FILE));
+        serde_json::to_writer(BufWriter::new(File::create(&tmp_path)?), aliases)?;
+        fs::rename(&tmp_path, &path)?;
+        Ok(())
+    }
+
+    /// Resolve the names of indexes, aliases or patterns (ending with `*`) into index names
+    fn resolve(&self, names: &[&str]) -> Result<BTreeSet<String>, Error> {
+        self.reload_aliases()?;
+        let all_indexes = self.all_indexes()?;
+        let state = self.state.read().unwrap();
+        let mut indexes = BTreeSet::new();
+        for name in names {
+            if name.ends_with('*') {
+                let prefix = &name[..name.len() - 1];
+                indexes.extend(
+                    all_indexes
+                        .iter()
+                        .filter(|i| i.starts_with(prefix))
+                        .cloned(),
+                );
+                for (alias, alias_indexes) in &state.aliases {
+                    if alias.starts_with(prefix) {
+                        indexes.extend(alias_indexes.iter().cloned());
+                    }
+                }
+            } else if let Some(alias_indexes) = state.aliases.get(*name) {
+                indexes.extend(alias_indexes.iter().cloned());
+            } else if all_indexes.iter().any(|i| i == name) {
+                indexes.insert((*name).to_owned());
+            }
+        }
+        // like with elasticsearch, the unavailable indexes are ignored
+        Ok(indexes
+            .into_iter()
+            .filter(|i| all_indexes.contains(i))
+            .collect())
+    }
+
+    fn load(&self, index: &str) -> Result<Arc<IndexData>, Error> {
+        if let Some(data) = self.state.read().unwrap().indexes.get(index) {
+            return Ok(data.clone());
+        }
+        debug!("loading the embedded index {}", index);
+        let path = self.index_path(index);
+        let mut places = vec![];
+        for line in BufReader::new(File::open(&path)?).lines() {
+            let value: Value = serde_json::from_str(&line?)?;
+            let doc_type = match value.get(DOC_TYPE_FIELD).and_then(|t| t.as_str()) {
+                Some(doc_type) => doc_type.to_owned(),
+                None => {
+                    warn!("document without type in {:?}", path);
+                    continue;
+                }
+            };
+            places.extend(make_place(doc_type, Some(Box::new(value)), None));
+        }
+        let data = Arc::new(IndexData::new(places));
+        self.state
+            .write()
+            .unwrap()
+            .indexes
+            .insert(index.to_owned(), data.clone());
+        Ok(data)
+    }
+
+    fn load_all(&self, names: &[&str]) -> Result<Vec<Arc<IndexData>>, Error> {
+        self.resolve(names)?
+            .iter()
+            .map(|index| self.load(index))
+            .collect()
+    }
+
+    /// Full text search of `q` in the labels and names of the places of `indexes`.
+    ///
+    /// All the words of the query must be the prefix of a word of the place. If no place is
+    /// found this way, the places sharing enough trigrams with the query are returned.
+    /// The places are sorted by the text matching score, their weight and
+    /// their proximity to `coord`. Only the places accepted by `filter` are kept.
+    pub fn autocomplete<F>(
+        &self,
+        indexes: &[&str],
+        q: &str,
+        coord: Option<&Coord>,
+        filter: F,
+        offset: u64,
+        limit: u64,
+    ) -> Result<Vec<Place>, Error>
+    where
+        F: Fn(&Place) -> bool,
+    {
+        let query_words = words(q);
+        if query_words.is_empty() {
+            return Ok(vec![]);
+        }
+        let data = self.load_all(indexes)?;
+
+        let score = |matches: HashMap<usize, f64>, index: &Arc<IndexData>| {
+            matches
+                .into_iter()
+                .filter(|(p, _)| filter(&index.places[*p]))
+                .map(|(p, text_score)| {
+                    let place = with_distance(index.places[p].clone(), coord);
+                    let proximity = place
+                        .distance()
+                        .map_or(0., |d| (-f64::from(d) / PROXIMITY_SCALE).exp());
+                    let score = 10. * text_score + place.weight().min(1.) + 2. * proximity;
+                    (score, place)
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let mut results = data
+            .iter()
+            .flat_map(|index| score(index.prefix_matches(&query_words), index))
+            .collect::<Vec<_>>();
+        if results.is_empty() {
+            results = data
+                .iter()
+                .flat_map(|index| score(index.fuzzy_matches(&query_words), index))
+                .collect();
+        }
+
+        results.sort_by(|(a, _), (b, _)| b.partial_cmp(a).unwrap_or(std::cmp::Ordering::Equal));
+        Ok(with_relative_scores(
+            results
+                .into_iter()
+                .skip(offset as usize)
+                .take(limit as usize)
+                .map(|(score, place)| (Some(score), place))
+                .collect(),
+        ))
+    }
+}
+
+impl SearchBackend for EmbeddedBackend {
+    fn initialize_templates(&self) -> Result<(), Error> {
+        // there is no mapping to define
+        Ok(())
+    }
+
+    fn create_index(&self, name: &str, _index_settings: &IndexSettings) -> Result<(), Error> {
+        debug!("creating index");
+        File::create(self.index_path(name))
+            .with_context(|_| format!("Error while creating new index {}", name))?;
+        Ok(())
+    }
+
+    fn bulk_index<T, I>(&mut self, index: &TypedIndex<T>, iter: I) -> Result<usize, Error>
+    where
+        T: MimirObject + std::marker::Send + 'static,
+        I: Iterator<Item = T>,
+    {
+        let file = fs::OpenOptions::new()
+            .append(true)
+            .open(self.index_path(&index.name))
+            .with_context(|_| format!("impossible to open index {}", index.name))?;
+        let mut writer = BufWriter::new(file);
+        let mut nb = 0;
+        for doc in iter {
+            let mut doc = serde_json::to_value(&doc)?;
+            doc[DOC_TYPE_FIELD] = json!(T::doc_type());
+            serde_json::to_writer(&mut writer, &doc)?;
+            writer.write_all(b"\n")?;
+            nb += 1;
+        }
+        writer.flush()?;
+        Ok(nb)
+    }
+
+    fn refresh_index(&mut self, index: &str) -> Result<(), Error> {
+        self.state.write().unwrap().indexes.remove(index);
+        Ok(())
+    }
+
+    fn get_all_aliased_index(
+        &self,
+        base_index: &str,
+    ) -> Result<BTreeMap<String, Vec<String>>, Error> {
+        self.reload_aliases()?;
+        let state = self.state.read().unwrap();
+        let prefix = format!("{}_", base_index);
+        Ok(self
+            .all_indexes()?
+            .into_iter()
+            .filter(|i| i.starts_with(&prefix))
+            .map(|i| {
+                let aliases = state
+                    .aliases
+                    .iter()
+                    .filter(|(_, indexes)| indexes.contains(&i))
+                    .map(|(alias, _)| alias.clone())
+                    .collect();
+                (i, aliases)
+            })
+            .collect())
+    }
+
+    fn alias(&self, alias: &str, add: &[String], remove: &[String]) -> Result<(), Error> {
+        info!(
+            "for {}, adding alias {:?}, removing {:?}",
+            alias, add, remove
+        );
+        // like with elasticsearch, an alias of an alias is an alias of its indexes
+        let add = self.resolve(&add.iter().map(String::as_str).collect::<Vec<_>>())?;
+        let remove = self.resolve(&remove.iter().map(String::as_str).collect::<Vec<_>>())?;
+
+        let mut state = self.state.write().unwrap();
+        let indexes = state.aliases.entry(alias.to_owned()).or_default();
+        indexes.extend(add);
+        indexes.retain(|i| !remove.contains(i));
+        self.save_aliases(&state.aliases)
+            .with_context(|_| format!("failed to save aliases for {}", alias))?;
+        state.aliases_modified = fs::metadata(self.dir.join(ALIASES_FILE))
+            .and_then(|m| m.modified())
+            .ok();
+        state.evict_unaliased();
+        Ok(())
+    }
+
+    fn delete_index(&mut self, index: &str) -> Result<(), Error> {
+        debug!("deleting index {}", &index);
+        self.reload_aliases()?;
+        fs::remove_file(self.index_path(index))
+            .map_err(|e| format_err!("Error deleting index {}: {}", index, e))?;
+        let mut state = self.state.write().unwrap();
+        state.indexes.remove(index);
+        for indexes in state.aliases.values_mut() {
+            indexes.remove(index);
+        }
+        self.save_aliases(&state.aliases)?;
+        state.aliases_modified = fs::metadata(self.dir.join(ALIASES_FILE))
+            .and_then(|m| m.modified())
+            .ok();
+        Ok(())
+    }
+
+    fn delete_docs<T: MimirObject>(
+        &mut self,
+        index: &TypedIndex<T>,
+        ids: &[String],
+    ) -> Result<usize, Error> {
+        if ids.is_empty() {
+            return Ok(0);
+        }
+        let ids = ids.iter().map(String::as_str).collect::<BTreeSet<_>>();
+        let path = self.index_path(&index.name);
+        // the documents are kept in a temporary file, renamed as the index at the end
+        let tmp_path = self.dir.join(format!("{}.tmp", index.name));
+        let mut writer = BufWriter::new(File::create(&tmp_path)?);
+        let mut nb_deleted = 0;
+        for line in BufReader::new(
+            File::open(&path)
+                .with_context(|_| format!("impossible to open index {}", index.name))?,
+        )
+        .lines()
+        {
+            let line = line?;
+            let value: Value = serde_json::from_str(&line)?;
+            if value
+                .get("id")
+                .and_then(|id| id.as_str())
+                .map_or(false, |id| ids.contains(id))
+            {
+                nb_deleted += 1;
+                continue;
+            }
+            writer.write_all(line.as_bytes())?;
+            writer.write_all(b"\n")?;
+        }
+        writer.flush()?;
+        fs::rename(&tmp_path, &path)?;
+        self.state.write().unwrap().indexes.remove(&index.name);
+        Ok(nb_deleted)
+    }
+
+    fn upsert_docs<T, I>(&mut self, index: &TypedIndex<T>, iter: I) -> Result<usize, Error>
+    where
+        T: MimirObject + std::marker::Send + 'static,
+        I: Iterator<Item = T>,
+    {
+        // the documents are appended to the index, so the old versions must be removed first
+        let docs = iter.collect::<Vec<_>>();
+        let ids = docs
+            .iter()
+            .filter_map(|doc| doc.es_id())
+            .collect::<Vec<_>>();
+        self.delete_docs(index, &ids)?;
+        self.bulk_index(index, docs.into_iter())
+    }
+
+    fn search(
+        &mut self,
+        _indexes: &[&str],
+        _query: &rs_es::query::Query,
+        _from: u64,
+        _size: u64,
+        _coord: Option<&Coord>,
+    ) -> Result<Vec<Place>, Error> {
+        bail!("the embedded index does not support elasticsearch queries, use `autocomplete`")
+    }
+
+    fn get_by_id(&mut self, indexes: &[&str], id: &str) -> Result<Vec<Place>, Error> {
+        Ok(self
+            .load_all(indexes)?
+            .iter()
+            .filter_map(|index| index.ids.get(id).map(|p| index.places[*p].clone()))
+            .collect())
+    }
+
+    fn get_places_around(
+        &mut self,
+        coord: &Coord,
+        indexes: &[&str],
+        radius: f64,
+        limit: u64,
+    ) -> Result<Vec<Place>, Error> {
+        // the R-tree is in degrees, we search in a square larger than the radius
+        // then we filter on the real distance
+        let meters_by_degree = 111_320. * coord.lat().to_radians().cos().max(0.01);
+        let degrees = radius / meters_by_degree;
+        let point = [coord.lon(), coord.lat()];
+
+        let mut places = self
+            .load_all(indexes)?
+            .iter()
+            .flat_map(|index| {
+                index
+                    .coords
+                    .locate_within_distance(point, degrees * degrees)
+                    .map(|p| with_distance(index.places[p.data].clone(), Some(coord)))
+                    .collect::<Vec<_>>()
+            })
+            .filter(|p| p.distance().map_or(false, |d| f64::from(d) <= radius))
+            .collect::<Vec<_>>();
+        places.sort_by_key(|p| p.distance());
+        places.truncate(limit as usize);
+        Ok(places)
+    }
+
+    fn get_admins_containing(&mut self, coord: &Coord) -> Result<Vec<Place>, Error> {
+        let admins = self
+            .load_all(&[get_main_type_index::<Admin>().as_str()])?
+            .iter()
+            .flat_map(|index| {
+                index
+                    .places
+                    .iter()
+                    .filter(|p| match p {
+                        Place::Admin(admin) => bbox_contains(admin.bbox, coord),
+                        _ => false,
+                    })
+                    .map(|p| with_distance(p.clone(), Some(coord)))
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        Ok(keep_admins_containing(admins, coord))
+    }
+
+    fn get_postcodes_containing(&mut self, coord: &Coord) -> Result<Vec<Place>, Error> {
+        let postcodes = self
+            .load_all(&[get_main_type_index::<Postcode>().as_str()])?
+            .iter()
+            .flat_map(|index| {
+                index
+                    .places
+                    .iter()
+                    .filter(|p| match p {
+                        Place::Postcode(postcode) => bbox_contains(postcode.bbox, coord),
+                        _ => false,
+                    })
+                    .map(|p| with_distance(p.clone(), Some(coord)))
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        Ok(keep_postcodes_containing(postcodes, coord))
+    }
+
+    fn get_all_admins(&mut self) -> Result<Vec<Admin>, Error> {
+        Ok(self
+            .load_all(&[get_main_type_index::<Admin>().as_str()])?
+            .iter()
+            .flat_map(|index| {
+                index
+                    .places
+                    .iter()
+                    .filter_map(|p| match p {
+                        Place::Admin(admin) => Some(admin.clone()),
+                        _ => None,
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect())
+    }
+
+    fn patch_index<T: MimirObject>(
+        &mut self,
+        _index: &TypedIndex<T>,
+        _operations: &[PatchOperation<T>],
+    ) -> Result<usize, Error> {
+        bail!("the embedded index does not support patches, use `upsert_docs` and `delete_docs`")
+    }
+
+    fn get_all_objects<T>(&mut self, index: &str) -> Result<Vec<T>, Error>
+    where
+        T: MimirObject + DeserializeOwned + std::fmt::Debug,
+    {
+        let mut docs = vec![];
+        for index in self.resolve(&[index])? {
+            let path = self.index_path(&index);
+            for line in BufReader::new(File::open(&path)?).lines() {
+                let value: Value = serde_json::from_str(&line?)?;
+                if value.get(DOC_TYPE_FIELD).and_then(|t| t.as_str()) == Some(T::doc_type()) {
+                    docs.push(serde_json::from_value(value)?);
+                }
+            }
+        }
+        Ok(docs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::objects::{Addr, Street};
+    use crate::rubber::IndexVisibility;
+
+    fn street(id: &str, label: &str, lon: f64, lat: f64) -> Street {
+        Street {
+            id: id.to_owned(),
+            name: label.to_owned(),
+            label: label.to_owned(),
+            administrative_regions: vec![],
+            weight: 0.,
+            zip_codes: vec![],
+            coord: Coord::new(lon, lat),
+            approx_coord: None,
+            distance: None,
+            confidence: None,
+            country_codes: vec![],
+            context: None,
+            house_number_ranges: vec![],
+        }
+    }
+
+    fn addr(street: &Street, number: &str, lon: f64, lat: f64) -> Addr {
+        Addr {
+            id: format!("addr:{};{}", lon, lat),
+            name: format!("{} {}", number, street.name),
+            label: format!("{} {}", number, street.label),
+            house_number: number.to_owned(),
+            street: street.clone(),
+            coord: Coord::new(lon, lat),
+            approx_coord: None,
+            weight: 0.,
+            zip_codes: vec![],
+            distance: None,
+            confidence: None,
+            country_codes: vec![],
+            context: None,
+            interpolated: false,
+        }
+    }
+
+    fn backend(name: &str) -> EmbeddedBackend {
+        let dir =
+            std::env::temp_dir().join(format!("mimir_embedded_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        EmbeddedBackend::open(dir).unwrap()
+    }
+
+    fn index_settings() -> IndexSettings {
+        IndexSettings {
+            nb_shards: 1,
+            nb_replicas: 0,
+        }
+    }
+
+    #[test]
+    fn test_normalize() {
+        assert_eq!(
+            words("Rue de l'Église, Œuvre"),
+            vec!["rue", "de", "l", "eglise", "oeuvre"]
+        );
+    }
+
+    #[test]
+    fn test_autocomplete_and_reverse() {
+        let mut backend = backend("autocomplete");
+        let hector = street("street:1", "Rue Hector Malot (Paris)", 2.3763, 48.8465);
+        let streets = vec![
+            hector.clone(),
+            street("street:2", "Rue des Écoles (Paris)", 2.3491, 48.8491),
+        ];
+        backend
+            .public_index("fr", &index_settings(), streets.into_iter())
+            .unwrap();
+        let addrs = vec![addr(&hector, "15", 2.376379, 48.846495)];
+        backend
+            .public_index("fr", &index_settings(), addrs.into_iter())
+            .unwrap();
+
+        let labels = |places: Vec<Place>| {
+            places
+                .iter()
+                .map(|p| p.label().to_owned())
+                .collect::<Vec<_>>()
+        };
+        let res = backend
+            .autocomplete(&["munin"], "rue hect", None, |_| true, 0, 10)
+            .unwrap();
+        assert_eq!(
+            labels(res),
+            vec!["Rue Hector Malot (Paris)", "15 Rue Hector Malot (Paris)"]
+        );
+        // prefix on an accented word
+        let res = backend
+            .autocomplete(&["munin_street"], "ecol", None, |_| true, 0, 10)
+            .unwrap();
+        assert_eq!(labels(res), vec!["Rue des Écoles (Paris)"]);
+        // fuzzy matching
+        let res = backend
+            .autocomplete(&["munin"], "hectr malot", None, |_| true, 0, 1)
+            .unwrap();
+        assert_eq!(labels(res), vec!["Rue Hector Malot (Paris)"]);
+
+        let res = backend
+            .get_by_id(&["munin_addr"], "addr:2.376379;48.846495")
+            .unwrap();
+        assert_eq!(labels(res), vec!["15 Rue Hector Malot (Paris)"]);
+
+        let res = backend.get_address(&Coord::new(2.37638, 48.8465)).unwrap();
+        assert_eq!(labels(res), vec!["15 Rue Hector Malot (Paris)"]);
+        let res = backend
+            .get_places_around(&Coord::new(2.0, 48.0), &["munin"], 1000., 10)
+            .unwrap();
+        assert!(res.is_empty());
+    }
+
+    #[test]
+    fn test_publish_replaces_the_old_index() {
+        let mut backend = backend("publish");
+        let streets = vec![street("street:1", "Rue Hector Malot", 2.3763, 48.8465)];
+        backend
+            .public_index("fr", &index_settings(), streets.into_iter())
+            .unwrap();
+        let streets = vec![street("street:2", "Rue des Écoles", 2.3491, 48.8491)];
+        let index = backend.make_index("fr", &index_settings()).unwrap();
+        backend.bulk_index(&index, streets.into_iter()).unwrap();
+        backend
+            .publish_index("fr", index, IndexVisibility::Public)
+            .unwrap();
+
+        let res = backend
+            .autocomplete(&["munin"], "rue", None, |_| true, 0, 10)
+            .unwrap();
+        assert_eq!(res.len(), 1);
+        assert_eq!(res[0].id(), "street:2");
+        assert_eq!(backend.all_indexes().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_unaliased_indexes_are_evicted() {
+        let mut backend = backend("evict");
+        let streets = vec![street("street:1", "Rue Hector Malot", 2.3763, 48.8465)];
+        backend
+            .public_index("fr", &index_settings(), streets.into_iter())
+            .unwrap();
+        let index = backend.published_index::<Street>("fr").unwrap().unwrap();
+        backend
+            .autocomplete(&["munin"], "rue", None, |_| true, 0, 10)
+            .unwrap();
+        assert!(backend
+            .state
+            .read()
+            .unwrap()
+            .indexes
+            .contains_key(&index.name));
+
+        let aliases: Vec<String> = backend
+            .state
+            .read()
+            .unwrap()
+            .aliases
+            .keys()
+            .cloned()
+            .collect();
+        for alias in &aliases {
+            backend.alias(alias, &[], &[index.name.clone()]).unwrap();
+        }
+        assert!(backend.state.read().unwrap().indexes.is_empty());
+    }
+
+    #[test]
+    fn test_upsert_and_delete_docs() {
+        let mut backend = backend("upsert");
+        let streets = vec![
+            street("street:1", "Rue Hector Malot", 2.3763, 48.8465),
+            street("street:2", "Rue des Écoles", 2.3491, 48.8491),
+        ];
+        backend
+            .public_index("fr", &index_settings(), streets.into_iter())
+            .unwrap();
+        let index = backend.published_index::<Street>("fr").unwrap().unwrap();
+
+        let streets = vec![
+            street("street:1", "Rue Hector Berlioz", 2.3763, 48.8465),
+            street("street:3", "Rue Monge", 2.3522, 48.8447),
+        ];
+        assert_eq!(backend.upsert_docs(&index, streets.into_iter()).unwrap(), 2);
+        let ids = vec!["street:2".to_owned(), "street:4".to_owned()];
+        assert_eq!(backend.delete_docs(&index, &ids).unwrap(), 1);
+        backend.refresh_index(&index.name).unwrap();
+
+        let mut res = backend
+            .autocomplete(&["munin"], "rue", None, |_| true, 0, 10)
+            .unwrap()
+            .into_iter()
+            .map(|p| (p.id().to_owned(), p.label().to_owned()))
+            .collect::<Vec<_>>();
+        res.sort();
+        assert_eq!(
+            res,
+            vec![
+                ("street:1".to_owned(), "Rue Hector Berlioz".to_owned()),
+                ("street:3".to_owned(), "Rue Monge".to_owned()),
+            ]
+        );
+
+        let mut ids = backend
+            .get_all_objects::<Street>("munin_street")
+            .unwrap()
+            .into_iter()
+            .map(|s| s.id)
+            .collect::<Vec<_>>();
+        ids.sort();
+        assert_eq!(ids, vec!["street:1", "street:3"]);
+    }
+}
@@ -0,0 +1,227 @@
+// Copyright © 2016, Canal TP and/or its affiliates. All rights reserved.
+//
+// This file is part of Navitia,
+//     the software to build cool stuff with public transport.
+//
+// Hope you'll enjoy and contribute to this project,
+//     powered by Canal TP (www.canaltp.fr).
+// Help us simplify mobility and open public transport:
+//     a non ending quest to the responsive locomotion way of traveling!
+//
+// LICENCE: This program is free software; you can redistribute it
+// and/or modify it under the terms of the GNU Affero General Public
+// License as published by the Free Software Foundation, either
+// version 3 of the License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+// Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public
+// License along with this program. If not, see
+// <http://www.gnu.org/licenses/>.
+//
+// Stay tuned using
+// twitter @navitia
+// IRC #navitia on freenode
+// https://groups.google.com/d/forum/navitia
+// www.navitia.io
+
+//! Interpolation of the position of house numbers along the streets.
+//!
+//! The address datasets (BANO, OpenAddresses) do not contain all the houses. The OSM interpolation
+//! lines (ways tagged with `addr:interpolation`, with a house number on their ends) give the ranges
+//! of house numbers along a street, from which the position of a missing house is computed.
+
+use crate::objects::Coord;
+use geo::algorithm::haversine_distance::HaversineDistance;
+use serde::{Deserialize, Serialize};
+
+/// The house numbers of an interpolation line, from its `addr:interpolation` tag in OSM
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum InterpolationKind {
+    All,
+    Even,
+    Odd,
+}
+
+impl InterpolationKind {
+    pub fn from_osm_tag(tag: &str) -> Option<InterpolationKind> {
+        match tag {
+            "all" | "1" => Some(InterpolationKind::All),
+            "even" => Some(InterpolationKind::Even),
+            "odd" => Some(InterpolationKind::Odd),
+            _ => None,
+        }
+    }
+
+    fn accepts(self, number: u32) -> bool {
+        match self {
+            InterpolationKind::All => true,
+            InterpolationKind::Even => number % 2 == 0,
+            InterpolationKind::Odd => number % 2 == 1,
+        }
+    }
+}
+
+/// Split a house number like `15`, `15 bis` or `12A` into its number and its suffix,
+/// `None` if it does not start with a number
+pub fn split_house_number(house_number: &str) -> Option<(u32, &str)> {
+    let house_number = house_number.trim();
+    let end = house_number
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or_else(|| house_number.len());
+    let number = house_number[..end].parse().ok()?;
+    Some((number, house_number[end..].trim()))
+}
+
+/// The id of the address at `house_number` of the street `street_id`, interpolated from the
+/// house number ranges of the street: `addr:{street_id}:{house_number}`, with the spaces of the
+/// house number replaced by `_`.
+/// It is not in any index, but can be read back by `parse_interpolated_addr_id`.
+pub fn interpolated_addr_id(street_id: &str, house_number: &str) -> String {
+    format!(
+        "addr:{}:{}",
+        street_id,
+        house_number.trim().replace(' ', "_")
+    )
+}
+
+/// The street id and the house number of an id built by `interpolated_addr_id`,
+/// `None` if it is not the id of an interpolated address.
+pub fn parse_interpolated_addr_id(id: &str) -> Option<(&str, String)> {
+    if !id.starts_with("addr:street:") {
+        return None;
+    }
+    let mut parts = id["addr:".len()..].rsplitn(2, ':');
+    let house_number = parts.next()?.replace('_', " ");
+    let street_id = parts.next()?;
+    split_house_number(&house_number)?;
+    Some((street_id, house_number))
+}
+
+/// A range of house numbers along a part of a street
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct HouseNumberRange {
+    pub start: u32,
+    pub end: u32,
+    pub kind: InterpolationKind,
+    /// The line of the range, from the house number `start` to the house number `end`
+    pub line: Vec<Coord>,
+}
+
+impl HouseNumberRange {
+    pub fn contains(&self, number: u32) -> bool {
+        self.start.min(self.end) <= number
+            && number <= self.start.max(self.end)
+            && self.kind.accepts(number)
+    }
+
+    /// The position of the house `number` on the line of the range, at a distance from
+    /// the start of the line proportional to the difference with the `start` number.
+    pub fn interpolate(&self, number: u32) -> Option<Coord> {
+        if !self.contains(number) {
+            return None;
+        }
+        let first = *self.line.first()?;
+        if self.start == self.end {
+            return Some(first);
+        }
+        let lengths = self
+            .line
+            .windows(2)
+            .map(|w| {
+                let from: geo_types::Point<f64> = w[0].0.into();
+                from.haversine_distance(&w[1].0.into())
+            })
+            .collect::<Vec<_>>();
+        let ratio = (f64::from(number) - f64::from(self.start))
+            / (f64::from(self.end) - f64::from(self.start));
+        let mut remaining = ratio * lengths.iter().sum::<f64>();
+        for (w, length) in self.line.windows(2).zip(lengths) {
+            if remaining <= length {
+                let t = if length > 0. { remaining / length } else { 0. };
+                return Some(Coord::new(
+                    w[0].lon() + t * (w[1].lon() - w[0].lon()),
+                    w[0].lat() + t * (w[1].lat() - w[0].lat()),
+                ));
+            }
+            remaining -= length;
+        }
+        self.line.last().cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(start: u32, end: u32, kind: InterpolationKind) -> HouseNumberRange {
+        HouseNumberRange {
+            start,
+            end,
+            kind,
+            line: vec![
+                Coord::new(2.0, 48.0),
+                Coord::new(2.001, 48.0),
+                Coord::new(2.001, 48.002),
+            ],
+        }
+    }
+
+    #[test]
+    fn test_split_house_number() {
+        assert_eq!(split_house_number("15"), Some((15, "")));
+        assert_eq!(split_house_number("15 bis"), Some((15, "bis")));
+        assert_eq!(split_house_number("12a"), Some((12, "a")));
+        assert_eq!(split_house_number("bis"), None);
+        assert_eq!(split_house_number(""), None);
+    }
+
+    #[test]
+    fn test_interpolated_addr_id() {
+        let id = interpolated_addr_id("street:osm:way:42", "15 bis");
+        assert_eq!(id, "addr:street:osm:way:42:15_bis");
+        assert_eq!(
+            parse_interpolated_addr_id(&id),
+            Some(("street:osm:way:42", "15 bis".to_string()))
+        );
+        assert_eq!(parse_interpolated_addr_id("addr:2.3;48.8:15"), None);
+        assert_eq!(parse_interpolated_addr_id("addr:street:osm:way:42:"), None);
+        assert_eq!(parse_interpolated_addr_id("street:osm:way:42"), None);
+    }
+
+    #[test]
+    fn test_contains() {
+        let even = range(2, 20, InterpolationKind::Even);
+        assert!(even.contains(2));
+        assert!(even.contains(12));
+        assert!(!even.contains(13));
+        assert!(!even.contains(22));
+        // the numbers can decrease along the line
+        let odd = range(21, 1, InterpolationKind::Odd);
+        assert!(odd.contains(1));
+        assert!(odd.contains(13));
+        assert!(!odd.contains(12));
+        assert!(range(1, 5, InterpolationKind::All).contains(4));
+    }
+
+    #[test]
+    fn test_interpolate() {
+        let range = range(2, 14, InterpolationKind::Even);
+        let start = range.interpolate(2).unwrap();
+        assert!((start.lon() - 2.0).abs() < 1e-9 && (start.lat() - 48.0).abs() < 1e-9);
+        let end = range.interpolate(14).unwrap();
+        assert!((end.lon() - 2.001).abs() < 1e-9 && (end.lat() - 48.002).abs() < 1e-9);
+        // the first segment is ~75m long and the second one ~222m
+        let first_segment = range.interpolate(4).unwrap();
+        assert!((first_segment.lon() - 2.000664).abs() < 1e-5);
+        assert!((first_segment.lat() - 48.0).abs() < 1e-9);
+        let middle = range.interpolate(8).unwrap();
+        assert!((middle.lon() - 2.001).abs() < 1e-6);
+        assert!(middle.lat() > 48.0 && middle.lat() < 48.001);
+        assert!(range.interpolate(16).is_none());
+    }
+}
@@ -35,7 +35,12 @@
 // #[macro_use]
 // extern crate failure;
 
+pub mod backend;
+pub mod embedded;
+pub mod interpolation;
 pub mod objects;
+pub mod opening_hours;
+pub mod rest_backend;
 pub mod rubber;
 
 pub use crate::objects::*;
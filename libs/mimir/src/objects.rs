@@ -27,6 +27,8 @@
 // IRC #navitia on freenode
 // https://groups.google.com/d/forum/navitia
 // www.navitia.io
+use crate::interpolation::{interpolated_addr_id, split_house_number, HouseNumberRange};
+use crate::opening_hours::OpeningHours;
 use cosmogony::ZoneType;
 use geo_types::{Coordinate, MultiPolygon, Rect};
 use geojson::Geometry;
@@ -57,6 +59,9 @@ pub enum Place {
     Addr(Addr),
     Poi(Poi),
     Stop(Stop),
+    Intersection(Intersection),
+    Postcode(Postcode),
+    Locality(Locality),
 }
 
 /// Object stored in elastic search
@@ -89,6 +94,18 @@ impl Place {
         matches!(self, Place::Stop(_))
     }
 
+    pub fn is_intersection(&self) -> bool {
+        matches!(self, Place::Intersection(_))
+    }
+
+    pub fn is_postcode(&self) -> bool {
+        matches!(self, Place::Postcode(_))
+    }
+
+    pub fn is_locality(&self) -> bool {
+        matches!(self, Place::Locality(_))
+    }
+
     pub fn poi(&self) -> Option<&Poi> {
         match *self {
             Place::Poi(ref poi) => Some(poi),
@@ -103,6 +120,9 @@ impl Place {
             Place::Addr(ref o) => o.label(),
             Place::Poi(ref o) => o.label(),
             Place::Stop(ref o) => o.label(),
+            Place::Intersection(ref o) => o.label(),
+            Place::Postcode(ref o) => o.label(),
+            Place::Locality(ref o) => o.label(),
         }
     }
 
@@ -113,6 +133,9 @@ impl Place {
             Place::Addr(ref o) => o.admins(),
             Place::Poi(ref o) => o.admins(),
             Place::Stop(ref o) => o.admins(),
+            Place::Intersection(ref o) => o.admins(),
+            Place::Postcode(ref o) => o.admins(),
+            Place::Locality(ref o) => o.admins(),
         }
     }
 
@@ -123,6 +146,9 @@ impl Place {
             Place::Addr(ref o) => Some(Address::Addr(o.clone())),
             Place::Poi(_) => None,
             Place::Stop(_) => None,
+            Place::Intersection(_) => None,
+            Place::Postcode(_) => None,
+            Place::Locality(_) => None,
         }
     }
 
@@ -133,6 +159,9 @@ impl Place {
             Place::Addr(ref o) => o.distance,
             Place::Poi(ref o) => o.distance,
             Place::Stop(ref o) => o.distance,
+            Place::Intersection(ref o) => o.distance,
+            Place::Postcode(ref o) => o.distance,
+            Place::Locality(ref o) => o.distance,
         }
     }
 
@@ -143,6 +172,35 @@ impl Place {
             Place::Addr(ref mut o) => o.distance = Some(d),
             Place::Poi(ref mut o) => o.distance = Some(d),
             Place::Stop(ref mut o) => o.distance = Some(d),
+            Place::Intersection(ref mut o) => o.distance = Some(d),
+            Place::Postcode(ref mut o) => o.distance = Some(d),
+            Place::Locality(ref mut o) => o.distance = Some(d),
+        }
+    }
+
+    pub fn confidence(&self) -> Option<f64> {
+        match *self {
+            Place::Admin(ref o) => o.confidence,
+            Place::Street(ref o) => o.confidence,
+            Place::Addr(ref o) => o.confidence,
+            Place::Poi(ref o) => o.confidence,
+            Place::Stop(ref o) => o.confidence,
+            Place::Intersection(ref o) => o.confidence,
+            Place::Postcode(ref o) => o.confidence,
+            Place::Locality(ref o) => o.confidence,
+        }
+    }
+
+    pub fn set_confidence(&mut self, c: f64) {
+        match self {
+            Place::Admin(ref mut o) => o.confidence = Some(c),
+            Place::Street(ref mut o) => o.confidence = Some(c),
+            Place::Addr(ref mut o) => o.confidence = Some(c),
+            Place::Poi(ref mut o) => o.confidence = Some(c),
+            Place::Stop(ref mut o) => o.confidence = Some(c),
+            Place::Intersection(ref mut o) => o.confidence = Some(c),
+            Place::Postcode(ref mut o) => o.confidence = Some(c),
+            Place::Locality(ref mut o) => o.confidence = Some(c),
         }
     }
 
@@ -153,6 +211,35 @@ impl Place {
             Place::Addr(ref o) => &o.coord,
             Place::Poi(ref o) => &o.coord,
             Place::Stop(ref o) => &o.coord,
+            Place::Intersection(ref o) => &o.coord,
+            Place::Postcode(ref o) => &o.coord,
+            Place::Locality(ref o) => &o.coord,
+        }
+    }
+
+    pub fn id(&self) -> &str {
+        match self {
+            Place::Admin(ref o) => &o.id,
+            Place::Street(ref o) => &o.id,
+            Place::Addr(ref o) => &o.id,
+            Place::Poi(ref o) => &o.id,
+            Place::Stop(ref o) => &o.id,
+            Place::Intersection(ref o) => &o.id,
+            Place::Postcode(ref o) => &o.id,
+            Place::Locality(ref o) => &o.id,
+        }
+    }
+
+    pub fn weight(&self) -> f64 {
+        match self {
+            Place::Admin(ref o) => o.weight,
+            Place::Street(ref o) => o.weight,
+            Place::Addr(ref o) => o.weight,
+            Place::Poi(ref o) => o.weight,
+            Place::Stop(ref o) => o.weight,
+            Place::Intersection(ref o) => o.weight,
+            Place::Postcode(ref o) => o.weight,
+            Place::Locality(ref o) => o.weight,
         }
     }
 
@@ -163,6 +250,9 @@ impl Place {
             Place::Addr(ref mut o) => o.context = Some(context),
             Place::Poi(ref mut o) => o.context = Some(context),
             Place::Stop(ref mut o) => o.context = Some(context),
+            Place::Intersection(ref mut o) => o.context = Some(context),
+            Place::Postcode(ref mut o) => o.context = Some(context),
+            Place::Locality(ref mut o) => o.context = Some(context),
         }
     }
 
@@ -175,6 +265,9 @@ impl Place {
             Place::Addr(ref o) => o.context.clone(),
             Place::Poi(ref o) => o.context.clone(),
             Place::Stop(ref o) => o.context.clone(),
+            Place::Intersection(ref o) => o.context.clone(),
+            Place::Postcode(ref o) => o.context.clone(),
+            Place::Locality(ref o) => o.context.clone(),
         }
     }
 }
@@ -188,6 +281,9 @@ pub enum PlaceDocType {
     Addr,
     Poi,
     Stop,
+    Intersection,
+    Postcode,
+    Locality,
 }
 
 impl PlaceDocType {
@@ -198,6 +294,9 @@ impl PlaceDocType {
             PlaceDocType::Addr => "addr",
             PlaceDocType::Poi => "poi",
             PlaceDocType::Stop => "stop",
+            PlaceDocType::Intersection => "intersection",
+            PlaceDocType::Postcode => "postcode",
+            PlaceDocType::Locality => "locality",
         }
     }
 }
@@ -292,8 +391,103 @@ pub struct Poi {
     /// Not serialized as is because it is returned in the `Feature` object
     #[serde(default, skip)]
     pub distance: Option<u32>,
+    /// How well the place matches the query, between 0 and 1
+    /// Not serialized as is because it is returned in the `Feature` object
+    #[serde(default, skip)]
+    pub confidence: Option<f64>,
 
     pub context: Option<Context>,
+
+    #[serde(default)]
+    pub details: PoiDetails,
+}
+
+/// Accessibility of a place with a wheelchair, from the `wheelchair` tag in OSM
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Wheelchair {
+    Yes,
+    Limited,
+    No,
+}
+
+impl Wheelchair {
+    pub fn from_osm_tag(tag: &str) -> Option<Wheelchair> {
+        match tag {
+            "yes" | "designated" => Some(Wheelchair::Yes),
+            "limited" => Some(Wheelchair::Limited),
+            "no" => Some(Wheelchair::No),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Wheelchair::Yes => "yes",
+            Wheelchair::Limited => "limited",
+            Wheelchair::No => "no",
+        }
+    }
+}
+
+/// Practical information on a POI
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct PoiDetails {
+    /// The opening hours, as given by the data source (in the OSM syntax)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub opening_hours: Option<String>,
+    /// The parsed opening hours, missing if their syntax is not handled
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub schedule: Option<OpeningHours>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub phone: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub website: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wheelchair: Option<Wheelchair>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub brand: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub operator: Option<String>,
+    /// The timezone of the POI (like `Europe/Paris`), in which the opening hours are given
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timezone: Option<String>,
+}
+
+impl PoiDetails {
+    /// Read the details from OSM tags, or from any properties using the same keys.
+    /// The `contact:*` keys are used when the simple ones are missing.
+    pub fn from_tags<'a, I>(tags: I) -> PoiDetails
+    where
+        I: IntoIterator<Item = (&'a str, &'a str)>,
+    {
+        fn set(field: &mut Option<String>, value: &str, is_main_key: bool) {
+            if field.is_none() || is_main_key {
+                *field = Some(value.to_string());
+            }
+        }
+        let mut details = PoiDetails::default();
+        for (key, value) in tags {
+            let value = value.trim();
+            if value.is_empty() {
+                continue;
+            }
+            match key {
+                "opening_hours" => {
+                    details.opening_hours = Some(value.to_string());
+                    details.schedule = value.parse().ok();
+                }
+                "phone" | "contact:phone" => set(&mut details.phone, value, key == "phone"),
+                "website" | "contact:website" => set(&mut details.website, value, key == "website"),
+                "wheelchair" => details.wheelchair = Wheelchair::from_osm_tag(value),
+                "brand" => details.brand = Some(value.to_string()),
+                "operator" => details.operator = Some(value.to_string()),
+                "timezone" => details.timezone = Some(value.to_string()),
+                _ => (),
+            }
+        }
+        details
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Default)]
@@ -507,6 +701,10 @@ pub struct Stop {
     /// Not serialized as is because it is returned in the `Feature` object
     #[serde(default, skip)]
     pub distance: Option<u32>,
+    /// How well the place matches the query, between 0 and 1
+    /// Not serialized as is because it is returned in the `Feature` object
+    #[serde(default, skip)]
+    pub confidence: Option<f64>,
     #[serde(default)]
     pub lines: Vec<Line>,
     #[serde(default)]
@@ -586,6 +784,10 @@ pub struct Admin {
     /// Not serialized as is because it is returned in the `Feature` object
     #[serde(default, skip)]
     pub distance: Option<u32>,
+    /// How well the place matches the query, between 0 and 1
+    /// Not serialized as is because it is returned in the `Feature` object
+    #[serde(default, skip)]
+    pub confidence: Option<f64>,
 
     pub context: Option<Context>,
 }
@@ -726,9 +928,54 @@ pub struct Street {
     /// Not serialized as is because it is returned in the `Feature` object
     #[serde(default, skip)]
     pub distance: Option<u32>,
+    /// How well the place matches the query, between 0 and 1
+    /// Not serialized as is because it is returned in the `Feature` object
+    #[serde(default, skip)]
+    pub confidence: Option<f64>,
 
     pub context: Option<Context>,
+    /// Ranges of house numbers along the street (from the OSM interpolation lines),
+    /// used to compute the position of the addresses missing in the address datasets.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub house_number_ranges: Vec<HouseNumberRange>,
+}
+
+impl Street {
+    /// Build the address at `house_number` of the street, with a position interpolated
+    /// from the house number ranges of the street.
+    /// The name and label of the address are the house number followed by the street's ones.
+    /// A suffix of the house number (like `bis` or `a`) is kept, the position being the one of
+    /// its number.
+    pub fn interpolate_addr(&self, house_number: &str) -> Option<Addr> {
+        let (number, _) = split_house_number(house_number)?;
+        let house_number = house_number.trim();
+        let coord = self
+            .house_number_ranges
+            .iter()
+            .find_map(|range| range.interpolate(number))?;
+        Some(Addr {
+            id: interpolated_addr_id(&self.id, house_number),
+            name: format!("{} {}", house_number, self.name),
+            label: format!("{} {}", house_number, self.label),
+            house_number: house_number.to_string(),
+            street: Street {
+                house_number_ranges: vec![],
+                ..self.clone()
+            },
+            coord,
+            approx_coord: None,
+            weight: self.weight,
+            zip_codes: self.zip_codes.clone(),
+            country_codes: self.country_codes.clone(),
+            distance: None,
+            // an interpolated address is not more reliable than its street
+            confidence: self.confidence,
+            context: None,
+            interpolated: true,
+        })
+    }
 }
+
 impl Incr for Street {
     fn id(&self) -> &str {
         &self.id
@@ -759,6 +1006,175 @@ impl Members for Street {
     }
 }
 
+/// The crossing of two streets, at a node shared by their ways
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Intersection {
+    pub id: String,
+    /// The names of the streets, like `Rue de Rivoli / Rue du Louvre`
+    pub name: String,
+    pub label: String,
+    pub street_names: Vec<String>,
+    pub administrative_regions: Vec<Arc<Admin>>,
+    pub weight: f64,
+    /// coord used for some geograhic queries in ES, less precise but  faster than `coord`
+    /// https://www.elastic.co/guide/en/elasticsearch/reference/2.4/geo-shape.html
+    #[serde(skip_deserializing)]
+    pub approx_coord: Option<Geometry>,
+    pub coord: Coord,
+    pub zip_codes: Vec<String>,
+    #[serde(default)]
+    pub country_codes: Vec<String>,
+    /// Distance to the coord in query.
+    /// Not serialized as is because it is returned in the `Feature` object
+    #[serde(default, skip)]
+    pub distance: Option<u32>,
+    /// How well the place matches the query, between 0 and 1
+    /// Not serialized as is because it is returned in the `Feature` object
+    #[serde(default, skip)]
+    pub confidence: Option<f64>,
+
+    pub context: Option<Context>,
+}
+
+impl MimirObject for Intersection {
+    fn is_geo_data() -> bool {
+        false
+    }
+    fn doc_type() -> &'static str {
+        PlaceDocType::Intersection.as_str()
+    }
+    fn es_id(&self) -> Option<String> {
+        Some(self.id.clone())
+    }
+}
+
+impl Members for Intersection {
+    fn label(&self) -> &str {
+        &self.label
+    }
+    fn admins(&self) -> Vec<Arc<Admin>> {
+        self.administrative_regions.clone()
+    }
+}
+
+/// The area of a postcode
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Postcode {
+    pub id: String,
+    /// The postcode itself, like `75011`
+    pub name: String,
+    pub label: String,
+    /// Only contains `name`, so that the postcode is matched like the other places
+    pub zip_codes: Vec<String>,
+    pub administrative_regions: Vec<Arc<Admin>>,
+    pub weight: f64,
+    /// coord used for some geograhic queries in ES, less precise but  faster than `coord`
+    /// https://www.elastic.co/guide/en/elasticsearch/reference/2.4/geo-shape.html
+    #[serde(skip_deserializing)]
+    pub approx_coord: Option<Geometry>,
+    /// The centroid of the area
+    pub coord: Coord,
+    #[serde(
+        serialize_with = "custom_multi_polygon_serialize",
+        deserialize_with = "custom_multi_polygon_deserialize",
+        skip_serializing_if = "Option::is_none",
+        default
+    )]
+    pub boundary: Option<MultiPolygon<f64>>,
+    #[serde(
+        serialize_with = "serialize_rect",
+        deserialize_with = "deserialize_rect",
+        skip_serializing_if = "Option::is_none",
+        default
+    )]
+    pub bbox: Option<Rect<f64>>,
+    #[serde(default)]
+    pub country_codes: Vec<String>,
+    /// Distance to the coord in query.
+    /// Not serialized as is because it is returned in the `Feature` object
+    #[serde(default, skip)]
+    pub distance: Option<u32>,
+    /// How well the place matches the query, between 0 and 1
+    /// Not serialized as is because it is returned in the `Feature` object
+    #[serde(default, skip)]
+    pub confidence: Option<f64>,
+
+    pub context: Option<Context>,
+}
+
+impl MimirObject for Postcode {
+    fn is_geo_data() -> bool {
+        true
+    }
+    fn doc_type() -> &'static str {
+        PlaceDocType::Postcode.as_str()
+    }
+    fn es_id(&self) -> Option<String> {
+        Some(self.id.clone())
+    }
+}
+
+impl Members for Postcode {
+    fn label(&self) -> &str {
+        &self.label
+    }
+    fn admins(&self) -> Vec<Arc<Admin>> {
+        self.administrative_regions.clone()
+    }
+}
+
+/// A named place without streets, like a hamlet or a lieu-dit
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Locality {
+    pub id: String,
+    pub name: String,
+    pub label: String,
+    /// The kind of place, from the osm `place` tag: `hamlet`, `locality`, `isolated_dwelling`
+    /// or `neighbourhood`
+    pub locality_type: String,
+    pub administrative_regions: Vec<Arc<Admin>>,
+    pub weight: f64,
+    /// coord used for some geograhic queries in ES, less precise but  faster than `coord`
+    /// https://www.elastic.co/guide/en/elasticsearch/reference/2.4/geo-shape.html
+    #[serde(skip_deserializing)]
+    pub approx_coord: Option<Geometry>,
+    pub coord: Coord,
+    pub zip_codes: Vec<String>,
+    #[serde(default)]
+    pub country_codes: Vec<String>,
+    /// Distance to the coord in query.
+    /// Not serialized as is because it is returned in the `Feature` object
+    #[serde(default, skip)]
+    pub distance: Option<u32>,
+    /// How well the place matches the query, between 0 and 1
+    /// Not serialized as is because it is returned in the `Feature` object
+    #[serde(default, skip)]
+    pub confidence: Option<f64>,
+
+    pub context: Option<Context>,
+}
+
+impl MimirObject for Locality {
+    fn is_geo_data() -> bool {
+        true
+    }
+    fn doc_type() -> &'static str {
+        PlaceDocType::Locality.as_str()
+    }
+    fn es_id(&self) -> Option<String> {
+        Some(self.id.clone())
+    }
+}
+
+impl Members for Locality {
+    fn label(&self) -> &str {
+        &self.label
+    }
+    fn admins(&self) -> Vec<Arc<Admin>> {
+        self.administrative_regions.clone()
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Addr {
     pub id: String,
@@ -780,8 +1196,17 @@ pub struct Addr {
     /// Not serialized as is because it is returned in the `Feature` object
     #[serde(default, skip)]
     pub distance: Option<u32>,
+    /// How well the place matches the query, between 0 and 1
+    /// Not serialized as is because it is returned in the `Feature` object
+    #[serde(default, skip)]
+    pub confidence: Option<f64>,
 
     pub context: Option<Context>,
+    /// The address is not in the index, its position has been interpolated
+    /// from the house number ranges of its street (cf `Street::interpolate_addr`),
+    /// its id is given by `interpolation::interpolated_addr_id`
+    #[serde(default, skip)]
+    pub interpolated: bool,
 }
 
 impl MimirObject for Addr {
@@ -0,0 +1,310 @@
+// Copyright © 2016, Canal TP and/or its affiliates. All rights reserved.
+//
+// This file is part of Navitia,
+//     the software to build cool stuff with public transport.
+//
+// Hope you'll enjoy and contribute to this project,
+//     powered by Canal TP (www.canaltp.fr).
+// Help us simplify mobility and open public transport:
+//     a non ending quest to the responsive locomotion way of traveling!
+//
+// LICENCE: This program is free software; you can redistribute it
+// and/or modify it under the terms of the GNU Affero General Public
+// License as published by the Free Software Foundation, either
+// version 3 of the License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+// Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public
+// License along with this program. If not, see
+// <http://www.gnu.org/licenses/>.
+//
+// Stay tuned using
+// twitter @navitia
+// IRC #navitia on freenode
+// https://groups.google.com/d/forum/navitia
+// www.navitia.io
+
+//! Opening hours of the POIs.
+//!
+//! The OSM `opening_hours` tag has a rich syntax (https://wiki.openstreetmap.org/wiki/Key:opening_hours).
+//! Only its most common part is handled here: rules separated by `;`, each made of weekdays
+//! (`Mo-Fr`, `Sa,Su`) and time spans (`08:00-12:00,14:00-18:00`) or `off`, and `24/7`.
+//! The public and school holidays (`PH`, `SH`) are ignored, as we don't know their dates.
+
+use chrono::{Datelike, NaiveDateTime, Timelike, Weekday};
+use failure::{bail, format_err, Error};
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+const MINUTES_PER_DAY: u16 = 24 * 60;
+
+/// A day of the week, with its name in the OSM syntax
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Day {
+    Mo,
+    Tu,
+    We,
+    Th,
+    Fr,
+    Sa,
+    Su,
+}
+
+const DAYS: [Day; 7] = [
+    Day::Mo,
+    Day::Tu,
+    Day::We,
+    Day::Th,
+    Day::Fr,
+    Day::Sa,
+    Day::Su,
+];
+
+impl Day {
+    fn from_osm(day: &str) -> Option<Day> {
+        match day {
+            "Mo" => Some(Day::Mo),
+            "Tu" => Some(Day::Tu),
+            "We" => Some(Day::We),
+            "Th" => Some(Day::Th),
+            "Fr" => Some(Day::Fr),
+            "Sa" => Some(Day::Sa),
+            "Su" => Some(Day::Su),
+            _ => None,
+        }
+    }
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+impl From<Weekday> for Day {
+    fn from(weekday: Weekday) -> Day {
+        DAYS[weekday.num_days_from_monday() as usize]
+    }
+}
+
+/// A period of opening of a day, in minutes since midnight.
+/// `end` is after midnight (more than 1440) for the periods ending the day after.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeSpan {
+    pub start: u16,
+    pub end: u16,
+}
+
+impl FromStr for TimeSpan {
+    type Err = Error;
+
+    /// Parse a span like `08:30-12:00`
+    fn from_str(s: &str) -> Result<TimeSpan, Error> {
+        fn parse_time(time: &str) -> Option<u16> {
+            let mut parts = time.trim().splitn(2, ':');
+            let hours: u16 = parts.next()?.parse().ok()?;
+            let minutes: u16 = parts.next()?.parse().ok()?;
+            if hours > 24 || minutes >= 60 || (hours == 24 && minutes > 0) {
+                return None;
+            }
+            Some(hours * 60 + minutes)
+        }
+        let mut parts = s.splitn(2, '-');
+        let start = parts.next().and_then(parse_time);
+        let end = parts.next().and_then(parse_time);
+        match (start, end) {
+            (Some(start), Some(end)) if start < MINUTES_PER_DAY => Ok(TimeSpan {
+                start,
+                end: if end <= start {
+                    end + MINUTES_PER_DAY
+                } else {
+                    end
+                },
+            }),
+            _ => bail!("invalid time span '{}'", s),
+        }
+    }
+}
+
+/// The opening periods of some days of the week.
+/// The days of a rule without spans are closed.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct OpeningRule {
+    pub days: Vec<Day>,
+    pub spans: Vec<TimeSpan>,
+}
+
+/// Opening hours of a POI, as a list of rules.
+/// As in OSM, a rule replaces the previous ones for its days.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct OpeningHours {
+    pub rules: Vec<OpeningRule>,
+}
+
+impl OpeningHours {
+    /// The opening periods of `day`
+    fn spans(&self, day: Day) -> &[TimeSpan] {
+        self.rules
+            .iter()
+            .rev()
+            .find(|rule| rule.days.contains(&day))
+            .map_or(&[], |rule| rule.spans.as_slice())
+    }
+
+    /// Is it open at the local date and time `datetime`?
+    pub fn is_open(&self, datetime: &NaiveDateTime) -> bool {
+        let day = Day::from(datetime.weekday());
+        let previous_day = DAYS[(day.index() + 6) % 7];
+        let minute = (datetime.hour() * 60 + datetime.minute()) as u16;
+        self.spans(day)
+            .iter()
+            .any(|span| span.start <= minute && minute < span.end)
+            || self
+                .spans(previous_day)
+                .iter()
+                .any(|span| minute + MINUTES_PER_DAY < span.end)
+    }
+}
+
+/// Parse the days of a rule, like `Mo-Fr,Su`.
+/// Return None if `selector` is not a list of days.
+fn parse_days(selector: &str) -> Option<Vec<Day>> {
+    let mut days = vec![];
+    for item in selector.split(',') {
+        let mut bounds = item.splitn(2, '-');
+        let first = bounds.next()?;
+        if first == "PH" || first == "SH" {
+            continue;
+        }
+        let first = Day::from_osm(first)?;
+        match bounds.next() {
+            // the ranges can wrap around the end of the week, like `Fr-Mo`
+            Some(last) => {
+                let last = Day::from_osm(last)?;
+                let nb_days = (last.index() + 7 - first.index()) % 7 + 1;
+                days.extend((0..nb_days).map(|i| DAYS[(first.index() + i) % 7]));
+            }
+            None => days.push(first),
+        }
+    }
+    Some(days)
+}
+
+impl FromStr for OpeningHours {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<OpeningHours, Error> {
+        let mut rules = vec![];
+        for rule in s.split(';').map(str::trim).filter(|r| !r.is_empty()) {
+            if rule == "24/7" {
+                rules.push(OpeningRule {
+                    days: DAYS.to_vec(),
+                    spans: vec![TimeSpan {
+                        start: 0,
+                        end: MINUTES_PER_DAY,
+                    }],
+                });
+                continue;
+            }
+            let mut parts = rule.splitn(2, ' ');
+            let first = parts.next().unwrap_or_default();
+            let (days, times) = match parse_days(first) {
+                Some(days) => (days, parts.next().unwrap_or_default().trim()),
+                None => (DAYS.to_vec(), rule),
+            };
+            if days.is_empty() {
+                // the rule is only for the holidays
+                continue;
+            }
+            let spans = match times {
+                // some days without time are open all day long
+                "" => vec![TimeSpan {
+                    start: 0,
+                    end: MINUTES_PER_DAY,
+                }],
+                "off" | "closed" => vec![],
+                _ => times
+                    .split(',')
+                    .map(str::parse::<TimeSpan>)
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(|e| format_err!("unsupported opening hours '{}': {}", s, e))?,
+            };
+            rules.push(OpeningRule { days, spans });
+        }
+        if rules.is_empty() {
+            bail!("no opening hours in '{}'", s);
+        }
+        Ok(OpeningHours { rules })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn at(day: u32, hour: u32, minute: u32) -> NaiveDateTime {
+        // the 2019-12-02 is a monday
+        NaiveDate::from_ymd(2019, 12, 1 + day).and_hms(hour, minute, 0)
+    }
+
+    #[test]
+    fn test_parse_opening_hours() {
+        let hours: OpeningHours = "Mo-Fr 08:00-12:00,14:00-18:30; Sa 09:00-12:00; PH off"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            hours.rules,
+            vec![
+                OpeningRule {
+                    days: vec![Day::Mo, Day::Tu, Day::We, Day::Th, Day::Fr],
+                    spans: vec![
+                        TimeSpan {
+                            start: 480,
+                            end: 720
+                        },
+                        TimeSpan {
+                            start: 840,
+                            end: 1110
+                        },
+                    ],
+                },
+                OpeningRule {
+                    days: vec![Day::Sa],
+                    spans: vec![TimeSpan {
+                        start: 540,
+                        end: 720
+                    }],
+                },
+            ]
+        );
+
+        assert!("Mo-Fr 08:00-12:00 open".parse::<OpeningHours>().is_err());
+        assert!("Jan-Mar 08:00-12:00".parse::<OpeningHours>().is_err());
+        assert!("".parse::<OpeningHours>().is_err());
+    }
+
+    #[test]
+    fn test_is_open() {
+        let hours: OpeningHours = "Mo-Fr 08:00-12:00,14:00-18:30; We off".parse().unwrap();
+        assert!(hours.is_open(&at(1, 8, 0)));
+        assert!(!hours.is_open(&at(1, 12, 0)));
+        assert!(hours.is_open(&at(5, 18, 29)));
+        assert!(!hours.is_open(&at(3, 10, 0)));
+        assert!(!hours.is_open(&at(6, 10, 0)));
+
+        let always: OpeningHours = "24/7".parse().unwrap();
+        assert!(always.is_open(&at(7, 3, 0)));
+
+        // the night of friday continues on saturday, and sunday is open all day
+        let hours: OpeningHours = "Fr-Sa 22:00-02:00; Su".parse().unwrap();
+        assert!(hours.is_open(&at(5, 23, 0)));
+        assert!(hours.is_open(&at(6, 1, 0)));
+        assert!(!hours.is_open(&at(6, 3, 0)));
+        assert!(hours.is_open(&at(7, 1, 0)));
+        assert!(hours.is_open(&at(7, 12, 0)));
+        assert!(!hours.is_open(&at(1, 3, 0)));
+    }
+}
@@ -0,0 +1,851 @@
+// Copyright © 2016, Canal TP and/or its affiliates. All rights reserved.
+//
+// This file is part of Navitia,
+//     the software to build cool stuff with public transport.
+//
+// Hope you'll enjoy and contribute to this project,
+//     powered by Canal TP (www.canaltp.fr).
+// Help us simplify mobility and open public transport:
+//     a non ending quest to the responsive locomotion way of traveling!
+//
+// LICENCE: This program is free software; you can redistribute it
+// and/or modify it under the terms of the GNU Affero General Public
+// License as published by the Free Software Foundation, either
+// version 3 of the License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+// Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public
+// License along with this program. If not, see
+// <http://www.gnu.org/licenses/>.
+//
+// Stay tuned using
+// twitter @navitia
+// IRC #navitia on freenode
+// https://groups.google.com/d/forum/navitia
+// www.navitia.io
+
+//! `SearchBackend` for the REST API of the recent versions of Elasticsearch (7+)
+//! and OpenSearch, which have no mapping types anymore.
+//!
+//! Since the type of a document cannot be given by its `_type`, it is stored in the
+//! `doc_type` field of each document, and the `_type` used in the queries are replaced by it.
+//! The index templates are converted from the Elasticsearch 2 ones in `config/`.
+
+use super::backend::SearchBackend;
+use super::objects::{
+    Admin, AliasOperation, AliasOperations, AliasParameter, Coord, MimirObject, Place, Postcode,
+};
+use super::rubber::{
+    build_containing_query, bulk_items, get_main_type_index, keep_admins_containing,
+    keep_postcodes_containing, make_index_settings, make_place, read_admin_names, with_distance,
+    with_relative_scores, IndexSettings, PatchOperation, TypedIndex, ADMIN_NAME_FIELDS,
+    MAX_ADMINS_CONTAINING, TEMPLATES,
+};
+use failure::{bail, format_err, Error, ResultExt};
+use reqwest::header::CONTENT_TYPE;
+use reqwest::{StatusCode, Url};
+use rs_es::query::Query;
+use serde::de::DeserializeOwned;
+use serde_json::{json, Map, Value};
+use slog_scope::{debug, info, warn};
+use std::collections::BTreeMap;
+use std::time;
+
+/// Field of the documents holding their type
+pub const DOC_TYPE_FIELD: &str = "doc_type";
+
+#[derive(Clone, Debug)]
+pub struct RestBackend {
+    http_client: reqwest::blocking::Client,
+    base_url: Url,
+    // Note: The timeout is used for the http client AND for the search engine internal query
+    pub timeout: Option<time::Duration>,
+    pub nb_insert_threads: usize,
+    max_bulk_errors: usize,
+}
+
+impl RestBackend {
+    // build a backend with a connection string (http://host:port/)
+    pub fn new(cnx: &str) -> Result<RestBackend, Error> {
+        RestBackend::new_with_timeout(cnx, None)
+    }
+
+    pub fn new_with_timeout<T>(cnx: &str, timeout: T) -> Result<RestBackend, Error>
+    where
+        T: Into<Option<time::Duration>>,
+    {
+        debug!("search engine host {} ", cnx);
+        let timeout = timeout.into();
+        let mut base_url =
+            Url::parse(cnx).with_context(|_| format!("invalid connection string {}", cnx))?;
+        // like with rs_es, only the host of the connection string is used
+        base_url.set_path("/");
+        base_url.set_query(None);
+        Ok(RestBackend {
+            http_client: reqwest::blocking::Client::builder()
+                .timeout(timeout)
+                .build()?,
+            base_url,
+            timeout,
+            nb_insert_threads: 1,
+            max_bulk_errors: 0,
+        })
+    }
+
+    /// Change the number of parallel threads used to insert data during `bulk_index`.
+    pub fn with_nb_insert_threads(mut self, value: usize) -> Self {
+        self.nb_insert_threads = value;
+        self
+    }
+
+    pub fn with_max_bulk_errors(mut self, value: usize) -> Self {
+        self.max_bulk_errors = value;
+        self
+    }
+
+    fn url(&self, path: &str) -> Result<Url, Error> {
+        Ok(self.base_url.join(path)?)
+    }
+
+    /// Send the request and read its json response, `None` if nothing has been found
+    fn send(
+        &self,
+        method: reqwest::Method,
+        path: &str,
+        body: Option<String>,
+    ) -> Result<Option<Value>, Error> {
+        debug!("doing a {} on {} with {:?}", method, path, body);
+        let mut request = self.http_client.request(method, self.url(path)?);
+        if let Some(body) = body {
+            request = request.header(CONTENT_TYPE, "application/json").body(body);
+        }
+        let response = request.send()?;
+        match response.status() {
+            StatusCode::NOT_FOUND => Ok(None),
+            status if status.is_success() => Ok(Some(response.json()?)),
+            status => bail!(
+                "invalid response for {}: {}, {}",
+                path,
+                status,
+                response.text().unwrap_or_default()
+            ),
+        }
+    }
+
+    fn put(&self, path: &str, body: &Value) -> Result<(), Error> {
+        self.send(reqwest::Method::PUT, path, Some(body.to_string()))?;
+        Ok(())
+    }
+
+    fn search_raw(&self, indexes: &[&str], body: &Value) -> Result<Value, Error> {
+        if indexes.is_empty() {
+            // without index, the search engine would search on all the indexes,
+            // but we want to return an empty response in this case.
+            return Ok(json!({}));
+        }
+        Ok(self
+            .send(
+                reqwest::Method::POST,
+                &format!("{}/_search?ignore_unavailable=true", indexes.join(",")),
+                Some(body.to_string()),
+            )?
+            .unwrap_or_else(|| json!({})))
+    }
+
+    fn timeout_param(&self) -> Option<String> {
+        self.timeout.map(|t| format!("{}ms", t.as_millis()))
+    }
+
+    /// Search the places of `index` whose indexed boundary contains `coord`.
+    fn search_containing(&self, index: &str, coord: &Coord) -> Result<Vec<Place>, Error> {
+        let mut body = json!({
+            "query": convert_query(&build_containing_query(coord))?,
+            "size": MAX_ADMINS_CONTAINING,
+        });
+        if let Some(timeout) = self.timeout_param() {
+            body["timeout"] = json!(timeout);
+        }
+        let response = self.search_raw(&[index], &body)?;
+        Ok(read_places(&response, Some(coord)))
+    }
+
+    /// Scroll through all the documents of `index` searched with `body`,
+    /// reading each of them from its `_source` with `read_doc`.
+    fn scroll<T>(
+        &self,
+        index: &str,
+        body: Value,
+        read_doc: impl Fn(&Value) -> Result<T, Error>,
+    ) -> Result<Vec<T>, Error> {
+        let scroll = "1m";
+        let mut docs = vec![];
+        let mut response = self
+            .send(
+                reqwest::Method::POST,
+                &format!("{}/_search?scroll={}", index, scroll),
+                Some(body.to_string()),
+            )?
+            .unwrap_or_else(|| json!({}));
+        loop {
+            let hits = response
+                .pointer("/hits/hits")
+                .and_then(|hits| hits.as_array())
+                .cloned()
+                .unwrap_or_else(Vec::new);
+            if hits.is_empty() {
+                break;
+            }
+            for hit in hits {
+                if let Some(source) = hit.get("_source") {
+                    docs.push(read_doc(source)?);
+                }
+            }
+            let scroll_id = match response.get("_scroll_id") {
+                Some(scroll_id) => scroll_id.clone(),
+                None => break,
+            };
+            response = self
+                .send(
+                    reqwest::Method::POST,
+                    "_search/scroll",
+                    Some(json!({ "scroll": scroll, "scroll_id": scroll_id }).to_string()),
+                )?
+                .unwrap_or_else(|| json!({}));
+        }
+        if let Some(scroll_id) = response.get("_scroll_id") {
+            // the search context would otherwise be kept until the scroll expires
+            if let Err(e) = self.send(
+                reqwest::Method::DELETE,
+                "_search/scroll",
+                Some(json!({ "scroll_id": scroll_id }).to_string()),
+            ) {
+                warn!("impossible to clear the scroll of {}: {}", index, e);
+            }
+        }
+        Ok(docs)
+    }
+}
+
+/// Read the places of a search response, their type being given by the `doc_type` field
+fn read_places(response: &Value, coord: Option<&Coord>) -> Vec<Place> {
+    response
+        .pointer("/hits/hits")
+        .and_then(|hits| hits.as_array())
+        .map(|hits| {
+            hits.iter()
+                .filter_map(|hit| {
+                    let source = hit.get("_source")?;
+                    let doc_type = source.get(DOC_TYPE_FIELD)?.as_str()?.to_string();
+                    let score = hit.get("_score").and_then(|s| s.as_f64());
+                    make_place(
+                        doc_type,
+                        Some(Box::new(source.clone())),
+                        hit.get("_explanation").cloned(),
+                    )
+                    .map(|place| (score, with_distance(place, coord)))
+                })
+                .collect()
+        })
+        .map(with_relative_scores)
+        .unwrap_or_else(Vec::new)
+}
+
+/// Replace the filters on `_type` by filters on the `doc_type` field
+fn use_doc_type_field(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            if let Some(doc_type) = map.remove("_type") {
+                map.insert(DOC_TYPE_FIELD.to_owned(), doc_type);
+            }
+            map.values_mut().for_each(use_doc_type_field);
+        }
+        Value::Array(values) => values.iter_mut().for_each(use_doc_type_field),
+        _ => {}
+    }
+}
+
+/// Serialize a query built for Elasticsearch 2, the filters on `_type`
+/// being replaced by filters on the `doc_type` field.
+pub fn convert_query(query: &Query) -> Result<Value, Error> {
+    let mut query = serde_json::to_value(query)?;
+    use_doc_type_field(&mut query);
+    Ok(query)
+}
+
+/// Convert in place the body of a search built for Elasticsearch 2 (cf `rubber::search_body`):
+/// the filters on `_type` are replaced by filters on the `doc_type` field,
+/// and the source filtering uses the current names of its parameters.
+pub fn convert_search_body(body: &mut Value) {
+    if let Some(query) = body.get_mut("query") {
+        use_doc_type_field(query);
+    }
+    if let Some(Value::Object(source)) = body.get_mut("_source") {
+        for (old, new) in &[("include", "includes"), ("exclude", "excludes")] {
+            if let Some(fields) = source.remove(*old) {
+                source.insert((*new).to_owned(), fields);
+            }
+        }
+    }
+}
+
+/// Convert an Elasticsearch 2 index template (cf `config/*_settings.json`)
+/// to a template without mapping type.
+pub fn convert_template(template: &str) -> Result<Value, Error> {
+    let mut template: Value = serde_json::from_str(template)?;
+    let template_obj = template
+        .as_object_mut()
+        .ok_or_else(|| format_err!("the template is not a json object"))?;
+
+    if let Some(pattern) = template_obj.remove("template") {
+        template_obj.insert("index_patterns".to_owned(), json!([pattern]));
+    }
+
+    if let Some(settings) = template_obj.get_mut("settings") {
+        convert_settings(settings);
+    }
+
+    // there is only one mapping type by template, its mapping becomes the index mapping
+    let mapping = match template_obj.remove("mappings") {
+        Some(Value::Object(types)) => types.into_iter().map(|(_, m)| m).next(),
+        _ => None,
+    };
+    let mut mapping = match mapping {
+        Some(Value::Object(mapping)) => mapping,
+        _ => bail!("the template has no mapping"),
+    };
+    mapping.remove("_all");
+
+    if let Some(Value::Array(dynamic_templates)) = mapping.get_mut("dynamic_templates") {
+        for dynamic_template in dynamic_templates.iter_mut() {
+            if let Value::Object(dynamic_template) = dynamic_template {
+                for named in dynamic_template.values_mut() {
+                    if let Some(Value::Object(field)) = named.get_mut("mapping") {
+                        convert_field(field);
+                    }
+                }
+            }
+        }
+    }
+    convert_sub_fields(&mut mapping);
+
+    let properties = mapping.entry("properties").or_insert_with(|| json!({}));
+    properties[DOC_TYPE_FIELD] = json!({ "type": "keyword" });
+
+    template_obj.insert("mappings".to_owned(), Value::Object(mapping));
+    Ok(template)
+}
+
+/// Convert Elasticsearch 2 index settings (the `settings` object of
+/// `config/settings.json` or of a template) in place.
+fn convert_settings(settings: &mut Value) {
+    // the 'nGram' filters and tokenizers have been renamed
+    if let Some(analysis) = settings.get_mut("analysis") {
+        for section in &["filter", "tokenizer"] {
+            if let Some(Value::Object(items)) = analysis.get_mut(*section) {
+                for item in items.values_mut() {
+                    if item.get("type") == Some(&json!("nGram")) {
+                        item["type"] = json!("ngram");
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn convert_sub_fields(field: &mut Map<String, Value>) {
+    for key in &["properties", "fields"] {
+        if let Some(Value::Object(sub_fields)) = field.get_mut(*key) {
+            for sub_field in sub_fields.values_mut() {
+                if let Value::Object(sub_field) = sub_field {
+                    convert_field(sub_field);
+                }
+            }
+        }
+    }
+}
+
+fn convert_field(field: &mut Map<String, Value>) {
+    let not_analyzed = field.get("index") == Some(&json!("not_analyzed"));
+    match field.get("type").and_then(Value::as_str) {
+        Some("string") if not_analyzed => {
+            field.insert("type".to_owned(), json!("keyword"));
+            field.remove("index");
+        }
+        Some("string") => {
+            field.insert("type".to_owned(), json!("text"));
+        }
+        Some("geo_point") => {
+            for removed in &["lat_lon", "geohash_prefix", "geohash_precision"] {
+                field.remove(*removed);
+            }
+        }
+        Some("geo_shape") => {
+            for removed in &["tree", "precision"] {
+                field.remove(*removed);
+            }
+        }
+        _ => {}
+    }
+    if field.get("index") == Some(&json!("no")) {
+        field.insert("index".to_owned(), json!(false));
+    }
+    if let Some(enabled) = field.get("norms").and_then(|n| n.get("enabled")).cloned() {
+        field.insert("norms".to_owned(), enabled);
+    }
+    convert_sub_fields(field);
+}
+
+impl SearchBackend for RestBackend {
+    fn initialize_templates(&self) -> Result<(), Error> {
+        for (name, settings) in TEMPLATES.iter() {
+            let template = convert_template(settings)
+                .with_context(|_| format!("invalid template {}", name))?;
+            self.put(&format!("_template/{}", name), &template)
+                .with_context(|_| format!("Error while creating template {}", name))?;
+        }
+        Ok(())
+    }
+
+    fn create_index(&self, name: &str, index_settings: &IndexSettings) -> Result<(), Error> {
+        debug!("creating index");
+        let mut settings = make_index_settings(name, index_settings)?;
+        if let Some(settings) = settings.get_mut("settings") {
+            convert_settings(settings);
+        }
+        self.put(name, &settings)
+            .with_context(|_| format!("Error while creating new index {}", name))?;
+        Ok(())
+    }
+
+    fn bulk_index<T, I>(&mut self, index: &TypedIndex<T>, iter: I) -> Result<usize, Error>
+    where
+        T: MimirObject + std::marker::Send + 'static,
+        I: Iterator<Item = T>,
+    {
+        use par_map::ParMap;
+
+        let chunk_size = 1000;
+        let index_name = index.name.to_owned();
+        let client = self.http_client.clone();
+        let url = self.url("_bulk")?;
+        let max_bulk_errors = self.max_bulk_errors;
+
+        iter.pack(chunk_size)
+            .par_map(move |chunk| -> Result<(usize, usize), Error> {
+                let mut body = String::new();
+                for doc in &chunk {
+                    let mut action = json!({ "index": { "_index": index_name } });
+                    if let Some(id) = doc.es_id() {
+                        action["index"]["_id"] = json!(id);
+                    }
+                    let mut doc = serde_json::to_value(doc)?;
+                    doc[DOC_TYPE_FIELD] = json!(T::doc_type());
+                    body.push_str(&action.to_string());
+                    body.push('\n');
+                    body.push_str(&doc.to_string());
+                    body.push('\n');
+                }
+                let res: Value = client
+                    .post(url.clone())
+                    .header(CONTENT_TYPE, "application/x-ndjson")
+                    .body(body)
+                    .send()?
+                    .error_for_status()?
+                    .json()?;
+
+                let mut nb_errors_in_chunk = 0;
+                if res["errors"].as_bool().unwrap_or(false) {
+                    for item in res["items"].as_array().into_iter().flatten() {
+                        let item = &item["index"];
+                        if item["status"].as_u64().map_or(true, |s| s >= 300) {
+                            nb_errors_in_chunk += 1;
+                            warn!(
+                                "An error occured while importing {} '{}'. Status {}: {}",
+                                T::doc_type(),
+                                item["_id"],
+                                item["status"],
+                                item["error"]
+                            );
+                        }
+                    }
+                }
+                Ok((chunk.len() - nb_errors_in_chunk, nb_errors_in_chunk))
+            })
+            .with_nb_threads(self.nb_insert_threads)
+            .try_fold((0, 0), |(nb_ok, nb_errors), res| {
+                let (chunk_ok, chunk_errors) = res?;
+                if nb_errors + chunk_errors > max_bulk_errors {
+                    return Err(format_err!("too many errors during bulk_index"));
+                }
+                Ok((nb_ok + chunk_ok, nb_errors + chunk_errors))
+            })
+            .map(|(nb_ok, _)| nb_ok)
+    }
+
+    fn refresh_index(&mut self, index: &str) -> Result<(), Error> {
+        self.send(reqwest::Method::POST, &format!("{}/_refresh", index), None)?;
+        Ok(())
+    }
+
+    fn get_all_aliased_index(
+        &self,
+        base_index: &str,
+    ) -> Result<BTreeMap<String, Vec<String>>, Error> {
+        let res = self
+            .send(
+                reqwest::Method::GET,
+                &format!("{}_*/_alias", base_index),
+                None,
+            )
+            .with_context(|_| format!("Error occurred when getting {}*/_alias", base_index))?;
+        Ok(res
+            .as_ref()
+            .and_then(Value::as_object)
+            .map(|all_aliases| {
+                all_aliases
+                    .iter()
+                    .filter_map(|(i, a)| {
+                        a.pointer("/aliases")
+                            .and_then(|a| a.as_object())
+                            .map(|aliases| (i.clone(), aliases.keys().cloned().collect()))
+                    })
+                    .collect()
+            })
+            .unwrap_or_else(|| {
+                info!("no aliases for {}", base_index);
+                BTreeMap::new()
+            }))
+    }
+
+    fn alias(&self, alias: &str, add: &[String], remove: &[String]) -> Result<(), Error> {
+        info!(
+            "for {}, adding alias {:?}, removing {:?}",
+            alias, add, remove
+        );
+        let add_operations = add.iter().map(|x| AliasOperation {
+            remove: None,
+            add: Some(AliasParameter {
+                index: x.clone(),
+                alias: alias.to_string(),
+            }),
+        });
+        let remove_operations = remove.iter().map(|x| AliasOperation {
+            add: None,
+            remove: Some(AliasParameter {
+                index: x.clone(),
+                alias: alias.to_string(),
+            }),
+        });
+        let operations = AliasOperations {
+            actions: add_operations.chain(remove_operations).collect(),
+        };
+        self.send(
+            reqwest::Method::POST,
+            "_aliases",
+            Some(serde_json::to_string(&operations)?),
+        )
+        .with_context(|_| format!("failed to post aliases for {}", alias))?;
+        Ok(())
+    }
+
+    fn delete_index(&mut self, index: &str) -> Result<(), Error> {
+        debug!("deleting index {}", &index);
+        match self.send(reqwest::Method::DELETE, index, None)? {
+            Some(_) => Ok(()),
+            None => bail!("Error deleting index {}", &index),
+        }
+    }
+
+    fn delete_docs<T: MimirObject>(
+        &mut self,
+        index: &TypedIndex<T>,
+        ids: &[String],
+    ) -> Result<usize, Error> {
+        let operations = ids
+            .iter()
+            .map(|id| PatchOperation::Delete { id: id.clone() })
+            .collect::<Vec<_>>();
+        self.patch_index(index, &operations)
+    }
+
+    fn patch_index<T: MimirObject>(
+        &mut self,
+        index: &TypedIndex<T>,
+        operations: &[PatchOperation<T>],
+    ) -> Result<usize, Error> {
+        let chunk_size = 1000;
+        let url = self.url("_bulk")?;
+        let mut nb_ok = 0;
+        let mut nb_errors = 0;
+        for chunk in operations.chunks(chunk_size) {
+            // each operation is an action line, followed by a source line for the upserts
+            // and the updates
+            let mut body = String::new();
+            for operation in chunk {
+                let (action, id, source) = match operation {
+                    PatchOperation::Upsert(doc) => {
+                        // without id, the document would be added instead of replacing
+                        // the existing one
+                        let id = doc.es_id().ok_or_else(|| {
+                            format_err!(
+                                "impossible to upsert a {} without id in {}",
+                                T::doc_type(),
+                                index.name
+                            )
+                        })?;
+                        let mut doc = serde_json::to_value(doc)?;
+                        doc[DOC_TYPE_FIELD] = json!(T::doc_type());
+                        ("index", id, Some(doc))
+                    }
+                    PatchOperation::Update { id, fields } => {
+                        ("update", id.clone(), Some(json!({ "doc": fields })))
+                    }
+                    PatchOperation::Delete { id } => ("delete", id.clone(), None),
+                };
+                body.push_str(&json!({ action: { "_index": index.name, "_id": id } }).to_string());
+                body.push('\n');
+                if let Some(source) = source {
+                    body.push_str(&source.to_string());
+                    body.push('\n');
+                }
+            }
+            let res: Value = self
+                .http_client
+                .post(url.clone())
+                .header(CONTENT_TYPE, "application/x-ndjson")
+                .body(body)
+                .send()?
+                .error_for_status()?
+                .json()?;
+            let items = bulk_items(&res, chunk.len())
+                .with_context(|_| format!("Error occurred when patching {}", index.name))?;
+            for (item, operation) in items.iter().zip(chunk) {
+                // an item is an object with the action as only key
+                let (action, result) = match item.as_object().and_then(|i| i.iter().next()) {
+                    Some(item) => item,
+                    None => continue,
+                };
+                match (result["status"].as_u64(), operation) {
+                    (Some(200), _) | (Some(201), _) => nb_ok += 1,
+                    // the deleted document was not in the index
+                    (Some(404), PatchOperation::Delete { .. }) => (),
+                    _ => {
+                        nb_errors += 1;
+                        warn!(
+                            "An error occured during the {} of {} '{}'. Status {}: {}",
+                            action,
+                            T::doc_type(),
+                            result["_id"],
+                            result["status"],
+                            result["error"]
+                        );
+                    }
+                }
+            }
+            if nb_errors > self.max_bulk_errors {
+                bail!("too many errors during patch_index");
+            }
+        }
+        Ok(nb_ok)
+    }
+
+    fn get_all_objects<T>(&mut self, index: &str) -> Result<Vec<T>, Error>
+    where
+        T: MimirObject + DeserializeOwned + std::fmt::Debug,
+    {
+        let body = json!({
+            "size": 1000,
+            "query": { "term": { DOC_TYPE_FIELD: T::doc_type() } },
+        });
+        self.scroll(index, body, |source| {
+            Ok(serde_json::from_value(source.clone())?)
+        })
+    }
+
+    fn search(
+        &mut self,
+        indexes: &[&str],
+        query: &Query,
+        from: u64,
+        size: u64,
+        coord: Option<&Coord>,
+    ) -> Result<Vec<Place>, Error> {
+        let mut body = json!({
+            "query": convert_query(query)?,
+            "from": from,
+            "size": size,
+            // No need to fetch "boundary" as it's not used in the geocoding response
+            "_source": { "excludes": ["boundary"] },
+        });
+        if let Some(timeout) = self.timeout_param() {
+            body["timeout"] = json!(timeout);
+        }
+        Ok(read_places(&self.search_raw(indexes, &body)?, coord))
+    }
+
+    fn get_admins_containing(&mut self, coord: &Coord) -> Result<Vec<Place>, Error> {
+        let admins = self.search_containing(&get_main_type_index::<Admin>(), coord)?;
+        Ok(keep_admins_containing(admins, coord))
+    }
+
+    fn get_postcodes_containing(&mut self, coord: &Coord) -> Result<Vec<Place>, Error> {
+        let postcodes = self.search_containing(&get_main_type_index::<Postcode>(), coord)?;
+        Ok(keep_postcodes_containing(postcodes, coord))
+    }
+
+    fn get_all_admins(&mut self) -> Result<Vec<Admin>, Error> {
+        self.get_all_objects(&get_main_type_index::<Admin>())
+    }
+
+    fn get_admin_names(&mut self) -> Result<Vec<Admin>, Error> {
+        let body = json!({ "size": 1000, "_source": { "includes": ADMIN_NAME_FIELDS } });
+        self.scroll(&get_main_type_index::<Admin>(), body, |source| {
+            Ok(read_admin_names(source)?)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_convert_templates() {
+        fn all_types(value: &Value, types: &mut Vec<String>) {
+            match value {
+                Value::Object(map) => {
+                    if let Some(Value::String(t)) = map.get("type") {
+                        types.push(t.clone());
+                    }
+                    map.values().for_each(|v| all_types(v, types));
+                }
+                Value::Array(values) => values.iter().for_each(|v| all_types(v, types)),
+                _ => {}
+            }
+        }
+
+        for (name, settings) in TEMPLATES.iter() {
+            let template = convert_template(settings).unwrap();
+            assert!(template.get("template").is_none(), "{}", name);
+            assert!(template["index_patterns"].is_array(), "{}", name);
+            assert!(template["mappings"].get("_all").is_none(), "{}", name);
+            assert_eq!(
+                template["mappings"]["properties"][DOC_TYPE_FIELD],
+                json!({"type": "keyword"})
+            );
+            assert_eq!(
+                template["mappings"]["properties"]["coord"],
+                json!({"type": "geo_point"})
+            );
+
+            let mut types = vec![];
+            all_types(&template["mappings"], &mut types);
+            assert!(!types.contains(&"string".to_owned()), "{}", name);
+            assert!(types.contains(&"text".to_owned()), "{}", name);
+
+            let mut types = vec![];
+            all_types(&template["settings"], &mut types);
+            assert!(!types.contains(&"nGram".to_owned()), "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_convert_settings() {
+        let mut settings = json!({
+            "analysis": {
+                "filter": {
+                    "ngram_filter": { "type": "nGram", "min_gram": 3, "max_gram": 3 },
+                    "synonym_filter": { "type": "synonym", "synonyms": [] },
+                },
+                "tokenizer": {
+                    "ngram_tokenizer": { "type": "nGram", "min_gram": 2, "max_gram": 2 },
+                },
+            },
+            "number_of_shards": 1,
+        });
+        convert_settings(&mut settings);
+        assert_eq!(
+            settings["analysis"]["filter"]["ngram_filter"]["type"],
+            "ngram"
+        );
+        assert_eq!(
+            settings["analysis"]["filter"]["synonym_filter"]["type"],
+            "synonym"
+        );
+        assert_eq!(
+            settings["analysis"]["tokenizer"]["ngram_tokenizer"]["type"],
+            "ngram"
+        );
+        assert_eq!(settings["number_of_shards"], 1);
+
+        let mut index_settings = make_index_settings(
+            "munin_addr_fr_test",
+            &IndexSettings {
+                nb_shards: 2,
+                nb_replicas: 1,
+            },
+        )
+        .unwrap();
+        convert_settings(&mut index_settings["settings"]);
+        assert_eq!(index_settings["settings"]["number_of_shards"], 2);
+    }
+
+    #[test]
+    fn test_convert_field() {
+        let mut field = json!({
+            "type": "string",
+            "index": "no",
+            "norms": { "enabled": false },
+            "fields": {
+                "id": { "type": "string", "index": "not_analyzed" },
+                "prefix": { "type": "string", "analyzer": "prefix" },
+            },
+        });
+        convert_field(field.as_object_mut().unwrap());
+        assert_eq!(
+            field,
+            json!({
+                "type": "text",
+                "index": false,
+                "norms": false,
+                "fields": {
+                    "id": { "type": "keyword" },
+                    "prefix": { "type": "text", "analyzer": "prefix" },
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn test_convert_search_body() {
+        let query = Query::build_bool()
+            .with_filter(Query::build_term("_type", "poi").build())
+            .build();
+        let mut body =
+            crate::rubber::search_body(&query, 0, 10, Some(time::Duration::from_secs(1)));
+        convert_search_body(&mut body);
+        let query = body["query"].to_string();
+        assert!(!query.contains("\"_type\""));
+        assert!(query.contains(r#"{"term":{"doc_type":"#));
+        assert_eq!(body["_source"], json!({ "excludes": ["boundary"] }));
+        assert_eq!(body["timeout"], json!("1000ms"));
+    }
+
+    #[test]
+    fn test_convert_query() {
+        let query = Query::build_bool()
+            .with_must(vec![
+                Query::build_term("_type", "addr").build(),
+                Query::build_match("name", "rue").build(),
+            ])
+            .build();
+        let query = convert_query(&query).unwrap();
+        let query = query.to_string();
+        assert!(!query.contains("\"_type\""));
+        assert!(query.contains(r#"{"term":{"doc_type":"#));
+    }
+}
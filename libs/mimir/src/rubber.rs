@@ -28,14 +28,17 @@
 // https://groups.google.com/d/forum/navitia
 // www.navitia.io
 
-use super::objects::{Admin, Context, Explanation, MimirObject};
+use super::backend::SearchBackend;
+use super::objects::{Admin, Context, Explanation, MimirObject, Postcode};
 use super::objects::{AliasOperation, AliasOperations, AliasParameter, Coord, Place};
+use super::rest_backend::DOC_TYPE_FIELD;
 use failure::{bail, format_err, Error, ResultExt};
 use prometheus::{exponential_buckets, histogram_opts, register_histogram, Histogram};
 use reqwest::StatusCode;
 use rs_es::error::EsError;
 use rs_es::operations::search::ScanResult;
 use rs_es::operations::search::SearchResult;
+use rs_es::operations::search::Source;
 use rs_es::query::functions::{FilteredFunction, Function};
 use rs_es::query::Query;
 use rs_es::units as rs_u;
@@ -67,12 +70,18 @@ const SYNONYMS: [&str; 17] = [
 ];
 
 lazy_static::lazy_static! {
-    static ref ES_REQ_HISTOGRAM: Histogram = register_histogram!(
+    pub static ref ES_REVERSE_HISTOGRAM: Histogram = register_histogram!(
         "bragi_elasticsearch_reverse_duration_seconds",
         "The elasticsearch reverse request latencies in seconds.",
         exponential_buckets(0.001, 1.5, 25).unwrap()
     )
     .unwrap();
+    pub static ref ES_MSEARCH_HISTOGRAM: Histogram = register_histogram!(
+        "bragi_elasticsearch_msearch_duration_seconds",
+        "The elasticsearch multi search request latencies in seconds.",
+        exponential_buckets(0.001, 1.5, 25).unwrap()
+    )
+    .unwrap();
 }
 
 fn check_response(
@@ -86,6 +95,86 @@ fn check_response(
     }
 }
 
+/// The template shared by the places which only differ by some fields of their mapping
+const PLACE_SETTINGS: &str = include_str!("../../../config/place_settings.json");
+
+/// The template of the places of `doc_type`, from `config/place_settings.json` with the
+/// `properties` of these places added to its mapping
+fn make_place_template(doc_type: &str, properties: serde_json::Value) -> String {
+    let mut template = serde_json::from_str::<serde_json::Value>(PLACE_SETTINGS).unwrap();
+    template["template"] = serde_json::Value::from(format!("munin_{}_*", doc_type));
+    let mappings = template["mappings"].as_object_mut().unwrap();
+    let mut mapping = mappings.remove("to_be_replaced").unwrap();
+    if let (Some(all), serde_json::Value::Object(added)) =
+        (mapping["properties"].as_object_mut(), properties)
+    {
+        all.extend(added);
+    }
+    mappings.insert(doc_type.to_owned(), mapping);
+    template.to_string()
+}
+
+lazy_static::lazy_static! {
+    /// The index templates (name and settings) of all the document types
+    pub(crate) static ref TEMPLATES: Vec<(&'static str, String)> = vec![
+        (
+            "template_addr",
+            include_str!("../../../config/addr_settings.json").to_owned(),
+        ),
+        (
+            "template_stop",
+            include_str!("../../../config/stop_settings.json").to_owned(),
+        ),
+        (
+            "template_admin",
+            include_str!("../../../config/admin_settings.json").to_owned(),
+        ),
+        (
+            "template_street",
+            include_str!("../../../config/street_settings.json").to_owned(),
+        ),
+        (
+            "template_poi",
+            include_str!("../../../config/poi_settings.json").to_owned(),
+        ),
+        (
+            "template_intersection",
+            make_place_template(
+                "intersection",
+                serde_json::json!({
+                    "street_names": {
+                        "type": "string",
+                        "index_options": "docs",
+                        "analyzer": "word",
+                    },
+                }),
+            ),
+        ),
+        (
+            "template_postcode",
+            make_place_template(
+                "postcode",
+                serde_json::json!({
+                    "boundary": {
+                        "type": "geo_shape",
+                        "tree": "quadtree",
+                        "precision": "1km",
+                    },
+                }),
+            ),
+        ),
+        (
+            "template_locality",
+            make_place_template(
+                "locality",
+                serde_json::json!({
+                    "locality_type": { "type": "string", "index": "not_analyzed" },
+                }),
+            ),
+        ),
+    ];
+}
+
 // Rubber is an wrapper around elasticsearch API
 #[derive(Clone, Debug)]
 pub struct Rubber {
@@ -101,7 +190,7 @@ pub struct Rubber {
 
 #[derive(Clone, Debug)]
 pub struct TypedIndex<T> {
-    name: String,
+    pub(crate) name: String,
     _type: PhantomData<T>,
 }
 
@@ -112,6 +201,43 @@ impl<T> TypedIndex<T> {
             _type: PhantomData,
         }
     }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+/// An operation on a document of an index, applied by `Rubber::patch_index`
+#[derive(Clone, Debug)]
+pub enum PatchOperation<T> {
+    /// Add the document, replacing the document with the same id
+    Upsert(T),
+    /// Change some fields of the existing document `id`
+    Update {
+        id: String,
+        fields: serde_json::Value,
+    },
+    /// Remove the document `id`, nothing is done if it does not exist
+    Delete { id: String },
+}
+
+/// The items of the response of a `_bulk` request, one by operation
+pub(crate) fn bulk_items(
+    res: &serde_json::Value,
+    nb_operations: usize,
+) -> Result<&[serde_json::Value], Error> {
+    if res["errors"].as_bool().is_none() {
+        bail!("invalid bulk response: {}", res);
+    }
+    match res["items"].as_array() {
+        Some(items) if items.len() == nb_operations => Ok(items),
+        Some(items) => bail!(
+            "the bulk response has {} items for {} operations",
+            items.len(),
+            nb_operations
+        ),
+        None => bail!("the bulk response has no items: {}", res),
+    }
 }
 
 /// Index Visibility
@@ -123,12 +249,54 @@ pub enum IndexVisibility {
     Private,
 }
 
+/// A search sent in an elasticsearch `_msearch` request, with the other searches
+#[derive(Clone, Copy)]
+pub struct MultiSearch<'a> {
+    /// the searched indexes, each search having its own
+    pub indexes: &'a [String],
+    pub query: &'a Query,
+    pub from: u64,
+    pub size: u64,
+    /// coord used to compute the distance of the places found
+    pub coord: Option<Coord>,
+}
+
 #[derive(Debug)]
 pub struct IndexSettings {
     pub nb_shards: usize,
     pub nb_replicas: usize,
 }
 
+/// Build the settings of a new index, from `config/settings.json`
+pub(crate) fn make_index_settings(
+    name: &str,
+    index_settings: &IndexSettings,
+) -> Result<serde_json::Value, Error> {
+    // Note: in rs_es it can be done with MappingOperation but for the moment I think
+    // storing the mapping in json is more convenient
+    let settings = include_str!("../../../config/settings.json");
+
+    let mut settings_json_value = serde_json::from_str::<serde_json::Value>(&settings)
+        .map_err(|err| format_err!("Error occurred when creating index: {} err: {}", name, err))?;
+
+    let synonyms: Vec<_> = SYNONYMS
+        .iter()
+        .map(|s| serde_json::Value::String((*s).to_string()))
+        .collect();
+
+    *settings_json_value
+        .pointer_mut("/settings/analysis/filter/synonym_filter/synonyms")
+        .unwrap() = serde_json::Value::Array(synonyms);
+
+    *settings_json_value
+        .pointer_mut("/settings/number_of_shards")
+        .unwrap() = serde_json::Value::from(index_settings.nb_shards);
+    *settings_json_value
+        .pointer_mut("/settings/number_of_replicas")
+        .unwrap() = serde_json::Value::from(index_settings.nb_replicas);
+    Ok(settings_json_value)
+}
+
 /// return the index associated to the given type and dataset
 /// this will be an alias over another real index
 pub fn get_main_type_and_dataset_index<T: MimirObject>(dataset: &str) -> String {
@@ -168,23 +336,80 @@ pub fn read_places(
         "{} documents found in {} ms",
         result.hits.total, result.took
     );
-    let point: Option<geo_types::Point<f64>> = coord.map(|c| c.0.into());
     // for the moment rs-es does not handle enum Document,
     // so we need to convert the ES glob to a Place
-    Ok(result
+    let places = result
         .hits
         .hits
         .into_iter()
-        .filter_map(|hit| make_place(hit.doc_type, hit.source, hit.explanation))
-        .map(|mut place| {
-            if let Some(ref p) = point {
-                use geo::algorithm::haversine_distance::HaversineDistance;
-                let distance = p.haversine_distance(&place.coord().0.into()) as u32;
-                place.set_distance(distance);
+        .filter_map(|hit| {
+            let score = hit.score;
+            make_place(hit.doc_type, hit.source, hit.explanation).map(|place| (score, place))
+        })
+        .map(|(score, place)| (score, with_distance(place, coord)))
+        .collect();
+    Ok(with_relative_scores(places))
+}
+
+/// Read the places of the response of an elasticsearch search, as a json value
+/// (like the responses of an `_msearch` request, that rs_es does not handle).
+/// The type of a place is its `doc_type` field if it has one (cf `RestBackend`),
+/// its `_type` otherwise.
+pub fn read_raw_places(response: &serde_json::Value, coord: Option<&Coord>) -> Vec<Place> {
+    response
+        .pointer("/hits/hits")
+        .and_then(|hits| hits.as_array())
+        .map(|hits| {
+            hits.iter()
+                .filter_map(|hit| {
+                    let doc_type = hit
+                        .pointer(&format!("/_source/{}", DOC_TYPE_FIELD))
+                        .or_else(|| hit.get("_type"))?
+                        .as_str()?
+                        .to_string();
+                    let score = hit.get("_score").and_then(|s| s.as_f64());
+                    make_place(
+                        doc_type,
+                        hit.get("_source").cloned().map(Box::new),
+                        hit.get("_explanation").cloned(),
+                    )
+                    .map(|place| (score, with_distance(place, coord)))
+                })
+                .collect()
+        })
+        .map(with_relative_scores)
+        .unwrap_or_else(Vec::new)
+}
+
+/// Set the confidence of the places found by a search to their score relative to the best
+/// score of the places found (the places without score are left as is).
+pub fn with_relative_scores(places: Vec<(Option<f64>, Place)>) -> Vec<Place> {
+    let max_score = places
+        .iter()
+        .filter_map(|(score, _)| *score)
+        .fold(0., f64::max);
+    places
+        .into_iter()
+        .map(|(score, mut place)| {
+            if let Some(score) = score {
+                if max_score > 0. {
+                    place.set_confidence(score / max_score);
+                }
             }
             place
         })
-        .collect())
+        .collect()
+}
+
+// set the distance of the place to `coord`
+pub fn with_distance(mut place: Place, coord: Option<&Coord>) -> Place {
+    if let Some(coord) = coord {
+        use geo::algorithm::haversine_distance::HaversineDistance;
+        let point: geo_types::Point<f64> = coord.0.into();
+        let distance = point.haversine_distance(&place.coord().0.into()) as u32;
+        place.set_distance(distance);
+    }
+    place
 }
 
 /// takes a ES json blob and build a Place from it
@@ -210,6 +435,9 @@ pub fn make_place(
             "admin" => convert(*v, Place::Admin),
             "poi" => convert(*v, Place::Poi),
             "stop" => convert(*v, Place::Stop),
+            "intersection" => convert(*v, Place::Intersection),
+            "postcode" => convert(*v, Place::Postcode),
+            "locality" => convert(*v, Place::Locality),
             _ => {
                 warn!("unknown ES return value, _type field = {}", doc_type);
                 None
@@ -233,6 +461,96 @@ pub fn make_place(
     }
 }
 
+// there are never many admins containing a point, but we can't set no limit
+pub const MAX_ADMINS_CONTAINING: u64 = 100;
+
+/// Create a `rs_es::Query` on the places (admins or postcodes) whose indexed boundary
+/// contains `coord`.
+///
+/// Elasticsearch indexes an approximation of the boundaries, so the places found
+/// must be checked with `keep_admins_containing` or `keep_postcodes_containing`.
+pub fn build_containing_query(coord: &Coord) -> Query {
+    let shape = geojson::Geometry::new(geojson::Value::Point(vec![coord.lon(), coord.lat()]));
+    Query::build_bool()
+        .with_filter(
+            Query::build_geo_shape("boundary")
+                .with_geojson(shape)
+                .build(),
+        )
+        .build()
+}
+
+/// Keep only the admins whose boundary really contains `coord`, from the smallest to the largest.
+/// Their boundary is removed since it is not needed anymore and it can be really big.
+pub fn keep_admins_containing(places: Vec<Place>, coord: &Coord) -> Vec<Place> {
+    use geo::algorithm::contains::Contains;
+    let point: geo_types::Point<f64> = coord.0.into();
+
+    let mut admins = places
+        .into_iter()
+        .filter_map(|place| match place {
+            Place::Admin(mut admin) => {
+                let contains = admin
+                    .boundary
+                    .as_ref()
+                    .map_or(false, |b| b.contains(&point));
+                admin.boundary = None;
+                if contains {
+                    Some(admin)
+                } else {
+                    None
+                }
+            }
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    admins.sort_by(|a, b| b.level.cmp(&a.level));
+    admins.into_iter().map(Place::Admin).collect()
+}
+
+/// Keep only the postcodes whose boundary really contains `coord`, the nearest first.
+/// Like for the admins, their boundary is removed.
+pub fn keep_postcodes_containing(places: Vec<Place>, coord: &Coord) -> Vec<Place> {
+    use geo::algorithm::contains::Contains;
+    let point: geo_types::Point<f64> = coord.0.into();
+
+    let mut postcodes = places
+        .into_iter()
+        .filter_map(|place| match place {
+            Place::Postcode(mut postcode) => {
+                let contains = postcode
+                    .boundary
+                    .as_ref()
+                    .map_or(false, |b| b.contains(&point));
+                postcode.boundary = None;
+                if contains {
+                    Some(Place::Postcode(postcode))
+                } else {
+                    None
+                }
+            }
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    postcodes.sort_by_key(|p| p.distance());
+    postcodes
+}
+
+/// Fields of the admins needed to know their names (by the query parser),
+/// much lighter to read than the whole admins with their boundaries
+pub const ADMIN_NAME_FIELDS: &[&str] =
+    &["name", "names", "zip_codes", "zone_type", "country_codes"];
+
+/// Read an admin from a `_source` limited to `ADMIN_NAME_FIELDS`,
+/// the other fields keep their default value.
+pub fn read_admin_names(source: &serde_json::Value) -> Result<Admin, serde_json::Error> {
+    let mut admin = serde_json::to_value(Admin::default())?;
+    if let (Some(admin), Some(source)) = (admin.as_object_mut(), source.as_object()) {
+        admin.extend(source.clone());
+    }
+    serde_json::from_value(admin)
+}
+
 /// Create a `rs_es::Query` that boosts results according to the
 /// distance to `coord`.
 pub fn build_proximity_with_boost(coord: &Coord, boost: f64) -> Query {
@@ -299,6 +617,86 @@ pub fn get_indexes(
     result
 }
 
+/// Create a `rs_es::Query` on the places in a `radius` (in meters) around `coord`,
+/// the nearest having the best score.
+pub fn build_places_around_query(coord: &Coord, radius: f64) -> Query {
+    let distance = rs_u::Distance::new(radius, rs_u::DistanceUnit::Meter);
+    let geo_distance =
+        Query::build_geo_distance("coord", (coord.lat(), coord.lon()), distance).build();
+    Query::build_bool()
+        .with_should(build_proximity_with_boost(coord, 1.))
+        .with_must(geo_distance)
+        .build()
+}
+
+/// The body of an elasticsearch search request.
+///
+/// The "boundary" of the places is not fetched, it is very large in some documents
+/// (countries...) and not needed to answer the queries.
+pub fn search_body(
+    query: &Query,
+    from: u64,
+    size: u64,
+    timeout: Option<time::Duration>,
+) -> serde_json::Value {
+    let mut body = serde_json::json!({
+        "query": query,
+        "from": from,
+        "size": size,
+        "_source": { "exclude": ["boundary"] },
+    });
+    if let Some(timeout) = timeout {
+        // the recent elasticsearch versions don't accept fractional time values
+        body["timeout"] = serde_json::Value::from(format!("{}ms", timeout.as_millis()));
+    }
+    body
+}
+
+/// The body of an elasticsearch `_msearch` request sending all the `searches`,
+/// given as their indexes and their search body (cf `search_body`)
+pub fn multi_search_body(searches: &[(&[String], serde_json::Value)]) -> String {
+    // the body is made of a header and a search body for each search,
+    // each of them on its own line (and it must end with a newline)
+    let mut body = String::new();
+    for (indexes, search_body) in searches {
+        let header = serde_json::json!({
+            "index": indexes,
+            "ignore_unavailable": true,
+        });
+        body.push_str(&header.to_string());
+        body.push('\n');
+        body.push_str(&search_body.to_string());
+        body.push('\n');
+    }
+    body
+}
+
+/// Read the places found by each search in the response of an `_msearch` request,
+/// in the same order as the searches, `coords` being the coords of the searches.
+/// A search that failed does not fail the others, its error is given instead of its places.
+pub fn read_multi_search(
+    response: &serde_json::Value,
+    coords: &[Option<Coord>],
+) -> Vec<Result<Vec<Place>, String>> {
+    let responses = response
+        .get("responses")
+        .and_then(|r| r.as_array())
+        .map(Vec::as_slice)
+        .unwrap_or(&[]);
+
+    coords
+        .iter()
+        .enumerate()
+        .map(|(i, coord)| match responses.get(i) {
+            Some(response) => match response.get("error") {
+                Some(error) => Err(error.to_string()),
+                None => Ok(read_raw_places(response, coord.as_ref())),
+            },
+            None => Err("no response from elasticsearch".to_string()),
+        })
+        .collect()
+}
+
 impl Rubber {
     // build a rubber with a connection string (http://host:port/)
     pub fn new(cnx: &str) -> Rubber {
@@ -360,43 +758,9 @@ impl Rubber {
         check_response(result)
     }
 
-    pub fn make_index<T: MimirObject>(
-        &self,
-        dataset: &str,
-        index_settings: &IndexSettings,
-    ) -> Result<TypedIndex<T>, Error> {
-        let index_name = get_date_index_name(&get_main_type_and_dataset_index::<T>(dataset));
-        info!("creating index {}", index_name);
-        self.create_index(&index_name, index_settings)?;
-        Ok(TypedIndex::new(index_name))
-    }
-
     pub fn create_index(&self, name: &str, index_settings: &IndexSettings) -> Result<(), Error> {
         debug!("creating index");
-        // Note: in rs_es it can be done with MappingOperation but for the moment I think
-        // storing the mapping in json is more convenient
-        let settings = include_str!("../../../config/settings.json");
-
-        let mut settings_json_value = serde_json::from_str::<serde_json::Value>(&settings)
-            .map_err(|err| {
-                format_err!("Error occurred when creating index: {} err: {}", name, err)
-            })?;
-
-        let synonyms: Vec<_> = SYNONYMS
-            .iter()
-            .map(|s| serde_json::Value::String((*s).to_string()))
-            .collect();
-
-        *settings_json_value
-            .pointer_mut("/settings/analysis/filter/synonym_filter/synonyms")
-            .unwrap() = serde_json::Value::Array(synonyms);
-
-        *settings_json_value
-            .pointer_mut("/settings/number_of_shards")
-            .unwrap() = serde_json::Value::from(index_settings.nb_shards);
-        *settings_json_value
-            .pointer_mut("/settings/number_of_replicas")
-            .unwrap() = serde_json::Value::from(index_settings.nb_replicas);
+        let settings_json_value = make_index_settings(name, index_settings)?;
 
         self.put(name, &settings_json_value.to_string())
             .map_err(|e| {
@@ -432,26 +796,9 @@ impl Rubber {
     }
 
     pub fn initialize_templates(&self) -> Result<(), Error> {
-        self.create_template(
-            &"template_addr",
-            include_str!("../../../config/addr_settings.json"),
-        )?;
-        self.create_template(
-            &"template_stop",
-            include_str!("../../../config/stop_settings.json"),
-        )?;
-        self.create_template(
-            &"template_admin",
-            include_str!("../../../config/admin_settings.json"),
-        )?;
-        self.create_template(
-            &"template_street",
-            include_str!("../../../config/street_settings.json"),
-        )?;
-        self.create_template(
-            &"template_poi",
-            include_str!("../../../config/poi_settings.json"),
-        )?;
+        for (name, settings) in TEMPLATES.iter() {
+            self.create_template(name, settings)?;
+        }
         Ok(())
     }
 
@@ -492,24 +839,6 @@ impl Rubber {
         }
     }
 
-    // get the last indexes for this doc_type/dataset
-    // Note: to be resilient to ghost ES indexes, we return all indexes for this doc_type/dataset
-    // but the new index
-    fn get_last_index<T: MimirObject>(
-        &self,
-        new_index: &TypedIndex<T>,
-        dataset: &str,
-    ) -> Result<Vec<String>, Error> {
-        let base_index = get_main_type_and_dataset_index::<T>(dataset);
-        // we don't want to remove the newly created index
-        Ok(self
-            .get_all_aliased_index(&base_index)?
-            .into_iter()
-            .map(|(k, _)| k)
-            .filter(|i| i.as_str() != new_index.name)
-            .collect())
-    }
-
     pub fn get_address(&mut self, coord: &Coord) -> Result<Vec<Place>, EsError> {
         let types = vec!["house", "street"];
         let indexes = get_indexes(false, &[], &[], &types);
@@ -518,24 +847,31 @@ impl Rubber {
             .map(|index| index.as_str())
             .collect::<Vec<&str>>();
 
-        let distance = rs_u::Distance::new(1000., rs_u::DistanceUnit::Meter);
-        let geo_distance =
-            Query::build_geo_distance("coord", (coord.lat(), coord.lon()), distance).build();
-        let query = Query::build_bool()
-            .with_should(build_proximity_with_boost(coord, 1.))
-            .with_must(geo_distance)
-            .build();
+        self.get_places_around(coord, &indexes, 1000., 1)
+    }
 
-        let timer = ES_REQ_HISTOGRAM.start_timer();
+    /// Get the `limit` places of `indexes` nearest to `coord`, in a `radius` (in meters),
+    /// sorted by distance.
+    pub fn get_places_around(
+        &mut self,
+        coord: &Coord,
+        indexes: &[&str],
+        radius: f64,
+        limit: u64,
+    ) -> Result<Vec<Place>, EsError> {
+        let query = build_places_around_query(coord, radius);
+
+        let timer = ES_REVERSE_HISTOGRAM.start_timer();
 
         let timeout = self.timeout.map(|t| format!("{:?}", t));
         let mut search_query = self.es_client.search_query();
 
         let search_query = search_query
             .with_ignore_unavailable(true)
-            .with_indexes(&indexes)
+            .with_indexes(indexes)
             .with_query(&query)
-            .with_size(1);
+            .with_source(Source::exclude(&["boundary"]))
+            .with_size(limit);
 
         if let Some(timeout) = &timeout {
             search_query.with_timeout(timeout.as_str());
@@ -543,62 +879,46 @@ impl Rubber {
         let result = search_query.send()?;
 
         timer.observe_duration();
-        read_places(result, Some(coord))
+        let mut places = read_places(result, Some(coord))?;
+        // the score decreases with the distance, but some places can have the same score
+        places.sort_by_key(|p| p.distance());
+        Ok(places)
     }
 
-    /// publish the index as the new index for this doc_type and this dataset
-    /// move the index alias of the doc_type and the dataset to point to this indexes
-    /// and remove the old index
-    /// visibility: Indicate if the index is public or private
-    pub fn publish_index<T: MimirObject>(
-        &mut self,
-        dataset: &str,
-        index: TypedIndex<T>,
-        visibility: IndexVisibility,
-    ) -> Result<(), Error> {
-        debug!("publishing index");
+    /// Get all the admins whose boundary contains `coord`, from the smallest to the largest.
+    pub fn get_admins_containing(&mut self, coord: &Coord) -> Result<Vec<Place>, EsError> {
+        let admins = self.search_containing(&get_main_type_index::<Admin>(), coord)?;
+        Ok(keep_admins_containing(admins, coord))
+    }
 
-        // Refresh index before publishing
-        self.es_client
-            .refresh()
-            .with_indexes(&[&index.name])
-            .send()?;
-        let last_indexes = self.get_last_index(&index, dataset)?;
-
-        let dataset_index = get_main_type_and_dataset_index::<T>(dataset);
-        self.alias(&dataset_index, &[index.name], &last_indexes)
-            .with_context(|err| {
-                format!(
-                    "Error occurred when making alias {}: {}",
-                    dataset_index, err
-                )
-            })?;
-
-        let type_index = get_main_type_index::<T>();
-        if let IndexVisibility::Public = visibility {
-            self.alias(&type_index, &[dataset_index], &last_indexes)
-                .with_context(|err| {
-                    format!("Error occurred when making alias {}: {}", type_index, err)
-                })?;
-        }
+    /// Get the postcodes whose boundary contains `coord`, the nearest first.
+    pub fn get_postcodes_containing(&mut self, coord: &Coord) -> Result<Vec<Place>, EsError> {
+        let postcodes = self.search_containing(&get_main_type_index::<Postcode>(), coord)?;
+        Ok(keep_postcodes_containing(postcodes, coord))
+    }
 
-        if let IndexVisibility::Public = visibility {
-            if T::is_geo_data() {
-                self.alias("munin_geo_data", &[type_index], &[])
-                    .context("Error occurred when making alias: munin_geo_data")?;
-                self.alias("munin", &["munin_geo_data".to_string()], &[])
-                    .context("Error occurred when making alias: munin")?;
-            } else {
-                self.alias("munin", &[type_index], &[])
-                    .context("Error occurred when making alias: munin")?;
-            }
-        }
+    /// Search the places of `index` whose indexed boundary contains `coord`.
+    fn search_containing(&mut self, index: &str, coord: &Coord) -> Result<Vec<Place>, EsError> {
+        let query = build_containing_query(coord);
+
+        let timer = ES_REVERSE_HISTOGRAM.start_timer();
 
-        for i in last_indexes {
-            self.delete_index(&i)
-                .with_context(|err| format!("Error occurred when deleting index {}: {}", i, err))?;
+        let timeout = self.timeout.map(|t| format!("{:?}", t));
+        let mut search_query = self.es_client.search_query();
+
+        let search_query = search_query
+            .with_ignore_unavailable(true)
+            .with_indexes(&[index])
+            .with_query(&query)
+            .with_size(MAX_ADMINS_CONTAINING);
+
+        if let Some(timeout) = &timeout {
+            search_query.with_timeout(timeout.as_str());
         }
-        Ok(())
+        let result = search_query.send()?;
+
+        timer.observe_duration();
+        read_places(result, Some(coord))
     }
 
     /// add a list of new indexes to the alias
@@ -745,63 +1065,197 @@ impl Rubber {
             .map(|counts| counts.nb_ok)
     }
 
-    /// Shortcut to `index` for a public index
-    pub fn public_index<T, I>(
+    /// Apply the `operations` to the documents of `index`, in bulk.
+    /// Return the number of documents upserted, updated or deleted.
+    ///
+    /// Like with `bulk_index`, the failed operations are logged, and an error is returned
+    /// if there are more than `max_bulk_errors` of them.
+    pub fn patch_index<T: MimirObject>(
+        &mut self,
+        index: &TypedIndex<T>,
+        operations: &[PatchOperation<T>],
+    ) -> Result<usize, Error> {
+        let chunk_size = 1000;
+        let mut nb_ok = 0;
+        let mut nb_errors = 0;
+        for chunk in operations.chunks(chunk_size) {
+            // each operation is an action line, followed by a source line for the upserts
+            // and the updates
+            let mut body = String::new();
+            for operation in chunk {
+                let mut meta = serde_json::json!({ "_index": index.name, "_type": T::doc_type() });
+                let (action, source) = match operation {
+                    PatchOperation::Upsert(doc) => {
+                        // without id, the document would be added instead of replacing
+                        // the existing one
+                        let id = doc.es_id().ok_or_else(|| {
+                            format_err!(
+                                "impossible to upsert a {} without id in {}",
+                                T::doc_type(),
+                                index.name
+                            )
+                        })?;
+                        meta["_id"] = serde_json::Value::from(id);
+                        ("index", Some(serde_json::to_value(doc)?))
+                    }
+                    PatchOperation::Update { id, fields } => {
+                        meta["_id"] = serde_json::Value::from(id.as_str());
+                        ("update", Some(serde_json::json!({ "doc": fields })))
+                    }
+                    PatchOperation::Delete { id } => {
+                        meta["_id"] = serde_json::Value::from(id.as_str());
+                        ("delete", None)
+                    }
+                };
+                body.push_str(&serde_json::json!({ action: meta }).to_string());
+                body.push('\n');
+                if let Some(source) = source {
+                    body.push_str(&source.to_string());
+                    body.push('\n');
+                }
+            }
+
+            let res: serde_json::Value = self
+                .post("_bulk", &body)
+                .with_context(|_| format!("Error occurred when patching {}", index.name))?
+                .read_response()?;
+            let items = bulk_items(&res, chunk.len())
+                .with_context(|_| format!("Error occurred when patching {}", index.name))?;
+            for (item, operation) in items.iter().zip(chunk) {
+                // an item is an object with the action as only key
+                let (action, result) = match item.as_object().and_then(|i| i.iter().next()) {
+                    Some(item) => item,
+                    None => continue,
+                };
+                match (result["status"].as_u64(), operation) {
+                    (Some(200), _) | (Some(201), _) => nb_ok += 1,
+                    // the deleted document was not in the index
+                    (Some(404), PatchOperation::Delete { .. }) => (),
+                    _ => {
+                        nb_errors += 1;
+                        warn!(
+                            "An error occured during the {} of {} '{}'. Status {}: {}",
+                            action,
+                            T::doc_type(),
+                            result["_id"],
+                            result["status"],
+                            result["error"]
+                        );
+                    }
+                }
+            }
+            if nb_errors > self.max_bulk_errors {
+                bail!("too many errors during patch_index");
+            }
+        }
+        Ok(nb_ok)
+    }
+
+    /// Apply the `operations` to the documents of the index currently published for
+    /// the doc_type `T` and `dataset`, and make them visible to the searches.
+    pub fn patch<T: MimirObject>(
         &mut self,
         dataset: &str,
-        index_settings: &IndexSettings,
-        iter: I,
-    ) -> Result<usize, Error>
+        operations: &[PatchOperation<T>],
+    ) -> Result<usize, Error> {
+        let index = self.published_index::<T>(dataset)?.ok_or_else(|| {
+            format_err!(
+                "no {} index published for the dataset {}",
+                T::doc_type(),
+                dataset
+            )
+        })?;
+        let nb_patched = self.patch_index(&index, operations)?;
+        self.es_client
+            .refresh()
+            .with_indexes(&[index.name.as_str()])
+            .send()?;
+        Ok(nb_patched)
+    }
+
+    /// Add the documents to the index published for `dataset`,
+    /// replacing the documents with the same ids
+    pub fn upsert<T, I>(&mut self, dataset: &str, docs: I) -> Result<usize, Error>
     where
-        T: MimirObject + std::marker::Send + 'static,
-        I: Iterator<Item = T>,
+        T: MimirObject,
+        I: IntoIterator<Item = T>,
     {
-        self.index(dataset, IndexVisibility::Public, index_settings, iter)
+        let operations = docs
+            .into_iter()
+            .map(PatchOperation::Upsert)
+            .collect::<Vec<_>>();
+        self.patch(dataset, &operations)
     }
 
-    /// Shortcut to `index` for a private index
-    pub fn private_index<T, I>(
+    /// Change some fields of the document `id` of the index published for `dataset`
+    pub fn update<T: MimirObject>(
         &mut self,
         dataset: &str,
-        index_settings: &IndexSettings,
-        iter: I,
-    ) -> Result<usize, Error>
-    where
-        T: MimirObject + std::marker::Send + 'static,
-        I: Iterator<Item = T>,
-    {
-        self.index(dataset, IndexVisibility::Private, index_settings, iter)
+        id: &str,
+        fields: serde_json::Value,
+    ) -> Result<usize, Error> {
+        let operation = PatchOperation::<T>::Update {
+            id: id.to_owned(),
+            fields,
+        };
+        self.patch(dataset, &[operation])
     }
 
-    /// add all the element of 'iter' into elasticsearch
-    ///
-    /// To have zero downtime:
-    /// first all the elements are added in a temporary index and when all has been indexed
-    /// the index is published and the old index is removed
-    pub fn index<T, I>(
+    /// Remove the documents `ids` from the index published for `dataset`
+    pub fn delete<T: MimirObject>(
         &mut self,
         dataset: &str,
-        visibility: IndexVisibility,
-        index_settings: &IndexSettings,
-        iter: I,
-    ) -> Result<usize, Error>
-    where
-        T: MimirObject + std::marker::Send + 'static,
-        I: Iterator<Item = T>,
-    {
-        // TODO better error handling
-        let index = self
-            .make_index(dataset, index_settings)
-            .with_context(|err| format!("Error occurred when making index {}: {}", dataset, err))?;
-        let nb_elements = self.bulk_index(&index, iter)?;
-        self.publish_index(dataset, index, visibility)?;
-        Ok(nb_elements)
+        ids: &[String],
+    ) -> Result<usize, Error> {
+        let operations = ids
+            .iter()
+            .map(|id| PatchOperation::<T>::Delete { id: id.clone() })
+            .collect::<Vec<_>>();
+        self.patch(dataset, &operations)
+    }
+
+    /// Delete the documents with the ids `ids` from `index`, return the number of deleted documents
+    pub fn delete_docs<T: MimirObject>(
+        &mut self,
+        index: &TypedIndex<T>,
+        ids: &[String],
+    ) -> Result<usize, Error> {
+        let operations = ids
+            .iter()
+            .map(|id| PatchOperation::Delete { id: id.clone() })
+            .collect::<Vec<_>>();
+        self.patch_index(index, &operations)
     }
 
     pub fn get_all_admins(&mut self) -> Result<Vec<Admin>, rs_es::error::EsError> {
         self.get_all_objects_from_index(&get_main_type_index::<Admin>())
     }
 
+    /// Get all the admins of the public admin indexes, with only their names
+    /// (see `ADMIN_NAME_FIELDS`), without reading their boundaries
+    pub fn get_admin_names(&mut self) -> Result<Vec<Admin>, Error> {
+        let mut result = vec![];
+        let mut scan: ScanResult<serde_json::Value> = self
+            .es_client
+            .search_query()
+            .with_indexes(&[&get_main_type_index::<Admin>()])
+            .with_size(1000)
+            .with_types(&[&Admin::doc_type()])
+            .with_source(Source::include(ADMIN_NAME_FIELDS))
+            .scan(&Duration::minutes(1))?;
+        loop {
+            let page = scan.scroll(&mut self.es_client, &Duration::minutes(1))?;
+            if page.hits.hits.is_empty() {
+                break;
+            }
+            for source in page.hits.hits.into_iter().filter_map(|hit| hit.source) {
+                result.push(read_admin_names(&source)?);
+            }
+        }
+        scan.close(&mut self.es_client)?;
+        Ok(result)
+    }
+
     pub fn get_all_objects_from_index<T>(
         &mut self,
         index: &str,
@@ -835,6 +1289,133 @@ impl Rubber {
     }
 }
 
+impl SearchBackend for Rubber {
+    fn initialize_templates(&self) -> Result<(), Error> {
+        Rubber::initialize_templates(self)
+    }
+
+    fn create_index(&self, name: &str, index_settings: &IndexSettings) -> Result<(), Error> {
+        Rubber::create_index(self, name, index_settings)
+    }
+
+    fn bulk_index<T, I>(&mut self, index: &TypedIndex<T>, iter: I) -> Result<usize, Error>
+    where
+        T: MimirObject + std::marker::Send + 'static,
+        I: Iterator<Item = T>,
+    {
+        Rubber::bulk_index(self, index, iter)
+    }
+
+    fn refresh_index(&mut self, index: &str) -> Result<(), Error> {
+        self.es_client.refresh().with_indexes(&[index]).send()?;
+        Ok(())
+    }
+
+    fn get_all_aliased_index(
+        &self,
+        base_index: &str,
+    ) -> Result<BTreeMap<String, Vec<String>>, Error> {
+        Rubber::get_all_aliased_index(self, base_index)
+    }
+
+    fn alias(&self, alias: &str, add: &[String], remove: &[String]) -> Result<(), Error> {
+        Rubber::alias(self, alias, add, remove)
+    }
+
+    fn delete_index(&mut self, index: &str) -> Result<(), Error> {
+        Rubber::delete_index(self, index)
+    }
+
+    fn delete_docs<T: MimirObject>(
+        &mut self,
+        index: &TypedIndex<T>,
+        ids: &[String],
+    ) -> Result<usize, Error> {
+        Rubber::delete_docs(self, index, ids)
+    }
+
+    fn patch_index<T: MimirObject>(
+        &mut self,
+        index: &TypedIndex<T>,
+        operations: &[PatchOperation<T>],
+    ) -> Result<usize, Error> {
+        Rubber::patch_index(self, index, operations)
+    }
+
+    fn patch<T: MimirObject>(
+        &mut self,
+        dataset: &str,
+        operations: &[PatchOperation<T>],
+    ) -> Result<usize, Error> {
+        Rubber::patch(self, dataset, operations)
+    }
+
+    fn get_all_objects<T>(&mut self, index: &str) -> Result<Vec<T>, Error>
+    where
+        T: MimirObject + serde::de::DeserializeOwned + std::fmt::Debug,
+    {
+        Ok(Rubber::get_all_objects_from_index(self, index)?)
+    }
+
+    fn search(
+        &mut self,
+        indexes: &[&str],
+        query: &Query,
+        from: u64,
+        size: u64,
+        coord: Option<&Coord>,
+    ) -> Result<Vec<Place>, Error> {
+        let timeout = self.timeout.map(|t| format!("{:?}", t));
+        let mut search_query = self.es_client.search_query();
+
+        let search_query = search_query
+            .with_ignore_unavailable(true)
+            .with_indexes(indexes)
+            .with_query(query)
+            .with_from(from)
+            .with_size(size)
+            // No need to fetch "boundary" as it's not used in the geocoding response
+            .with_source(Source::exclude(&["boundary"]));
+
+        if let Some(timeout) = &timeout {
+            search_query.with_timeout(timeout.as_str());
+        }
+        Ok(read_places(search_query.send()?, coord)?)
+    }
+
+    fn get_admins_containing(&mut self, coord: &Coord) -> Result<Vec<Place>, Error> {
+        Ok(Rubber::get_admins_containing(self, coord)?)
+    }
+
+    fn get_postcodes_containing(&mut self, coord: &Coord) -> Result<Vec<Place>, Error> {
+        Ok(Rubber::get_postcodes_containing(self, coord)?)
+    }
+
+    fn get_all_admins(&mut self) -> Result<Vec<Admin>, Error> {
+        Ok(Rubber::get_all_admins(self)?)
+    }
+
+    fn get_admin_names(&mut self) -> Result<Vec<Admin>, Error> {
+        Rubber::get_admin_names(self)
+    }
+
+    fn get_address(&mut self, coord: &Coord) -> Result<Vec<Place>, Error> {
+        Ok(Rubber::get_address(self, coord)?)
+    }
+
+    fn get_places_around(
+        &mut self,
+        coord: &Coord,
+        indexes: &[&str],
+        radius: f64,
+        limit: u64,
+    ) -> Result<Vec<Place>, Error> {
+        Ok(Rubber::get_places_around(
+            self, coord, indexes, radius, limit,
+        )?)
+    }
+}
+
 #[cfg(test)]
 mod tests {
 
@@ -853,6 +1434,61 @@ mod tests {
         Rubber::new("localhost");
     }
 
+    #[test]
+    fn test_place_templates() {
+        // the templates derived from config/place_settings.json, with a field of their own
+        for (doc_type, field) in &[
+            ("intersection", "street_names"),
+            ("postcode", "boundary"),
+            ("locality", "locality_type"),
+        ] {
+            let (_, settings) = TEMPLATES
+                .iter()
+                .find(|(name, _)| *name == format!("template_{}", doc_type))
+                .unwrap();
+            let template: serde_json::Value = serde_json::from_str(settings).unwrap();
+            assert_eq!(template["template"], format!("munin_{}_*", doc_type));
+            let properties = &template["mappings"][doc_type]["properties"];
+            assert!(properties["coord"].is_object(), "{}", doc_type);
+            assert!(properties[field].is_object(), "{}", doc_type);
+        }
+    }
+
+    #[test]
+    fn test_read_admin_names() {
+        let source = serde_json::json!({
+            "name": "Paris",
+            "zip_codes": ["75001", "75002"],
+            "zone_type": "city",
+            "country_codes": ["FR"],
+        });
+        let admin = read_admin_names(&source).unwrap();
+        assert_eq!(admin.name, "Paris");
+        assert_eq!(admin.zip_codes, vec!["75001", "75002"]);
+        assert!(admin.is_city());
+        assert_eq!(admin.country_codes, vec!["FR"]);
+        assert!(admin.boundary.is_none());
+    }
+
+    #[test]
+    fn test_bulk_items() {
+        let res = serde_json::json!({
+            "errors": true,
+            "items": [
+                { "index": { "_id": "1", "status": 201 } },
+                { "delete": { "_id": "2", "status": 404 } },
+            ],
+        });
+        assert_eq!(bulk_items(&res, 2).unwrap().len(), 2);
+        assert!(bulk_items(&res, 3).is_err());
+        let res = serde_json::json!({
+            "error": { "type": "illegal_argument_exception" },
+            "status": 400,
+        });
+        assert!(bulk_items(&res, 2).is_err());
+        assert!(bulk_items(&serde_json::json!({ "errors": false }), 2).is_err());
+    }
+
     #[test]
     fn test_get_indexes_impl() {
         // all_data
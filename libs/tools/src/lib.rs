@@ -168,17 +168,23 @@ pub struct BragiHandler {
 
 impl BragiHandler {
     pub fn new(url: String) -> BragiHandler {
-        let ctx = bragi::Context::try_from(&bragi::Args {
+        Self::with_args(bragi::Args {
             connection_string: url,
             ..Default::default()
         })
-        .expect("failed to create bragi Context");
+    }
+
+    pub fn with_args(args: bragi::Args) -> BragiHandler {
+        let ctx = bragi::Context::try_from(&args).expect("failed to create bragi Context");
 
         let prometheus = bragi::prometheus_middleware::PrometheusMetrics::new("bragi", "/metrics");
         let srv = actix_http_test::TestServer::new(move || {
             actix_http::HttpService::new(
                 actix_web::App::new()
                     .data(ctx.clone())
+                    .wrap(bragi::auth_middleware::ApiKeyAuth::new(
+                        ctx.get_api_keys().cloned(),
+                    ))
                     .wrap(actix_cors::Cors::new().allowed_methods(vec!["GET"]))
                     .wrap(prometheus.clone())
                     .wrap(actix_web::middleware::Logger::default())
@@ -208,6 +214,32 @@ impl BragiHandler {
         (status, body)
     }
 
+    /// get with some headers, returning the headers of the response too
+    pub fn raw_get_with_headers(
+        &mut self,
+        query: &str,
+        headers: &[(&'static str, &str)],
+    ) -> (
+        actix_http::http::StatusCode,
+        actix_http::http::HeaderMap,
+        bytes::Bytes,
+    ) {
+        let query = url_encode(query);
+        let req = headers
+            .iter()
+            .fold(self.app.get(query), |req, (name, value)| {
+                req.header(*name, *value)
+            })
+            .timeout(Duration::from_secs(10));
+
+        let mut resp = self.app.block_on(req.send()).unwrap();
+
+        let status = resp.status();
+        let headers = resp.headers().clone();
+        let body = self.app.block_on(resp.body()).unwrap();
+        (status, headers, body)
+    }
+
     pub fn get_status(&mut self, q: &str) -> actix_http::http::StatusCode {
         let q = url_encode(q);
         let req = self.app.get(q);
@@ -258,6 +290,46 @@ impl BragiHandler {
         (status, body)
     }
 
+    pub fn raw_post_with_content_type(
+        &mut self,
+        q: &str,
+        content_type: &'static str,
+        body: &'static str,
+    ) -> (actix_http::http::StatusCode, bytes::Bytes) {
+        let q = url_encode(q);
+        let mut r = self
+            .app
+            .block_on(
+                self.app
+                    .post(q)
+                    .header(actix_web::http::header::CONTENT_TYPE, content_type)
+                    .timeout(Duration::from_secs(10))
+                    .send_body(body),
+            )
+            .unwrap_or_else(|e| panic!("impossible to query bragi: {}", e));
+
+        let status = r.status();
+        let body = self.app.block_on(r.body()).unwrap();
+        (status, body)
+    }
+
+    /// post a batch to bragi and return one json Value by line of the NDJSON response
+    pub fn post_batch(
+        &mut self,
+        q: &str,
+        content_type: &'static str,
+        body: &'static str,
+    ) -> Vec<Value> {
+        let (status, s) = self.raw_post_with_content_type(q, content_type, body);
+        assert!(status.is_success(), "invalid status: {}", status);
+
+        std::str::from_utf8(&s)
+            .unwrap()
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect()
+    }
+
     pub fn post(&mut self, q: &str, shape: &'static str) -> Vec<Map<String, Value>> {
         let j = self.post_as_json(q, shape);
         self.get_results(j)
@@ -1,7 +1,8 @@
 use crate::Error;
 use failure::ResultExt;
 use flate2::read::GzDecoder;
-use mimir::rubber::{IndexSettings, IndexVisibility, Rubber};
+use mimir::backend::SearchBackend;
+use mimir::rubber::{IndexSettings, IndexVisibility};
 use mimir::Addr;
 use par_map::ParMap;
 use serde::de::DeserializeOwned;
@@ -12,8 +13,8 @@ use std::io::Read;
 use std::marker::{Send, Sync};
 use std::path::PathBuf;
 
-fn import_addresses<T, F>(
-    rubber: &mut Rubber,
+fn import_addresses<B, T, F>(
+    backend: &mut B,
     nb_threads: usize,
     index_settings: IndexSettings,
     dataset: &str,
@@ -21,14 +22,15 @@ fn import_addresses<T, F>(
     into_addr: F,
 ) -> Result<(), Error>
 where
+    B: SearchBackend,
     F: Fn(T) -> Result<Addr, Error> + Send + Sync + 'static,
     T: DeserializeOwned + Send + 'static,
 {
-    let addr_index = rubber
+    let addr_index = backend
         .make_index(dataset, &index_settings)
         .with_context(|err| format!("Error occurred when making index {}: {}", dataset, err))?;
 
-    info!("Add data in elasticsearch db.");
+    info!("Add data in the index.");
 
     let mut country_stats = HashMap::new();
 
@@ -64,11 +66,11 @@ where
             }
         });
 
-    let nb = rubber
+    let nb = backend
         .bulk_index(&addr_index, iter)
         .with_context(|err| format!("failed to bulk insert: {}", err))?;
     info!("importing addresses: {} addresses added.", nb);
-    rubber
+    backend
         .publish_index(dataset, addr_index, IndexVisibility::Public)
         .context("Error while publishing the index")?;
 
@@ -83,8 +85,8 @@ where
     Ok(())
 }
 
-pub fn import_addresses_from_streams<T, F>(
-    rubber: &mut Rubber,
+pub fn import_addresses_from_streams<B, T, F>(
+    backend: &mut B,
     has_headers: bool,
     nb_threads: usize,
     index_settings: IndexSettings,
@@ -93,6 +95,7 @@ pub fn import_addresses_from_streams<T, F>(
     into_addr: F,
 ) -> Result<(), Error>
 where
+    B: SearchBackend,
     F: Fn(T) -> Result<Addr, Error> + Send + Sync + 'static,
     T: DeserializeOwned + Send + 'static,
 {
@@ -109,11 +112,18 @@ where
                 .ok()
         });
 
-    import_addresses(rubber, nb_threads, index_settings, dataset, iter, into_addr)
+    import_addresses(
+        backend,
+        nb_threads,
+        index_settings,
+        dataset,
+        iter,
+        into_addr,
+    )
 }
 
-pub fn import_addresses_from_files<T, F>(
-    rubber: &mut Rubber,
+pub fn import_addresses_from_files<B, T, F>(
+    backend: &mut B,
     has_headers: bool,
     nb_threads: usize,
     index_settings: IndexSettings,
@@ -122,6 +132,7 @@ pub fn import_addresses_from_files<T, F>(
     into_addr: F,
 ) -> Result<(), Error>
 where
+    B: SearchBackend,
     F: Fn(T) -> Result<Addr, Error> + Send + Sync + 'static,
     T: DeserializeOwned + Send + 'static,
 {
@@ -148,7 +159,7 @@ where
     });
 
     import_addresses_from_streams(
-        rubber,
+        backend,
         has_headers,
         nb_threads,
         index_settings,
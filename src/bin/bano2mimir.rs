@@ -30,7 +30,10 @@
 
 use failure::ensure;
 use lazy_static::lazy_static;
+use mimir::backend::{BackendKind, SearchBackend};
+use mimir::embedded::EmbeddedBackend;
 use mimir::objects::Admin;
+use mimir::rest_backend::RestBackend;
 use mimir::rubber::{IndexSettings, Rubber};
 use mimirsbrunn::addr_reader::{import_addresses_from_files, import_addresses_from_streams};
 use mimirsbrunn::admin_geofinder::AdminGeoFinder;
@@ -132,8 +135,10 @@ impl Bano {
             coord,
             approx_coord: None,
             distance: None,
+            confidence: None,
             country_codes: country_codes.clone(),
             context: None,
+            house_number_ranges: vec![],
         };
         Ok(mimir::Addr {
             id: format!(
@@ -166,8 +171,10 @@ impl Bano {
             weight,
             zip_codes,
             distance: None,
+            confidence: None,
             country_codes,
             context: None,
+            interpolated: false,
         })
     }
 }
@@ -193,6 +200,13 @@ struct Args {
         default_value = "http://localhost:9200/munin"
     )]
     connection_string: String,
+    /// Search engine of the connection string: `es2` (Elasticsearch 2) or `rest`
+    /// (Elasticsearch 7+ or OpenSearch).
+    #[structopt(long = "backend", default_value = "es2")]
+    backend: BackendKind,
+    /// Directory of an embedded index to import into, instead of the search engine.
+    #[structopt(long = "embedded-index", parse(from_os_str))]
+    embedded_index: Option<PathBuf>,
     /// Name of the dataset.
     #[structopt(short = "d", long = "dataset", default_value = "fr")]
     dataset: String,
@@ -222,9 +236,22 @@ struct Args {
 fn run(args: Args) -> Result<(), mimirsbrunn::Error> {
     info!("importing bano into Mimir");
 
-    let mut rubber =
-        Rubber::new(&args.connection_string).with_nb_insert_threads(args.nb_insert_threads);
+    match (args.embedded_index.clone(), args.backend) {
+        (Some(dir), _) => import(EmbeddedBackend::open(dir)?, args),
+        (None, BackendKind::Es2) => {
+            let rubber =
+                Rubber::new(&args.connection_string).with_nb_insert_threads(args.nb_insert_threads);
+            import(rubber, args)
+        }
+        (None, BackendKind::Rest) => {
+            let backend = RestBackend::new(&args.connection_string)?
+                .with_nb_insert_threads(args.nb_insert_threads);
+            import(backend, args)
+        }
+    }
+}
 
+fn import<B: SearchBackend>(mut backend: B, args: Args) -> Result<(), mimirsbrunn::Error> {
     let index_settings = IndexSettings {
         nb_shards: args.nb_shards,
         nb_replicas: args.nb_replicas,
@@ -232,9 +259,9 @@ fn run(args: Args) -> Result<(), mimirsbrunn::Error> {
 
     // Fetch and index admins for `into_addr`
     let into_addr = {
-        let admins = rubber.get_all_admins().unwrap_or_else(|err| {
+        let admins = backend.get_all_admins().unwrap_or_else(|err| {
             warn!(
-                "Administratives regions not found in es db for dataset {}. (error: {})",
+                "Administratives regions not found in the index for dataset {}. (error: {})",
                 &args.dataset, err
             );
             vec![]
@@ -260,7 +287,7 @@ fn run(args: Args) -> Result<(), mimirsbrunn::Error> {
         if input_path.is_dir() {
             let paths: std::fs::ReadDir = fs::read_dir(&input_path)?;
             import_addresses_from_files(
-                &mut rubber,
+                &mut backend,
                 false,
                 args.nb_threads,
                 index_settings,
@@ -270,7 +297,7 @@ fn run(args: Args) -> Result<(), mimirsbrunn::Error> {
             )
         } else {
             import_addresses_from_files(
-                &mut rubber,
+                &mut backend,
                 false,
                 args.nb_threads,
                 index_settings,
@@ -282,7 +309,7 @@ fn run(args: Args) -> Result<(), mimirsbrunn::Error> {
     } else {
         // Import from stdin
         import_addresses_from_streams(
-            &mut rubber,
+            &mut backend,
             false,
             args.nb_threads,
             index_settings,
@@ -0,0 +1,374 @@
+// Copyright © 2016, Canal TP and/or its affiliates. All rights reserved.
+//
+// This file is part of Navitia,
+//     the software to build cool stuff with public transport.
+//
+// Hope you'll enjoy and contribute to this project,
+//     powered by Canal TP (www.canaltp.fr).
+// Help us simplify mobility and open public transport:
+//     a non ending quest to the responsive locomotion way of traveling!
+//
+// LICENCE: This program is free software; you can redistribute it
+// and/or modify it under the terms of the GNU Affero General Public
+// License as published by the Free Software Foundation, either
+// version 3 of the License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+// Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public
+// License along with this program. If not, see
+// <http://www.gnu.org/licenses/>.
+//
+// Stay tuned using
+// twitter @navitia
+// IRC #navitia on freenode
+// https://groups.google.com/d/forum/navitia
+// www.navitia.io
+
+//! Evaluate the relevance of bragi on a golden set of queries.
+//!
+//! The input is a JSON-lines file, each line being an autocomplete query with the ids of the
+//! places it should find:
+//!
+//! ```text
+//! {"q": "15 rue hector malot", "expected": [{"id": "addr:2.376379;48.846495:15", "rank": 1}]}
+//! {"name": "gare near paris", "q": "gare", "lat": 48.85, "lon": 2.35, "pt_dataset": ["fr-idf"],
+//!  "expected": [{"id": "stop_area:SNCF:87686006"}]}
+//! ```
+//!
+//! A query passes if each expected place is found at its `rank` or before (in the first `--k`
+//! results if no rank is given). The queries are sent to the bragi of `--url`, or else to a bragi
+//! run in-process with the bragi parameters. The report gives the precision@k and the MRR (mean
+//! reciprocal rank of the first expected place) of the set. It can be saved with `--output` and
+//! given as the `--baseline` of a later evaluation, which then fails if a query passing in the
+//! baseline now fails or finds its first expected place at a lower rank.
+
+use actix_web::{test, App};
+use failure::{bail, format_err, ResultExt};
+use serde::{Deserialize, Serialize};
+use slog_scope::{info, warn};
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use structopt::StructOpt;
+
+/// An expected place of a query
+#[derive(Deserialize, Debug)]
+struct Expected {
+    id: String,
+    /// max rank of the place, starting at 1
+    rank: Option<usize>,
+}
+
+/// A line of the golden file
+#[derive(Deserialize, Debug)]
+struct GoldenQuery {
+    /// name of the query in the report, `q` if not given
+    name: Option<String>,
+    q: String,
+    lat: Option<f64>,
+    lon: Option<f64>,
+    #[serde(default)]
+    pt_dataset: Vec<String>,
+    #[serde(default)]
+    poi_dataset: Vec<String>,
+    #[serde(default, rename = "type")]
+    types: Vec<String>,
+    expected: Vec<Expected>,
+}
+
+impl GoldenQuery {
+    fn name(&self) -> &str {
+        self.name.as_deref().unwrap_or(&self.q)
+    }
+
+    /// The path and query string of the autocomplete request
+    fn path(&self, limit: usize, settings: Option<&str>) -> String {
+        let mut url = reqwest::Url::parse("http://localhost/autocomplete").unwrap();
+        {
+            let mut params = url.query_pairs_mut();
+            params
+                .append_pair("q", &self.q)
+                .append_pair("limit", &limit.to_string());
+            if let (Some(lat), Some(lon)) = (self.lat, self.lon) {
+                params
+                    .append_pair("lat", &lat.to_string())
+                    .append_pair("lon", &lon.to_string());
+            }
+            for dataset in &self.pt_dataset {
+                params.append_pair("pt_dataset[]", dataset);
+            }
+            for dataset in &self.poi_dataset {
+                params.append_pair("poi_dataset[]", dataset);
+            }
+            for place_type in &self.types {
+                params.append_pair("type[]", place_type);
+            }
+            if let Some(settings) = settings {
+                params.append_pair("_settings", settings);
+            }
+        }
+        format!("{}?{}", url.path(), url.query().unwrap_or_default())
+    }
+
+    /// The evaluation of the query on the ids of the places found
+    fn evaluate(&self, ids: &[String], k: usize) -> QueryReport {
+        let ranks: Vec<_> = self
+            .expected
+            .iter()
+            .map(|expected| ids.iter().position(|id| *id == expected.id).map(|i| i + 1))
+            .collect();
+        let passed = self
+            .expected
+            .iter()
+            .zip(&ranks)
+            .all(|(expected, rank)| rank.map_or(false, |r| r <= expected.rank.unwrap_or(k)));
+        let nb_found_at_k = ranks.iter().flatten().filter(|&&r| r <= k).count();
+        let reciprocal_rank = ranks.iter().flatten().min().map_or(0., |&r| 1. / r as f64);
+        QueryReport {
+            name: self.name().to_string(),
+            ranks,
+            precision_at_k: nb_found_at_k as f64 / k as f64,
+            reciprocal_rank,
+            passed,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct QueryReport {
+    name: String,
+    /// rank of each expected place, if found
+    ranks: Vec<Option<usize>>,
+    precision_at_k: f64,
+    reciprocal_rank: f64,
+    passed: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct Report {
+    k: usize,
+    nb_queries: usize,
+    nb_passed: usize,
+    precision_at_k: f64,
+    mrr: f64,
+    queries: Vec<QueryReport>,
+}
+
+impl Report {
+    fn new(queries: Vec<QueryReport>, k: usize) -> Self {
+        let mean = |value: fn(&QueryReport) -> f64| {
+            if queries.is_empty() {
+                0.
+            } else {
+                queries.iter().map(value).sum::<f64>() / queries.len() as f64
+            }
+        };
+        Report {
+            k,
+            nb_queries: queries.len(),
+            nb_passed: queries.iter().filter(|q| q.passed).count(),
+            precision_at_k: mean(|q| q.precision_at_k),
+            mrr: mean(|q| q.reciprocal_rank),
+            queries,
+        }
+    }
+
+    /// The names of the queries which are worse than in the baseline:
+    /// failing but passing in the baseline, or finding their first expected place at a lower rank
+    fn regressions<'a>(&'a self, baseline: &Report) -> Vec<&'a str> {
+        let baseline: HashMap<_, _> = baseline.queries.iter().map(|q| (&q.name, q)).collect();
+        self.queries
+            .iter()
+            .filter(|query| {
+                baseline.get(&query.name).map_or(false, |base| {
+                    (base.passed && !query.passed) || query.reciprocal_rank < base.reciprocal_rank
+                })
+            })
+            .map(|query| query.name.as_str())
+            .collect()
+    }
+}
+
+/// Read the queries of the golden file, checking that their names are unique
+fn read_golden_queries(input: &Path) -> Result<Vec<GoldenQuery>, mimirsbrunn::Error> {
+    let file = File::open(input).with_context(|_| format!("cannot open {:?}", input))?;
+    let mut queries = vec![];
+    let mut names = HashSet::new();
+    for (i, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let query: GoldenQuery = serde_json::from_str(&line)
+            .with_context(|_| format!("invalid query at line {}", i + 1))?;
+        if !names.insert(query.name().to_string()) {
+            bail!("the query '{}' is duplicated", query.name());
+        }
+        queries.push(query);
+    }
+    Ok(queries)
+}
+
+/// The ids of the places of an autocomplete response
+fn response_ids(response: &serde_json::Value) -> Result<Vec<String>, mimirsbrunn::Error> {
+    response
+        .pointer("/features")
+        .and_then(|features| features.as_array())
+        .ok_or_else(|| format_err!("the response has no features"))?
+        .iter()
+        .map(|feature| {
+            feature
+                .pointer("/properties/geocoding/id")
+                .and_then(|id| id.as_str())
+                .map(str::to_string)
+                .ok_or_else(|| format_err!("a feature of the response has no id"))
+        })
+        .collect()
+}
+
+#[derive(StructOpt, Debug)]
+struct Args {
+    /// JSON-lines file of the golden queries
+    #[structopt(short = "i", long = "input", parse(from_os_str))]
+    input: PathBuf,
+
+    /// Number of results of each query which are evaluated.
+    #[structopt(short = "k", long = "k", default_value = "5")]
+    k: usize,
+
+    /// Url of a running bragi (like http://localhost:4000), a bragi is run in-process with the
+    /// bragi parameters if not given.
+    #[structopt(long = "url")]
+    url: Option<String>,
+
+    /// Api key sent to the bragi of `--url`.
+    #[structopt(long = "api-key")]
+    api_key: Option<String>,
+
+    /// Query settings profile used by the queries (cf. `--settings-profile`).
+    #[structopt(long = "settings")]
+    settings: Option<String>,
+
+    /// Report of a previous evaluation, the evaluation fails if some queries regress.
+    #[structopt(long = "baseline", parse(from_os_str))]
+    baseline: Option<PathBuf>,
+
+    /// File where the report is written, to be used as a later baseline.
+    #[structopt(short = "o", long = "output", parse(from_os_str))]
+    output: Option<PathBuf>,
+
+    #[structopt(flatten)]
+    bragi: bragi::Args,
+}
+
+/// The ids of the places found by each query
+fn search(args: &Args, queries: &[GoldenQuery]) -> Result<Vec<Vec<String>>, mimirsbrunn::Error> {
+    let paths = queries
+        .iter()
+        .map(|query| query.path(args.k, args.settings.as_deref()));
+    match &args.url {
+        Some(url) => {
+            let client = reqwest::blocking::Client::new();
+            paths
+                .map(|path| {
+                    let mut req = client.get(&format!("{}{}", url.trim_end_matches('/'), path));
+                    if let Some(api_key) = &args.api_key {
+                        req = req.header(reqwest::header::AUTHORIZATION, api_key.as_str());
+                    }
+                    let response = req
+                        .send()
+                        .and_then(|r| r.error_for_status())
+                        .with_context(|_| format!("request {} failed", path))?;
+                    response_ids(&response.json()?)
+                })
+                .collect()
+        }
+        None => {
+            let ctx = bragi::Context::try_from(&args.bragi).map_err(failure::err_msg)?;
+            let mut app = test::init_service(
+                App::new()
+                    .data(ctx)
+                    .configure(bragi::server::configure_server),
+            );
+            paths
+                .map(|path| {
+                    let req = test::TestRequest::get().uri(&path).to_request();
+                    let response = test::call_service(&mut app, req);
+                    if !response.status().is_success() {
+                        bail!("request {} failed with status {}", path, response.status());
+                    }
+                    response_ids(&serde_json::from_slice(&test::read_body(response))?)
+                })
+                .collect()
+        }
+    }
+}
+
+fn run(args: Args) -> Result<(), mimirsbrunn::Error> {
+    if args.k == 0 {
+        bail!("k must be positive");
+    }
+    let queries = read_golden_queries(&args.input)?;
+    let baseline: Option<Report> = args
+        .baseline
+        .as_ref()
+        .map(|path| -> Result<_, mimirsbrunn::Error> {
+            let file = File::open(path).with_context(|_| format!("cannot open {:?}", path))?;
+            Ok(serde_json::from_reader(BufReader::new(file))
+                .with_context(|_| format!("invalid baseline {:?}", path))?)
+        })
+        .transpose()?;
+
+    let results = search(&args, &queries)?;
+    let report = Report::new(
+        queries
+            .iter()
+            .zip(&results)
+            .map(|(query, ids)| query.evaluate(ids, args.k))
+            .collect(),
+        args.k,
+    );
+    for query in report.queries.iter().filter(|q| !q.passed) {
+        warn!("query '{}' failed, ranks: {:?}", query.name, query.ranks);
+    }
+    info!(
+        "{}/{} queries passed, precision@{}: {:.3}, MRR: {:.3}",
+        report.nb_passed, report.nb_queries, report.k, report.precision_at_k, report.mrr
+    );
+    if let Some(output) = &args.output {
+        let file = File::create(output).with_context(|_| format!("cannot create {:?}", output))?;
+        serde_json::to_writer_pretty(file, &report)?;
+    }
+
+    if let Some(baseline) = baseline {
+        if baseline.k != report.k {
+            warn!(
+                "the baseline was evaluated with k = {}, its precision is not comparable",
+                baseline.k
+            );
+        }
+        info!(
+            "baseline precision@{}: {:.3}, MRR: {:.3}",
+            baseline.k, baseline.precision_at_k, baseline.mrr
+        );
+        let regressions = report.regressions(&baseline);
+        if !regressions.is_empty() {
+            bail!(
+                "{} queries regressed compared with the baseline: {}",
+                regressions.len(),
+                regressions.join(", ")
+            );
+        }
+    }
+    Ok(())
+}
+
+fn main() {
+    mimirsbrunn::utils::launch_run(run);
+}
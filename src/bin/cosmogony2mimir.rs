@@ -30,7 +30,9 @@
 
 use cosmogony::{Zone, ZoneIndex};
 use failure::Error;
+use mimir::backend::{BackendKind, SearchBackend};
 use mimir::objects::Admin;
+use mimir::rest_backend::RestBackend;
 use mimir::rubber::{IndexSettings, Rubber};
 use mimirsbrunn::osm_reader::admin;
 use mimirsbrunn::osm_reader::osm_utils;
@@ -121,6 +123,7 @@ impl IntoAdmin for Zone {
                 .filter(|(k, _)| langs.contains(&k))
                 .collect(),
             distance: None,
+            confidence: None,
             context: None,
             administrative_regions: Vec::new(),
         };
@@ -147,15 +150,14 @@ impl IntoAdmin for Zone {
     }
 }
 
-fn send_to_es(
+fn send_to_es<B: SearchBackend>(
     admins: impl Iterator<Item = Admin>,
-    cnx_string: &str,
+    mut backend: B,
     dataset: &str,
     index_settings: IndexSettings,
 ) -> Result<(), Error> {
-    let mut rubber = Rubber::new(cnx_string);
-    rubber.initialize_templates()?;
-    let nb_admins = rubber.public_index(dataset, &index_settings, admins)?;
+    backend.initialize_templates()?;
+    let nb_admins = backend.public_index(dataset, &index_settings, admins)?;
     info!("{} admins added.", nb_admins);
     Ok(())
 }
@@ -211,12 +213,20 @@ fn index_cosmogony(args: Args) -> Result<(), Error> {
         nb_shards: args.nb_shards,
         nb_replicas: args.nb_replicas,
     };
-    send_to_es(
-        admins,
-        &args.connection_string,
-        &args.dataset,
-        index_settings,
-    )?;
+    match args.backend {
+        BackendKind::Es2 => send_to_es(
+            admins,
+            Rubber::new(&args.connection_string),
+            &args.dataset,
+            index_settings,
+        )?,
+        BackendKind::Rest => send_to_es(
+            admins,
+            RestBackend::new(&args.connection_string)?,
+            &args.dataset,
+            index_settings,
+        )?,
+    }
 
     Ok(())
 }
@@ -233,6 +243,10 @@ struct Args {
         default_value = "http://localhost:9200/munin"
     )]
     connection_string: String,
+    /// Search engine of the connection string: `es2` (Elasticsearch 2) or `rest`
+    /// (Elasticsearch 7+ or OpenSearch).
+    #[structopt(long = "backend", default_value = "es2")]
+    backend: BackendKind,
     /// Name of the dataset.
     #[structopt(short = "d", long = "dataset", default_value = "fr")]
     dataset: String,
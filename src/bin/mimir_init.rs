@@ -30,6 +30,8 @@
 
 use slog_scope::info;
 
+use mimir::backend::{BackendKind, SearchBackend};
+use mimir::rest_backend::RestBackend;
 use mimir::rubber::Rubber;
 use structopt::StructOpt;
 
@@ -42,12 +44,18 @@ struct Args {
         default_value = "http://localhost:9200/"
     )]
     connection_string: String,
+    /// Search engine of the connection string: `es2` (Elasticsearch 2) or `rest`
+    /// (Elasticsearch 7+ or OpenSearch).
+    #[structopt(long = "backend", default_value = "es2")]
+    backend: BackendKind,
 }
 
 fn run(args: Args) -> Result<(), failure::Error> {
     info!("creating templates");
-    let rubber = Rubber::new(&args.connection_string);
-    rubber.initialize_templates()
+    match args.backend {
+        BackendKind::Es2 => Rubber::new(&args.connection_string).initialize_templates(),
+        BackendKind::Rest => RestBackend::new(&args.connection_string)?.initialize_templates(),
+    }
 }
 
 fn main() {
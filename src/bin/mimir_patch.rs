@@ -0,0 +1,250 @@
+// Copyright © 2016, Canal TP and/or its affiliates. All rights reserved.
+//
+// This file is part of Navitia,
+//     the software to build cool stuff with public transport.
+//
+// Hope you'll enjoy and contribute to this project,
+//     powered by Canal TP (www.canaltp.fr).
+// Help us simplify mobility and open public transport:
+//     a non ending quest to the responsive locomotion way of traveling!
+//
+// LICENCE: This program is free software; you can redistribute it
+// and/or modify it under the terms of the GNU Affero General Public
+// License as published by the Free Software Foundation, either
+// version 3 of the License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+// Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public
+// License along with this program. If not, see
+// <http://www.gnu.org/licenses/>.
+//
+// Stay tuned using
+// twitter @navitia
+// IRC #navitia on freenode
+// https://groups.google.com/d/forum/navitia
+// www.navitia.io
+
+//! Apply a list of operations to the documents of the published indexes.
+//!
+//! The input is a JSON-lines file, each line being an operation:
+//!
+//! ```text
+//! {"op": "upsert", "type": "poi", "doc": {"id": "poi:osm:node:1", ...}}
+//! {"op": "update", "type": "street", "id": "street:osm:way:2", "doc": {"name": "Rue Neuve"}}
+//! {"op": "delete", "type": "addr", "dataset": "fr", "id": "addr:2.3;48.8:12"}
+//! ```
+//!
+//! `type` is one of `admin`, `street`, `addr`, `poi` or `stop`, and `dataset`
+//! defaults to the `--dataset` parameter.
+
+use failure::{bail, format_err, ResultExt};
+use mimir::backend::{BackendKind, SearchBackend};
+use mimir::objects::{Addr, Admin, MimirObject, Poi, Stop, Street};
+use mimir::rest_backend::RestBackend;
+use mimir::rubber::{PatchOperation, Rubber};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use slog_scope::info;
+use std::collections::btree_map::{BTreeMap, Entry};
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use structopt::StructOpt;
+
+#[derive(Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+enum Op {
+    Upsert,
+    Update,
+    Delete,
+}
+
+/// A line of the input file
+#[derive(Deserialize, Debug)]
+struct Line {
+    op: Op,
+    #[serde(rename = "type")]
+    doc_type: String,
+    dataset: Option<String>,
+    id: Option<String>,
+    doc: Option<serde_json::Value>,
+}
+
+/// The operations on the documents of a doc_type
+enum Operations {
+    Admin(Vec<PatchOperation<Admin>>),
+    Street(Vec<PatchOperation<Street>>),
+    Addr(Vec<PatchOperation<Addr>>),
+    Poi(Vec<PatchOperation<Poi>>),
+    Stop(Vec<PatchOperation<Stop>>),
+}
+
+impl Operations {
+    fn new(doc_type: &str) -> Result<Operations, mimirsbrunn::Error> {
+        Ok(match doc_type {
+            "admin" => Operations::Admin(vec![]),
+            "street" => Operations::Street(vec![]),
+            "addr" => Operations::Addr(vec![]),
+            "poi" => Operations::Poi(vec![]),
+            "stop" => Operations::Stop(vec![]),
+            _ => bail!("unknown type '{}'", doc_type),
+        })
+    }
+
+    fn push(&mut self, line: Line) -> Result<(), mimirsbrunn::Error> {
+        match self {
+            Operations::Admin(ops) => ops.push(to_operation(line)?),
+            Operations::Street(ops) => ops.push(to_operation(line)?),
+            Operations::Addr(ops) => ops.push(to_operation(line)?),
+            Operations::Poi(ops) => ops.push(to_operation(line)?),
+            Operations::Stop(ops) => ops.push(to_operation(line)?),
+        }
+        Ok(())
+    }
+
+    fn apply<B: SearchBackend>(
+        &self,
+        backend: &mut B,
+        dataset: &str,
+    ) -> Result<usize, mimirsbrunn::Error> {
+        match self {
+            Operations::Admin(ops) => backend.patch(dataset, ops),
+            Operations::Street(ops) => backend.patch(dataset, ops),
+            Operations::Addr(ops) => backend.patch(dataset, ops),
+            Operations::Poi(ops) => backend.patch(dataset, ops),
+            Operations::Stop(ops) => backend.patch(dataset, ops),
+        }
+    }
+}
+
+fn to_operation<T>(line: Line) -> Result<PatchOperation<T>, mimirsbrunn::Error>
+where
+    T: MimirObject + DeserializeOwned,
+{
+    let doc = line.doc;
+    let id = line.id;
+    match line.op {
+        Op::Upsert => {
+            let doc = doc.ok_or_else(|| format_err!("an upsert needs a 'doc'"))?;
+            let doc: T = serde_json::from_value(doc)
+                .with_context(|_| format!("invalid {}", T::doc_type()))?;
+            if let Some(id) = id {
+                if doc.es_id().as_ref() != Some(&id) {
+                    bail!("the id '{}' is not the id of the doc", id);
+                }
+            }
+            Ok(PatchOperation::Upsert(doc))
+        }
+        Op::Update => {
+            let id = id.ok_or_else(|| format_err!("an update needs an 'id'"))?;
+            match doc {
+                Some(fields @ serde_json::Value::Object(_)) => {
+                    Ok(PatchOperation::Update { id, fields })
+                }
+                _ => bail!("an update needs the updated fields as 'doc'"),
+            }
+        }
+        Op::Delete => {
+            let id = id.ok_or_else(|| format_err!("a delete needs an 'id'"))?;
+            Ok(PatchOperation::Delete { id })
+        }
+    }
+}
+
+/// Read the operations of `input`, grouped by doc_type and dataset.
+/// Nothing is returned if a line is invalid.
+fn read_operations(
+    input: &Path,
+    default_dataset: &str,
+) -> Result<BTreeMap<(String, String), Operations>, mimirsbrunn::Error> {
+    let file = File::open(input).with_context(|_| format!("cannot open {:?}", input))?;
+    let mut operations = BTreeMap::new();
+    for (i, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let mut add_line = || -> Result<(), mimirsbrunn::Error> {
+            let line: Line = serde_json::from_str(&line)?;
+            let dataset = line
+                .dataset
+                .clone()
+                .unwrap_or_else(|| default_dataset.to_string());
+            let ops = match operations.entry((line.doc_type.clone(), dataset)) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => entry.insert(Operations::new(&line.doc_type)?),
+            };
+            ops.push(line)
+        };
+        add_line().with_context(|_| format!("invalid operation at line {}", i + 1))?;
+    }
+    Ok(operations)
+}
+
+#[derive(StructOpt, Debug)]
+struct Args {
+    /// JSON-lines file of the operations
+    #[structopt(short = "i", long = "input", parse(from_os_str))]
+    input: PathBuf,
+
+    /// Elasticsearch parameters.
+    #[structopt(
+        short = "c",
+        long = "connection-string",
+        default_value = "http://localhost:9200/munin"
+    )]
+    connection_string: String,
+
+    /// Search engine of the connection string: `es2` (Elasticsearch 2) or `rest`
+    /// (Elasticsearch 7+ or OpenSearch).
+    #[structopt(long = "backend", default_value = "es2")]
+    backend: BackendKind,
+
+    /// Dataset of the operations without a dataset.
+    #[structopt(short = "d", long = "dataset", default_value = "fr")]
+    dataset: String,
+
+    /// Number of failed operations tolerated before aborting.
+    #[structopt(long = "max-bulk-errors", default_value = "0")]
+    max_bulk_errors: usize,
+}
+
+fn run(args: Args) -> Result<(), mimirsbrunn::Error> {
+    let operations = read_operations(&args.input, &args.dataset)?;
+    match args.backend {
+        BackendKind::Es2 => {
+            let rubber =
+                Rubber::new(&args.connection_string).with_max_bulk_errors(args.max_bulk_errors);
+            apply_all(rubber, &operations)
+        }
+        BackendKind::Rest => {
+            let backend = RestBackend::new(&args.connection_string)?
+                .with_max_bulk_errors(args.max_bulk_errors);
+            apply_all(backend, &operations)
+        }
+    }
+}
+
+fn apply_all<B: SearchBackend>(
+    mut backend: B,
+    operations: &BTreeMap<(String, String), Operations>,
+) -> Result<(), mimirsbrunn::Error> {
+    for ((doc_type, dataset), ops) in operations {
+        let nb_patched = ops
+            .apply(&mut backend, dataset)
+            .with_context(|_| format!("failed to patch the {} of {}", doc_type, dataset))?;
+        info!(
+            "{} {} documents of {} patched",
+            nb_patched, doc_type, dataset
+        );
+    }
+    Ok(())
+}
+
+fn main() {
+    mimirsbrunn::utils::launch_run(run);
+}
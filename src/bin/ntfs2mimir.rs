@@ -29,6 +29,7 @@
 // www.navitia.io
 
 use failure::ResultExt;
+use mimir::backend::BackendKind;
 use mimir::rubber::IndexSettings;
 use mimirsbrunn::stops::*;
 use slog_scope::{info, warn};
@@ -55,6 +56,10 @@ struct Args {
         default_value = "http://localhost:9200/munin"
     )]
     connection_string: String,
+    /// Search engine of the connection string: `es2` (Elasticsearch 2) or `rest`
+    /// (Elasticsearch 7+ or OpenSearch).
+    #[structopt(long = "backend", default_value = "es2")]
+    backend: BackendKind,
     /// Deprecated option.
     #[structopt(short = "C", long = "city-level")]
     city_level: Option<String>,
@@ -226,6 +231,7 @@ fn run(args: Args) -> Result<(), transit_model::Error> {
     import_stops(
         stops,
         &args.connection_string,
+        args.backend,
         &args.dataset,
         index_settings,
     )
@@ -243,6 +249,7 @@ fn test_bad_connection_string() {
     let args = Args {
         input: PathBuf::from("./tests/fixtures/ntfs"),
         connection_string: "http://localhost:1".to_string(),
+        backend: BackendKind::Es2,
         dataset: "bob".to_string(),
         city_level: None,
         nb_replicas: 1,
@@ -267,6 +274,7 @@ fn test_bad_file() {
     let args = Args {
         input: PathBuf::from("./tests/fixtures/not_exist"),
         connection_string: "http://localhost:9200".to_string(),
+        backend: BackendKind::Es2,
         dataset: "bob".to_string(),
         city_level: None,
         nb_replicas: 1,
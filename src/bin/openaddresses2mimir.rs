@@ -29,6 +29,8 @@
 // www.navitia.io
 
 use lazy_static::lazy_static;
+use mimir::backend::{BackendKind, SearchBackend};
+use mimir::rest_backend::RestBackend;
 use mimir::rubber::{IndexSettings, Rubber};
 use mimirsbrunn::addr_reader::{import_addresses_from_files, import_addresses_from_streams};
 use mimirsbrunn::admin_geofinder::AdminGeoFinder;
@@ -100,8 +102,10 @@ impl OpenAddress {
             coord,
             approx_coord: None,
             distance: None,
+            confidence: None,
             country_codes: country_codes.clone(),
             context: None,
+            house_number_ranges: vec![],
         };
 
         let id_suffix = {
@@ -148,8 +152,10 @@ impl OpenAddress {
             weight,
             zip_codes,
             distance: None,
+            confidence: None,
             country_codes,
             context: None,
+            interpolated: false,
         })
     }
 }
@@ -171,6 +177,10 @@ struct Args {
         default_value = "http://localhost:9200/munin"
     )]
     connection_string: String,
+    /// Search engine of the connection string: `es2` (Elasticsearch 2) or `rest`
+    /// (Elasticsearch 7+ or OpenSearch).
+    #[structopt(long = "backend", default_value = "es2")]
+    backend: BackendKind,
     /// Name of the dataset.
     #[structopt(short = "d", long = "dataset", default_value = "fr")]
     dataset: String,
@@ -207,9 +217,21 @@ fn run(args: Args) -> Result<(), failure::Error> {
         warn!("city-level option is deprecated, it now has no effect.");
     }
 
-    let mut rubber =
-        Rubber::new(&args.connection_string).with_nb_insert_threads(args.nb_insert_threads);
+    match args.backend {
+        BackendKind::Es2 => {
+            let rubber =
+                Rubber::new(&args.connection_string).with_nb_insert_threads(args.nb_insert_threads);
+            import(rubber, args)
+        }
+        BackendKind::Rest => {
+            let backend = RestBackend::new(&args.connection_string)?
+                .with_nb_insert_threads(args.nb_insert_threads);
+            import(backend, args)
+        }
+    }
+}
 
+fn import<B: SearchBackend>(mut backend: B, args: Args) -> Result<(), failure::Error> {
     let index_settings = IndexSettings {
         nb_shards: args.nb_shards,
         nb_replicas: args.nb_replicas,
@@ -217,7 +239,7 @@ fn run(args: Args) -> Result<(), failure::Error> {
 
     // Fetch and index admins for `into_addr`
     let into_addr = {
-        let admins = rubber.get_all_admins().unwrap_or_else(|err| {
+        let admins = backend.get_all_admins().unwrap_or_else(|err| {
             warn!(
                 "Administratives regions not found in es db for dataset {}. (error: {})",
                 &args.dataset, err
@@ -252,7 +274,7 @@ fn run(args: Args) -> Result<(), failure::Error> {
                 });
 
             import_addresses_from_files(
-                &mut rubber,
+                &mut backend,
                 true,
                 args.nb_threads,
                 index_settings,
@@ -262,7 +284,7 @@ fn run(args: Args) -> Result<(), failure::Error> {
             )
         } else {
             import_addresses_from_files(
-                &mut rubber,
+                &mut backend,
                 true,
                 args.nb_threads,
                 index_settings,
@@ -274,7 +296,7 @@ fn run(args: Args) -> Result<(), failure::Error> {
     } else {
         // Import from stdin
         import_addresses_from_streams(
-            &mut rubber,
+            &mut backend,
             true,
             args.nb_threads,
             index_settings,
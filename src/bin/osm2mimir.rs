@@ -29,27 +29,113 @@
 // www.navitia.io
 
 use failure::ResultExt;
+use mimir::backend::{BackendKind, SearchBackend};
+use mimir::embedded::EmbeddedBackend;
+use mimir::rest_backend::RestBackend;
 use mimir::rubber::{IndexSettings, Rubber};
 use mimirsbrunn::admin_geofinder::AdminGeoFinder;
+use mimirsbrunn::osm_reader::addr::addresses;
 use mimirsbrunn::osm_reader::admin::read_administrative_regions;
-use mimirsbrunn::osm_reader::make_osm_reader;
+use mimirsbrunn::osm_reader::locality::localities;
+use mimirsbrunn::osm_reader::osc::{
+    apply_osm_change, read_osm_change_file, read_sequence_number, sequence_number_from_path,
+    write_sequence_number,
+};
 use mimirsbrunn::osm_reader::poi::{add_address, compute_poi_weight, pois, PoiConfig};
+use mimirsbrunn::osm_reader::postcode::{postcodes, postcodes_from_addresses};
 use mimirsbrunn::osm_reader::street::{compute_street_weight, streets};
+use mimirsbrunn::osm_reader::{make_osm_reader, OsmPbfReader};
 use mimirsbrunn::settings::osm2mimir::{Args, Settings};
-use slog_scope::{debug, info};
+use slog_scope::{debug, info, warn};
 
 fn run(args: Args) -> Result<(), mimirsbrunn::Error> {
-    let input = args.input.clone(); // we save the input, because args will be consumed by settings.
     validate_args(&args)?;
-    let settings = Settings::new(args)?;
+    // we keep the args, because they will be consumed by settings.
+    let settings = Settings::new(args.clone())?;
 
-    let mut osm_reader = make_osm_reader(&input)?;
+    match (args.embedded_index.as_ref(), args.backend) {
+        (Some(dir), _) => import_or_update(EmbeddedBackend::open(dir)?, &args, &settings),
+        (None, BackendKind::Es2) => {
+            let rubber = Rubber::new(&settings.elasticsearch.connection_string)
+                .with_nb_insert_threads(settings.elasticsearch.insert_thread_count);
+            import_or_update(rubber, &args, &settings)
+        }
+        (None, BackendKind::Rest) => {
+            let backend = RestBackend::new(&settings.elasticsearch.connection_string)?
+                .with_nb_insert_threads(settings.elasticsearch.insert_thread_count);
+            import_or_update(backend, &args, &settings)
+        }
+    }
+}
+
+fn import_or_update<B: SearchBackend>(
+    backend: B,
+    args: &Args,
+    settings: &Settings,
+) -> Result<(), mimirsbrunn::Error> {
+    if args.update {
+        update(backend, args, settings)
+    } else {
+        let mut osm_reader = make_osm_reader(&args.input)?;
+        import(backend, &mut osm_reader, settings)
+    }
+}
+
+fn update<B: SearchBackend>(
+    mut backend: B,
+    args: &Args,
+    settings: &Settings,
+) -> Result<(), mimirsbrunn::Error> {
+    let sequence_number = args
+        .sequence_number
+        .or_else(|| sequence_number_from_path(&args.input));
+    let last_sequence_number = match args.state_file.as_ref() {
+        Some(state_file) => read_sequence_number(state_file)?,
+        None => None,
+    };
+    if let (Some(sequence_number), Some(last_sequence_number)) =
+        (sequence_number, last_sequence_number)
+    {
+        if sequence_number <= last_sequence_number {
+            info!(
+                "the diff {} has already been applied, the last applied diff is {}",
+                sequence_number, last_sequence_number
+            );
+            return Ok(());
+        }
+        if sequence_number > last_sequence_number + 1 {
+            return Err(failure::format_err!(
+                "the diff {} does not follow the last applied diff {}",
+                sequence_number,
+                last_sequence_number
+            ));
+        }
+    }
+
+    info!("reading the osmChange file {:?}", args.input);
+    let change = read_osm_change_file(&args.input)?;
+    apply_osm_change(&mut backend, &change, settings)?;
+
+    if let Some(state_file) = args.state_file.as_ref() {
+        match sequence_number {
+            Some(sequence_number) => write_sequence_number(state_file, sequence_number)?,
+            None => warn!(
+                "the sequence number of {:?} is unknown, {:?} has not been updated",
+                args.input, state_file
+            ),
+        }
+    }
+    Ok(())
+}
+
+fn import<B: SearchBackend>(
+    mut backend: B,
+    osm_reader: &mut OsmPbfReader,
+    settings: &Settings,
+) -> Result<(), mimirsbrunn::Error> {
     debug!("creation of indexes");
-    let mut rubber = Rubber::new(&settings.elasticsearch.connection_string)
-        .with_nb_insert_threads(settings.elasticsearch.insert_thread_count);
-    rubber.initialize_templates()?;
+    backend.initialize_templates()?;
 
-    let settings = &settings;
     let admins = if settings
         .admin
         .as_ref()
@@ -64,36 +150,62 @@ fn run(args: Args) -> Result<(), mimirsbrunn::Error> {
         let levels = admins.levels.iter().cloned().collect();
         let city_level = admins.city_level;
         info!("creating administrative regions from OSM");
-        read_administrative_regions(&mut osm_reader, levels, city_level)
+        read_administrative_regions(osm_reader, levels, city_level)
     } else {
-        info!("retrieving administrative regions from Elasticsearch");
-        rubber.get_all_admins()?
+        info!("retrieving administrative regions from the index");
+        backend.get_all_admins()?
     };
 
     let admins_geofinder = admins.into_iter().collect::<AdminGeoFinder>();
 
-    if settings
+    let import_streets = settings
         .street
         .as_ref()
         .map(|street| street.import)
-        .unwrap_or_else(|| false)
-    {
-        info!("Extracting streets from osm");
-        let mut streets = streets(&mut osm_reader, &admins_geofinder, &settings)?;
+        .unwrap_or_else(|| false);
+    let import_addresses = settings
+        .address
+        .as_ref()
+        .map(|address| address.import)
+        .unwrap_or_else(|| false);
+
+    let import_intersections = settings
+        .intersection
+        .as_ref()
+        .map(|intersection| intersection.import)
+        .unwrap_or_else(|| false);
+
+    let import_postcodes = settings
+        .postcode
+        .as_ref()
+        .map(|postcode| postcode.import)
+        .unwrap_or_else(|| false);
 
-        info!("computing street weight");
-        compute_street_weight(&mut streets);
+    // the addresses are linked to the streets, so we need the streets to import them,
+    // and the postcodes without relation are built from the addresses
+    let (streets, intersections) =
+        if import_streets || import_addresses || import_intersections || import_postcodes {
+            info!("Extracting streets from osm");
+            let (mut streets, intersections) = streets(osm_reader, &admins_geofinder, settings)?;
 
+            info!("computing street weight");
+            compute_street_weight(&mut streets);
+            (streets, intersections)
+        } else {
+            (vec![], vec![])
+        };
+
+    if import_streets {
         let street_index_settings = IndexSettings {
             nb_shards: settings.elasticsearch.streets_shards,
             nb_replicas: settings.elasticsearch.streets_replicas,
         };
         info!("importing streets into Mimir");
-        let nb_streets = rubber
+        let nb_streets = backend
             .public_index(
                 &settings.dataset,
                 &street_index_settings,
-                streets.into_iter(),
+                streets.iter().cloned(),
             )
             .with_context(|err| {
                 format!(
@@ -103,6 +215,114 @@ fn run(args: Args) -> Result<(), mimirsbrunn::Error> {
             })?;
         info!("Nb of indexed street: {}", nb_streets);
     }
+
+    if import_intersections {
+        let intersection_index_settings = IndexSettings {
+            nb_shards: settings.elasticsearch.streets_shards,
+            nb_replicas: settings.elasticsearch.streets_replicas,
+        };
+        info!("importing intersections into Mimir");
+        let nb_intersections = backend
+            .public_index(
+                &settings.dataset,
+                &intersection_index_settings,
+                intersections.into_iter(),
+            )
+            .with_context(|err| {
+                format!(
+                    "Error occurred when requesting intersection number in {}: {}",
+                    settings.dataset, err
+                )
+            })?;
+        info!("Nb of indexed intersection: {}", nb_intersections);
+    }
+
+    let addresses = if import_addresses || import_postcodes {
+        info!("Extracting addresses from osm");
+        addresses(osm_reader, &admins_geofinder, &streets)?
+    } else {
+        vec![]
+    };
+
+    if import_postcodes {
+        info!("Extracting postcodes from osm");
+        let mut postcodes = postcodes(osm_reader, &admins_geofinder)?;
+        let from_addresses = postcodes_from_addresses(&addresses, &postcodes, &admins_geofinder);
+        info!(
+            "{} postcodes built from the addresses",
+            from_addresses.len()
+        );
+        postcodes.extend(from_addresses);
+
+        let postcode_index_settings = IndexSettings {
+            nb_shards: settings.elasticsearch.admins_shards,
+            nb_replicas: settings.elasticsearch.admins_replicas,
+        };
+        info!("importing postcodes into Mimir");
+        let nb_postcodes = backend
+            .public_index(
+                &settings.dataset,
+                &postcode_index_settings,
+                postcodes.into_iter(),
+            )
+            .with_context(|err| {
+                format!(
+                    "Error occurred when requesting postcode number in {}: {}",
+                    settings.dataset, err
+                )
+            })?;
+        info!("Nb of indexed postcode: {}", nb_postcodes);
+    }
+
+    if settings
+        .locality
+        .as_ref()
+        .map(|locality| locality.import)
+        .unwrap_or_else(|| false)
+    {
+        info!("Extracting localities from osm");
+        let localities = localities(osm_reader, &admins_geofinder)?;
+
+        let locality_index_settings = IndexSettings {
+            nb_shards: settings.elasticsearch.admins_shards,
+            nb_replicas: settings.elasticsearch.admins_replicas,
+        };
+        info!("importing localities into Mimir");
+        let nb_localities = backend
+            .public_index(
+                &settings.dataset,
+                &locality_index_settings,
+                localities.into_iter(),
+            )
+            .with_context(|err| {
+                format!(
+                    "Error occurred when requesting locality number in {}: {}",
+                    settings.dataset, err
+                )
+            })?;
+        info!("Nb of indexed locality: {}", nb_localities);
+    }
+
+    if import_addresses {
+        let address_index_settings = IndexSettings {
+            nb_shards: settings.elasticsearch.addresses_shards,
+            nb_replicas: settings.elasticsearch.addresses_replicas,
+        };
+        info!("importing addresses into Mimir");
+        let nb_addresses = backend
+            .public_index(
+                &settings.dataset,
+                &address_index_settings,
+                addresses.into_iter(),
+            )
+            .with_context(|err| {
+                format!(
+                    "Error occurred when requesting address number in {}: {}",
+                    settings.dataset, err
+                )
+            })?;
+        info!("Nb of indexed address: {}", nb_addresses);
+    }
     if settings
         .admin
         .as_ref()
@@ -113,7 +333,7 @@ fn run(args: Args) -> Result<(), mimirsbrunn::Error> {
             nb_shards: settings.elasticsearch.admins_shards,
             nb_replicas: settings.elasticsearch.admins_replicas,
         };
-        let nb_admins = rubber
+        let nb_admins = backend
             .public_index(
                 &settings.dataset,
                 &admin_index_settings,
@@ -141,20 +361,20 @@ fn run(args: Args) -> Result<(), mimirsbrunn::Error> {
             .unwrap_or_else(PoiConfig::default);
 
         info!("Extracting pois from osm");
-        let mut pois = pois(&mut osm_reader, &config, &admins_geofinder);
+        let mut pois = pois(osm_reader, &config, &admins_geofinder);
 
         info!("computing poi weight");
         compute_poi_weight(&mut pois);
 
         info!("Adding address in poi");
-        add_address(&mut pois, &mut rubber);
+        add_address(&mut pois, &mut backend);
 
         let poi_index_settings = IndexSettings {
             nb_shards: settings.elasticsearch.pois_shards,
             nb_replicas: settings.elasticsearch.pois_replicas,
         };
         info!("Importing pois into Mimir");
-        let nb_pois = rubber
+        let nb_pois = backend
             .public_index(&settings.dataset, &poi_index_settings, pois.into_iter())
             .context("Importing pois into Mimir")?;
 
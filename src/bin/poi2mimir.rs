@@ -32,7 +32,9 @@ use slog_scope::{error, info};
 
 use failure::format_err;
 use lazy_static::lazy_static;
-use mimir::objects::{Coord, I18nProperties, Poi, PoiType, Property};
+use mimir::backend::{BackendKind, SearchBackend};
+use mimir::objects::{Coord, I18nProperties, Poi, PoiDetails, PoiType, Property};
+use mimir::rest_backend::RestBackend;
 use mimir::rubber::{IndexSettings, IndexVisibility, Rubber, TypedIndex};
 use mimirsbrunn::{admin_geofinder::AdminGeoFinder, labels, utils};
 use navitia_poi_model::{Model as NavitiaModel, Poi as NavitiaPoi, PoiType as NavitiaPoiType};
@@ -47,10 +49,10 @@ lazy_static! {
 
 // This function takes a Poi from the navitia model, ie from the CSV deserialization, and returns
 // a Poi from the mimir model, with all the contextual information added.
-fn into_mimir_poi(
+fn into_mimir_poi<B: SearchBackend>(
     poi: NavitiaPoi,
     poi_types: &HashMap<String, NavitiaPoiType>,
-    rubber: &mut Rubber,
+    backend: &mut B,
     admins_geofinder: &AdminGeoFinder,
 ) -> Result<Poi, mimirsbrunn::Error> {
     let poi_type = poi_types
@@ -60,7 +62,7 @@ fn into_mimir_poi(
 
     let coord = Coord::from(&poi.coord);
 
-    let place = rubber
+    let place = backend
         .get_address(&coord) // No timeout
         .ok()
         .and_then(|addrs| addrs.into_iter().next()); // Take the first place
@@ -87,6 +89,12 @@ fn into_mimir_poi(
     let label =
         labels::format_poi_label(&poi.name, admins.iter().map(|a| a.deref()), &country_codes);
 
+    let details = PoiDetails::from_tags(
+        poi.properties
+            .iter()
+            .map(|p| (p.key.as_str(), p.value.as_str())),
+    );
+
     let poi = Poi {
         id: mimir::objects::normalize_id("poi", &poi.id),
         label,
@@ -103,14 +111,16 @@ fn into_mimir_poi(
         names: I18nProperties::default(),
         labels: I18nProperties::default(),
         distance: None,
+        confidence: None,
         context: None,
+        details,
     };
 
     Ok(poi)
 }
 
-fn import_pois(
-    rubber: &mut Rubber,
+fn import_pois<B: SearchBackend>(
+    backend: &mut B,
     index: &TypedIndex<Poi>,
     admins_geofinder: AdminGeoFinder,
     file: &Path,
@@ -127,13 +137,13 @@ where
         .pois
         .into_iter()
         .filter_map(|(id, poi)| {
-            into_mimir_poi(poi, &poi_types, rubber, &admins_geofinder)
+            into_mimir_poi(poi, &poi_types, backend, &admins_geofinder)
                 .map_err(|err| info!("Could not extract information for POI '{}': {}", id, err))
                 .ok()
         })
         .collect(); // TODO Can we get rid of collect, and chain with the following rubber...?
 
-    let count = rubber
+    let count = backend
         .bulk_index(&index, pois.into_iter())
         .map_err(|err| format_err!("Failed bulk insertion {}", err))?;
 
@@ -144,8 +154,8 @@ where
 
 /// This function initializes the ES context: It creates an index for this dataset,
 /// and then import the POIs in it.
-fn index_poi(
-    cnx_string: &str,
+fn index_poi<B: SearchBackend>(
+    mut backend: B,
     dataset: &str,
     file: &Path,
     visibility: IndexVisibility,
@@ -154,25 +164,24 @@ fn index_poi(
 ) -> Result<(), mimirsbrunn::Error>
 where
 {
-    let mut rubber = Rubber::new(cnx_string);
-    rubber.initialize_templates()?;
+    backend.initialize_templates()?;
 
     let settings = IndexSettings {
         nb_shards,
         nb_replicas,
     };
 
-    let index = rubber.make_index(dataset, &settings)?;
+    let index = backend.make_index(dataset, &settings)?;
 
-    let admins = rubber.get_all_admins().map_err(|err| {
+    let admins = backend.get_all_admins().map_err(|err| {
         error!("Administratives regions not found in es db");
         err
     })?;
     let admins_geofinder = admins.into_iter().collect();
 
-    import_pois(&mut rubber, &index, admins_geofinder, file)?;
+    import_pois(&mut backend, &index, admins_geofinder, file)?;
 
-    rubber
+    backend
         .publish_index(dataset, index, visibility)
         .map_err(|err| format_err!("Failed to publish index {}.", err))
 }
@@ -191,6 +200,11 @@ struct Args {
     )]
     connection_string: String,
 
+    /// Search engine of the connection string: `es2` (Elasticsearch 2) or `rest`
+    /// (Elasticsearch 7+ or OpenSearch).
+    #[structopt(long = "backend", default_value = "es2")]
+    backend: BackendKind,
+
     /// Name of the dataset.
     /// A dataset is a label, that can be used for filtering the data.
     #[structopt(short = "d", long = "dataset", default_value = "fr")]
@@ -224,14 +238,24 @@ fn run(args: Args) -> Result<(), mimirsbrunn::Error> {
         IndexVisibility::Public
     };
 
-    index_poi(
-        &args.connection_string,
-        &args.dataset,
-        &args.input,
-        visibility,
-        args.nb_shards,
-        args.nb_replicas,
-    )
+    match args.backend {
+        BackendKind::Es2 => index_poi(
+            Rubber::new(&args.connection_string),
+            &args.dataset,
+            &args.input,
+            visibility,
+            args.nb_shards,
+            args.nb_replicas,
+        ),
+        BackendKind::Rest => index_poi(
+            RestBackend::new(&args.connection_string)?,
+            &args.dataset,
+            &args.input,
+            visibility,
+            args.nb_shards,
+            args.nb_replicas,
+        ),
+    }
 }
 fn main() {
     mimirsbrunn::utils::launch_run(run);
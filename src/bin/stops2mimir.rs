@@ -29,6 +29,7 @@
 // www.navitia.io
 
 use failure::ResultExt;
+use mimir::backend::BackendKind;
 use mimir::rubber::IndexSettings;
 use mimirsbrunn::stops::*;
 use serde::Deserialize;
@@ -58,6 +59,10 @@ struct Args {
         default_value = "http://localhost:9200/munin"
     )]
     connection_string: String,
+    /// Search engine of the connection string: `es2` (Elasticsearch 2) or `rest`
+    /// (Elasticsearch 7+ or OpenSearch).
+    #[structopt(long = "backend", default_value = "es2")]
+    backend: BackendKind,
     /// Deprecated option.
     #[structopt(short = "C", long = "city-level")]
     city_level: Option<String>,
@@ -168,6 +173,7 @@ fn run(args: Args) -> Result<(), failure::Error> {
     import_stops(
         stops,
         &args.connection_string,
+        args.backend,
         &args.dataset,
         index_settings,
     )
@@ -70,6 +70,24 @@ pub fn format_stop_label<'a>(
     format_label(name.to_owned(), admins, country_codes)
 }
 
+/// format a label for a Postcode
+pub fn format_postcode_label<'a>(
+    postcode: &str,
+    admins: impl Iterator<Item = &'a mimir::Admin> + Clone,
+    country_codes: &[String],
+) -> String {
+    format_label(postcode.to_owned(), admins, country_codes)
+}
+
+/// format a label for a Locality
+pub fn format_locality_label<'a>(
+    name: &str,
+    admins: impl Iterator<Item = &'a mimir::Admin> + Clone,
+    country_codes: &[String],
+) -> String {
+    format_label(name.to_owned(), admins, country_codes)
+}
+
 /// format a name and a label for an Address
 pub fn format_addr_name_and_label<'a>(
     house_number: &str,
@@ -0,0 +1,226 @@
+// Copyright © 2016, Canal TP and/or its affiliates. All rights reserved.
+//
+// This file is part of Navitia,
+//     the software to build cool stuff with public transport.
+//
+// Hope you'll enjoy and contribute to this project,
+//     powered by Canal TP (www.canaltp.fr).
+// Help us simplify mobility and open public transport:
+//     a non ending quest to the responsive locomotion way of traveling!
+//
+// LICENCE: This program is free software; you can redistribute it
+// and/or modify it under the terms of the GNU Affero General Public
+// License as published by the Free Software Foundation, either
+// version 3 of the License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+// Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public
+// License along with this program. If not, see
+// <http://www.gnu.org/licenses/>.
+//
+// Stay tuned using
+// twitter @navitia
+// IRC #navitia on freenode
+// https://groups.google.com/d/forum/navitia
+// www.navitia.io
+
+use super::OsmPbfReader;
+use crate::admin_geofinder::AdminGeoFinder;
+use crate::{labels, Error};
+use failure::ResultExt;
+use osmpbfreader::{OsmId, OsmObj};
+use slog_scope::info;
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Deref;
+
+fn is_associated_street(obj: &OsmObj) -> bool {
+    match *obj {
+        OsmObj::Relation(ref rel) => rel
+            .tags
+            .get("type")
+            .map_or(false, |v| v == "associatedStreet"),
+        _ => false,
+    }
+}
+
+fn has_house_number(obj: &OsmObj) -> bool {
+    match *obj {
+        OsmObj::Node(_) | OsmObj::Way(_) => obj
+            .tags()
+            .get("addr:housenumber")
+            .map_or(false, |v| !v.trim().is_empty()),
+        OsmObj::Relation(_) => false,
+    }
+}
+
+/// The name of the street of the houses of the associatedStreet relations,
+/// for the houses without `addr:street` tag.
+fn street_names_of_houses(objects: &BTreeMap<OsmId, OsmObj>) -> HashMap<OsmId, String> {
+    let mut names = HashMap::new();
+    for rel in objects
+        .values()
+        .filter(|obj| is_associated_street(obj))
+        .filter_map(OsmObj::relation)
+    {
+        // the name of the relation, or else the name of its street
+        let name = rel.tags.get("name").map(|n| n.to_string()).or_else(|| {
+            rel.refs
+                .iter()
+                .filter(|r| &r.role == "street")
+                .filter_map(|r| objects.get(&r.member))
+                .filter_map(|obj| obj.tags().get("name"))
+                .map(|n| n.to_string())
+                .next()
+        });
+        if let Some(name) = name {
+            for house in rel.refs.iter().filter(|r| &r.role == "house") {
+                names.insert(house.member, name.clone());
+            }
+        }
+    }
+    names
+}
+
+/// the mean of the nodes of the way (usually the outline of a building)
+fn get_way_centroid(
+    objects: &BTreeMap<OsmId, OsmObj>,
+    way: &osmpbfreader::objects::Way,
+) -> mimir::Coord {
+    let coords = way
+        .nodes
+        .iter()
+        .filter_map(|node_id| objects.get(&(*node_id).into()))
+        .filter_map(OsmObj::node)
+        .map(|node| (node.lon(), node.lat()))
+        .collect::<Vec<_>>();
+    if coords.is_empty() {
+        return mimir::Coord::default();
+    }
+    let nb = coords.len() as f64;
+    mimir::Coord::new(
+        coords.iter().map(|c| c.0).sum::<f64>() / nb,
+        coords.iter().map(|c| c.1).sum::<f64>() / nb,
+    )
+}
+
+fn format_addr_id(osm_type: &str, id: i64) -> String {
+    format!("addr:osm:{}:{}", osm_type, id)
+}
+
+/// Extract the addresses from OSM: the nodes and the ways (mainly buildings) with an
+/// `addr:housenumber` tag.
+///
+/// The street of an address is given by its `addr:street` tag, or else by the
+/// `associatedStreet` relation it belongs to. Each address is linked to the street of
+/// `streets` with this name in the same city, the addresses without such a street are ignored.
+pub fn addresses(
+    pbf: &mut OsmPbfReader,
+    admins_geofinder: &AdminGeoFinder,
+    streets: &[mimir::Street],
+) -> Result<Vec<mimir::Addr>, Error> {
+    let objects = pbf
+        .get_objs_and_deps(|obj| has_house_number(obj) || is_associated_street(obj))
+        .context("Error occurred when reading pbf")?;
+
+    let street_names = street_names_of_houses(&objects);
+
+    let mut streets_by_name_and_city = HashMap::new();
+    for street in streets {
+        if let Some(city) = street
+            .administrative_regions
+            .iter()
+            .find(|admin| admin.is_city())
+        {
+            streets_by_name_and_city
+                .entry((street.name.as_str(), city.id.as_str()))
+                .or_insert(street);
+        }
+    }
+
+    let mut nb_without_street = 0;
+    let addresses = objects
+        .values()
+        .filter(|obj| has_house_number(obj))
+        .filter_map(|obj| {
+            let house_number = obj.tags().get("addr:housenumber")?.trim().to_string();
+            let street_name = obj
+                .tags()
+                .get("addr:street")
+                .map(|s| s.to_string())
+                .or_else(|| street_names.get(&obj.id()).cloned())?;
+            let (id, coord) = match *obj {
+                OsmObj::Node(ref node) => (
+                    format_addr_id("node", node.id.0),
+                    mimir::Coord::new(node.lon(), node.lat()),
+                ),
+                OsmObj::Way(ref way) => (
+                    format_addr_id("way", way.id.0),
+                    get_way_centroid(&objects, way),
+                ),
+                OsmObj::Relation(_) => return None,
+            };
+            if coord.is_default() {
+                return None;
+            }
+
+            let street = admins_geofinder
+                .get(&coord)
+                .iter()
+                .find(|admin| admin.is_city())
+                .and_then(|city| {
+                    streets_by_name_and_city
+                        .get(&(street_name.as_str(), city.id.as_str()))
+                        .cloned()
+                });
+            let street = match street {
+                Some(street) => street,
+                None => {
+                    nb_without_street += 1;
+                    return None;
+                }
+            };
+
+            let (name, label) = labels::format_addr_name_and_label(
+                &house_number,
+                &street.name,
+                street.administrative_regions.iter().map(Deref::deref),
+                &street.country_codes,
+            );
+            let zip_codes = match obj.tags().get("addr:postcode") {
+                Some(val) if !val.is_empty() => vec![val.to_string()],
+                _ => street.zip_codes.clone(),
+            };
+            Some(mimir::Addr {
+                id,
+                name,
+                label,
+                house_number,
+                street: mimir::Street {
+                    house_number_ranges: vec![],
+                    ..street.clone()
+                },
+                coord,
+                approx_coord: Some(coord.into()),
+                weight: street.weight,
+                zip_codes,
+                distance: None,
+                confidence: None,
+                country_codes: street.country_codes.clone(),
+                context: None,
+                interpolated: false,
+            })
+        })
+        .collect::<Vec<_>>();
+
+    if nb_without_street > 0 {
+        info!(
+            "{} addresses have been ignored because their street was not found",
+            nb_without_street
+        );
+    }
+    Ok(addresses)
+}
@@ -176,6 +176,7 @@ pub fn read_administrative_regions(
                 names: mimir::I18nProperties::default(),
                 labels: mimir::I18nProperties::default(),
                 distance: None,
+                confidence: None,
                 context: None,
                 administrative_regions: Vec::new(),
             };
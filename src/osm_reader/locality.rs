@@ -0,0 +1,145 @@
+// Copyright © 2016, Canal TP and/or its affiliates. All rights reserved.
+//
+// This file is part of Navitia,
+//     the software to build cool stuff with public transport.
+//
+// Hope you'll enjoy and contribute to this project,
+//     powered by Canal TP (www.canaltp.fr).
+// Help us simplify mobility and open public transport:
+//     a non ending quest to the responsive locomotion way of traveling!
+//
+// LICENCE: This program is free software; you can redistribute it
+// and/or modify it under the terms of the GNU Affero General Public
+// License as published by the Free Software Foundation, either
+// version 3 of the License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+// Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public
+// License along with this program. If not, see
+// <http://www.gnu.org/licenses/>.
+//
+// Stay tuned using
+// twitter @navitia
+// IRC #navitia on freenode
+// https://groups.google.com/d/forum/navitia
+// www.navitia.io
+
+use super::osm_utils::{get_way_coord, make_centroid};
+use super::OsmPbfReader;
+use crate::admin_geofinder::AdminGeoFinder;
+use crate::{labels, utils, Error};
+use failure::ResultExt;
+use osm_boundaries_utils::build_boundary;
+use slog_scope::{info, warn};
+use std::collections::BTreeMap;
+use std::ops::Deref;
+
+/// The rank of the osm `place` values imported as localities, like the address ranks of
+/// Nominatim: the smaller, the more important the place.
+fn place_rank(place: &str) -> Option<u32> {
+    match place {
+        "hamlet" => Some(20),
+        "neighbourhood" | "isolated_dwelling" => Some(22),
+        "locality" => Some(25),
+        _ => None,
+    }
+}
+
+/// The weight of a locality, decreasing with its rank
+fn rank_weight(rank: u32) -> f64 {
+    f64::from(30u32.saturating_sub(rank)) / 30.
+}
+
+fn is_locality(obj: &osmpbfreader::OsmObj) -> bool {
+    let tags = obj.tags();
+    tags.get("place")
+        .map_or(false, |place| place_rank(place).is_some())
+        && tags.get("name").map_or(false, |name| !name.is_empty())
+}
+
+/// The id of the locality of `osmobj` and its coordinates, computed with the objects of `obj_map`
+fn get_locality_id_and_coord(
+    osmobj: &osmpbfreader::OsmObj,
+    obj_map: &BTreeMap<osmpbfreader::OsmId, osmpbfreader::OsmObj>,
+) -> (String, mimir::Coord) {
+    match *osmobj {
+        osmpbfreader::OsmObj::Node(ref node) => (
+            format!("locality:osm:node:{}", node.id.0),
+            mimir::Coord::new(node.lon(), node.lat()),
+        ),
+        osmpbfreader::OsmObj::Way(ref way) => (
+            format!("locality:osm:way:{}", way.id.0),
+            get_way_coord(obj_map, way),
+        ),
+        osmpbfreader::OsmObj::Relation(ref relation) => (
+            format!("locality:osm:relation:{}", relation.id.0),
+            make_centroid(&build_boundary(relation, obj_map)),
+        ),
+    }
+}
+
+/// Read the localities of the osm `place=hamlet|locality|isolated_dwelling|neighbourhood`
+/// objects (the places without streets).
+pub fn localities(
+    pbf: &mut OsmPbfReader,
+    admins_geofinder: &AdminGeoFinder,
+) -> Result<Vec<mimir::Locality>, Error> {
+    let objects = pbf
+        .get_objs_and_deps(is_locality)
+        .context("Error occurred when reading pbf")?;
+
+    let mut localities = vec![];
+    for obj in objects.values().filter(|obj| is_locality(obj)) {
+        let (id, coord) = get_locality_id_and_coord(obj, &objects);
+        if coord.is_default() {
+            warn!("The locality {} is rejected, its coord is unknown", id);
+            continue;
+        }
+        let tags = obj.tags();
+        let locality_type = tags.get("place").map_or("", |v| v.as_str());
+        let name = tags.get("name").map_or("", |v| v.as_str());
+
+        let admins = admins_geofinder.get(&coord);
+        let zip_codes = match tags.get("addr:postcode") {
+            Some(val) if !val.is_empty() => vec![val.to_string()],
+            _ => utils::get_zip_codes_from_admins(&admins),
+        };
+        let admins_iter = admins.iter().map(Deref::deref);
+        let country_codes = utils::find_country_codes(admins_iter.clone());
+        localities.push(mimir::Locality {
+            id,
+            name: name.to_string(),
+            label: labels::format_locality_label(name, admins_iter, &country_codes),
+            locality_type: locality_type.to_string(),
+            weight: place_rank(locality_type).map_or(0., rank_weight),
+            administrative_regions: admins,
+            coord,
+            approx_coord: Some(coord.into()),
+            zip_codes,
+            country_codes,
+            distance: None,
+            confidence: None,
+            context: None,
+        });
+    }
+    info!("{} localities read", localities.len());
+    Ok(localities)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hamlets_weigh_more_than_lieux_dits() {
+        let weight = |place| place_rank(place).map(rank_weight);
+        assert!(weight("hamlet") > weight("isolated_dwelling"));
+        assert!(weight("isolated_dwelling") > weight("locality"));
+        assert!(weight("locality") > Some(0.));
+        assert_eq!(weight("village"), None);
+    }
+}
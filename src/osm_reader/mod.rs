@@ -2,10 +2,14 @@ use crate::Error;
 use std::fs::File;
 use std::path::Path;
 
+pub mod addr;
 pub mod admin;
+pub mod locality;
+pub mod osc;
 pub mod osm_store;
 pub mod osm_utils;
 pub mod poi;
+pub mod postcode;
 pub mod street;
 
 pub type OsmPbfReader = osmpbfreader::OsmPbfReader<File>;
@@ -0,0 +1,756 @@
+// Copyright © 2016, Canal TP and/or its affiliates. All rights reserved.
+//
+// This file is part of Navitia,
+//     the software to build cool stuff with public transport.
+//
+// Hope you'll enjoy and contribute to this project,
+//     powered by Canal TP (www.canaltp.fr).
+// Help us simplify mobility and open public transport:
+//     a non ending quest to the responsive locomotion way of traveling!
+//
+// LICENCE: This program is free software; you can redistribute it
+// and/or modify it under the terms of the GNU Affero General Public
+// License as published by the Free Software Foundation, either
+// version 3 of the License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+// Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public
+// License along with this program. If not, see
+// <http://www.gnu.org/licenses/>.
+//
+// Stay tuned using
+// twitter @navitia
+// IRC #navitia on freenode
+// https://groups.google.com/d/forum/navitia
+// www.navitia.io
+
+//! Incremental updates of the indexes with the osmChange diffs (`.osc` files) of OpenStreetMap.
+//!
+//! Only the objects of the diff are read, so the geometry of a modified way or relation is only
+//! known if its nodes are in the diff too, otherwise the coordinates of its current document
+//! are kept.
+
+use super::admin::{format_zip_codes, read_insee, read_zip_codes, AdminMatcher};
+use super::osm_utils::{get_osm_codes_from_tags, get_way_coord};
+use super::poi::{
+    add_address, build_poi, compute_poi_weight, format_poi_id, get_poi_id_and_coord, PoiConfig,
+};
+use super::street::{build_street, compute_street_weight, is_street};
+use crate::admin_geofinder::AdminGeoFinder;
+use crate::settings::osm2mimir::Settings;
+use crate::{utils, Error};
+use failure::{format_err, ResultExt};
+use flate2::read::GzDecoder;
+use mimir::backend::SearchBackend;
+use mimir::rubber::TypedIndex;
+use mimir::{MimirObject, Place};
+use osmpbfreader::{Node, NodeId, OsmId, OsmObj, Ref, Relation, RelationId, Tags, Way, WayId};
+use quick_xml::events::{BytesStart, Event};
+use slog_scope::{info, warn};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Display;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+
+// a new way with the name of a street of its city within this distance (in meters)
+// is considered as a part of this street
+const SAME_STREET_DISTANCE: f64 = 1000.;
+
+/// The objects created, modified or deleted by an osmChange diff
+#[derive(Debug, Default)]
+pub struct OsmChange {
+    /// The last version of the created and modified objects
+    pub objects: BTreeMap<OsmId, OsmObj>,
+    /// The objects created by the diff
+    pub created: BTreeSet<OsmId>,
+    pub deleted: BTreeSet<OsmId>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Action {
+    Create,
+    Modify,
+    Delete,
+}
+
+impl OsmChange {
+    fn add(&mut self, action: Action, obj: OsmObj) {
+        let id = obj.id();
+        match action {
+            Action::Delete => {
+                self.objects.remove(&id);
+                self.deleted.insert(id);
+            }
+            Action::Create | Action::Modify => {
+                if action == Action::Create {
+                    self.created.insert(id);
+                }
+                self.deleted.remove(&id);
+                self.objects.insert(id, obj);
+            }
+        }
+    }
+
+    /// Can the object have been indexed by a previous import or update,
+    /// i.e. was it known before this diff?
+    fn may_be_indexed(&self, id: &OsmId) -> bool {
+        !self.created.contains(id)
+    }
+}
+
+fn make_tags(tags: Vec<(String, String)>) -> Tags {
+    tags.into_iter()
+        .map(|(k, v)| (k.into(), v.into()))
+        .collect()
+}
+
+fn attribute(e: &BytesStart<'_>, name: &[u8]) -> Result<Option<String>, Error> {
+    for attr in e.attributes() {
+        let attr = attr?;
+        if attr.key == name {
+            return Ok(Some(String::from_utf8(
+                attr.unescaped_value()?.into_owned(),
+            )?));
+        }
+    }
+    Ok(None)
+}
+
+fn parse_attribute<T>(e: &BytesStart<'_>, name: &[u8]) -> Result<Option<T>, Error>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    attribute(e, name)?
+        .map(|value| {
+            value.parse().map_err(|err| {
+                format_err!(
+                    "invalid {} {:?} in <{}>: {}",
+                    String::from_utf8_lossy(name),
+                    value,
+                    String::from_utf8_lossy(e.name()),
+                    err
+                )
+            })
+        })
+        .transpose()
+}
+
+fn required_attribute<T>(e: &BytesStart<'_>, name: &[u8]) -> Result<T, Error>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    parse_attribute(e, name)?.ok_or_else(|| {
+        format_err!(
+            "missing {} in <{}>",
+            String::from_utf8_lossy(name),
+            String::from_utf8_lossy(e.name())
+        )
+    })
+}
+
+fn to_decimicro(degrees: f64) -> i32 {
+    (degrees * 1e7).round() as i32
+}
+
+#[derive(Default)]
+struct OscParser {
+    change: OsmChange,
+    action: Option<Action>,
+    // the object being read, with its tags
+    current: Option<(OsmObj, Vec<(String, String)>)>,
+}
+
+impl OscParser {
+    fn start(&mut self, e: &BytesStart<'_>) -> Result<(), Error> {
+        match e.name() {
+            b"create" => self.action = Some(Action::Create),
+            b"modify" => self.action = Some(Action::Modify),
+            b"delete" => self.action = Some(Action::Delete),
+            b"node" => {
+                // the deleted nodes can be given without coordinates
+                let lat = parse_attribute::<f64>(e, b"lat")?.unwrap_or_default();
+                let lon = parse_attribute::<f64>(e, b"lon")?.unwrap_or_default();
+                let node = Node {
+                    id: NodeId(required_attribute(e, b"id")?),
+                    tags: make_tags(vec![]),
+                    decimicro_lat: to_decimicro(lat),
+                    decimicro_lon: to_decimicro(lon),
+                };
+                self.current = Some((OsmObj::Node(node), vec![]));
+            }
+            b"way" => {
+                let way = Way {
+                    id: WayId(required_attribute(e, b"id")?),
+                    tags: make_tags(vec![]),
+                    nodes: vec![],
+                };
+                self.current = Some((OsmObj::Way(way), vec![]));
+            }
+            b"relation" => {
+                let relation = Relation {
+                    id: RelationId(required_attribute(e, b"id")?),
+                    tags: make_tags(vec![]),
+                    refs: vec![],
+                };
+                self.current = Some((OsmObj::Relation(relation), vec![]));
+            }
+            b"tag" => {
+                if let Some((_, tags)) = self.current.as_mut() {
+                    tags.push((required_attribute(e, b"k")?, required_attribute(e, b"v")?));
+                }
+            }
+            b"nd" => {
+                if let Some((OsmObj::Way(way), _)) = self.current.as_mut() {
+                    way.nodes.push(NodeId(required_attribute(e, b"ref")?));
+                }
+            }
+            b"member" => {
+                if let Some((OsmObj::Relation(relation), _)) = self.current.as_mut() {
+                    let id = required_attribute(e, b"ref")?;
+                    let member = match required_attribute::<String>(e, b"type")?.as_str() {
+                        "node" => OsmId::Node(NodeId(id)),
+                        "way" => OsmId::Way(WayId(id)),
+                        "relation" => OsmId::Relation(RelationId(id)),
+                        t => return Err(format_err!("invalid member type {}", t)),
+                    };
+                    let role = attribute(e, b"role")?.unwrap_or_default();
+                    relation.refs.push(Ref {
+                        member,
+                        role: role.into(),
+                    });
+                }
+            }
+            _ => (),
+        }
+        Ok(())
+    }
+
+    fn end(&mut self, name: &[u8]) -> Result<(), Error> {
+        match name {
+            b"create" | b"modify" | b"delete" => self.action = None,
+            b"node" | b"way" | b"relation" => {
+                let (mut obj, tags) = match self.current.take() {
+                    Some(current) => current,
+                    None => return Ok(()),
+                };
+                let action = self.action.ok_or_else(|| {
+                    format_err!(
+                        "object {:?} outside of a create, modify or delete",
+                        obj.id()
+                    )
+                })?;
+                let tags = make_tags(tags);
+                match obj {
+                    OsmObj::Node(ref mut node) => node.tags = tags,
+                    OsmObj::Way(ref mut way) => way.tags = tags,
+                    OsmObj::Relation(ref mut relation) => relation.tags = tags,
+                }
+                self.change.add(action, obj);
+            }
+            _ => (),
+        }
+        Ok(())
+    }
+}
+
+/// Read an osmChange document.
+///
+/// When an object is changed several times, only its last version is kept.
+pub fn read_osm_change<R: BufRead>(reader: R) -> Result<OsmChange, Error> {
+    let mut reader = quick_xml::Reader::from_reader(reader);
+    reader.trim_text(true);
+    let mut parser = OscParser::default();
+    let mut buf = Vec::new();
+    loop {
+        match reader.read_event(&mut buf)? {
+            Event::Start(ref e) => parser.start(e)?,
+            Event::Empty(ref e) => {
+                parser.start(e)?;
+                parser.end(e.name())?;
+            }
+            Event::End(ref e) => parser.end(e.name())?,
+            Event::Eof => break,
+            _ => (),
+        }
+        buf.clear();
+    }
+    Ok(parser.change)
+}
+
+/// Read an osmChange file, compressed with gzip if its extension is `.gz`
+pub fn read_osm_change_file(path: &Path) -> Result<OsmChange, Error> {
+    let file = File::open(path).with_context(|_| format!("impossible to open {:?}", path))?;
+    let change = if path.extension().map_or(false, |ext| ext == "gz") {
+        read_osm_change(BufReader::new(GzDecoder::new(file)))
+    } else {
+        read_osm_change(BufReader::new(file))
+    };
+    Ok(change.with_context(|_| format!("invalid osmChange file {:?}", path))?)
+}
+
+/// The sequence number of a diff of the OSM replication, deduced from its path
+/// (`.../000/123/456.osc.gz` is the diff 123456).
+pub fn sequence_number_from_path(path: &Path) -> Option<u64> {
+    let is_number = |s: &str| !s.is_empty() && s.chars().all(|c| c.is_ascii_digit());
+    let file_number = path.file_name()?.to_str()?.split('.').next()?;
+    if !is_number(file_number) {
+        return None;
+    }
+    let mut parts = path
+        .parent()
+        .into_iter()
+        .flat_map(|dir| dir.iter().rev())
+        .filter_map(|dir| dir.to_str())
+        .take_while(|dir| dir.len() == 3 && is_number(dir))
+        .take(2)
+        .collect::<Vec<_>>();
+    parts.reverse();
+    parts.push(file_number);
+    parts.concat().parse().ok()
+}
+
+const SEQUENCE_NUMBER_KEY: &str = "sequenceNumber=";
+
+/// Read the sequence number of the last applied diff in a state file, written in the format
+/// of the `state.txt` files of the OSM replication (with a `sequenceNumber=<n>` line).
+pub fn read_sequence_number(path: &Path) -> Result<Option<u64>, Error> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let content =
+        fs::read_to_string(path).with_context(|_| format!("impossible to read {:?}", path))?;
+    content
+        .lines()
+        .map(str::trim)
+        .find(|line| line.starts_with(SEQUENCE_NUMBER_KEY))
+        .map(|line| {
+            let n = &line[SEQUENCE_NUMBER_KEY.len()..];
+            n.parse()
+                .map_err(|e| format_err!("invalid sequence number in {:?}: {}", path, e))
+        })
+        .transpose()
+}
+
+pub fn write_sequence_number(path: &Path, sequence_number: u64) -> Result<(), Error> {
+    fs::write(path, format!("sequenceNumber={}\n", sequence_number))
+        .with_context(|_| format!("impossible to write {:?}", path))?;
+    Ok(())
+}
+
+fn get_doc<B: SearchBackend>(
+    backend: &mut B,
+    index: &str,
+    id: &str,
+) -> Result<Option<Place>, Error> {
+    Ok(backend.get_by_id(&[index], id)?.into_iter().next())
+}
+
+fn get_city_id(admins: &[Arc<mimir::Admin>]) -> Option<&str> {
+    admins
+        .iter()
+        .find(|admin| admin.is_city())
+        .map(|city| city.id.as_str())
+}
+
+/// Delete the documents `deleted` and upsert the documents `docs` in `index`
+fn update_index<T, B>(
+    backend: &mut B,
+    index: &TypedIndex<T>,
+    docs: Vec<T>,
+    deleted: Vec<String>,
+) -> Result<(), Error>
+where
+    T: MimirObject + std::marker::Send + 'static,
+    B: SearchBackend,
+{
+    let nb_deleted = backend
+        .delete_docs(index, &deleted)
+        .with_context(|_| format!("Error occurred when deleting documents of {}", index.name()))?;
+    let nb_upserted = backend
+        .upsert_docs(index, docs.into_iter())
+        .with_context(|_| {
+            format!(
+                "Error occurred when upserting documents in {}",
+                index.name()
+            )
+        })?;
+    backend.refresh_index(index.name())?;
+    info!(
+        "Nb of {} updated: {}, deleted: {}",
+        T::doc_type(),
+        nb_upserted,
+        nb_deleted
+    );
+    Ok(())
+}
+
+/// Update the names of the modified admins and delete the deleted ones.
+///
+/// The boundaries cannot be built from a diff, so they are kept, and the new admins are ignored
+/// until the next import.
+fn update_admins<B: SearchBackend>(
+    backend: &mut B,
+    change: &OsmChange,
+    admins: &mut Vec<mimir::Admin>,
+    settings: &Settings,
+) -> Result<(), Error> {
+    let levels = match settings.admin.as_ref() {
+        Some(admin) => admin.levels.iter().cloned().collect(),
+        None => return Ok(()),
+    };
+    let index = match backend.published_index::<mimir::Admin>(&settings.dataset)? {
+        Some(index) => index,
+        None => {
+            warn!(
+                "no admin index published for the dataset {}, the admins are not updated",
+                settings.dataset
+            );
+            return Ok(());
+        }
+    };
+    let matcher = AdminMatcher::new(levels);
+
+    let mut updated = vec![];
+    let mut nb_ignored = 0;
+    for obj in change.objects.values().filter(|obj| matcher.is_admin(obj)) {
+        let relation = match obj.relation() {
+            Some(relation) => relation,
+            None => continue,
+        };
+        let osm_id = format!("admin:osm:relation:{}", relation.id.0);
+        let insee_id = read_insee(&relation.tags).map(|insee| format!("admin:fr:{}", insee));
+        let admin = admins
+            .iter_mut()
+            .find(|admin| admin.id == osm_id || Some(&admin.id) == insee_id.as_ref());
+        match admin {
+            Some(admin) => {
+                if let Some(name) = relation.tags.get("name") {
+                    admin.name = name.to_string();
+                }
+                admin.zip_codes = read_zip_codes(&relation.tags);
+                admin.label = format!("{}{}", admin.name, format_zip_codes(&admin.zip_codes));
+                admin.codes = get_osm_codes_from_tags(&relation.tags);
+                admin.country_codes = utils::get_country_code(&admin.codes).into_iter().collect();
+                updated.push(admin.clone());
+            }
+            None => nb_ignored += 1,
+        }
+    }
+    if nb_ignored > 0 {
+        info!(
+            "{} new admins have been ignored, their boundary can only be built by an import",
+            nb_ignored
+        );
+    }
+
+    let deleted = change
+        .deleted
+        .iter()
+        .filter(|id| change.may_be_indexed(id))
+        .filter_map(|id| match id {
+            OsmId::Relation(id) => Some(format!("admin:osm:relation:{}", id.0)),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    admins.retain(|admin| !deleted.contains(&admin.id));
+
+    update_index(backend, &index, updated, deleted)
+}
+
+/// Upsert the streets of the created and modified ways, and delete the streets of the deleted ways
+/// and of the ways that are not streets anymore.
+///
+/// A new way with the name of a street of its city is a part of this street, and is ignored.
+fn update_streets<B: SearchBackend>(
+    backend: &mut B,
+    change: &OsmChange,
+    admins_geofinder: &AdminGeoFinder,
+    settings: &Settings,
+) -> Result<(), Error> {
+    let index = match backend.published_index::<mimir::Street>(&settings.dataset)? {
+        Some(index) => index,
+        None => {
+            warn!(
+                "no street index published for the dataset {}, the streets are not updated",
+                settings.dataset
+            );
+            return Ok(());
+        }
+    };
+
+    let mut streets = vec![];
+    let mut deleted = vec![];
+    let mut nb_ignored = 0;
+    for (osm_id, obj) in &change.objects {
+        let way = match obj.way() {
+            Some(way) => way,
+            None => continue,
+        };
+        let id = format!("street:osm:way:{}", way.id.0);
+        if !is_street(way, settings) {
+            if change.may_be_indexed(osm_id) {
+                deleted.push(id);
+            }
+            continue;
+        }
+        let existing = if change.may_be_indexed(osm_id) {
+            match get_doc(backend, index.name(), &id)? {
+                Some(Place::Street(street)) => Some(street),
+                _ => None,
+            }
+        } else {
+            None
+        };
+
+        let coord = get_way_coord(&change.objects, way);
+        let coord = match existing.as_ref() {
+            Some(street) if coord.is_default() => street.coord,
+            None if coord.is_default() => {
+                nb_ignored += 1;
+                continue;
+            }
+            _ => coord,
+        };
+        let name = way.tags.get("name").map_or("", |v| v.as_ref()).to_string();
+        let admins = admins_geofinder.get(&coord);
+
+        if existing.is_none() {
+            let city_id = get_city_id(&admins);
+            let is_part_of_a_street = backend
+                .get_places_around(&coord, &[index.name()], SAME_STREET_DISTANCE, 10)?
+                .iter()
+                .any(|place| match place {
+                    Place::Street(street) => {
+                        street.name == name
+                            && get_city_id(&street.administrative_regions) == city_id
+                    }
+                    _ => false,
+                });
+            if is_part_of_a_street {
+                continue;
+            }
+        }
+
+        let mut street = build_street(id, name, coord, admins);
+        if let Some(existing) = existing {
+            // the interpolation lines are only read by the imports
+            street.house_number_ranges = existing.house_number_ranges;
+        }
+        streets.push(street);
+    }
+    if nb_ignored > 0 {
+        info!(
+            "{} streets without the coordinates of their nodes have been ignored",
+            nb_ignored
+        );
+    }
+
+    deleted.extend(
+        change
+            .deleted
+            .iter()
+            .filter(|id| change.may_be_indexed(id))
+            .filter_map(|id| match id {
+                OsmId::Way(id) => Some(format!("street:osm:way:{}", id.0)),
+                OsmId::Relation(id) => Some(format!("street:osm:relation:{}", id.0)),
+                OsmId::Node(_) => None,
+            }),
+    );
+
+    compute_street_weight(&mut streets);
+    update_index(backend, &index, streets, deleted)
+}
+
+/// Upsert the pois of the created and modified objects, and delete the pois of the deleted
+/// objects and of the objects that are not pois anymore.
+fn update_pois<B: SearchBackend>(
+    backend: &mut B,
+    change: &OsmChange,
+    admins_geofinder: &AdminGeoFinder,
+    settings: &Settings,
+) -> Result<(), Error> {
+    let index = match backend.published_index::<mimir::Poi>(&settings.dataset)? {
+        Some(index) => index,
+        None => {
+            warn!(
+                "no poi index published for the dataset {}, the pois are not updated",
+                settings.dataset
+            );
+            return Ok(());
+        }
+    };
+    let config = settings
+        .poi
+        .as_ref()
+        .and_then(|poi| poi.config.clone())
+        .unwrap_or_else(PoiConfig::default);
+
+    let mut pois = vec![];
+    let mut deleted = vec![];
+    let mut nb_ignored = 0;
+    for (osm_id, obj) in &change.objects {
+        let (id, coord) = get_poi_id_and_coord(obj, &change.objects);
+        if !config.is_poi(obj.tags()) {
+            // the nodes without tags are only the geometry of ways, a poi losing all its tags
+            // is too rare to delete all of them
+            let has_tags = obj.tags().iter().next().is_some();
+            if change.may_be_indexed(osm_id) && (has_tags || obj.node().is_none()) {
+                deleted.push(id);
+            }
+            continue;
+        }
+        let coord = if coord.is_default() && change.may_be_indexed(osm_id) {
+            match get_doc(backend, index.name(), &id)? {
+                Some(Place::Poi(poi)) => poi.coord,
+                _ => coord,
+            }
+        } else {
+            coord
+        };
+        match build_poi(obj, id, coord, &config, admins_geofinder) {
+            Some(poi) => pois.push(poi),
+            None => nb_ignored += 1,
+        }
+    }
+    if nb_ignored > 0 {
+        info!("{} pois have been ignored", nb_ignored);
+    }
+
+    deleted.extend(
+        change
+            .deleted
+            .iter()
+            .filter(|id| change.may_be_indexed(id))
+            .map(|id| match id {
+                OsmId::Node(id) => format_poi_id("node", id.0),
+                OsmId::Way(id) => format_poi_id("way", id.0),
+                OsmId::Relation(id) => format_poi_id("relation", id.0),
+            }),
+    );
+
+    compute_poi_weight(&mut pois);
+    add_address(&mut pois, backend);
+    update_index(backend, &index, pois, deleted)
+}
+
+/// Apply an osmChange diff to the indexes published for the dataset, for the types of objects
+/// whose import is enabled in the settings.
+///
+/// Only the documents of the objects of the diff are upserted or deleted, and their admins are
+/// computed with the admins of the index.
+pub fn apply_osm_change<B: SearchBackend>(
+    backend: &mut B,
+    change: &OsmChange,
+    settings: &Settings,
+) -> Result<(), Error> {
+    info!(
+        "applying {} created or modified objects and {} deleted objects",
+        change.objects.len(),
+        change.deleted.len()
+    );
+
+    info!("retrieving administrative regions from the index");
+    let mut admins = backend.get_all_admins()?;
+    if settings.admin.as_ref().map_or(false, |admin| admin.import) {
+        info!("updating admins");
+        update_admins(backend, change, &mut admins, settings)?;
+    }
+    let admins_geofinder = admins.into_iter().collect::<AdminGeoFinder>();
+
+    if settings
+        .street
+        .as_ref()
+        .map_or(false, |street| street.import)
+    {
+        info!("updating streets");
+        update_streets(backend, change, &admins_geofinder, settings)?;
+    }
+    if settings.poi.as_ref().map_or(false, |poi| poi.import) {
+        info!("updating pois");
+        update_pois(backend, change, &admins_geofinder, settings)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_osm_change() {
+        let osc = r#"<?xml version='1.0' encoding='UTF-8'?>
+<osmChange version="0.6" generator="test">
+  <create>
+    <node id="1" version="1" lat="48.8465" lon="2.3763">
+      <tag k="amenity" v="post_office"/>
+      <tag k="name" v="La Poste &amp; Cie"/>
+    </node>
+    <node id="2" version="1" lat="48.8466" lon="2.3764"/>
+    <way id="10" version="1">
+      <nd ref="1"/>
+      <nd ref="2"/>
+      <tag k="highway" v="residential"/>
+    </way>
+  </create>
+  <modify>
+    <relation id="100" version="2">
+      <member type="way" ref="10" role="street"/>
+      <tag k="type" v="associatedStreet"/>
+    </relation>
+    <node id="2" version="2" lat="48.8467" lon="2.3765"/>
+  </modify>
+  <delete>
+    <node id="3" version="2"/>
+    <way id="11" version="3"/>
+  </delete>
+</osmChange>"#;
+        let change = read_osm_change(osc.as_bytes()).unwrap();
+
+        assert_eq!(change.objects.len(), 4);
+        let node = change.objects[&OsmId::Node(NodeId(1))].node().unwrap();
+        assert_eq!(node.tags.get("name").unwrap().as_str(), "La Poste & Cie");
+        assert!((node.lat() - 48.8465).abs() < 1e-7);
+        assert!((node.lon() - 2.3763).abs() < 1e-7);
+        // the last version of the node is kept, and it is still a created node
+        let node = change.objects[&OsmId::Node(NodeId(2))].node().unwrap();
+        assert!((node.lat() - 48.8467).abs() < 1e-7);
+        assert!(!change.may_be_indexed(&OsmId::Node(NodeId(2))));
+
+        let way = change.objects[&OsmId::Way(WayId(10))].way().unwrap();
+        assert_eq!(way.nodes, vec![NodeId(1), NodeId(2)]);
+        let relation = change.objects[&OsmId::Relation(RelationId(100))]
+            .relation()
+            .unwrap();
+        assert_eq!(relation.refs.len(), 1);
+        assert_eq!(relation.refs[0].member, OsmId::Way(WayId(10)));
+        assert!(change.may_be_indexed(&OsmId::Relation(RelationId(100))));
+
+        assert_eq!(
+            change.deleted.iter().cloned().collect::<Vec<_>>(),
+            vec![OsmId::Node(NodeId(3)), OsmId::Way(WayId(11))]
+        );
+    }
+
+    #[test]
+    fn test_sequence_number_from_path() {
+        assert_eq!(
+            sequence_number_from_path(Path::new("/data/minute/004/123/456.osc.gz")),
+            Some(4_123_456)
+        );
+        assert_eq!(
+            sequence_number_from_path(Path::new("123/456.osc")),
+            Some(123_456)
+        );
+        assert_eq!(sequence_number_from_path(Path::new("42.osc")), Some(42));
+        assert_eq!(sequence_number_from_path(Path::new("changes.osc")), None);
+    }
+}
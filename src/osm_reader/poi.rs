@@ -33,7 +33,8 @@ use super::osm_utils::make_centroid;
 use super::OsmPbfReader;
 use crate::admin_geofinder::AdminGeoFinder;
 use crate::{labels, settings::osm2mimir::Settings, utils};
-use mimir::{rubber, Poi, PoiType};
+use mimir::backend::SearchBackend;
+use mimir::{Poi, PoiType};
 use osm_boundaries_utils::build_boundary;
 use serde::{Deserialize, Serialize};
 use slog_scope::{info, warn};
@@ -60,6 +61,9 @@ pub struct PoiConfig {
     #[serde(rename = "types")]
     pub poi_types: Vec<PoiType>,
     pub rules: Vec<Rule>,
+    /// Timezone of the POIs without a `timezone` tag, used to know if they are open
+    #[serde(default)]
+    pub timezone: Option<String>,
 }
 
 impl Default for PoiConfig {
@@ -115,6 +119,11 @@ impl PoiConfig {
                 );
             }
         }
+        if let Some(timezone) = &self.timezone {
+            timezone
+                .parse::<chrono_tz::Tz>()
+                .map_err(|err| format!("invalid timezone {:?}: {}", timezone, err))?;
+        }
         Ok(())
     }
 }
@@ -134,17 +143,16 @@ fn parse_poi(
     matcher: &PoiConfig,
     admins_geofinder: &AdminGeoFinder,
 ) -> Option<mimir::Poi> {
-    let poi_type = match matcher.get_poi_type(osmobj.tags()) {
-        Some(poi_type) => poi_type,
-        None => {
-            warn!(
-                "The poi {:?} has no tags even if it passes the filters",
-                osmobj.id()
-            );
-            return None;
-        }
-    };
-    let (id, coord) = match *osmobj {
+    let (id, coord) = get_poi_id_and_coord(osmobj, obj_map);
+    build_poi(osmobj, id, coord, matcher, admins_geofinder)
+}
+
+/// The id of the poi of `osmobj` and its coordinates, computed with the objects of `obj_map`
+pub(crate) fn get_poi_id_and_coord(
+    osmobj: &osmpbfreader::OsmObj,
+    obj_map: &BTreeMap<osmpbfreader::OsmId, osmpbfreader::OsmObj>,
+) -> (String, mimir::Coord) {
+    match *osmobj {
         osmpbfreader::OsmObj::Node(ref node) => (
             format_poi_id("node", node.id.0),
             mimir::Coord::new(node.lon(), node.lat()),
@@ -156,8 +164,26 @@ fn parse_poi(
             format_poi_id("relation", relation.id.0),
             make_centroid(&build_boundary(relation, obj_map)),
         ),
-    };
+    }
+}
 
+pub(crate) fn build_poi(
+    osmobj: &osmpbfreader::OsmObj,
+    id: String,
+    coord: mimir::Coord,
+    matcher: &PoiConfig,
+    admins_geofinder: &AdminGeoFinder,
+) -> Option<mimir::Poi> {
+    let poi_type = match matcher.get_poi_type(osmobj.tags()) {
+        Some(poi_type) => poi_type,
+        None => {
+            warn!(
+                "The poi {:?} has no tags even if it passes the filters",
+                osmobj.id()
+            );
+            return None;
+        }
+    };
     let name = osmobj
         .tags()
         .get("name")
@@ -177,6 +203,11 @@ fn parse_poi(
         _ => utils::get_zip_codes_from_admins(&adms),
     };
     let country_codes = utils::find_country_codes(adms.iter().map(|a| a.deref()));
+    let mut details =
+        mimir::PoiDetails::from_tags(osmobj.tags().iter().map(|(k, v)| (k.as_str(), v.as_str())));
+    if details.timezone.is_none() {
+        details.timezone = matcher.timezone.clone();
+    }
     Some(mimir::Poi {
         id,
         name: name.to_string(),
@@ -192,12 +223,14 @@ fn parse_poi(
         names: mimir::I18nProperties::default(),
         labels: mimir::I18nProperties::default(),
         distance: None,
+        confidence: None,
         country_codes,
         context: None,
+        details,
     })
 }
 
-fn format_poi_id(osm_type: &str, id: i64) -> String {
+pub(crate) fn format_poi_id(osm_type: &str, id: i64) -> String {
     format!("poi:osm:{}:{}", osm_type, id)
 }
 
@@ -225,9 +258,9 @@ pub fn compute_poi_weight(pois_vec: &mut [Poi]) {
     }
 }
 
-pub fn add_address(pois_vec: &mut [Poi], rubber: &mut rubber::Rubber) {
+pub fn add_address<B: SearchBackend>(pois_vec: &mut [Poi], backend: &mut B) {
     for poi in pois_vec {
-        poi.address = rubber
+        poi.address = backend
             .get_address(&poi.coord)
             .ok()
             .and_then(|addrs| addrs.into_iter().next())
@@ -417,4 +450,55 @@ mod tests {
             ],))
         );
     }
+    #[test]
+    fn check_timezone() {
+        let json = |timezone: &str| {
+            format!(
+                r#"{{"types": [], "rules": [], "timezone": "{}"}}"#,
+                timezone
+            )
+        };
+        from_str(&json("Europe/Paris")).unwrap();
+        from_str(&json("Europe/Lutece")).unwrap_err();
+    }
+    #[test]
+    fn poi_details() {
+        let mut c = PoiConfig::default();
+        c.timezone = Some("Europe/Paris".to_string());
+        let node = osmpbfreader::Node {
+            id: osmpbfreader::NodeId(42),
+            tags: tags(&[
+                ("amenity", "post_office"),
+                ("name", "La Poste"),
+                ("opening_hours", "Mo-Fr 09:00-18:00; Sa 09:00-12:00"),
+                ("contact:phone", "+33 1 23 45 67 89"),
+                ("website", "https://www.laposte.fr"),
+                ("wheelchair", "limited"),
+                ("operator", "La Poste"),
+            ]),
+            decimicro_lat: 488_000_000,
+            decimicro_lon: 23_000_000,
+        };
+        let osmobj = osmpbfreader::OsmObj::Node(node);
+        let poi = build_poi(
+            &osmobj,
+            "poi:osm:node:42".to_string(),
+            mimir::Coord::new(2.3, 48.8),
+            &c,
+            &AdminGeoFinder::default(),
+        )
+        .unwrap();
+        let details = poi.details;
+        assert_eq!(
+            details.opening_hours.as_deref(),
+            Some("Mo-Fr 09:00-18:00; Sa 09:00-12:00")
+        );
+        assert_eq!(details.schedule.unwrap().rules.len(), 2);
+        assert_eq!(details.phone.as_deref(), Some("+33 1 23 45 67 89"));
+        assert_eq!(details.website.as_deref(), Some("https://www.laposte.fr"));
+        assert_eq!(details.wheelchair, Some(mimir::Wheelchair::Limited));
+        assert_eq!(details.brand, None);
+        assert_eq!(details.operator.as_deref(), Some("La Poste"));
+        assert_eq!(details.timezone.as_deref(), Some("Europe/Paris"));
+    }
 }
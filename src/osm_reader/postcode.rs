@@ -0,0 +1,190 @@
+// Copyright © 2016, Canal TP and/or its affiliates. All rights reserved.
+//
+// This file is part of Navitia,
+//     the software to build cool stuff with public transport.
+//
+// Hope you'll enjoy and contribute to this project,
+//     powered by Canal TP (www.canaltp.fr).
+// Help us simplify mobility and open public transport:
+//     a non ending quest to the responsive locomotion way of traveling!
+//
+// LICENCE: This program is free software; you can redistribute it
+// and/or modify it under the terms of the GNU Affero General Public
+// License as published by the Free Software Foundation, either
+// version 3 of the License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+// Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public
+// License along with this program. If not, see
+// <http://www.gnu.org/licenses/>.
+//
+// Stay tuned using
+// twitter @navitia
+// IRC #navitia on freenode
+// https://groups.google.com/d/forum/navitia
+// www.navitia.io
+
+use super::osm_utils::make_centroid;
+use super::OsmPbfReader;
+use crate::admin_geofinder::AdminGeoFinder;
+use crate::{labels, utils, Error};
+use failure::ResultExt;
+use geo::bounding_rect::BoundingRect;
+use geo::prelude::{Area, ConvexHull};
+use geo::{MultiPoint, MultiPolygon, Point};
+use osm_boundaries_utils::build_boundary;
+use slog_scope::{info, warn};
+use std::collections::{BTreeMap, BTreeSet};
+use std::ops::Deref;
+use std::sync::Arc;
+
+fn is_postcode(obj: &osmpbfreader::OsmObj) -> bool {
+    match *obj {
+        osmpbfreader::OsmObj::Relation(ref rel) => rel
+            .tags
+            .get("boundary")
+            .map_or(false, |v| v == "postal_code"),
+        _ => false,
+    }
+}
+
+/// Read the postcodes from the `boundary=postal_code` relations, their code being
+/// in the `postal_code` tag (or `ref`).
+pub fn postcodes(
+    pbf: &mut OsmPbfReader,
+    admins_geofinder: &AdminGeoFinder,
+) -> Result<Vec<mimir::Postcode>, Error> {
+    let objects = pbf
+        .get_objs_and_deps(is_postcode)
+        .context("Error occurred when reading pbf")?;
+
+    let mut postcodes = vec![];
+    for obj in objects.values() {
+        let relation = match obj {
+            osmpbfreader::OsmObj::Relation(rel) if is_postcode(obj) => rel,
+            _ => continue,
+        };
+        let code = match relation
+            .tags
+            .get("postal_code")
+            .or_else(|| relation.tags.get("ref"))
+        {
+            Some(code) => code,
+            None => {
+                warn!("relation/{}: postcode without code, skipped", relation.id.0);
+                continue;
+            }
+        };
+        let boundary = build_boundary(relation, &objects);
+        if boundary.is_none() {
+            warn!(
+                "relation/{} ({}): postcode without valid boundary, skipped",
+                relation.id.0, code
+            );
+            continue;
+        }
+        let coord = make_centroid(&boundary);
+        postcodes.push(build_postcode(
+            format!("postcode:osm:relation:{}", relation.id.0),
+            code.to_string(),
+            coord,
+            boundary,
+            admins_geofinder.get(&coord),
+        ));
+    }
+    info!("{} postcodes read from the relations", postcodes.len());
+    Ok(postcodes)
+}
+
+/// Build the postcodes of the `addresses` that are not in `known_postcodes`, their boundary
+/// being the convex hull of the addresses.
+///
+/// Only the addresses with a single postcode are used, and the postcodes of different countries
+/// are kept apart. Without at least three addresses that are not aligned, the postcode has no
+/// boundary and its coord is the center of its addresses.
+pub fn postcodes_from_addresses(
+    addresses: &[mimir::Addr],
+    known_postcodes: &[mimir::Postcode],
+    admins_geofinder: &AdminGeoFinder,
+) -> Vec<mimir::Postcode> {
+    let known = known_postcodes
+        .iter()
+        .map(|p| (p.country_codes.first().cloned(), p.name.clone()))
+        .collect::<BTreeSet<_>>();
+
+    let mut points_by_postcode = BTreeMap::<_, Vec<Point<f64>>>::new();
+    for addr in addresses {
+        let code = match addr.zip_codes.as_slice() {
+            [code] => code,
+            _ => continue,
+        };
+        let key = (addr.country_codes.first().cloned(), code.clone());
+        if known.contains(&key) {
+            continue;
+        }
+        points_by_postcode
+            .entry(key)
+            .or_default()
+            .push(Point::from(addr.coord.0));
+    }
+
+    points_by_postcode
+        .into_iter()
+        .map(|((country, code), points)| {
+            let nb_points = points.len() as f64;
+            let (sum_x, sum_y) = points
+                .iter()
+                .fold((0., 0.), |(x, y), p| (x + p.x(), y + p.y()));
+            let hull = MultiPoint(points).convex_hull();
+            let boundary = if hull.unsigned_area() > 0. {
+                Some(MultiPolygon(vec![hull]))
+            } else {
+                None
+            };
+            let coord = if boundary.is_some() {
+                make_centroid(&boundary)
+            } else {
+                mimir::Coord::new(sum_x / nb_points, sum_y / nb_points)
+            };
+            let id = match &country {
+                Some(country) => format!("postcode:{}:{}", country.to_lowercase(), code),
+                None => format!("postcode:{}", code),
+            };
+            build_postcode(id, code, coord, boundary, admins_geofinder.get(&coord))
+        })
+        .collect()
+}
+
+fn build_postcode(
+    id: String,
+    code: String,
+    coord: mimir::Coord,
+    boundary: Option<MultiPolygon<f64>>,
+    admins: Vec<Arc<mimir::Admin>>,
+) -> mimir::Postcode {
+    let admins_iter = admins.iter().map(Deref::deref);
+    let country_codes = utils::find_country_codes(admins_iter.clone());
+    mimir::Postcode {
+        id,
+        label: labels::format_postcode_label(&code, admins_iter, &country_codes),
+        zip_codes: vec![code.clone()],
+        name: code,
+        weight: admins
+            .iter()
+            .find(|admin| admin.is_city())
+            .map_or(0., |city| city.weight),
+        administrative_regions: admins,
+        coord,
+        approx_coord: Some(coord.into()),
+        bbox: boundary.as_ref().and_then(|b| b.bounding_rect()),
+        boundary,
+        country_codes,
+        distance: None,
+        confidence: None,
+        context: None,
+    }
+}
@@ -39,9 +39,10 @@ use crate::admin_geofinder::AdminGeoFinder;
 use crate::{labels, settings, utils, Error};
 use cosmogony::ZoneType;
 use failure::ResultExt;
-use osmpbfreader::{OsmId, StoreObjs};
+use mimir::interpolation::{HouseNumberRange, InterpolationKind};
+use osmpbfreader::{NodeId, OsmId, StoreObjs};
 use slog_scope::info;
-use std::collections::{BTreeMap, HashMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::ops::Deref;
 use std::sync::Arc;
 
@@ -59,40 +60,14 @@ pub fn streets(
     pbf: &mut OsmPbfReader,
     admins_geofinder: &AdminGeoFinder,
     settings: &settings::osm2mimir::Settings,
-) -> Result<Vec<mimir::Street>, Error> {
-    let invalid_highways = settings
-        .street
-        .as_ref()
-        .and_then(|street| street.exclusion.highway.as_deref())
-        .unwrap_or(&[]);
-
-    let is_valid_highway = |tag: &str| -> bool { !invalid_highways.iter().any(|k| k == tag) };
-
-    let invalid_public_transports = settings
-        .street
-        .as_ref()
-        .and_then(|street| street.exclusion.public_transport.as_deref())
-        .unwrap_or(&[]);
-
-    let is_valid_public_transport =
-        |tag: &str| -> bool { !invalid_public_transports.iter().any(|k| k == tag) };
-
+) -> Result<(Vec<mimir::Street>, Vec<mimir::Intersection>), Error> {
     // For the object to be a valid street, it needs to be an osm highway of a valid type,
     // or a relation of type associatedStreet.
+    // The interpolation lines are also read, to get the house number ranges of the streets.
     let is_valid_obj = |obj: &osmpbfreader::OsmObj| -> bool {
         match *obj {
-            osmpbfreader::OsmObj::Way(ref way) => {
-                let has_valid_highway_tag = way
-                    .tags
-                    .get("highway")
-                    .map_or(false, |v| !v.is_empty() && is_valid_highway(v));
-                let has_no_excluded_public_transport_tag = way
-                    .tags
-                    .get("public_transport")
-                    .map_or(true, |v| is_valid_public_transport(v));
-                let has_valid_name_tag = way.tags.get("name").map_or(false, |v| !v.is_empty());
-                has_valid_name_tag && has_valid_highway_tag && has_no_excluded_public_transport_tag
-            }
+            osmpbfreader::OsmObj::Way(ref way) if is_interpolation(way) => true,
+            osmpbfreader::OsmObj::Way(ref way) => is_street(way, settings),
             osmpbfreader::OsmObj::Relation(ref rel) => rel
                 .tags
                 .get("type")
@@ -111,26 +86,6 @@ pub fn streets(
         .context("Error occurred when reading pbf")?;
     info!("reading pbf done.");
 
-    // Builder for street object
-    let build_street =
-        |id: String, name: String, coord: mimir::Coord, admins: Vec<Arc<mimir::Admin>>| {
-            let admins_iter = admins.iter().map(Deref::deref);
-            let country_codes = utils::find_country_codes(admins_iter.clone());
-            mimir::Street {
-                id,
-                label: labels::format_street_label(&name, admins_iter, &country_codes),
-                name,
-                weight: 0.,
-                zip_codes: utils::get_zip_codes_from_admins(&admins),
-                administrative_regions: admins,
-                coord,
-                approx_coord: Some(coord.into()),
-                distance: None,
-                country_codes,
-                context: None,
-            }
-        };
-
     // Return an iterator giving documents that will be inserted for a given
     // street: one for each hierarchy of admins.
     let build_streets_for_admins =
@@ -207,7 +162,7 @@ pub fn streets(
         let osmid = obj.id();
         let way = obj.way().expect("invalid way filter");
 
-        if street_in_relation.contains(&osmid) {
+        if street_in_relation.contains(&osmid) || is_interpolation(way) {
             return;
         }
 
@@ -263,7 +218,295 @@ pub fn streets(
             .flatten(),
     );
 
-    Ok(street_list)
+    let ranges = house_number_ranges(admins_geofinder, &objs_map);
+    let mut streets_with_ranges = HashSet::new();
+    for street in &mut street_list {
+        let key = street
+            .administrative_regions
+            .iter()
+            .find(|admin| admin.is_city())
+            .map(|city| (street.name.clone(), city.id.to_string()));
+        if let Some((key, street_ranges)) = key.and_then(|k| ranges.get_key_value(&k)) {
+            street.house_number_ranges = street_ranges.clone();
+            streets_with_ranges.insert(key);
+        }
+    }
+    let nb_ignored_ranges: usize = ranges
+        .iter()
+        .filter(|(key, _)| !streets_with_ranges.contains(key))
+        .map(|(_, street_ranges)| street_ranges.len())
+        .sum();
+    if nb_ignored_ranges > 0 {
+        info!(
+            "{} house number ranges without street have been ignored",
+            nb_ignored_ranges
+        );
+    }
+
+    let import_intersections = settings
+        .intersection
+        .as_ref()
+        .map_or(false, |intersection| intersection.import);
+    let intersections = if import_intersections {
+        intersections(admins_geofinder, &objs_map, settings)
+    } else {
+        vec![]
+    };
+
+    Ok((street_list, intersections))
+}
+
+/// The intersections of the streets with different names, at the nodes shared by their ways.
+///
+/// Two streets can cross at several nodes (around a roundabout, or with dual carriageways),
+/// only the node of smallest id is kept in each city.
+fn intersections(
+    admins_geofinder: &AdminGeoFinder,
+    objs_map: &ObjWrapper,
+    settings: &settings::osm2mimir::Settings,
+) -> Vec<mimir::Intersection> {
+    // the names of the streets of each node
+    let mut names_by_node: HashMap<NodeId, BTreeSet<String>> = HashMap::new();
+    objs_map.for_each_filter(Kind::Way, |obj| {
+        let way = obj.way().expect("invalid way filter");
+        if !is_street(way, settings) {
+            return;
+        }
+        if let Some(name) = way.tags.get("name") {
+            for node_id in &way.nodes {
+                names_by_node
+                    .entry(*node_id)
+                    .or_default()
+                    .insert(name.to_string());
+            }
+        }
+    });
+
+    let mut crossings = BTreeMap::new();
+    for (node_id, names) in names_by_node
+        .into_iter()
+        .filter(|(_, names)| names.len() > 1)
+    {
+        let coord = match objs_map.get(&node_id.into()) {
+            Some(node_obj) => match node_obj.node() {
+                Some(node) => mimir::Coord::new(node.lon(), node.lat()),
+                None => continue,
+            },
+            None => continue,
+        };
+        let admins = admins_geofinder.get(&coord);
+        let city = match admins.iter().find(|admin| admin.is_city()) {
+            Some(city) => city.id.to_string(),
+            None => continue,
+        };
+        let names = names.into_iter().collect::<Vec<_>>();
+        for (i, first) in names.iter().enumerate() {
+            for second in &names[i + 1..] {
+                crossings
+                    .entry((first.clone(), second.clone(), city.clone()))
+                    .and_modify(|(stored_id, stored_coord, stored_admins)| {
+                        if *stored_id > node_id {
+                            *stored_id = node_id;
+                            *stored_coord = coord;
+                            *stored_admins = admins.clone();
+                        }
+                    })
+                    .or_insert_with(|| (node_id, coord, admins.clone()));
+            }
+        }
+    }
+
+    // a node can be the intersection of more than two streets
+    let mut nb_by_node = HashMap::new();
+    crossings
+        .into_iter()
+        .map(|((first, second, _), (node_id, coord, admins))| {
+            let nb = nb_by_node.entry(node_id).or_insert(0);
+            let id = if *nb == 0 {
+                format!("intersection:osm:node:{}", node_id.0)
+            } else {
+                format!("intersection:osm:node:{}-{}", node_id.0, nb)
+            };
+            *nb += 1;
+            build_intersection(id, vec![first, second], coord, admins)
+        })
+        .collect()
+}
+
+/// Build the intersection document `id` of the streets `street_names`
+fn build_intersection(
+    id: String,
+    street_names: Vec<String>,
+    coord: mimir::Coord,
+    admins: Vec<Arc<mimir::Admin>>,
+) -> mimir::Intersection {
+    let name = street_names.join(" / ");
+    let admins_iter = admins.iter().map(Deref::deref);
+    let country_codes = utils::find_country_codes(admins_iter.clone());
+    mimir::Intersection {
+        id,
+        label: labels::format_street_label(&name, admins_iter, &country_codes),
+        name,
+        street_names,
+        weight: admins
+            .iter()
+            .find(|admin| admin.is_city())
+            .map_or(0., |city| city.weight),
+        zip_codes: utils::get_zip_codes_from_admins(&admins),
+        administrative_regions: admins,
+        coord,
+        approx_coord: Some(coord.into()),
+        distance: None,
+        confidence: None,
+        country_codes,
+        context: None,
+    }
+}
+
+/// A way is a street if it is a named osm highway of a valid type, without any excluded
+/// public_transport tag.
+pub(crate) fn is_street(
+    way: &osmpbfreader::objects::Way,
+    settings: &settings::osm2mimir::Settings,
+) -> bool {
+    let exclusion = settings.street.as_ref().map(|street| &street.exclusion);
+    let invalid_highways = exclusion
+        .and_then(|exclusion| exclusion.highway.as_deref())
+        .unwrap_or(&[]);
+    let invalid_public_transports = exclusion
+        .and_then(|exclusion| exclusion.public_transport.as_deref())
+        .unwrap_or(&[]);
+
+    let is_valid_highway = |tag: &str| -> bool { !invalid_highways.iter().any(|k| k == tag) };
+    let is_valid_public_transport =
+        |tag: &str| -> bool { !invalid_public_transports.iter().any(|k| k == tag) };
+
+    let has_valid_highway_tag = way
+        .tags
+        .get("highway")
+        .map_or(false, |v| !v.is_empty() && is_valid_highway(v));
+    let has_no_excluded_public_transport_tag = way
+        .tags
+        .get("public_transport")
+        .map_or(true, |v| is_valid_public_transport(v));
+    let has_valid_name_tag = way.tags.get("name").map_or(false, |v| !v.is_empty());
+    has_valid_name_tag && has_valid_highway_tag && has_no_excluded_public_transport_tag
+}
+
+/// Build the street document `id`, with its label computed from its admins
+pub(crate) fn build_street(
+    id: String,
+    name: String,
+    coord: mimir::Coord,
+    admins: Vec<Arc<mimir::Admin>>,
+) -> mimir::Street {
+    let admins_iter = admins.iter().map(Deref::deref);
+    let country_codes = utils::find_country_codes(admins_iter.clone());
+    mimir::Street {
+        id,
+        label: labels::format_street_label(&name, admins_iter, &country_codes),
+        name,
+        weight: 0.,
+        zip_codes: utils::get_zip_codes_from_admins(&admins),
+        administrative_regions: admins,
+        coord,
+        approx_coord: Some(coord.into()),
+        distance: None,
+        confidence: None,
+        country_codes,
+        context: None,
+        house_number_ranges: vec![],
+    }
+}
+
+fn is_interpolation(way: &osmpbfreader::objects::Way) -> bool {
+    way.tags
+        .get("addr:interpolation")
+        .map_or(false, |v| InterpolationKind::from_osm_tag(v).is_some())
+}
+
+/// Read the house number ranges of the interpolation lines (ways tagged with
+/// `addr:interpolation`), by street name and city id.
+///
+/// A range is built between each pair of consecutive nodes of the line that have a house number.
+/// The name of the street is the `addr:street` tag of the nodes or of the line.
+fn house_number_ranges(
+    admins_geofinder: &AdminGeoFinder,
+    obj_map: &ObjWrapper,
+) -> HashMap<(String, String), Vec<HouseNumberRange>> {
+    let mut ranges: HashMap<_, Vec<_>> = HashMap::new();
+
+    obj_map.for_each_filter(Kind::Way, |obj| {
+        let way = match obj.way() {
+            Some(way) if is_interpolation(way) => way,
+            _ => return,
+        };
+        let kind = match way
+            .tags
+            .get("addr:interpolation")
+            .and_then(|v| InterpolationKind::from_osm_tag(v))
+        {
+            Some(kind) => kind,
+            None => return,
+        };
+        let city = match get_street_admin(admins_geofinder, obj_map, way)
+            .into_iter()
+            .flatten()
+            .find(|admin| admin.is_city())
+        {
+            Some(city) => city.id.to_string(),
+            None => return,
+        };
+
+        let mut street_name = way.tags.get("addr:street").map(|s| s.to_string());
+        // the house number (if any) and the coord of each node of the line
+        let mut nodes = vec![];
+        for node_id in &way.nodes {
+            let node_obj = match obj_map.get(&(*node_id).into()) {
+                Some(node_obj) => node_obj,
+                None => return,
+            };
+            let node = match node_obj.node() {
+                Some(node) => node,
+                None => return,
+            };
+            let house_number = node
+                .tags
+                .get("addr:housenumber")
+                .and_then(|n| n.trim().parse::<u32>().ok());
+            if street_name.is_none() {
+                street_name = node.tags.get("addr:street").map(|s| s.to_string());
+            }
+            nodes.push((house_number, mimir::Coord::new(node.lon(), node.lat())));
+        }
+        let street_name = match street_name {
+            Some(name) => name,
+            None => return,
+        };
+
+        let mut start: Option<(u32, Vec<mimir::Coord>)> = None;
+        for (house_number, coord) in nodes {
+            if let Some((_, line)) = start.as_mut() {
+                line.push(coord);
+            }
+            if let Some(number) = house_number {
+                if let Some((start_number, line)) = start.take() {
+                    ranges
+                        .entry((street_name.clone(), city.clone()))
+                        .or_default()
+                        .push(HouseNumberRange {
+                            start: start_number,
+                            end: number,
+                            kind,
+                            line,
+                        });
+                }
+                start = Some((number, vec![coord]));
+            }
+        }
+    });
+
+    ranges
 }
 
 /// Returns branches of admins encompassing the street `way`.
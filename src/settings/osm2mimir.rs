@@ -1,5 +1,6 @@
 use config::{Config, ConfigError, File, FileFormat, Source, Value};
 use failure::ResultExt;
+use mimir::backend::BackendKind;
 use serde::Deserialize;
 use slog_scope::{info, warn};
 use std::collections::HashMap;
@@ -22,6 +23,26 @@ pub struct Street {
     pub exclusion: StreetExclusion,
 }
 
+#[derive(Debug, Clone, Deserialize)]
+pub struct Address {
+    pub import: bool,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Intersection {
+    pub import: bool,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Postcode {
+    pub import: bool,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Locality {
+    pub import: bool,
+}
+
 #[derive(Debug, Clone, Deserialize)]
 pub struct Admin {
     pub import: bool,
@@ -52,6 +73,8 @@ pub struct Elasticsearch {
     pub admins_replicas: usize,
     pub pois_shards: usize,
     pub pois_replicas: usize,
+    pub addresses_shards: usize,
+    pub addresses_replicas: usize,
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -61,6 +84,10 @@ pub struct Settings {
     pub database: Option<Database>,
     pub elasticsearch: Elasticsearch,
     pub street: Option<Street>,
+    pub address: Option<Address>,
+    pub intersection: Option<Intersection>,
+    pub postcode: Option<Postcode>,
+    pub locality: Option<Locality>,
     pub poi: Option<Poi>,
     pub admin: Option<Admin>,
 }
@@ -176,9 +203,20 @@ impl Settings {
 
 #[derive(StructOpt, Clone, Debug)]
 pub struct Args {
-    /// OSM PBF file.
+    /// OSM PBF file, or osmChange file (.osc or .osc.gz) with --update.
     #[structopt(short = "i", long = "input", parse(from_os_str))]
     pub input: PathBuf,
+    /// Apply the osmChange diff of the input to the published indexes, instead of
+    /// importing a PBF file.
+    #[structopt(long = "update")]
+    pub update: bool,
+    /// File recording the sequence number of the last applied diff (in the format of the
+    /// state.txt files of the OSM replication), to chain the updates.
+    #[structopt(long = "state-file", parse(from_os_str))]
+    pub state_file: Option<PathBuf>,
+    /// Sequence number of the diff, deduced from the path of the input by default.
+    #[structopt(long = "sequence-number")]
+    pub sequence_number: Option<u64>,
     /// Admin levels to keep.
     #[structopt(short = "l", long = "level")]
     level: Option<Vec<u32>>,
@@ -188,6 +226,13 @@ pub struct Args {
     /// Elasticsearch parameters.
     #[structopt(short = "c", long = "connection-string")]
     connection_string: Option<String>,
+    /// Search engine of the connection string: `es2` (Elasticsearch 2) or `rest`
+    /// (Elasticsearch 7+ or OpenSearch).
+    #[structopt(long = "backend", default_value = "es2")]
+    pub backend: BackendKind,
+    /// Directory of an embedded index to import into, instead of the search engine.
+    #[structopt(long = "embedded-index", parse(from_os_str))]
+    pub embedded_index: Option<PathBuf>,
     /// Import ways.
     #[structopt(short = "w", long = "import-way")]
     import_way: Option<bool>,
@@ -197,6 +242,18 @@ pub struct Args {
     /// Import POIs.
     #[structopt(short = "p", long = "import-poi")]
     import_poi: Option<bool>,
+    /// Import addresses.
+    #[structopt(long = "import-address")]
+    import_address: Option<bool>,
+    /// Import the intersections of the streets.
+    #[structopt(long = "import-intersection")]
+    import_intersection: Option<bool>,
+    /// Import the postcodes.
+    #[structopt(long = "import-postcode")]
+    import_postcode: Option<bool>,
+    /// Import the localities (hamlets, lieux-dits...).
+    #[structopt(long = "import-locality")]
+    import_locality: Option<bool>,
     /// Name of the dataset.
     #[structopt(short = "d", long = "dataset")]
     pub dataset: Option<String>,
@@ -218,6 +275,12 @@ pub struct Args {
     /// Number of replicas for the es index
     #[structopt(long = "nb-poi-replicas")]
     nb_poi_replicas: Option<usize>,
+    /// Number of shards for the address es index
+    #[structopt(long = "nb-address-shards")]
+    nb_address_shards: Option<usize>,
+    /// Number of replicas for the address es index
+    #[structopt(long = "nb-address-replicas")]
+    nb_address_replicas: Option<usize>,
     /// If you use this option by providing a filename, then we
     /// will use a SQlite database that will be persisted. You
     /// can only do that if osm2mimir was compiled with the
@@ -313,6 +376,38 @@ impl Source for Args {
             m.insert(String::from("poi.import"), Value::new(None, import_poi));
         }
 
+        // ADDRESS
+        if let Some(import_address) = self.import_address {
+            m.insert(
+                String::from("address.import"),
+                Value::new(None, import_address),
+            );
+        }
+
+        // INTERSECTION
+        if let Some(import_intersection) = self.import_intersection {
+            m.insert(
+                String::from("intersection.import"),
+                Value::new(None, import_intersection),
+            );
+        }
+
+        // POSTCODE
+        if let Some(import_postcode) = self.import_postcode {
+            m.insert(
+                String::from("postcode.import"),
+                Value::new(None, import_postcode),
+            );
+        }
+
+        // LOCALITY
+        if let Some(import_locality) = self.import_locality {
+            m.insert(
+                String::from("locality.import"),
+                Value::new(None, import_locality),
+            );
+        }
+
         // ELASTICSEARCH SETTINGS
 
         if let Some(connection_string) = self.connection_string.clone() {
@@ -382,6 +477,36 @@ impl Source for Args {
             );
         }
 
+        if let Some(nb_address_shards) = self.nb_address_shards {
+            m.insert(
+                String::from("elasticsearch.addresses_shards"),
+                Value::new(
+                    None,
+                    i64::try_from(nb_address_shards).map_err(|e| {
+                        ConfigError::Message(format!(
+                            "Could not convert count of address shards to integer: {}",
+                            e
+                        ))
+                    })?,
+                ),
+            );
+        }
+
+        if let Some(nb_address_replicas) = self.nb_address_replicas {
+            m.insert(
+                String::from("elasticsearch.addresses_replicas"),
+                Value::new(
+                    None,
+                    i64::try_from(nb_address_replicas).map_err(|e| {
+                        ConfigError::Message(format!(
+                            "Could not convert count of address replicas to integer: {}",
+                            e
+                        ))
+                    })?,
+                ),
+            );
+        }
+
         if let Some(nb_admin_shards) = self.nb_admin_shards {
             m.insert(
                 String::from("elasticsearch.admins_shards"),
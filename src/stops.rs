@@ -32,6 +32,8 @@ use crate::admin_geofinder::AdminGeoFinder;
 use crate::{labels, utils};
 use failure::format_err;
 use failure::{Error, ResultExt};
+use mimir::backend::{BackendKind, SearchBackend};
+use mimir::rest_backend::RestBackend;
 use mimir::rubber::{IndexSettings, Rubber, TypedIndex};
 use slog_scope::{info, warn};
 use std::collections::HashMap;
@@ -58,16 +60,38 @@ pub fn initialize_weights<'a, It, S: ::std::hash::BuildHasher>(
 }
 
 pub fn import_stops(
-    mut stops: Vec<mimir::Stop>,
+    stops: Vec<mimir::Stop>,
     connection_string: &str,
+    backend: BackendKind,
+    dataset: &str,
+    index_settings: IndexSettings,
+) -> Result<(), Error> {
+    match backend {
+        BackendKind::Es2 => index_stops(
+            stops,
+            Rubber::new(connection_string),
+            dataset,
+            index_settings,
+        ),
+        BackendKind::Rest => index_stops(
+            stops,
+            RestBackend::new(connection_string)?,
+            dataset,
+            index_settings,
+        ),
+    }
+}
+
+fn index_stops<B: SearchBackend>(
+    mut stops: Vec<mimir::Stop>,
+    mut backend: B,
     dataset: &str,
     index_settings: IndexSettings,
 ) -> Result<(), Error> {
     info!("creation of indexes");
-    let mut rubber = Rubber::new(connection_string);
-    rubber.initialize_templates()?;
+    backend.initialize_templates()?;
 
-    attach_stops_to_admins(stops.iter_mut(), &mut rubber);
+    attach_stops_to_admins(stops.iter_mut(), &mut backend);
 
     for stop in &mut stops {
         stop.coverages.push(dataset.to_string());
@@ -88,13 +112,13 @@ pub fn import_stops(
     }
 
     let global_index =
-        update_global_stop_index(&mut rubber, stops.iter(), dataset, &index_settings)?;
+        update_global_stop_index(&mut backend, stops.iter(), dataset, &index_settings)?;
 
     info!("Importing {} stops into Mimir", stops.len());
-    let nb_stops = rubber.public_index(dataset, &index_settings, stops.into_iter())?;
+    let nb_stops = backend.public_index(dataset, &index_settings, stops.into_iter())?;
     info!("Nb of indexed stops: {}", nb_stops);
 
-    publish_global_index(&mut rubber, &global_index)
+    publish_global_index(&mut backend, &global_index)
         .context("Error while publishing global index")?;
     Ok(())
 }
@@ -115,11 +139,11 @@ fn attach_stop(stop: &mut mimir::Stop, admins: Vec<Arc<mimir::Admin>>) {
 /// The admins are loaded from Elasticsearch and stored in a quadtree
 /// We attach a stop with all the admins that have a boundary containing
 /// the coordinate of the stop
-fn attach_stops_to_admins<'a, It: Iterator<Item = &'a mut mimir::Stop>>(
+fn attach_stops_to_admins<'a, It: Iterator<Item = &'a mut mimir::Stop>, B: SearchBackend>(
     stops: It,
-    rubber: &mut Rubber,
+    backend: &mut B,
 ) {
-    let admins = rubber.get_all_admins().unwrap_or_else(|_| {
+    let admins = backend.get_all_admins().unwrap_or_else(|_| {
         warn!("Administratives regions not found in elasticsearch db");
         vec![]
     });
@@ -186,27 +210,30 @@ fn merge_stops<It: IntoIterator<Item = mimir::Stop>>(
     stops_by_id.into_iter().map(|(_, v)| v)
 }
 
-fn get_all_stops(rubber: &mut Rubber, index: String) -> Result<Vec<mimir::Stop>, Error> {
-    rubber
-        .get_all_objects_from_index(&index)
+fn get_all_stops<B: SearchBackend>(
+    backend: &mut B,
+    index: String,
+) -> Result<Vec<mimir::Stop>, Error> {
+    backend
+        .get_all_objects(&index)
         .map_err(|e| format_err!("Getting all stops {}", e.to_string()))
 }
 
-fn update_global_stop_index<'a, It: Iterator<Item = &'a mimir::Stop>>(
-    rubber: &mut Rubber,
+fn update_global_stop_index<'a, It: Iterator<Item = &'a mimir::Stop>, B: SearchBackend>(
+    backend: &mut B,
     stops: It,
     dataset: &str,
     index_settings: &IndexSettings,
 ) -> Result<String, Error> {
     let dataset_index = mimir::rubber::get_main_type_and_dataset_index::<mimir::Stop>(dataset);
-    let stops_indexes = rubber
+    let stops_indexes = backend
         .get_all_aliased_index(&mimir::rubber::get_main_type_index::<mimir::Stop>())?
         .into_iter()
         .filter(|&(_, ref aliases)| !aliases.contains(&dataset_index))
         .map(|(index, _)| index);
 
     let all_es_stops = stops_indexes
-        .map(|index| get_all_stops(rubber, index))
+        .map(|index| get_all_stops(backend, index))
         .collect::<Result<Vec<_>, _>>()?
         .into_iter()
         .flat_map(|stops| stops.into_iter())
@@ -215,10 +242,10 @@ fn update_global_stop_index<'a, It: Iterator<Item = &'a mimir::Stop>>(
     let all_merged_stops = merge_stops(all_es_stops);
     let es_index_name = mimir::rubber::get_date_index_name(GLOBAL_STOP_INDEX_NAME);
 
-    rubber.create_index(&es_index_name, &index_settings)?;
+    backend.create_index(&es_index_name, &index_settings)?;
     let typed_index = TypedIndex::new(es_index_name.clone());
 
-    let nb_stops_added = rubber.bulk_index(&typed_index, all_merged_stops)?;
+    let nb_stops_added = backend.bulk_index(&typed_index, all_merged_stops)?;
     info!("{} stops added in the global index", nb_stops_added);
     // create global index
     // fill structure for each stop indexes
@@ -227,21 +254,24 @@ fn update_global_stop_index<'a, It: Iterator<Item = &'a mimir::Stop>>(
 
 // publish the global stop index
 // alias the new index to the global stop alias, and remove the old index
-fn publish_global_index(rubber: &mut Rubber, new_global_index: &str) -> Result<(), Error> {
-    let last_global_indexes: Vec<_> = rubber
+fn publish_global_index<B: SearchBackend>(
+    backend: &mut B,
+    new_global_index: &str,
+) -> Result<(), Error> {
+    let last_global_indexes: Vec<_> = backend
         .get_all_aliased_index(GLOBAL_STOP_INDEX_NAME)?
         .into_iter()
         .map(|(k, _)| k)
         .filter(|k| k != new_global_index)
         .collect();
-    rubber.alias(
+    backend.alias(
         GLOBAL_STOP_INDEX_NAME,
         &[new_global_index.to_string()],
         &last_global_indexes,
     )?;
 
     for index in last_global_indexes {
-        rubber.delete_index(&index)?;
+        backend.delete_index(&index)?;
     }
     Ok(())
 }
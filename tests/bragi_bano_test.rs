@@ -28,10 +28,13 @@
 // https://groups.google.com/d/forum/navitia
 // www.navitia.io
 
+use super::get_types;
 use super::get_values;
 use super::BragiHandler;
 use serde_json::json;
 use std::path::Path;
+#[cfg(feature = "eval")]
+use std::process::Command;
 
 pub fn bragi_bano_test(es_wrapper: crate::ElasticSearchWrapper<'_>) {
     let mut bragi = BragiHandler::new(es_wrapper.host());
@@ -54,10 +57,15 @@ pub fn bragi_bano_test(es_wrapper: crate::ElasticSearchWrapper<'_>) {
 
     status_test(&mut bragi);
     simple_bano_autocomplete_test(&mut bragi);
+    parsed_query_bano_test(&mut bragi);
     simple_bano_shape_filter_test(&mut bragi);
     simple_bano_lon_lat_test(&mut bragi);
     long_bano_address_test(&mut bragi);
     reverse_bano_test(&mut bragi);
+    coordinate_bano_test(&mut bragi);
+    batch_bano_test(&mut bragi);
+    #[cfg(feature = "eval")]
+    eval_bano_test(&es_wrapper);
 }
 
 fn status_test(bragi: &mut BragiHandler) {
@@ -96,11 +104,13 @@ fn simple_bano_autocomplete_test(bragi: &mut BragiHandler) {
                             "type": "house"
                         }
                     },
-                    "type": "Feature"
+                    "type": "Feature",
+                    "confidence": 1.0
                 }
             ],
             "geocoding": {
                 "query": "",
+                "match_stage": "prefix",
                 "version": "0.1.0"
             },
             "type": "FeatureCollection"
@@ -109,6 +119,24 @@ fn simple_bano_autocomplete_test(bragi: &mut BragiHandler) {
     );
 }
 
+fn parsed_query_bano_test(bragi: &mut BragiHandler) {
+    // the components of the address recognized in the query are given with _debug
+    let result = bragi.get_json("/autocomplete?q=15 bis Rue Hector Malot 75012&_debug=true");
+    let parsed_query = result.pointer("/geocoding/parsed_query").unwrap();
+    assert_eq!(parsed_query["house_number"], json!("15 bis"));
+    assert_eq!(parsed_query["street_type"], json!("rue"));
+    assert_eq!(parsed_query["street_name"], json!("hector malot"));
+    assert_eq!(parsed_query["postcode"], json!("75012"));
+    assert_eq!(
+        parsed_query["words"][0],
+        json!({"word": "15", "component": "house_number"})
+    );
+
+    // and not given without _debug
+    let result = bragi.get_json("/autocomplete?q=15 Rue Hector Malot 75012");
+    assert!(result.pointer("/geocoding/parsed_query").is_none());
+}
+
 // A(48.846431 2.376488)
 // B(48.846430 2.376306)
 // C(48.846606 2.376309)
@@ -137,11 +165,13 @@ fn simple_bano_shape_filter_test(bragi: &mut BragiHandler) {
           "type": "FeatureCollection",
           "geocoding": {
             "version": "0.1.0",
-            "query": ""
+            "query": "",
+            "match_stage": "prefix"
           },
           "features": [
             {
               "type": "Feature",
+              "confidence": 1.0,
               "geometry": {
                 "coordinates": [
                   2.376_379,
@@ -182,11 +212,13 @@ fn simple_bano_shape_filter_test(bragi: &mut BragiHandler) {
           "type": "FeatureCollection",
           "geocoding": {
             "version": "0.1.0",
-            "query": ""
+            "query": "",
+            "match_stage": "prefix"
           },
           "features": [
             {
               "type": "Feature",
+              "confidence": 1.0,
               "geometry": {
                 "coordinates": [
                   2.376_58,
@@ -226,7 +258,8 @@ fn simple_bano_shape_filter_test(bragi: &mut BragiHandler) {
           "type": "FeatureCollection",
           "geocoding": {
             "version": "0.1.0",
-            "query": ""
+            "query": "",
+            "match_stage": "fuzzy"
           },
           "features": []
         }
@@ -301,3 +334,109 @@ fn reverse_bano_test(bragi: &mut BragiHandler) {
         vec!["2 Rue des Pins (Beauzelle)"]
     );
 }
+
+fn coordinate_bano_test(bragi: &mut BragiHandler) {
+    // a coordinate is given first, followed by the address at this position
+    let res = bragi.get("/autocomplete?q=48.8468, 2.37716");
+    assert_eq!(get_types(&res), vec!["coordinate", "house"]);
+    assert_eq!(
+        get_values(&res, "label"),
+        vec!["48.8468, 2.37716", "20 Rue Hector Malot (Paris)"]
+    );
+
+    // as well as a plus code, a short one being recovered near the position of the request
+    let res = bragi.get("/autocomplete?q=8FW4R9WG%2BPV");
+    assert_eq!(get_types(&res), vec!["coordinate", "house"]);
+    assert_eq!(get_values(&res, "label")[0], "8FW4R9WG+PV");
+    let res = bragi.get("/autocomplete?q=R9WG%2BPV&lat=48.85&lon=2.35");
+    assert_eq!(
+        get_values(&res, "label"),
+        vec!["8FW4R9WG+PV", "20 Rue Hector Malot (Paris)"]
+    );
+}
+
+fn batch_bano_test(bragi: &mut BragiHandler) {
+    let labels = |lines: &[serde_json::Value]| {
+        lines
+            .iter()
+            .map(|l| {
+                (
+                    l["row"].as_u64().unwrap(),
+                    l.pointer("/features/0/properties/geocoding/label")
+                        .and_then(|l| l.as_str())
+                        .map(str::to_owned),
+                    l.get("error").is_some(),
+                )
+            })
+            .collect::<Vec<_>>()
+    };
+
+    // The results are in the order of the rows, and an invalid row does not fail the batch
+    let res = bragi.post_batch(
+        "/batch",
+        "application/x-ndjson",
+        r#"{"q": "15 Rue Hector Malot"}
+{"q": "20 rue hector malot", "lat": 48, "lon": 2.4}
+{"lat": 48}
+{"q": "15 Rue Hector Malot", "lat": 480, "lon": 2.4}"#,
+    );
+    assert_eq!(
+        labels(&res),
+        vec![
+            (0, Some("15 Rue Hector Malot (Paris)".to_owned()), false),
+            (1, Some("20 Rue Hector Malot (Paris)".to_owned()), false),
+            (2, None, true),
+            (3, None, true),
+        ]
+    );
+
+    let res = bragi.post_batch(
+        "/batch",
+        "text/csv",
+        "q,lat,lon\n18 rue hector malot,,\n15 Rue Hector Malot,48.846495,2.376379\n",
+    );
+    assert_eq!(
+        labels(&res),
+        vec![
+            (0, Some("18 Rue Hector Malot (Paris)".to_owned()), false),
+            (1, Some("15 Rue Hector Malot (Paris)".to_owned()), false),
+        ]
+    );
+}
+
+/// Evaluate a golden set of queries with bragi_eval, run with an in-process bragi
+#[cfg(feature = "eval")]
+fn eval_bano_test(es_wrapper: &crate::ElasticSearchWrapper<'_>) {
+    let bragi_eval = Path::new(env!("OUT_DIR"))
+        .join("../../../bragi_eval")
+        .display()
+        .to_string();
+    let report_path = Path::new(env!("OUT_DIR")).join("eval_report.json");
+    crate::launch_and_assert(
+        &bragi_eval,
+        &[
+            "--input=./tests/fixtures/eval/golden.jsonl".into(),
+            format!("--output={}", report_path.display()),
+            format!("--connection-string={}", es_wrapper.host()),
+        ],
+        es_wrapper,
+    );
+    let report: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&report_path).unwrap()).unwrap();
+    assert_eq!(report["nb_queries"], json!(2));
+    assert_eq!(report["nb_passed"], json!(1));
+    assert_eq!(report["mrr"], json!(0.5));
+    assert_eq!(report["queries"][0]["ranks"], json!([1]));
+    assert_eq!(report["queries"][1]["ranks"], json!([null]));
+
+    // the unknown place is found in the baseline, so it has regressed
+    let status = Command::new(&bragi_eval)
+        .args(&[
+            "--input=./tests/fixtures/eval/golden.jsonl".to_string(),
+            "--baseline=./tests/fixtures/eval/baseline.json".to_string(),
+            format!("--connection-string={}", es_wrapper.host()),
+        ])
+        .status()
+        .unwrap();
+    assert!(!status.success());
+}
@@ -35,8 +35,10 @@ use super::get_types;
 use super::get_value;
 use super::get_values;
 use super::BragiHandler;
+use mimir::backend::SearchBackend;
 use mimir::{MimirObject, Poi};
 use serde_json::json;
+use std::convert::TryFrom;
 use std::path::Path;
 
 pub fn bragi_poi_test(es_wrapper: crate::ElasticSearchWrapper<'_>) {
@@ -84,9 +86,15 @@ pub fn bragi_poi_test(es_wrapper: crate::ElasticSearchWrapper<'_>) {
     poi_zip_code_test(&mut bragi);
     poi_from_osm_test(&mut bragi);
     poi_misspelt_one_word_admin_test(&mut bragi);
+    poi_confidence_test(&mut bragi);
+    poi_cascade_test(&mut bragi, &es_wrapper.host());
+    poi_timeout_test(&mut bragi);
+    poi_cache_test(&es_wrapper.host());
+    poi_settings_profiles_test(&es_wrapper.host());
     poi_from_osm_with_address_addr_test(&mut bragi);
     poi_filter_poi_type_test(&mut bragi);
     poi_filter_error_message_test(&mut bragi);
+    poi_reverse_test(&mut bragi);
 }
 
 pub fn bragi_private_poi_test(es_wrapper: crate::ElasticSearchWrapper<'_>) {
@@ -153,6 +161,7 @@ pub fn bragi_private_poi_test(es_wrapper: crate::ElasticSearchWrapper<'_>) {
     );
 
     poi_filter_dataset_visibility_test(&mut bragi);
+    poi_api_keys_test(&es_wrapper.host());
 }
 
 fn poi_admin_address_test(bragi: &mut BragiHandler) {
@@ -311,6 +320,242 @@ fn poi_misspelt_one_word_admin_test(bragi: &mut BragiHandler) {
     assert_eq!(get_value(melun, "name"), "Melun");
 }
 
+fn poi_confidence_test(bragi: &mut BragiHandler) {
+    let confidences = |response: &serde_json::Value| {
+        response["features"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|f| f["confidence"].as_f64().unwrap())
+            .collect::<Vec<_>>()
+    };
+
+    // 'Melun' is found by the prefix search, and the best place found covers the whole query
+    let response = bragi.get_json("/autocomplete?q=Melun");
+    assert_eq!(response["geocoding"]["match_stage"], "prefix");
+    let melun_confidences = confidences(&response);
+    assert_eq!(melun_confidences.first(), Some(&1.));
+    assert!(melun_confidences.iter().all(|c| (0. ..=1.).contains(c)));
+
+    // 'Meluun' is only found by the fuzzy search, with a lower confidence
+    let response = bragi.get_json("/autocomplete?q=Meluun");
+    assert_eq!(response["geocoding"]["match_stage"], "fuzzy");
+    let meluun_confidences = confidences(&response);
+    assert!(!meluun_confidences.is_empty());
+    assert!(meluun_confidences.iter().all(|c| (0. ..1.).contains(c)));
+}
+
+fn poi_cascade_test(bragi: &mut BragiHandler, es_host: &str) {
+    // bragi with the default settings and the given cascade
+    let default_settings = std::fs::read_to_string("./config/bragi-settings.toml").unwrap();
+    let bragi_with_cascade = |name: &str, cascade: &str| {
+        let settings = Path::new(env!("OUT_DIR")).join(name);
+        std::fs::write(&settings, format!("{}\n{}", default_settings, cascade)).unwrap();
+        BragiHandler::with_args(bragi::Args {
+            connection_string: es_host.to_owned(),
+            weight_config_file: Some(settings.display().to_string()),
+            ..Default::default()
+        })
+    };
+
+    // the exact stage finds 'Melun', 'Meluun' is only found by the fuzzy stage
+    let cascade = r#"
+        [cascade]
+            [[cascade.stages]]
+            match = "exact"
+            [[cascade.stages]]
+            match = "fuzzy"
+    "#;
+    let mut exact_bragi = bragi_with_cascade("exact_cascade.toml", cascade);
+    let response = exact_bragi.get_json("/autocomplete?q=Melun");
+    assert_eq!(response["geocoding"]["match_stage"], "exact");
+    let response = exact_bragi.get_json("/autocomplete?q=Meluun");
+    assert_eq!(response["geocoding"]["match_stage"], "fuzzy");
+    assert!(!response["features"].as_array().unwrap().is_empty());
+
+    // the same stages sent at once
+    let cascade = r#"
+        [cascade]
+        parallel = true
+        budget = 10000
+            [[cascade.stages]]
+            match = "exact"
+            [[cascade.stages]]
+            match = "fuzzy"
+    "#;
+    let mut parallel_bragi = bragi_with_cascade("parallel_cascade.toml", cascade);
+    let response = parallel_bragi.get_json("/autocomplete?q=Melun");
+    assert_eq!(response["geocoding"]["match_stage"], "exact");
+    let response = parallel_bragi.get_json("/autocomplete?q=Meluun");
+    assert_eq!(response["geocoding"]["match_stage"], "fuzzy");
+
+    // the prefix stage never finds enough places, so the places of the fuzzy stage are
+    // appended to them
+    let cascade = r#"
+        [cascade]
+            [[cascade.stages]]
+            match = "prefix"
+            min_results = 100
+            [[cascade.stages]]
+            match = "fuzzy"
+            merge = "append"
+    "#;
+    let mut append_bragi = bragi_with_cascade("append_cascade.toml", cascade);
+    let response = append_bragi.get_json("/autocomplete?q=Melun");
+    assert_eq!(response["geocoding"]["match_stage"], "fuzzy");
+    let prefix_response = bragi.get_json("/autocomplete?q=Melun");
+    assert_eq!(response["features"][0], prefix_response["features"][0]);
+
+    // an empty cascade is rejected
+    let settings = Path::new(env!("OUT_DIR")).join("empty_cascade.toml");
+    std::fs::write(
+        &settings,
+        format!("{}\n[cascade]\nstages = []\n", default_settings),
+    )
+    .unwrap();
+    assert!(bragi::Context::try_from(&bragi::Args {
+        connection_string: es_host.to_owned(),
+        weight_config_file: Some(settings.display().to_string()),
+        ..Default::default()
+    })
+    .is_err());
+}
+
+fn poi_timeout_test(bragi: &mut BragiHandler) {
+    // with enough time, the response is complete
+    let response = bragi.get_json("/autocomplete?q=Meluun&timeout=10000");
+    assert_eq!(response["geocoding"]["match_stage"], "fuzzy");
+    assert!(response["geocoding"].get("partial").is_none());
+
+    // without enough time, the places found before the timeout are returned as partial
+    // results instead of an error
+    let response = bragi.get_json("/autocomplete?q=Meluun&timeout=1");
+    assert_eq!(response["geocoding"]["partial"], true);
+}
+
+fn poi_cache_test(es_host: &str) {
+    let mut bragi = BragiHandler::with_args(bragi::Args {
+        connection_string: es_host.to_owned(),
+        cache_size: 100,
+        cache_ttl: 300,
+        cache_check_interval: 1,
+        ..Default::default()
+    });
+    fn cache_counter(bragi: &mut BragiHandler, name: &str) -> f64 {
+        let (_, metrics) = bragi.raw_get("/metrics");
+        std::str::from_utf8(&metrics)
+            .unwrap()
+            .lines()
+            .find(|l| l.starts_with(&format!("{}{{handler=\"autocomplete\"}}", name)))
+            .and_then(|l| l.split(' ').last())
+            .map_or(0., |v| v.parse().unwrap())
+    }
+    let etag = |headers: &actix_web::http::HeaderMap| {
+        headers
+            .get("etag")
+            .expect("no etag")
+            .to_str()
+            .unwrap()
+            .to_owned()
+    };
+
+    let hits = cache_counter(&mut bragi, "bragi_cache_hits_total");
+    let (status, headers, body) = bragi.raw_get_with_headers("/autocomplete?q=Melun", &[]);
+    assert!(status.is_success());
+    let first_etag = etag(&headers);
+
+    // the parameters which don't change the response are not part of the key
+    let (_, headers, cached_body) = bragi.raw_get_with_headers(
+        "/autocomplete?q=Melun&request_id=cache_test&timeout=10000",
+        &[],
+    );
+    assert_eq!(cached_body, body);
+    assert_eq!(etag(&headers), first_etag);
+    assert_eq!(
+        cache_counter(&mut bragi, "bragi_cache_hits_total"),
+        hits + 1.
+    );
+
+    // a client with the response gets a '304 Not Modified'
+    let (status, _, body) = bragi.raw_get_with_headers(
+        "/autocomplete?q=Melun",
+        &[("If-None-Match", first_etag.as_str())],
+    );
+    assert_eq!(status, actix_web::http::StatusCode::NOT_MODIFIED);
+    assert!(body.is_empty());
+
+    // the cache is cleared when the published indexes change
+    let rubber = mimir::rubber::Rubber::new(es_host);
+    let index = rubber
+        .get_all_aliased_index("munin")
+        .unwrap()
+        .keys()
+        .next()
+        .cloned()
+        .unwrap();
+    rubber
+        .alias("munin_cache_test", &[index.clone()], &[])
+        .unwrap();
+    std::thread::sleep(std::time::Duration::from_secs(3));
+    let misses = cache_counter(&mut bragi, "bragi_cache_misses_total");
+    bragi.get_json("/autocomplete?q=Melun");
+    assert_eq!(
+        cache_counter(&mut bragi, "bragi_cache_misses_total"),
+        misses + 1.
+    );
+    rubber.alias("munin_cache_test", &[], &[index]).unwrap();
+}
+
+fn poi_settings_profiles_test(es_host: &str) {
+    let default_settings = std::fs::read_to_string("./config/bragi-settings.toml").unwrap();
+    let profile = Path::new(env!("OUT_DIR")).join("exact_profile.toml");
+    let write_profile = |match_type: &str| {
+        let cascade = format!(
+            "[cascade]\n[[cascade.stages]]\nmatch = \"{}\"\n",
+            match_type
+        );
+        std::fs::write(&profile, format!("{}\n{}", default_settings, cascade)).unwrap();
+    };
+    write_profile("exact");
+    // all the requests without profile are given to the 'exact' profile
+    let mut bragi = BragiHandler::with_args(bragi::Args {
+        connection_string: es_host.to_owned(),
+        settings_profiles: vec![("exact".to_owned(), profile.display().to_string())],
+        settings_ab_test: vec![("exact".to_owned(), 1.)],
+        ..Default::default()
+    });
+
+    let response = bragi.get_json("/autocomplete?q=Melun&_settings=default");
+    assert_eq!(response["geocoding"]["match_stage"], "prefix");
+    let response = bragi.get_json("/autocomplete?q=Melun");
+    assert_eq!(response["geocoding"]["match_stage"], "exact");
+    assert_eq!(
+        bragi.raw_get("/autocomplete?q=Melun&_settings=unknown").0,
+        actix_web::http::StatusCode::BAD_REQUEST
+    );
+
+    // the profile is a label of the metrics of the requests
+    let (_, metrics) = bragi.raw_get("/metrics");
+    assert!(std::str::from_utf8(&metrics)
+        .unwrap()
+        .lines()
+        .any(|l| l.starts_with("bragi_http_requests_total")
+            && l.contains(r#"handler="autocomplete""#)
+            && l.contains(r#"settings="exact""#)));
+
+    // the profiles are read again when they are reloaded
+    write_profile("fuzzy");
+    let (status, body) =
+        bragi.raw_post_with_content_type("/settings/reload", "application/json", "");
+    assert!(status.is_success());
+    assert_eq!(
+        bragi.as_json(body),
+        json!({"profiles": ["default", "exact"]})
+    );
+    let response = bragi.get_json("/autocomplete?q=Melun&_settings=exact");
+    assert_eq!(response["geocoding"]["match_stage"], "fuzzy");
+}
+
 fn poi_from_osm_with_address_addr_test(bragi: &mut BragiHandler) {
     // search poi: Poi as a way in osm data
     let geocodings = bragi.get("/autocomplete?q=77000 Hôtel de Ville (Melun)");
@@ -411,6 +656,82 @@ pub fn test_i18n_poi(mut es: crate::ElasticSearchWrapper<'_>) {
     assert_eq!(result["label"], "Colosseo (Roma)");
 }
 
+pub fn bragi_poi_details_test(mut es: crate::ElasticSearchWrapper<'_>) {
+    let library = |name: &str, tags: &[(&str, &str)]| {
+        let coord = mimir::Coord::new(2.3, 48.8);
+        mimir::Poi {
+            id: format!("poi:{}", name),
+            label: name.to_string(),
+            name: name.to_string(),
+            coord,
+            approx_coord: Some(coord.into()),
+            poi_type: mimir::PoiType {
+                id: "poi_type:amenity:library".to_string(),
+                name: "Bibliothèque".to_string(),
+            },
+            details: mimir::PoiDetails::from_tags(tags.iter().cloned()),
+            ..Default::default()
+        }
+    };
+    let north = library(
+        "Bibliotheque Nord",
+        &[
+            ("opening_hours", "24/7"),
+            ("timezone", "Europe/Paris"),
+            ("wheelchair", "yes"),
+            ("phone", "+33 1 00 00 00 00"),
+            ("website", "https://nord.example.org"),
+            ("operator", "Ville de Paris"),
+        ],
+    );
+    let south = library(
+        "Bibliotheque Sud",
+        &[
+            ("opening_hours", "off"),
+            ("timezone", "Europe/Paris"),
+            ("wheelchair", "no"),
+        ],
+    );
+    let index_settings = mimir::rubber::IndexSettings {
+        nb_shards: 1,
+        nb_replicas: 0,
+    };
+    es.rubber
+        .public_index("fr", &index_settings, vec![north, south].into_iter())
+        .unwrap();
+    es.refresh();
+
+    let mut bragi = BragiHandler::new(format!("{}/munin", es.host()));
+
+    let res = bragi.get("/autocomplete?q=Bibliotheque Nord");
+    let poi = res.first().unwrap();
+    assert_eq!(poi["opening_hours"], "24/7");
+    assert_eq!(poi["timezone"], "Europe/Paris");
+    assert_eq!(poi["wheelchair"], "yes");
+    assert_eq!(poi["phone"], "+33 1 00 00 00 00");
+    assert_eq!(poi["website"], "https://nord.example.org");
+    assert_eq!(poi["operator"], "Ville de Paris");
+    assert!(poi.get("brand").is_none());
+
+    let names = |res: Vec<serde_json::Map<String, serde_json::Value>>| {
+        get_values(&res, "name")
+            .into_iter()
+            .map(str::to_string)
+            .collect::<Vec<_>>()
+    };
+    let res = bragi.get("/autocomplete?q=Bibliotheque&wheelchair=yes");
+    assert_eq!(names(res), vec!["Bibliotheque Nord"]);
+    let res = bragi.get("/autocomplete?q=Bibliotheque&type[]=poi&wheelchair=no");
+    assert_eq!(names(res), vec!["Bibliotheque Sud"]);
+    let res = bragi.get("/autocomplete?q=Bibliotheque&open_now=true");
+    assert_eq!(names(res), vec!["Bibliotheque Nord"]);
+    let res = bragi.get("/autocomplete?q=Bibliotheque&open_now=false");
+    assert_eq!(names(res), vec!["Bibliotheque Sud"]);
+
+    let res = bragi.get_unchecked_json("/autocomplete?q=Bibliotheque&type[]=zone&open_now=true");
+    assert_eq!(res.0, actix_web::http::StatusCode::BAD_REQUEST);
+}
+
 fn poi_filter_poi_type_test(bragi: &mut BragiHandler) {
     let geocodings =
         bragi.get("/autocomplete?q=77000&type[]=poi&poi_type[]=poi_type:amenity:post_office");
@@ -454,3 +775,139 @@ fn poi_filter_dataset_visibility_test(bragi: &mut BragiHandler) {
     let res = bragi.get("/autocomplete?q=Agence Keolis&type[]=poi&poi_dataset[]=effia");
     assert!(res.first().is_none());
 }
+
+fn poi_api_keys_test(es_host: &str) {
+    let api_keys_file = Path::new(env!("OUT_DIR")).join("bragi_api_keys.toml");
+    let keolis_client = r#"
+[[clients]]
+name = "keolis-client"
+key = "keolis-key"
+poi_datasets = ["keolis"]
+default_poi_datasets = ["keolis"]
+
+[[clients]]
+name = "limited-client"
+key = "limited-key"
+rate_limit = 1
+"#;
+    std::fs::write(&api_keys_file, keolis_client).unwrap();
+    let mut bragi = BragiHandler::with_args(bragi::Args {
+        connection_string: es_host.to_owned(),
+        api_keys_file: Some(api_keys_file.clone()),
+        api_keys_check_interval: 1,
+        ..Default::default()
+    });
+    let status = |bragi: &mut BragiHandler, query: &str, key: &str| {
+        bragi
+            .raw_get_with_headers(query, &[("Authorization", key)])
+            .0
+    };
+    let query = "/autocomplete?q=Agence Keolis&type[]=poi";
+
+    // the requests need a valid api key, except the status
+    assert_eq!(
+        bragi.raw_get(query).0,
+        actix_web::http::StatusCode::UNAUTHORIZED
+    );
+    assert_eq!(
+        status(&mut bragi, query, "unknown-key"),
+        actix_web::http::StatusCode::UNAUTHORIZED
+    );
+    assert!(bragi.raw_get("/status").0.is_success());
+
+    // without dataset, the default datasets of the client are used
+    let (_, _, body) = bragi.raw_get_with_headers(query, &[("Authorization", "keolis-key")]);
+    let res = bragi.as_json(body);
+    assert_eq!(
+        res.pointer("/features/0/properties/geocoding/id"),
+        Some(&json!("poi:TCL:AGE:516"))
+    );
+
+    // the client only sees its datasets
+    assert_eq!(
+        status(
+            &mut bragi,
+            &format!("{}&poi_dataset[]=effia", query),
+            "keolis-key"
+        ),
+        actix_web::http::StatusCode::FORBIDDEN
+    );
+    assert_eq!(
+        status(
+            &mut bragi,
+            &format!("{}&_all_data=true", query),
+            "keolis-key"
+        ),
+        actix_web::http::StatusCode::FORBIDDEN
+    );
+
+    // the requests over the rate limit are rejected
+    assert!(status(&mut bragi, query, "limited-key").is_success());
+    assert_eq!(
+        status(&mut bragi, query, "limited-key"),
+        actix_web::http::StatusCode::TOO_MANY_REQUESTS
+    );
+
+    // the api keys are reloaded when their file is modified
+    std::fs::write(
+        &api_keys_file,
+        keolis_client.replacen(
+            r#"poi_datasets = ["keolis"]"#,
+            r#"poi_datasets = ["keolis", "effia"]"#,
+            1,
+        ),
+    )
+    .unwrap();
+    std::thread::sleep(std::time::Duration::from_secs(3));
+    assert!(status(
+        &mut bragi,
+        &format!("{}&poi_dataset[]=effia", query),
+        "keolis-key"
+    )
+    .is_success());
+}
+
+fn poi_reverse_test(bragi: &mut BragiHandler) {
+    let json = bragi.get_json("/autocomplete?q=Melun Rp&type[]=poi");
+    let coords = json
+        .pointer("/features/0/geometry/coordinates")
+        .and_then(|c| c.as_array())
+        .unwrap();
+    let (lon, lat) = (coords[0].as_f64().unwrap(), coords[1].as_f64().unwrap());
+
+    // the nearest poi is the one we are on, and the pois are sorted by distance
+    let pois = bragi.get(&format!(
+        "/reverse?lon={}&lat={}&type[]=poi&limit=5&radius=2000",
+        lon, lat
+    ));
+    assert!(!pois.is_empty() && pois.len() <= 5);
+    assert_eq!(get_value(&pois[0], "label"), "Melun Rp (Melun)");
+    assert_eq!(count_types(&get_types(&pois), Poi::doc_type()), pois.len());
+    let json = bragi.get_json(&format!(
+        "/reverse?lon={}&lat={}&type[]=poi&limit=5&radius=2000",
+        lon, lat
+    ));
+    let distances = json["features"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|f| f["distance"].as_u64().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(distances[0], 0);
+    assert!(distances.windows(2).all(|d| d[0] <= d[1]));
+
+    // the zones are the admins containing the point
+    let zones = bragi.get(&format!("/reverse?lon={}&lat={}&type[]=zone", lon, lat));
+    assert!(!zones.is_empty());
+    assert_eq!(count_types(&get_types(&zones), "zone"), zones.len());
+    assert!(get_values(&zones, "name").contains(&"Melun"));
+
+    // a point far from everything has no poi around
+    let pois = bragi.get("/reverse?lon=-10&lat=0&type[]=poi&radius=100");
+    assert!(pois.is_empty());
+
+    // the radius must be positive
+    let (status, _) =
+        bragi.get_unchecked_json(&format!("/reverse?lon={}&lat={}&radius=-1", lon, lat));
+    assert_eq!(status, actix_web::http::StatusCode::BAD_REQUEST);
+}
@@ -33,6 +33,7 @@ use super::get_types;
 use super::get_value;
 use super::get_values;
 use super::BragiHandler;
+use serde_json::json;
 use std::path::Path;
 
 pub fn bragi_three_cities_test(es_wrapper: crate::ElasticSearchWrapper<'_>) {
@@ -83,6 +84,9 @@ pub fn bragi_three_cities_test(es_wrapper: crate::ElasticSearchWrapper<'_>) {
     three_cities_zip_code_test(&mut bragi);
     three_cities_zip_code_address_test(&mut bragi);
     three_cities_shape_test(&mut bragi);
+    three_cities_bbox_test(&mut bragi);
+    three_cities_within_test(&mut bragi);
+    three_cities_structured_search_test(&mut bragi);
 }
 
 fn three_cities_housenumber_zip_code_test(bragi: &mut BragiHandler) {
@@ -261,3 +265,153 @@ fn three_cities_shape_test(bragi: &mut BragiHandler) {
         vec!["Rue du Four à Chaux (Livry-sur-Seine)"]
     );
 }
+
+fn three_cities_bbox_test(bragi: &mut BragiHandler) {
+    // The same places as for the shape: the box contains street1 and stop1, street2 and stop2
+    // are outside.
+    //
+    //      +----------------------+                 48.5372
+    //      |      ===== street1   | ==== street2
+    //      |      O stop1         | O stop2
+    //      +----------------------+                 48.5366
+    //      2.6565                 2.6576
+    let bbox = "bbox=2.6565,48.5366,2.6576,48.5372";
+
+    // without shape_scope, the box filters all the places
+    let geocodings = bragi.get(&format!("/autocomplete?q=Rue du Port&{}", bbox));
+    assert_eq!(
+        get_values(&geocodings, "label"),
+        vec!["Rue du Port (Melun)"]
+    );
+    let geocodings = bragi.get(&format!("/autocomplete?q=Stop In&_all_data=true&{}", bbox));
+    assert_eq!(get_values(&geocodings, "label"), vec!["Stop In (Melun)"]);
+    let geocodings = bragi.get(&format!("/autocomplete?q=Stop Out&_all_data=true&{}", bbox));
+    assert!(geocodings.is_empty());
+    let geocodings = bragi.get(&format!("/autocomplete?q=Four&{}", bbox));
+    assert!(geocodings.is_empty());
+
+    // with shape_scope, only the places of its types are filtered
+    let geocodings = bragi.get(&format!(
+        "/autocomplete?q=Stop Out&_all_data=true&shape_scope[]=street&{}",
+        bbox
+    ));
+    assert_eq!(get_values(&geocodings, "label"), vec!["Stop Out (Melun)"]);
+
+    // as a bias, the places of the box come first but the others are still returned
+    let geocodings = bragi.get(
+        "/autocomplete?q=Stop&_all_data=true&bbox=2.6578,48.5368,2.6582,48.5372&bbox_mode=bias",
+    );
+    assert_eq!(
+        get_values(&geocodings, "label"),
+        vec!["Stop Out (Melun)", "Stop In (Melun)"]
+    );
+    let geocodings = bragi.get(
+        "/autocomplete?q=Stop&_all_data=true&bbox=2.6568,48.5368,2.6572,48.5372&bbox_mode=bias",
+    );
+    assert_eq!(
+        get_values(&geocodings, "label"),
+        vec!["Stop In (Melun)", "Stop Out (Melun)"]
+    );
+    // with a position, the box is a second bias
+    let geocodings = bragi.get(
+        "/autocomplete?q=Stop&_all_data=true&lat=48.537&lon=2.657\
+         &bbox=2.6568,48.5368,2.6572,48.5372&bbox_mode=bias",
+    );
+    assert_eq!(
+        get_values(&geocodings, "label"),
+        vec!["Stop In (Melun)", "Stop Out (Melun)"]
+    );
+
+    // the box should be valid
+    let (status, _) = bragi.get_unchecked_json("/autocomplete?q=Stop&bbox=2.6576,48.5366,2.6565");
+    assert_eq!(status, actix_web::http::StatusCode::BAD_REQUEST);
+    let (status, _) =
+        bragi.get_unchecked_json("/autocomplete?q=Stop&bbox=2.6576,48.5366,2.6565,48.5372");
+    assert_eq!(status, actix_web::http::StatusCode::BAD_REQUEST);
+}
+
+fn three_cities_within_test(bragi: &mut BragiHandler) {
+    let melun = "within[]=admin:osm:relation:80071";
+
+    // the places of Melun are found, as well as Melun itself
+    let geocodings = bragi.get(&format!("/autocomplete?q=Rue du Port&{}", melun));
+    assert_eq!(
+        get_values(&geocodings, "label"),
+        vec!["Rue du Port (Melun)"]
+    );
+    let geocodings = bragi.get(&format!("/autocomplete?q=Melun&type[]=city&{}", melun));
+    assert_eq!(get_values(&geocodings, "name"), vec!["Melun"]);
+    // the addresses are in the admins of their street
+    let geocodings = bragi.get(&format!(
+        "/autocomplete?q=2 Rue de la Reine Blanche&type[]=house&{}",
+        melun
+    ));
+    assert_eq!(
+        get_values(&geocodings, "label"),
+        vec!["2 Rue de la Reine Blanche (Melun)"]
+    );
+
+    // Rue du Four à Chaux is in Livry-sur-Seine
+    let geocodings = bragi.get(&format!("/autocomplete?q=Four&{}", melun));
+    assert!(geocodings.is_empty());
+
+    // with shape_scope, only the places of its types are restricted to the admin
+    let geocodings = bragi.get(&format!(
+        "/autocomplete?q=Four&shape_scope[]=admin&{}",
+        melun
+    ));
+    assert_eq!(
+        get_values(&geocodings, "label"),
+        vec!["Rue du Four à Chaux (Livry-sur-Seine)"]
+    );
+
+    // the places can be in any of the admins
+    let geocodings = bragi.get(&format!(
+        "/autocomplete?q=Four&{}&within[]=admin:osm:relation:215390",
+        melun
+    ));
+    assert_eq!(
+        get_values(&geocodings, "label"),
+        vec!["Rue du Four à Chaux (Livry-sur-Seine)"]
+    );
+
+    // only admins can be used
+    let (status, _) = bragi.get_unchecked_json("/autocomplete?q=Four&within[]=street:osm:way:4004");
+    assert_eq!(status, actix_web::http::StatusCode::BAD_REQUEST);
+}
+
+fn three_cities_structured_search_test(bragi: &mut BragiHandler) {
+    // each field is matched against its own field of the documents
+    let res = bragi.get("/search?housenumber=3&street=Rue du Four à Chaux&postcode=77255");
+    assert_eq!(
+        get_values(&res, "label"),
+        vec!["3 Rue du Four à Chaux (Livry-sur-Seine)"]
+    );
+
+    // without house number, only the streets are returned
+    let res = bragi.get("/search?street=Rue du Port&city=Melun");
+    assert_eq!(get_values(&res, "label"), vec!["Rue du Port (Melun)"]);
+    assert_eq!(count_types(&get_types(&res), "house"), 0);
+
+    // a field that does not match filters the results out, it's not just a lower score
+    let res = bragi.get("/search?housenumber=3&street=Rue du Four à Chaux&city=Melun");
+    assert!(res.is_empty());
+    let res = bragi.get("/search?housenumber=3&street=Rue du Four à Chaux&postcode=77000");
+    assert!(res.is_empty());
+
+    // we only know the city
+    let res = bragi.get("/search?city=Melun");
+    assert_eq!(get_values(&res, "name"), vec!["Melun"]);
+    assert_eq!(get_types(&res), vec!["zone"]);
+
+    assert_eq!(
+        bragi.get_unchecked_json("/search?limit=3"),
+        (
+            actix_web::http::StatusCode::BAD_REQUEST,
+            json!({
+                "short": "validation error",
+                "long": "Invalid parameter: at least one of 'housenumber', 'street', 'postcode', 'city' or 'country' is required",
+            })
+        )
+    );
+}
@@ -0,0 +1,111 @@
+// Copyright © 2016, Canal TP and/or its affiliates. All rights reserved.
+//
+// This file is part of Navitia,
+//     the software to build cool stuff with public transport.
+//
+// Hope you'll enjoy and contribute to this project,
+//     powered by Canal TP (www.canaltp.fr).
+// Help us simplify mobility and open public transport:
+//     a non ending quest to the responsive locomotion way of traveling!
+//
+// LICENCE: This program is free software; you can redistribute it
+// and/or modify it under the terms of the GNU Affero General Public
+// License as published by the Free Software Foundation, either
+// version 3 of the License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+// Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public
+// License along with this program. If not, see
+// <http://www.gnu.org/licenses/>.
+//
+// Stay tuned using
+// twitter @navitia
+// IRC #navitia on freenode
+// https://groups.google.com/d/forum/navitia
+// www.navitia.io
+
+use mimir::backend::SearchBackend;
+use mimir::rubber::IndexSettings;
+use mimir::{Coord, Street};
+use std::path::Path;
+use std::process::Command;
+
+fn street(id: &str, name: &str) -> Street {
+    let coord = Coord::new(2.3, 48.8);
+    Street {
+        id: id.to_string(),
+        name: name.to_string(),
+        label: name.to_string(),
+        coord,
+        approx_coord: Some(coord.into()),
+        ..Default::default()
+    }
+}
+
+/// Returns the names of the streets in the base, sorted by id
+fn get_street_names(es_wrapper: &crate::ElasticSearchWrapper<'_>) -> Vec<(String, String)> {
+    let mut names: Vec<_> = es_wrapper
+        .search_and_filter("*:*", |_| true)
+        .filter_map(|place| match place {
+            mimir::Place::Street(street) => Some((street.id, street.name)),
+            _ => None,
+        })
+        .collect();
+    names.sort();
+    names
+}
+
+/// Patch some streets of a published index with mimir_patch
+pub fn mimir_patch_test(mut es_wrapper: crate::ElasticSearchWrapper<'_>) {
+    let index_settings = IndexSettings {
+        nb_shards: 1,
+        nb_replicas: 0,
+    };
+    let streets = vec![
+        street("street:bob", "Rue Bob"),
+        street("street:bobette", "Rue Bobette"),
+    ];
+    es_wrapper
+        .rubber
+        .public_index("fr", &index_settings, streets.into_iter())
+        .unwrap();
+    es_wrapper.refresh();
+
+    let mimir_patch = Path::new(env!("OUT_DIR"))
+        .join("../../../mimir_patch")
+        .display()
+        .to_string();
+
+    // nothing is applied if a line is invalid
+    let status = Command::new(&mimir_patch)
+        .args(&[
+            "--input=./tests/fixtures/patch/invalid_operations.jsonl".to_string(),
+            format!("--connection-string={}", es_wrapper.host()),
+        ])
+        .status()
+        .unwrap();
+    assert!(!status.success());
+    es_wrapper.refresh();
+    assert_eq!(get_street_names(&es_wrapper).len(), 2);
+
+    crate::launch_and_assert(
+        &mimir_patch,
+        &[
+            "--input=./tests/fixtures/patch/operations.jsonl".into(),
+            format!("--connection-string={}", es_wrapper.host()),
+        ],
+        &es_wrapper,
+    );
+
+    assert_eq!(
+        get_street_names(&es_wrapper),
+        vec![
+            ("street:alice".to_string(), "Rue Alice".to_string()),
+            ("street:bob".to_string(), "Boulevard Bob".to_string()),
+        ]
+    );
+}
@@ -28,6 +28,8 @@
 // https://groups.google.com/d/forum/navitia
 // www.navitia.io
 
+use super::get_value;
+use super::BragiHandler;
 use mimir::Members;
 use std::path::Path;
 
@@ -82,6 +84,273 @@ pub fn osm2mimir_sample_test_sqlite(es_wrapper: crate::ElasticSearchWrapper<'_>)
     check_results(es_wrapper, "sqlite backend");
 }
 
+/// Import of the OSM addresses
+/// Checks that the addresses are imported and linked to their street
+pub fn osm2mimir_address_test(es_wrapper: crate::ElasticSearchWrapper<'_>) {
+    let osm2mimir = Path::new(env!("OUT_DIR"))
+        .join("../../../osm2mimir")
+        .display()
+        .to_string();
+    crate::launch_and_assert(
+        &osm2mimir,
+        &[
+            "--input=./tests/fixtures/osm_fixture.osm.pbf".into(),
+            "--import-way=true".into(),
+            "--import-admin=true".into(),
+            "--import-address=true".into(),
+            "--level=8".into(),
+            "--level=7".into(),
+            format!("--connection-string={}", es_wrapper.host()),
+        ],
+        &es_wrapper,
+    );
+
+    let addresses: Vec<_> = es_wrapper
+        .search_and_filter("label:*", |p| p.is_addr())
+        .collect();
+    assert!(!addresses.is_empty());
+    for place in &addresses {
+        match place {
+            mimir::objects::Place::Addr(addr) => {
+                assert!(addr.id.starts_with("addr:osm:"), "{}", addr.id);
+                assert!(
+                    addr.street.id.starts_with("street:osm:"),
+                    "{}",
+                    addr.street.id
+                );
+                assert!(addr.label.contains(&addr.house_number), "{}", addr.label);
+            }
+            _ => panic!("should be an address"),
+        }
+    }
+}
+
+/// Import of the OSM intersections
+/// Checks that the crossings of the streets are imported, and found by bragi
+pub fn osm2mimir_intersection_test(es_wrapper: crate::ElasticSearchWrapper<'_>) {
+    let osm2mimir = Path::new(env!("OUT_DIR"))
+        .join("../../../osm2mimir")
+        .display()
+        .to_string();
+    crate::launch_and_assert(
+        &osm2mimir,
+        &[
+            "--input=./tests/fixtures/osm_fixture.osm.pbf".into(),
+            "--import-admin=true".into(),
+            "--import-intersection=true".into(),
+            "--level=8".into(),
+            "--level=7".into(),
+            format!("--connection-string={}", es_wrapper.host()),
+        ],
+        &es_wrapper,
+    );
+
+    let intersections: Vec<_> = es_wrapper
+        .search_and_filter("label:*", |p| p.is_intersection())
+        .collect();
+    assert!(!intersections.is_empty());
+    for place in &intersections {
+        match place {
+            mimir::objects::Place::Intersection(intersection) => {
+                assert!(
+                    intersection.id.starts_with("intersection:osm:node:"),
+                    "{}",
+                    intersection.id
+                );
+                assert_eq!(intersection.street_names.len(), 2);
+                assert_ne!(intersection.street_names[0], intersection.street_names[1]);
+                assert!(intersection
+                    .administrative_regions
+                    .iter()
+                    .any(|a| a.is_city()));
+            }
+            _ => panic!("should be an intersection"),
+        }
+    }
+
+    // an intersection is found by the search of its streets
+    let intersection = match &intersections[0] {
+        mimir::objects::Place::Intersection(intersection) => intersection,
+        _ => unreachable!(),
+    };
+    let mut bragi = BragiHandler::new(es_wrapper.host());
+    let res = bragi.get(&format!(
+        "/autocomplete?q={} / {}",
+        intersection.street_names[0], intersection.street_names[1]
+    ));
+    assert_eq!(get_value(&res[0], "type"), "intersection");
+    assert_eq!(get_value(&res[0], "id"), intersection.id);
+}
+
+/// Import of the postcodes
+/// Checks that the postcodes are built from the addresses, and that bragi finds them
+/// by their code and by reverse
+pub fn osm2mimir_postcode_test(es_wrapper: crate::ElasticSearchWrapper<'_>) {
+    let osm2mimir = Path::new(env!("OUT_DIR"))
+        .join("../../../osm2mimir")
+        .display()
+        .to_string();
+    crate::launch_and_assert(
+        &osm2mimir,
+        &[
+            "--input=./tests/fixtures/osm_fixture.osm.pbf".into(),
+            "--import-admin=true".into(),
+            "--import-postcode=true".into(),
+            "--level=8".into(),
+            "--level=7".into(),
+            format!("--connection-string={}", es_wrapper.host()),
+        ],
+        &es_wrapper,
+    );
+
+    let postcodes: Vec<_> = es_wrapper
+        .search_and_filter("label:*", |p| p.is_postcode())
+        .collect();
+    assert!(!postcodes.is_empty());
+    let postcode = postcodes
+        .iter()
+        .filter_map(|place| match place {
+            mimir::objects::Place::Postcode(postcode) => Some(postcode),
+            _ => None,
+        })
+        .find(|postcode| postcode.name == "77000")
+        .expect("the postcode of Livry-sur-Seine should be built from its addresses");
+    assert!(postcode.id.starts_with("postcode:"), "{}", postcode.id);
+    assert_eq!(postcode.zip_codes, vec!["77000".to_string()]);
+    assert!(postcode.coord.is_valid());
+
+    // the postcode is found by its code
+    let mut bragi = BragiHandler::new(es_wrapper.host());
+    let res = bragi.get("/autocomplete?q=77000&type[]=postcode");
+    assert_eq!(get_value(&res[0], "type"), "postcode");
+    assert_eq!(get_value(&res[0], "id"), postcode.id);
+
+    // and it is the postcode containing its centroid
+    let res = bragi.get(&format!(
+        "/reverse?lon={}&lat={}&type[]=postcode",
+        postcode.coord.lon(),
+        postcode.coord.lat()
+    ));
+    assert_eq!(get_value(&res[0], "type"), "postcode");
+    assert_eq!(get_value(&res[0], "id"), postcode.id);
+}
+
+/// Import of the localities
+/// Checks that the hamlets are imported with their admins, and found by bragi
+pub fn osm2mimir_locality_test(es_wrapper: crate::ElasticSearchWrapper<'_>) {
+    let osm2mimir = Path::new(env!("OUT_DIR"))
+        .join("../../../osm2mimir")
+        .display()
+        .to_string();
+    crate::launch_and_assert(
+        &osm2mimir,
+        &[
+            "--input=./tests/fixtures/osm_fixture.osm.pbf".into(),
+            "--import-admin=true".into(),
+            "--import-locality=true".into(),
+            "--level=8".into(),
+            "--level=7".into(),
+            format!("--connection-string={}", es_wrapper.host()),
+        ],
+        &es_wrapper,
+    );
+
+    let localities: Vec<_> = es_wrapper
+        .search_and_filter("label:*", |p| p.is_locality())
+        .collect();
+    assert_eq!(localities.len(), 1);
+    let locality = match &localities[0] {
+        mimir::objects::Place::Locality(locality) => locality,
+        _ => panic!("should be a locality"),
+    };
+    assert_eq!(locality.id, "locality:osm:node:7550218700");
+    assert_eq!(locality.label, "Le Moulin de Livry (Livry-sur-Seine)");
+    assert_eq!(locality.locality_type, "hamlet");
+    assert!(locality.weight > 0.);
+
+    let mut bragi = BragiHandler::new(es_wrapper.host());
+    let res = bragi.get("/autocomplete?q=moulin de livry");
+    assert_eq!(get_value(&res[0], "type"), "locality");
+    assert_eq!(get_value(&res[0], "id"), locality.id);
+    assert_eq!(get_value(&res[0], "city"), "Livry-sur-Seine");
+}
+
+/// Import of the OSM fixture, then update with a diff
+/// Checks that only the objects of the diff are updated, and that the diff is applied once
+pub fn osm2mimir_update_test(es_wrapper: crate::ElasticSearchWrapper<'_>) {
+    let osm2mimir = Path::new(env!("OUT_DIR"))
+        .join("../../../osm2mimir")
+        .display()
+        .to_string();
+    crate::launch_and_assert(
+        &osm2mimir,
+        &[
+            "--input=./tests/fixtures/osm_fixture.osm.pbf".into(),
+            "--import-way=true".into(),
+            "--import-admin=true".into(),
+            "--import-poi=true".into(),
+            "--level=8".into(),
+            "--level=7".into(),
+            format!("--connection-string={}", es_wrapper.host()),
+        ],
+        &es_wrapper,
+    );
+
+    let state_file =
+        std::env::temp_dir().join(format!("osm2mimir_state_{}.txt", std::process::id()));
+    let _ = std::fs::remove_file(&state_file);
+    let update_args = [
+        "--input=./tests/fixtures/osc/000/000/001.osc".into(),
+        "--update".into(),
+        format!("--state-file={}", state_file.display()),
+        "--import-way=true".into(),
+        "--import-poi=true".into(),
+        format!("--connection-string={}", es_wrapper.host()),
+    ];
+    crate::launch_and_assert(&osm2mimir, &update_args, &es_wrapper);
+    assert_eq!(
+        std::fs::read_to_string(&state_file).unwrap(),
+        "sequenceNumber=1\n"
+    );
+
+    // the created poi and street, with their admins
+    let res: Vec<_> = es_wrapper
+        .search_and_filter("label:Poste Provisoire", |p| p.is_poi())
+        .collect();
+    assert_eq!(res.len(), 1);
+    assert_eq!(res[0].id(), "poi:osm:node:9000000001");
+    assert!(res[0].admins().iter().any(|a| a.name == "Melun"));
+    let res: Vec<_> = es_wrapper
+        .search_and_filter("label:Rue des Diffs", |p| p.is_street())
+        .collect();
+    assert_eq!(res.len(), 1);
+    assert_eq!(res[0].label(), "Rue des Diffs (Melun)");
+
+    // the modified poi keeps its coordinates, since its nodes are not in the diff
+    let res: Vec<_> = es_wrapper
+        .search_and_filter("label:Mairie Annexe", |p| p.is_poi())
+        .collect();
+    assert_eq!(res.len(), 1);
+    assert_eq!(res[0].id(), "poi:osm:way:113080493");
+    assert!(res[0].poi().map_or(false, |poi| !poi.coord.is_default()));
+
+    // the deleted poi
+    let nb = es_wrapper
+        .search_and_filter("label:Hôtel de Ville", |p| {
+            p.id() == "poi:osm:way:112361498" || p.id() == "poi:osm:way:113080493"
+        })
+        .count();
+    assert_eq!(nb, 0);
+
+    // the diff has already been applied: nothing is done
+    crate::launch_and_assert(&osm2mimir, &update_args, &es_wrapper);
+    let nb = es_wrapper
+        .search_and_filter("label:Poste Provisoire", |p| p.is_poi())
+        .count();
+    assert_eq!(nb, 1);
+    let _ = std::fs::remove_file(&state_file);
+}
+
 fn check_results(es_wrapper: crate::ElasticSearchWrapper<'_>, test_name: &str) {
     // Test: Import of Admin
     let res: Vec<_> = es_wrapper
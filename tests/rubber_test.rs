@@ -30,9 +30,11 @@
 
 use cosmogony::ZoneType;
 use geo::prelude::BoundingRect;
-use mimir::rubber::{self, IndexSettings, Rubber};
+use mimir::backend::SearchBackend;
+use mimir::rubber::{self, IndexSettings, PatchOperation, Rubber};
 use mimir::{Admin, Coord, MimirObject, Street};
 use serde_json::{json, Value};
+use std::collections::BTreeMap;
 
 fn check_has_elt<F: FnMut(&Value)>(es: &crate::ElasticSearchWrapper<'_>, mut fun: F) {
     let search = es.search("*:*"); // we get all documents in the base
@@ -231,6 +233,18 @@ pub fn rubber_custom_id(mut es: crate::ElasticSearchWrapper<'_>) {
         .unwrap();
     assert_eq!(settings.get("number_of_shards"), Some(&json!("1")));
     assert_eq!(settings.get("number_of_replicas"), Some(&json!("0")));
+
+    // the admin can also be found with the generic search backend operations
+    let places = SearchBackend::get_by_id(&mut es.rubber, &["munin"], "admin:bob").unwrap();
+    assert_eq!(places.len(), 1);
+    assert_eq!(places[0].label(), "my admin (zip_code)");
+
+    let places = SearchBackend::get_admins_containing(&mut es.rubber, &coord).unwrap();
+    assert_eq!(places.len(), 1);
+    assert!(places[0].is_admin());
+    let places =
+        SearchBackend::get_admins_containing(&mut es.rubber, &Coord::new(3.5, 48.5)).unwrap();
+    assert!(places.is_empty());
 }
 
 /// test that rubber correctly cleanup ghost indexes
@@ -323,3 +337,100 @@ pub fn rubber_empty_bulk(mut es: crate::ElasticSearchWrapper<'_>) {
     assert!(result.is_ok());
     assert_eq!(result.unwrap(), 0); // we have indexed nothing, but it's ok
 }
+
+fn street(id: &str, name: &str) -> Street {
+    let coord = Coord::new(2.3, 48.8);
+    Street {
+        id: id.to_string(),
+        name: name.to_string(),
+        label: name.to_string(),
+        coord,
+        approx_coord: Some(coord.into()),
+        ..Default::default()
+    }
+}
+
+/// Returns the names of the documents in the base, by id
+fn get_names_by_id(es: &crate::ElasticSearchWrapper<'_>) -> BTreeMap<String, String> {
+    let search = es.search("*:*");
+    search
+        .pointer("/hits/hits")
+        .and_then(|hits| hits.as_array())
+        .unwrap()
+        .iter()
+        .map(|hit| {
+            (
+                hit["_id"].as_str().unwrap().to_string(),
+                hit["_source"]["name"].as_str().unwrap().to_string(),
+            )
+        })
+        .collect()
+}
+
+/// check the upsert, update and delete of documents in a published index
+pub fn rubber_patch_test(mut es: crate::ElasticSearchWrapper<'_>) {
+    info!("running rubber_patch_test");
+    let dataset = "my_dataset";
+
+    let index_settings = IndexSettings {
+        nb_shards: 1,
+        nb_replicas: 0,
+    };
+    let streets = vec![
+        street("bob", "bob's street"),
+        street("bobette", "bobette's street"),
+    ];
+    let result = es
+        .rubber
+        .public_index(dataset, &index_settings, streets.into_iter());
+    assert_eq!(result.unwrap(), 2);
+    es.refresh();
+
+    let operations = vec![
+        PatchOperation::Upsert(street("alice", "alice's street")),
+        PatchOperation::Upsert(street("bob", "bob's boulevard")),
+        PatchOperation::Update {
+            id: "bobette".to_string(),
+            fields: json!({"name": "bobette's avenue"}),
+        },
+    ];
+    let result = es.rubber.patch(dataset, &operations);
+    assert_eq!(result.unwrap(), 3);
+
+    // the changes are visible without a refresh, and the index is still the published one
+    assert_eq!(
+        get_names_by_id(&es),
+        vec![
+            ("alice".to_string(), "alice's street".to_string()),
+            ("bob".to_string(), "bob's boulevard".to_string()),
+            ("bobette".to_string(), "bobette's avenue".to_string()),
+        ]
+        .into_iter()
+        .collect()
+    );
+
+    // the deletion of an unknown document is not an error
+    let result = es
+        .rubber
+        .delete::<Street>(dataset, &["bob".to_string(), "unknown".to_string()]);
+    assert_eq!(result.unwrap(), 1);
+    let names = get_names_by_id(&es);
+    assert_eq!(names.keys().collect::<Vec<_>>(), vec!["alice", "bobette"]);
+
+    // the update of an unknown document is an error
+    let result = es
+        .rubber
+        .update::<Street>(dataset, "unknown", json!({"name": "nobody's street"}));
+    assert!(result.is_err());
+
+    // but not if some errors are tolerated
+    let mut rubber = Rubber::new(&es.host()).with_max_bulk_errors(1);
+    let result = rubber.update::<Street>(dataset, "unknown", json!({"name": "nobody's street"}));
+    assert_eq!(result.unwrap(), 0);
+
+    // there is nothing to patch in a dataset without a published index
+    let result = es
+        .rubber
+        .upsert("unknown_dataset", vec![street("bob", "bob")]);
+    assert!(result.is_err());
+}
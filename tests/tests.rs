@@ -47,10 +47,12 @@ mod bragi_synonyms_test;
 mod bragi_three_cities_test;
 mod canonical_import_process_test;
 mod cosmogony2mimir_test;
+mod mimir_patch_test;
 mod openaddresses2mimir_test;
 mod osm2mimir_bano2mimir_test;
 mod osm2mimir_test;
 mod poi2mimir_test;
+mod rest_backend_test;
 mod rubber_test;
 mod stops2mimir_test;
 
@@ -137,6 +139,11 @@ fn all_tests() {
     // we call all tests here
     bano2mimir_test::bano2mimir_sample_test(ElasticSearchWrapper::new(&docker_wrapper));
     osm2mimir_test::osm2mimir_sample_test(ElasticSearchWrapper::new(&docker_wrapper));
+    osm2mimir_test::osm2mimir_address_test(ElasticSearchWrapper::new(&docker_wrapper));
+    osm2mimir_test::osm2mimir_intersection_test(ElasticSearchWrapper::new(&docker_wrapper));
+    osm2mimir_test::osm2mimir_postcode_test(ElasticSearchWrapper::new(&docker_wrapper));
+    osm2mimir_test::osm2mimir_locality_test(ElasticSearchWrapper::new(&docker_wrapper));
+    osm2mimir_test::osm2mimir_update_test(ElasticSearchWrapper::new(&docker_wrapper));
 
     #[cfg(feature = "db-storage")]
     osm2mimir_test::osm2mimir_sample_test_sqlite(ElasticSearchWrapper::new(&docker_wrapper));
@@ -150,12 +157,15 @@ fn all_tests() {
     rubber_test::rubber_custom_id(ElasticSearchWrapper::new(&docker_wrapper));
     rubber_test::rubber_ghost_index_cleanup(ElasticSearchWrapper::new(&docker_wrapper));
     rubber_test::rubber_empty_bulk(ElasticSearchWrapper::new(&docker_wrapper));
+    rubber_test::rubber_patch_test(ElasticSearchWrapper::new(&docker_wrapper));
+    mimir_patch_test::mimir_patch_test(ElasticSearchWrapper::new(&docker_wrapper));
     bragi_bano_test::bragi_bano_test(ElasticSearchWrapper::new(&docker_wrapper));
     bragi_osm_test::bragi_osm_test(ElasticSearchWrapper::new(&docker_wrapper));
     bragi_poi_test::test_i18n_poi(ElasticSearchWrapper::new(&docker_wrapper));
     bragi_three_cities_test::bragi_three_cities_test(ElasticSearchWrapper::new(&docker_wrapper));
     bragi_poi_test::bragi_poi_test(ElasticSearchWrapper::new(&docker_wrapper));
     bragi_poi_test::bragi_private_poi_test(ElasticSearchWrapper::new(&docker_wrapper));
+    bragi_poi_test::bragi_poi_details_test(ElasticSearchWrapper::new(&docker_wrapper));
     bragi_stops_test::bragi_stops_test(ElasticSearchWrapper::new(&docker_wrapper));
     bragi_ntfs_test::bragi_ntfs_test(ElasticSearchWrapper::new(&docker_wrapper));
     bragi_filter_types_test::bragi_filter_types_test(ElasticSearchWrapper::new(&docker_wrapper));
@@ -170,3 +180,12 @@ fn all_tests() {
         &docker_wrapper,
     ));
 }
+
+/// Same as `all_tests`, but with an Elasticsearch 7 docker used through `--backend rest`
+#[test]
+fn rest_backend_tests() {
+    let _guard = mimir::logger_init();
+    let docker_wrapper = DockerWrapper::new_rest().unwrap();
+
+    rest_backend_test::bano2mimir_rest_test(ElasticSearchWrapper::new(&docker_wrapper));
+}